@@ -0,0 +1,377 @@
+use crate::config::{AuthConfig, AuthFailure, AuthMode};
+use crate::connections::{Connections, IpSlot};
+use crate::error::AppError;
+use crate::finite::Stop;
+use crate::maintenance::Window;
+use crate::quotas::{api_key, metered, Permit};
+use crate::server::Shutdown;
+use crate::state::AppState;
+use crate::stream::SSEQuery;
+use crate::transport::Message;
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::{watch, OwnedSemaphorePermit};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+const TOKEN_EXPIRED_EVENT: &str = "token_expired";
+const AUTH_EXPIRED_EVENT: &str = "auth_expired";
+const MAINTENANCE_START_EVENT: &str = "maintenance-start";
+const MAINTENANCE_END_EVENT: &str = "maintenance-end";
+const SHUTDOWN_EVENT: &str = "server_shutdown";
+
+#[derive(Deserialize)]
+struct Claims {
+    exp: Option<i64>,
+}
+
+fn unauthorized(problem_type: &'static str, title: &str, detail: impl Into<String>) -> AppError {
+    AppError::new(StatusCode::UNAUTHORIZED)
+        .with_type(problem_type, title)
+        .with_detail(detail)
+        .with_header(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))
+}
+
+fn bearer_token<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Option<&'a str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query)
+}
+
+// the token's expiry, if it has one; signatures are not checked, this only shapes the stream
+fn token_expiry(token: &str) -> Result<Option<DateTime<Utc>>, AppError> {
+    let invalid = |detail: String| unauthorized("/problems/invalid-token", "Invalid token", detail);
+
+    let mut segments = token.split('.');
+    let (Some(_), Some(payload), Some(_), None) = (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) else {
+        return Err(invalid(
+            "a JWT has three dot-separated segments".to_string(),
+        ));
+    };
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|e| invalid(format!("the JWT payload is not base64url: {e}")))?;
+    let claims: Claims = serde_json::from_slice(&payload)
+        .map_err(|e| invalid(format!("the JWT payload is not a claims object: {e}")))?;
+
+    let Some(exp) = claims.exp else {
+        return Ok(None);
+    };
+    let expires_at = DateTime::from_timestamp(exp, 0)
+        .ok_or_else(|| invalid(format!("exp {exp} is out of range")))?;
+    if expires_at <= Utc::now() {
+        return Err(unauthorized(
+            "/problems/token-expired",
+            "Token expired",
+            format!("the token expired at {}", expires_at.to_rfc3339()),
+        ));
+    }
+    Ok(Some(expires_at))
+}
+
+// when a stream's credentials run out, and the event it then ends with
+struct Expiry {
+    at: DateTime<Utc>,
+    event: &'static str,
+    data: Value,
+}
+
+impl Expiry {
+    fn auth(after_secs: u64, reason: &str) -> Self {
+        Expiry {
+            // config caps after_secs, but a far-off expiry is never rather than a panic
+            at: i64::try_from(after_secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|after| Utc::now().checked_add_signed(after))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            event: AUTH_EXPIRED_EVENT,
+            data: json!({ "reason": reason }),
+        }
+    }
+}
+
+// Err with the reason the request's [auth] token doesn't pass
+fn check_auth(
+    auth: &AuthConfig,
+    headers: &HeaderMap,
+    query: &SSEQuery,
+) -> Result<(), &'static str> {
+    let token = match auth.mode {
+        AuthMode::Bearer => bearer_token(headers, None),
+        AuthMode::ApiKey => headers
+            .get(auth.header.as_str())
+            .and_then(|value| value.to_str().ok()),
+        AuthMode::Query => query.token.as_deref(),
+    };
+    match token {
+        None => Err("missing_token"),
+        Some(token) if auth.tokens.iter().any(|accepted| accepted == token) => Ok(()),
+        Some(_) => Err("invalid_token"),
+    }
+}
+
+// what a stream request was let in with: an API key's quota, a token's expiry and the
+// maintenance windows it has to sit out
+pub struct Admission {
+    slot: Option<OwnedSemaphorePermit>,
+    ip_slot: Option<IpSlot>,
+    client: Option<IpAddr>,
+    connections: Arc<Connections>,
+    permit: Option<Permit>,
+    expiry: Option<Expiry>,
+    // [auth] on_failure = "drop"
+    dropped: bool,
+    maintenance: watch::Receiver<Option<Window>>,
+    shutdown: Arc<Shutdown>,
+}
+
+fn too_many_connections(detail: String) -> AppError {
+    AppError::new(StatusCode::SERVICE_UNAVAILABLE)
+        .with_type("/problems/too-many-connections", "Too many connections")
+        .with_detail(detail)
+        .with_header(header::RETRY_AFTER, HeaderValue::from(1))
+}
+
+pub fn admit(
+    state: &AppState,
+    client: Option<ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+    query: &SSEQuery,
+) -> Result<Admission, AppError> {
+    if let Some(window) = state
+        .maintenance
+        .active()
+        .filter(|window| window.reject_new_connections)
+    {
+        let seconds = (window.until - Utc::now()).num_seconds().max(0) as u64 + 1;
+        return Err(AppError::new(StatusCode::SERVICE_UNAVAILABLE)
+            .with_type("/problems/maintenance", "Down for maintenance")
+            .with_detail(format!("maintenance until {}", window.until.to_rfc3339()))
+            .with_extension("until", json!(window.until))
+            .with_header(header::RETRY_AFTER, HeaderValue::from(seconds)));
+    }
+
+    let slot = state
+        .connection_slots
+        .as_ref()
+        .map(|slots| {
+            slots.clone().try_acquire_owned().map_err(|_| {
+                too_many_connections(format!(
+                    "the server is limited to {} concurrent streams",
+                    state.config.server.max_connections.unwrap_or_default()
+                ))
+            })
+        })
+        .transpose()?;
+    let client = client.map(|ConnectInfo(client)| client.ip());
+    let ip_slot = match (client, state.config.server.max_connections_per_ip) {
+        (Some(client), Some(max)) => {
+            Some(state.connections.take_ip_slot(client, max).ok_or_else(|| {
+                too_many_connections(format!("{client} is limited to {max} concurrent streams"))
+            })?)
+        }
+        _ => None,
+    };
+
+    let mut expiry = None;
+    let mut dropped = false;
+    if let Some(auth) = &state.config.auth {
+        match check_auth(auth, headers, query) {
+            Ok(()) => {
+                expiry = auth
+                    .session_secs
+                    .map(|secs| Expiry::auth(secs, "session_expired"))
+            }
+            Err(reason) => match auth.on_failure {
+                AuthFailure::Reject => {
+                    let how = match auth.mode {
+                        AuthMode::Bearer => "as a bearer token".to_string(),
+                        AuthMode::ApiKey => format!("in the {} header", auth.header),
+                        AuthMode::Query => "in the token parameter".to_string(),
+                    };
+                    return Err(unauthorized(
+                        "/problems/unauthorized",
+                        if reason == "missing_token" {
+                            "Missing token"
+                        } else {
+                            "Invalid token"
+                        },
+                        format!("pass one of the configured tokens {how}"),
+                    ));
+                }
+                AuthFailure::Expire => expiry = Some(Expiry::auth(auth.expire_after_secs, reason)),
+                AuthFailure::Drop => dropped = true,
+            },
+        }
+    }
+
+    if let Some(jwt) = &state.config.jwt {
+        let token = bearer_token(headers, query.access_token.as_deref()).ok_or_else(|| {
+            unauthorized(
+                "/problems/unauthorized",
+                "Missing token",
+                "pass a JWT as a bearer token or in the access_token parameter",
+            )
+        })?;
+        let token_expiry = token_expiry(token)?
+            .filter(|_| jwt.expire_streams)
+            .map(|at| Expiry {
+                at,
+                event: TOKEN_EXPIRED_EVENT,
+                data: json!({ "exp": at.timestamp() }),
+            });
+        // whichever runs out first
+        expiry = expiry
+            .into_iter()
+            .chain(token_expiry)
+            .min_by_key(|expiry| expiry.at);
+    }
+
+    let permit = state
+        .quotas
+        .admit(&state.config, api_key(headers, query.api_key.as_deref()))?;
+
+    Ok(Admission {
+        slot,
+        ip_slot,
+        client,
+        connections: state.connections.clone(),
+        permit,
+        expiry,
+        dropped,
+        maintenance: state.maintenance.watch(),
+        shutdown: state.shutdown.clone(),
+    })
+}
+
+impl Admission {
+    // meters the stream, holds it back during maintenance (after the handshake) and, once the
+    // token expires or the server drains, ends it with a token_expired, auth_expired or server_shutdown
+    // event once the generator has sent what it held back
+    pub fn guard(
+        self,
+        stream_id: Option<Uuid>,
+        messages: impl Stream<Item = Message> + Send + 'static,
+        stop: Stop,
+    ) -> impl Stream<Item = Message> + Send + 'static {
+        let mut expiry = self.expiry;
+        let dropped = self.dropped;
+        let mut maintenance = self.maintenance;
+        let draining = self.shutdown.stream_deadline();
+        let messages = metered(messages, self.permit);
+        let slots = (self.slot, self.ip_slot);
+        let tracked = stream_id.map(|stream_id| self.connections.track(stream_id, self.client));
+        let kicked = tracked.as_ref().map(|tracked| tracked.kick.clone());
+        async_stream::stream! {
+            // the slots free up and the stream leaves GET /connections when it ends
+            let _slots = slots;
+            if dropped {
+                yield Message::abort();
+                return;
+            }
+            let mut messages = Box::pin(messages);
+            if let Some(handshake) = messages.next().await {
+                yield handshake;
+            }
+            maintenance.mark_changed();
+            let mut paused = false;
+            let expired = sleep(
+                expiry
+                    .as_ref()
+                    .map(|expiry| (expiry.at - Utc::now()).to_std().unwrap_or_default())
+                    .unwrap_or(std::time::Duration::MAX),
+            );
+            tokio::pin!(expired);
+            tokio::pin!(draining);
+            let kicked = async move {
+                match kicked {
+                    Some(kick) => kick.notified().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::pin!(kicked);
+
+            let last = loop {
+                tokio::select! {
+                    biased;
+                    Ok(()) = maintenance.changed() => {
+                        let window = maintenance.borrow_and_update().clone();
+                        match &window {
+                            Some(window) => yield Message::control(MAINTENANCE_START_EVENT, json!(window)),
+                            None if paused => yield Message::control(
+                                MAINTENANCE_END_EVENT,
+                                json!({ "ts": Utc::now().to_rfc3339() }),
+                            ),
+                            None => {}
+                        }
+                        paused = window.is_some();
+                    }
+                    message = messages.next(), if !paused => match message {
+                        Some(message) => {
+                            if let Some(tracked) = tracked.as_ref().filter(|_| !message.control) {
+                                tracked.events.fetch_add(1, Ordering::Relaxed);
+                            }
+                            yield message;
+                        }
+                        None => return,
+                    },
+                    () = &mut kicked => break Some(Message::abort()),
+                    _ = &mut expired, if expiry.is_some() => {
+                        break expiry.take().map(|Expiry { event, mut data, .. }| {
+                            data["ts"] = json!(Utc::now().to_rfc3339());
+                            Message::control(event, data)
+                        });
+                    }
+                    _ = &mut draining => break Some(Message::control(
+                        SHUTDOWN_EVENT,
+                        json!({ "reconnect": true, "ts": Utc::now().to_rfc3339() }),
+                    )),
+                }
+            };
+
+            if stop.request() {
+                while let Some(message) = messages.next().await {
+                    if let Some(tracked) = tracked.as_ref().filter(|_| !message.control) {
+                        tracked.events.fetch_add(1, Ordering::Relaxed);
+                    }
+                    yield message;
+                }
+            }
+            if let Some(last) = last {
+                yield last;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn far_off_expiry_never_arrives_instead_of_panicking() {
+        assert_eq!(Expiry::auth(u64::MAX, "x").at, DateTime::<Utc>::MAX_UTC);
+        assert_eq!(
+            Expiry::auth(i64::MAX as u64, "x").at,
+            DateTime::<Utc>::MAX_UTC
+        );
+        assert!(Expiry::auth(5, "x").at < DateTime::<Utc>::MAX_UTC);
+    }
+}
@@ -0,0 +1,214 @@
+use chrono::{DateTime, Duration, Months, NaiveDate, NaiveDateTime, Utc};
+use serde_json::{json, Value};
+
+// a VEVENT: emitted as a `summary` event at every occurrence, `burst` times over
+#[derive(Clone)]
+pub struct CalendarEvent {
+    pub summary: String,
+    pub data: Value,
+    pub burst: usize,
+    pub occurrences: Occurrences,
+}
+
+#[derive(Clone, Copy)]
+enum Frequency {
+    Minutely,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// DTSTART plus the supported subset of RRULE: FREQ, INTERVAL, COUNT and UNTIL
+#[derive(Clone)]
+pub struct Occurrences {
+    start: DateTime<Utc>,
+    rule: Option<(Frequency, u32)>,
+    count: Option<u32>,
+    until: Option<DateTime<Utc>>,
+    pub rrule: Option<String>,
+}
+
+impl Occurrences {
+    fn nth(&self, n: u32) -> Option<DateTime<Utc>> {
+        if self.count.is_some_and(|count| n >= count) {
+            return None;
+        }
+
+        let at = match self.rule {
+            None if n > 0 => return None,
+            None => self.start,
+            Some((frequency, interval)) => {
+                let steps = n.checked_mul(interval)?;
+                match frequency {
+                    Frequency::Minutely => self.start + Duration::minutes(steps.into()),
+                    Frequency::Hourly => self.start + Duration::hours(steps.into()),
+                    Frequency::Daily => self.start + Duration::days(steps.into()),
+                    Frequency::Weekly => self.start + Duration::weeks(steps.into()),
+                    Frequency::Monthly => self.start.checked_add_months(Months::new(steps))?,
+                    Frequency::Yearly => self
+                        .start
+                        .checked_add_months(Months::new(steps.checked_mul(12)?))?,
+                }
+            }
+        };
+
+        match self.until {
+            Some(until) if at > until => None,
+            _ => Some(at),
+        }
+    }
+
+    // the first occurrence strictly after `after`
+    pub fn next(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        (0..).map_while(|n| self.nth(n)).find(|at| *at > after)
+    }
+}
+
+pub fn parse_calendar(source: &str) -> Result<Vec<CalendarEvent>, String> {
+    // long lines are folded onto continuation lines starting with a space or tab
+    let mut lines: Vec<String> = Vec::new();
+    for line in source.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<Vec<(String, String, String)>> = None;
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let (head, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("malformed line {line:?}"))?;
+        let (name, params) = head.split_once(';').unwrap_or((head, ""));
+        let name = name.to_ascii_uppercase();
+
+        match (name.as_str(), value, &mut current) {
+            ("BEGIN", "VEVENT", None) => current = Some(Vec::new()),
+            ("END", "VEVENT", Some(_)) => {
+                let properties = current.take().unwrap_or_default();
+                let event = calendar_event(&properties).map_err(|e| {
+                    let summary = property(&properties, "SUMMARY").unwrap_or("VEVENT");
+                    format!("{summary}: {e}")
+                })?;
+                events.push(event);
+            }
+            (_, _, Some(properties)) => {
+                properties.push((name, params.to_string(), unescape(value)));
+            }
+            _ => {}
+        }
+    }
+
+    if current.is_some() {
+        return Err("VEVENT is never closed".to_string());
+    }
+    Ok(events)
+}
+
+fn property<'a>(properties: &'a [(String, String, String)], name: &str) -> Option<&'a str> {
+    properties
+        .iter()
+        .find(|(property, _, _)| property == name)
+        .map(|(_, _, value)| value.as_str())
+}
+
+fn calendar_event(properties: &[(String, String, String)]) -> Result<CalendarEvent, String> {
+    let summary = property(properties, "SUMMARY").ok_or("missing SUMMARY")?;
+    let start = property(properties, "DTSTART").ok_or("missing DTSTART")?;
+
+    // a JSON description is the payload as is, anything else is wrapped
+    let data = match property(properties, "DESCRIPTION") {
+        None => Value::Null,
+        Some(description) => serde_json::from_str(description)
+            .unwrap_or_else(|_| json!({ "description": description })),
+    };
+    let burst = match property(properties, "X-SSE-BURST") {
+        None => 1,
+        Some(burst) => burst
+            .parse()
+            .ok()
+            .filter(|burst| (1..=1000).contains(burst))
+            .ok_or("X-SSE-BURST must be between 1 and 1000")?,
+    };
+
+    let mut occurrences = Occurrences {
+        start: parse_time(start)?,
+        rule: None,
+        count: None,
+        until: None,
+        rrule: property(properties, "RRULE").map(str::to_string),
+    };
+    if let Some(rrule) = &occurrences.rrule {
+        let mut frequency = None;
+        let mut interval = 1;
+        for part in rrule.split(';') {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("malformed RRULE part {part}"))?;
+            let invalid = || format!("invalid RRULE {key}={value}");
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "MINUTELY" => Frequency::Minutely,
+                        "HOURLY" => Frequency::Hourly,
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(invalid()),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(invalid)?
+                }
+                "COUNT" => occurrences.count = Some(value.parse().map_err(|_| invalid())?),
+                "UNTIL" => occurrences.until = Some(parse_time(value)?),
+                _ => return Err(format!("RRULE {key} is not supported")),
+            }
+        }
+        occurrences.rule = Some((frequency.ok_or("RRULE needs a FREQ")?, interval));
+    }
+
+    Ok(CalendarEvent {
+        summary: summary.to_string(),
+        data,
+        burst,
+        occurrences,
+    })
+}
+
+// dates and date-times, read as UTC whether or not they carry a Z or TZID
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    let value = value.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map(|at| at.and_utc())
+        .map_err(|_| format!("{value} is not an iCalendar date or date-time"))
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,164 @@
+use crate::error::AppError;
+use crate::pools::Pools;
+use crate::scenario::FixedEvent;
+use crate::stream::{EventGenerator, StreamSettings};
+use crate::template::GenContext;
+use crate::transport::Message;
+use futures::Stream;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::broadcast::{self, error::RecvError, Sender};
+use tokio::task::AbortHandle;
+use tokio::time::sleep;
+
+// events a subscriber may fall behind by before it skips ahead
+const CHANNEL_BUFFER: usize = 256;
+
+#[derive(Clone)]
+enum Broadcast {
+    Generated(u64, Option<String>, Value),
+    // pushed over /channel/{name}/publish, sent as is without an id
+    Published(FixedEvent),
+}
+
+// one generator whose events go to every subscriber of a /channel/{name}
+pub struct Channel {
+    events: Sender<Broadcast>,
+    handshake: Value,
+    producer: AbortHandle,
+}
+
+impl Channel {
+    fn spawn(settings: StreamSettings, pools: Arc<Pools>) -> Self {
+        let (events, _) = broadcast::channel(CHANNEL_BUFFER);
+        let handshake = settings.handshake();
+        let sender = events.clone();
+        let producer = tokio::spawn(async move {
+            let mut generator = EventGenerator::new(
+                &settings.shape,
+                &settings.directives,
+                GenContext::new(settings.seed, settings.packs, settings.clock, pools.clone())
+                    .with_normalization(settings.normalization)
+                    .with_locale(settings.locale)
+                    .with_custom(settings.custom.clone()),
+                settings.position,
+            );
+
+            loop {
+                sleep(generator.pattern_delay(
+                    settings.pattern,
+                    settings.interval_min,
+                    settings.interval_max,
+                ))
+                .await;
+                let (sequence, events) = generator.next_events();
+                if !generator.sampled(settings.sample) {
+                    continue;
+                }
+                let event_type = generator.event_type().map(str::to_string);
+                for mut event in events {
+                    settings.links.apply(&mut event, &pools, generator.rng());
+                    let _ = sender.send(Broadcast::Generated(sequence, event_type.clone(), event));
+                }
+            }
+        });
+
+        Channel {
+            events,
+            handshake,
+            producer: producer.abort_handle(),
+        }
+    }
+}
+
+// the generator stops with the last subscriber
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.producer.abort();
+    }
+}
+
+#[derive(Default)]
+pub struct Channels {
+    channels: Mutex<HashMap<String, Weak<Channel>>>,
+}
+
+impl Channels {
+    // a channel starts with its first subscriber, generating from that subscriber's settings;
+    // everyone joining while it runs gets the same events
+    pub fn get_or_spawn(
+        &self,
+        name: &str,
+        settings: impl FnOnce() -> StreamSettings,
+        pools: Arc<Pools>,
+    ) -> Arc<Channel> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.retain(|_, channel| channel.strong_count() > 0);
+        if let Some(channel) = channels.get(name).and_then(Weak::upgrade) {
+            return channel;
+        }
+        let channel = Arc::new(Channel::spawn(settings(), pools));
+        channels.insert(name.to_string(), Arc::downgrade(&channel));
+        channel
+    }
+
+    // Ok with the number of subscribers it reached
+    pub fn publish(&self, name: &str, event: FixedEvent) -> Result<usize, AppError> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(Weak::upgrade)
+            .ok_or_else(|| AppError::not_found(format!("no channel named {name} is running")))?;
+        Ok(channel
+            .events
+            .send(Broadcast::Published(event))
+            .unwrap_or_default())
+    }
+
+    // subscribers per running channel
+    pub fn subscribers(&self) -> BTreeMap<String, usize> {
+        self.channels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, channel)| {
+                let channel = channel.upgrade()?;
+                Some((name.clone(), channel.events.receiver_count()))
+            })
+            .collect()
+    }
+}
+
+pub fn channel_events(
+    settings: StreamSettings,
+    name: String,
+    channel: Arc<Channel>,
+) -> impl Stream<Item = Message> {
+    // subscribed before the first poll, so nothing sent in between is missed
+    let mut events = channel.events.subscribe();
+    async_stream::stream! {
+        let mut handshake = channel.handshake.clone();
+        handshake["channel"] = json!(name);
+        handshake["subscribers"] = json!(channel.events.receiver_count());
+        yield Message::control("handshake", handshake);
+
+        loop {
+            match events.recv().await {
+                Ok(Broadcast::Generated(sequence, event_type, event)) => {
+                    let event = settings.projection.apply(event);
+                    yield settings.data_message(sequence, event_type.as_deref(), event);
+                }
+                Ok(Broadcast::Published(published)) => {
+                    yield Message::data(published.event.or_else(|| settings.event.clone()), published.data);
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    yield Message::control("lagged", json!({ "missed": missed }));
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
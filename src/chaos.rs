@@ -0,0 +1,87 @@
+use crate::stream::SSEQuery;
+use crate::transport::Message;
+use futures::{Stream, StreamExt};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const DEFAULT_STALL: Duration = Duration::from_millis(5000);
+// the stall chance when only chaos.stall_ms is given
+const DEFAULT_STALL_PROB: f64 = 0.1;
+
+// per-event faults for resilience testing; each data event rolls for each of them
+#[derive(Clone, Copy)]
+pub struct Chaos {
+    // close the connection instead of sending the event
+    pub drop_prob: f64,
+    // freeze for stall before sending the event
+    pub stall_prob: f64,
+    pub stall: Duration,
+    // send something that isn't JSON in place of the event's data
+    pub malformed_prob: f64,
+}
+
+impl Chaos {
+    // None unless some chaos.* parameter is set
+    pub fn from_query(query: &SSEQuery) -> Option<Self> {
+        let stall = query.chaos_stall_ms.map(Duration::from_millis);
+        let stall_prob = query.chaos_stall_prob.or(stall.map(|_| DEFAULT_STALL_PROB));
+        if query.chaos_drop_prob.is_none()
+            && stall_prob.is_none()
+            && query.chaos_malformed_prob.is_none()
+        {
+            return None;
+        }
+        Some(Chaos {
+            drop_prob: query.chaos_drop_prob.unwrap_or_default(),
+            stall_prob: stall_prob.unwrap_or_default(),
+            stall: stall.unwrap_or(DEFAULT_STALL),
+            malformed_prob: query.chaos_malformed_prob.unwrap_or_default(),
+        })
+    }
+}
+
+fn garble(data: &str, rng: &mut impl Rng) -> String {
+    match rng.gen_range(0..4) {
+        // cut off mid-payload
+        0 => data.chars().take(data.chars().count() / 2).collect(),
+        1 => format!("{data}}}"),
+        2 => data.replacen(':', "", 1),
+        _ => "undefined".to_string(),
+    }
+}
+
+pub fn with_chaos(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    chaos: Option<Chaos>,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        let Some(chaos) = chaos else {
+            while let Some(message) = messages.next().await {
+                yield message;
+            }
+            return;
+        };
+
+        let mut rng = ChaCha8Rng::from_entropy();
+        while let Some(mut message) = messages.next().await {
+            if message.control {
+                yield message;
+                continue;
+            }
+            if rng.gen_bool(chaos.drop_prob) {
+                yield Message::abort();
+                return;
+            }
+            if rng.gen_bool(chaos.stall_prob) {
+                sleep(chaos.stall).await;
+            }
+            if rng.gen_bool(chaos.malformed_prob) {
+                message.raw = Some(garble(&message.data.to_string(), &mut rng));
+            }
+            yield message;
+        }
+    }
+}
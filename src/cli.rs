@@ -0,0 +1,359 @@
+use crate::config::{Config, Override};
+use crate::golden::{diff, Golden};
+use crate::loadtest::{self, LoadTest};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const USAGE: &str = "usage:
+  sse-proto [serve options]    serve, on 0.0.0.0:3000 unless configured otherwise
+  sse-proto export [options]   write the first events of a seed and shape as a golden file
+  sse-proto verify --golden <file> [options]
+                               regenerate a golden file and report every field that differs
+  sse-proto connect [url] [connect options]
+                               hold SSE connections open against url, this server's / by default,
+                               and report events/s, gaps between events and reconnects
+
+serve options (over SSE_PROTO_* environment variables, over the config file):
+  --config <file>       read this config file instead of sse-proto.toml, also $SSE_PROTO_CONFIG
+  --host <address>      same as --set server.host=<address>
+  --port <port>         same as --set server.port=<port>
+  --interval-min <ms>   same as --set defaults.interval_min=<ms>
+  --interval-max <ms>   same as --set defaults.interval_max=<ms>
+  --max-connections <n> same as --set server.max_connections=<n>
+  --max-connections-per-ip <n>
+                        same as --set server.max_connections_per_ip=<n>
+  --log-level <level>   same as --set server.log_level=<level>, one of off, error, warn, info, debug
+  -v, --verbose         same as --log-level debug, logging every chunk sent
+  --compression         same as --set server.compression=true, gzip streams when clients accept it
+  --fixture <file>      same as --set defaults.fixture=<file>, streams the file's lines on /
+  --spec <file>         same as --set spec.file=<file>, serves an OpenAPI or AsyncAPI document's streams
+  --cors-origin <origin>
+                        allow this browser origin, in place of any; repeatable, sets cors.origins
+  --header '<name>: <value>'
+                        send this header on every response; repeatable, sets server.headers
+  --set <path>=<value>  any config value, e.g. --set defaults.interval_min=1500
+
+options:
+  --shape <json|@file>  event template, defaults to the golden file's, then the [defaults] shape
+  --seed <n>            generator seed, defaults to the golden file's, then 0
+  --count <n>           events to export, defaults to 10 (verify checks the golden file's count)
+  --out <file>          export here instead of stdout
+
+connect options:
+  --connections <n>     concurrent connections, defaults to 1
+  --duration <s>        stop after s seconds, otherwise on ctrl-c
+  --reconnect-delay <ms>
+                        wait before reconnecting when a stream ends or fails, defaults to 1000";
+
+const DEFAULT_COUNT: usize = 10;
+const DEFAULT_RECONNECT_DELAY_MS: u64 = 1000;
+
+pub enum Command {
+    Serve(ServeArgs),
+    Export(GenerateArgs),
+    Verify(GenerateArgs),
+    Connect(ConnectArgs),
+}
+
+#[derive(Default)]
+pub struct ServeArgs {
+    pub config: Option<PathBuf>,
+    pub overrides: Vec<Override>,
+}
+
+pub struct GenerateArgs {
+    shape: Option<String>,
+    seed: Option<u64>,
+    count: usize,
+    out: Option<PathBuf>,
+    golden: Option<PathBuf>,
+}
+
+pub struct ConnectArgs {
+    url: Option<String>,
+    connections: usize,
+    duration: Option<Duration>,
+    reconnect_delay: Duration,
+}
+
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.peekable();
+    if args
+        .peek()
+        .is_none_or(|arg| arg.starts_with('-') && arg != "--help" && arg != "-h")
+    {
+        return serve_args(args).map(Command::Serve);
+    }
+    let command = args.next().unwrap_or_default();
+
+    match command.as_str() {
+        "serve" => serve_args(args).map(Command::Serve),
+        "export" => Ok(Command::Export(generate_args(args)?)),
+        "verify" => {
+            let args = generate_args(args)?;
+            if args.golden.is_none() {
+                return Err(format!("verify needs --golden\n\n{USAGE}"));
+            }
+            Ok(Command::Verify(args))
+        }
+        "connect" => Ok(Command::Connect(connect_args(args)?)),
+        "-h" | "--help" | "help" => Err(USAGE.to_string()),
+        other => Err(format!("unknown command {other}\n\n{USAGE}")),
+    }
+}
+
+fn serve_args(mut args: impl Iterator<Item = String>) -> Result<ServeArgs, String> {
+    let mut parsed = ServeArgs::default();
+    let mut cors_origins = Vec::new();
+    while let Some(flag) = args.next() {
+        if flag == "-v" || flag == "--verbose" {
+            parsed
+                .overrides
+                .push(("server.log_level".to_string(), "debug".to_string()));
+            continue;
+        }
+        if flag == "--compression" {
+            parsed
+                .overrides
+                .push(("server.compression".to_string(), "true".to_string()));
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{flag} needs a value\n\n{USAGE}"))?;
+        parsed.overrides.push(match flag.as_str() {
+            "--config" => {
+                parsed.config = Some(PathBuf::from(value));
+                continue;
+            }
+            "--host" => ("server.host".to_string(), value),
+            "--port" => ("server.port".to_string(), value),
+            "--interval-min" => ("defaults.interval_min".to_string(), value),
+            "--interval-max" => ("defaults.interval_max".to_string(), value),
+            "--max-connections" => ("server.max_connections".to_string(), value),
+            "--max-connections-per-ip" => ("server.max_connections_per_ip".to_string(), value),
+            "--log-level" => ("server.log_level".to_string(), value),
+            "--fixture" => ("defaults.fixture".to_string(), value),
+            "--spec" => ("spec.file".to_string(), value),
+            "--cors-origin" => {
+                cors_origins.push(toml::Value::String(value));
+                continue;
+            }
+            // quoted, so a value like 3600 stays a string
+            "--header" => {
+                let (name, value) = value
+                    .split_once(':')
+                    .ok_or_else(|| format!("--header takes '<name>: <value>', got {value}"))?;
+                (
+                    format!("server.headers.{}", name.trim()),
+                    toml::Value::String(value.trim().to_string()).to_string(),
+                )
+            }
+            "--set" => {
+                let (path, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("--set takes <path>=<value>, got {value}"))?;
+                (path.to_string(), value.to_string())
+            }
+            other => return Err(format!("unknown option {other}\n\n{USAGE}")),
+        });
+    }
+    if !cors_origins.is_empty() {
+        parsed.overrides.push((
+            "cors.origins".to_string(),
+            toml::Value::Array(cors_origins).to_string(),
+        ));
+    }
+    Ok(parsed)
+}
+
+fn generate_args(mut args: impl Iterator<Item = String>) -> Result<GenerateArgs, String> {
+    let mut parsed = GenerateArgs {
+        shape: None,
+        seed: None,
+        count: DEFAULT_COUNT,
+        out: None,
+        golden: None,
+    };
+
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{flag} needs a value\n\n{USAGE}"))
+        };
+        match flag.as_str() {
+            "--shape" => parsed.shape = Some(value()?),
+            "--seed" => parsed.seed = Some(value()?.parse().map_err(|e| format!("--seed: {e}"))?),
+            "--count" => parsed.count = value()?.parse().map_err(|e| format!("--count: {e}"))?,
+            "--out" => parsed.out = Some(PathBuf::from(value()?)),
+            "--golden" => parsed.golden = Some(PathBuf::from(value()?)),
+            other => return Err(format!("unknown option {other}\n\n{USAGE}")),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn connect_args(mut args: impl Iterator<Item = String>) -> Result<ConnectArgs, String> {
+    let mut parsed = ConnectArgs {
+        url: None,
+        connections: 1,
+        duration: None,
+        reconnect_delay: Duration::from_millis(DEFAULT_RECONNECT_DELAY_MS),
+    };
+
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{flag} needs a value\n\n{USAGE}"))
+        };
+        match flag.as_str() {
+            "--connections" => {
+                parsed.connections = value()?
+                    .parse()
+                    .map_err(|e| format!("--connections: {e}"))?
+            }
+            "--duration" => {
+                let seconds = value()?
+                    .parse::<f64>()
+                    .map_err(|e| format!("--duration: {e}"))?;
+                parsed.duration = Some(
+                    Duration::try_from_secs_f64(seconds).map_err(|e| format!("--duration: {e}"))?,
+                );
+            }
+            "--reconnect-delay" => {
+                parsed.reconnect_delay = Duration::from_millis(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("--reconnect-delay: {e}"))?,
+                )
+            }
+            url if !url.starts_with("--") && parsed.url.is_none() => {
+                parsed.url = Some(url.to_string())
+            }
+            other => return Err(format!("unknown option {other}\n\n{USAGE}")),
+        }
+    }
+    if parsed.connections == 0 {
+        return Err("--connections must be at least 1".to_string());
+    }
+
+    Ok(parsed)
+}
+
+fn load_shape(shape: Option<&str>, config: &Config) -> Result<Map<String, Value>, String> {
+    let source = match shape {
+        Some(path) if path.starts_with('@') => {
+            fs::read_to_string(&path[1..]).map_err(|e| format!("--shape {}: {e}", &path[1..]))?
+        }
+        Some(source) => source.to_string(),
+        None => config.defaults.shape.to_string(),
+    };
+
+    match serde_json::from_str(&source) {
+        Ok(Value::Object(shape)) => Ok(shape),
+        Ok(_) => Err("--shape must be a JSON object".to_string()),
+        Err(e) => Err(format!("--shape is not valid JSON: {e}")),
+    }
+}
+
+fn generate(args: &GenerateArgs, config: &Config) -> Result<Golden, String> {
+    let shape = load_shape(args.shape.as_deref(), config)?;
+    Golden::generate(
+        shape,
+        args.seed.unwrap_or_default(),
+        args.count,
+        config.packs,
+    )
+}
+
+pub fn export(args: GenerateArgs, config: &Config) -> Result<(), String> {
+    let canonical = generate(&args, config)?.canonical();
+    match &args.out {
+        Some(out) => fs::write(out, canonical).map_err(|e| format!("{}: {e}", out.display())),
+        None => {
+            print!("{canonical}");
+            Ok(())
+        }
+    }
+}
+
+pub async fn connect(args: ConnectArgs, config: &Config) -> Result<(), String> {
+    let url = args
+        .url
+        .unwrap_or_else(|| format!("http://127.0.0.1:{}/", config.server.port));
+    loadtest::run(LoadTest {
+        url,
+        connections: args.connections,
+        duration: args.duration,
+        reconnect_delay: args.reconnect_delay,
+    })
+    .await
+}
+
+// Ok(false) when the regenerated events differ from the golden file's
+pub fn verify(args: GenerateArgs, config: &Config) -> Result<bool, String> {
+    let path = args.golden.clone().unwrap_or_default();
+    let source = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let golden: Golden = serde_json::from_str(&source)
+        .map_err(|e| format!("{} is not a golden file: {e}", path.display()))?;
+
+    let shape = match args.shape.as_deref() {
+        Some(shape) => load_shape(Some(shape), config)?,
+        None => golden.shape.clone(),
+    };
+    let seed = args.seed.unwrap_or(golden.seed);
+    let count = golden.events.len();
+    let regenerated = Golden::generate(shape, seed, count, config.packs)?;
+
+    let mut differences = Vec::new();
+    diff(
+        &Value::Array(golden.events),
+        &Value::Array(regenerated.events),
+        "events",
+        &mut differences,
+    );
+
+    if differences.is_empty() {
+        println!("{}: {count} events match", path.display());
+        return Ok(true);
+    }
+    println!(
+        "{}: {} differences (golden from {}, regenerated by {})",
+        path.display(),
+        differences.len(),
+        golden.generator,
+        regenerated.generator,
+    );
+    for difference in differences {
+        println!("  {difference}");
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn leading_short_flags_are_serve_flags() {
+        for flags in [&["-v"][..], &["--port", "3001", "-v"], &["serve", "-v"]] {
+            let Ok(Command::Serve(args)) = parse_args(flags) else {
+                panic!("{flags:?} should serve");
+            };
+            assert!(args
+                .overrides
+                .contains(&("server.log_level".to_string(), "debug".to_string())));
+        }
+        for help in ["-h", "--help"] {
+            assert_eq!(parse_args(&[help]).err().unwrap(), USAGE);
+        }
+        assert!(parse_args(&["-x"]).is_err());
+    }
+}
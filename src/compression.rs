@@ -0,0 +1,151 @@
+use axum::body::{Body, Bytes};
+use axum::extract::Request;
+use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::StreamExt;
+use std::io::Write;
+use std::mem;
+
+// the streaming media types; other responses are short enough to send as they are
+const STREAMING_TYPES: [&str; 2] = ["text/event-stream", "application/x-ndjson"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Coding {
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    fn name(self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    // the highest-q coding of Accept-Encoding we support, gzip on a tie; None for identity
+    fn negotiate(headers: &HeaderMap) -> Option<Coding> {
+        let accept = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+        let mut best: Option<(f32, Coding)> = None;
+        for entry in accept.split(',') {
+            let mut params = entry.split(';').map(str::trim);
+            let coding = match params
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "gzip" | "x-gzip" | "*" => Coding::Gzip,
+                "deflate" => Coding::Deflate,
+                _ => continue,
+            };
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let better = match best {
+                Some((best_q, _)) => q > best_q || (q == best_q && coding == Coding::Gzip),
+                None => true,
+            };
+            if q > 0.0 && better {
+                best = Some((q, coding));
+            }
+        }
+        best.map(|(_, coding)| coding)
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: Coding) -> Self {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Coding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))
+            }
+        }
+    }
+
+    // compresses the chunk and sync-flushes, so the client can decode each event as it arrives
+    fn write(&mut self, chunk: &[u8]) -> std::io::Result<Bytes> {
+        let out = match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(mem::take(out)))
+    }
+
+    fn finish(self) -> std::io::Result<Bytes> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+        .map(Bytes::from)
+    }
+}
+
+fn is_streaming(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            STREAMING_TYPES
+                .iter()
+                .any(|streaming| content_type.starts_with(streaming))
+        })
+}
+
+// with [server] compression on, compresses streams for clients whose Accept-Encoding allows it
+pub async fn compress(request: Request, next: Next) -> Response {
+    let coding = Coding::negotiate(request.headers());
+    let response = next.run(request).await;
+    if !is_streaming(&response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(coding) = coding else {
+        return Response::from_parts(parts, body);
+    };
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+    parts.headers.remove(CONTENT_LENGTH);
+
+    let mut data = body.into_data_stream();
+    let body = Body::from_stream(async_stream::stream! {
+        let mut encoder = Encoder::new(coding);
+        while let Some(chunk) = data.next().await {
+            match chunk {
+                Ok(chunk) => yield encoder.write(&chunk).map_err(axum::Error::new),
+                // an aborted stream ends without the trailer, like the uncompressed one would
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        yield encoder.finish().map_err(axum::Error::new);
+    });
+    Response::from_parts(parts, body)
+}
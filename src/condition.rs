@@ -0,0 +1,392 @@
+use serde_json::Value;
+use std::cmp::Ordering;
+
+// a condition such as `status == 'refunded' && amount > 100`: comparisons of dotted field paths
+// and literals, `in [...]`, `!`, `&&`, `||` and parentheses; a lone path tests truthiness
+pub enum Condition {
+    Or(Vec<Condition>),
+    And(Vec<Condition>),
+    Not(Box<Condition>),
+    Compare(Operand, Comparison, Operand),
+    In(Operand, Vec<Value>),
+    Truthy(Operand),
+}
+
+pub enum Operand {
+    Literal(Value),
+    Path(Vec<String>),
+    Group(Box<Condition>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Path(String),
+    Literal(Value),
+    Comparison(Comparison),
+    And,
+    Or,
+    Not,
+    In,
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+}
+
+fn tokens(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, char)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|(_, c)| *c == expected).is_some();
+        let token = match char {
+            _ if char.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenList,
+            ']' => Token::CloseList,
+            ',' => Token::Comma,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Comparison(Comparison::Eq),
+            '!' if next_is('=') => Token::Comparison(Comparison::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Comparison(Comparison::Le),
+            '<' => Token::Comparison(Comparison::Lt),
+            '>' if next_is('=') => Token::Comparison(Comparison::Ge),
+            '>' => Token::Comparison(Comparison::Gt),
+            '\'' | '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, c)) if c == char => break,
+                        Some((_, c)) => string.push(c),
+                        None => return Err(format!("the string at {start} is never closed")),
+                    }
+                }
+                Token::Literal(Value::String(string))
+            }
+            _ if char.is_ascii_digit() || char == '-' => {
+                let mut number = char.to_string();
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E'))
+                {
+                    number.push(c);
+                }
+                let number = serde_json::from_str::<Value>(&number)
+                    .ok()
+                    .filter(Value::is_number)
+                    .ok_or_else(|| format!("{number} is not a number"))?;
+                Token::Literal(number)
+            }
+            _ if char.is_alphanumeric() || char == '_' => {
+                let mut word = char.to_string();
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+                {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    _ => Token::Path(word),
+                }
+            }
+            other => return Err(format!("unexpected {other:?} at {start}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matched = self.peek() == Some(token);
+        if matched {
+            self.at += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, token: &Token, what: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected {what}"))
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut branches = vec![self.and()?];
+        while self.eat(&Token::Or) {
+            branches.push(self.and()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.remove(0),
+            _ => Condition::Or(branches),
+        })
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut branches = vec![self.not()?];
+        while self.eat(&Token::And) {
+            branches.push(self.not()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.remove(0),
+            _ => Condition::And(branches),
+        })
+    }
+
+    fn not(&mut self) -> Result<Condition, String> {
+        if self.eat(&Token::Not) {
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Condition, String> {
+        let left = self.operand()?;
+        if self.eat(&Token::In) {
+            self.expect(&Token::OpenList, "[ after in")?;
+            let mut values = Vec::new();
+            while !self.eat(&Token::CloseList) {
+                if !values.is_empty() {
+                    self.expect(&Token::Comma, ", or ] in the list")?;
+                }
+                match self.operand()? {
+                    Operand::Literal(value) => values.push(value),
+                    _ => return Err("in lists take literals only".to_string()),
+                }
+            }
+            return Ok(Condition::In(left, values));
+        }
+        if let Some(&Token::Comparison(comparison)) = self.peek() {
+            self.at += 1;
+            return Ok(Condition::Compare(left, comparison, self.operand()?));
+        }
+        Ok(Condition::Truthy(left))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let token = self
+            .tokens
+            .get(self.at)
+            .cloned()
+            .ok_or("the condition ends early")?;
+        self.at += 1;
+        match token {
+            Token::Literal(value) => Ok(Operand::Literal(value)),
+            Token::Path(path) => Ok(Operand::Path(path.split('.').map(str::to_string).collect())),
+            Token::Open => {
+                let group = self.or()?;
+                self.expect(&Token::Close, ")")?;
+                Ok(Operand::Group(Box::new(group)))
+            }
+            _ => Err("expected a field or a value".to_string()),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokens(source)?,
+            at: 0,
+        };
+        let condition = parser.or()?;
+        if parser.at < parser.tokens.len() {
+            return Err("unexpected text after the condition".to_string());
+        }
+        Ok(condition)
+    }
+
+    // paths are looked up in `scope`, the object holding the field, then from the event's root
+    pub fn holds(&self, scope: &Value, root: &Value) -> bool {
+        match self {
+            Condition::Or(branches) => branches.iter().any(|branch| branch.holds(scope, root)),
+            Condition::And(branches) => branches.iter().all(|branch| branch.holds(scope, root)),
+            Condition::Not(condition) => !condition.holds(scope, root),
+            Condition::Compare(left, comparison, right) => {
+                let (left, right) = (left.value(scope, root), right.value(scope, root));
+                let ordering = order(&left, &right);
+                match comparison {
+                    Comparison::Eq => ordering == Some(Ordering::Equal) || left == right,
+                    Comparison::Ne => ordering != Some(Ordering::Equal) && left != right,
+                    Comparison::Lt => ordering == Some(Ordering::Less),
+                    Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Comparison::Gt => ordering == Some(Ordering::Greater),
+                    Comparison::Ge => {
+                        matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                }
+            }
+            Condition::In(operand, values) => {
+                let value = operand.value(scope, root);
+                values.iter().any(|candidate| {
+                    order(&value, candidate) == Some(Ordering::Equal) || value == *candidate
+                })
+            }
+            Condition::Truthy(operand) => truthy(&operand.value(scope, root)),
+        }
+    }
+}
+
+impl Operand {
+    fn value(&self, scope: &Value, root: &Value) -> Value {
+        match self {
+            Operand::Literal(value) => value.clone(),
+            Operand::Path(path) => lookup(scope, path)
+                .or_else(|| lookup(root, path))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Operand::Group(condition) => Value::Bool(condition.holds(scope, root)),
+        }
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match value {
+        Value::Object(object) => object.get(segment),
+        Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+// numbers by value and strings lexically, so RFC 3339 timestamps compare in time order
+fn order(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(boolean) => *boolean,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(string) => !string.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn holds(source: &str, event: &Value) -> bool {
+        Condition::parse(source).unwrap().holds(event, event)
+    }
+
+    fn error(source: &str) -> String {
+        Condition::parse(source).err().unwrap()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_not_tighter_than_both() {
+        let event = json!({ "a": true, "b": false, "c": false });
+        assert!(holds("a || b && c", &event));
+        assert!(!holds("(a || b) && c", &event));
+        assert!(holds("b && c || a", &event));
+        assert!(!holds("!a || b", &event));
+        assert!(holds("!(a && b)", &event));
+        assert!(holds("not b and not c or b", &event));
+        assert!(holds("!!a", &event));
+        assert!(holds("a == true && b != true", &event));
+        assert!(matches!(
+            Condition::parse("a || b && c").unwrap(),
+            Condition::Or(branches) if matches!(branches[1], Condition::And(_))
+        ));
+    }
+
+    #[test]
+    fn compares_numbers_by_value_and_strings_lexically() {
+        let event = json!({
+            "amount": 150,
+            "status": "refunded",
+            "at": "2024-05-01T10:00:00Z",
+            "user": { "tier": "gold", "tags": ["vip", "beta"] },
+        });
+        assert!(holds("amount > 100 && amount <= 150", &event));
+        assert!(holds("amount == 150.0", &event));
+        assert!(!holds("amount < -1e3", &event));
+        assert!(holds("status == 'refunded' && status != \"open\"", &event));
+        assert!(holds("at >= '2024-01-01T00:00:00Z'", &event));
+        assert!(holds("user.tier in ['gold', 'platinum']", &event));
+        assert!(!holds("amount in []", &event));
+        assert!(holds("amount in [1, 150]", &event));
+        assert!(holds("user.tags.0 == 'vip'", &event));
+        // values of different kinds are unordered and unequal
+        assert!(!holds("status > 1", &event));
+        assert!(holds("status != 1", &event));
+        assert!(holds("missing == null && !missing.deeper", &event));
+    }
+
+    #[test]
+    fn lone_paths_test_truthiness() {
+        let event = json!({
+            "zero": 0, "one": 1, "empty": "", "text": "x",
+            "none": [], "some": [0], "object": {}, "off": false, "nothing": null,
+        });
+        for truthy in ["one", "text", "some", "object"] {
+            assert!(holds(truthy, &event), "{truthy}");
+        }
+        for falsy in ["zero", "empty", "none", "off", "nothing", "absent"] {
+            assert!(!holds(falsy, &event), "{falsy}");
+        }
+    }
+
+    #[test]
+    fn paths_are_read_from_the_scope_then_the_root() {
+        let root = json!({ "kind": "order", "line": { "kind": "item", "qty": 2 } });
+        let condition = Condition::parse("kind == 'item' && qty > 1").unwrap();
+        assert!(condition.holds(&root["line"], &root));
+        assert!(!condition.holds(&root, &root));
+        let root_only = Condition::parse("line.qty == 2").unwrap();
+        assert!(root_only.holds(&root["line"], &root));
+    }
+
+    #[test]
+    fn explains_what_does_not_parse() {
+        assert_eq!(error("status == 'open"), "the string at 10 is never closed");
+        assert_eq!(error("a # b"), "unexpected '#' at 2");
+        assert_eq!(error("a = b"), "unexpected '=' at 2");
+        assert_eq!(error("n > 1.2.3"), "1.2.3 is not a number");
+        assert_eq!(error("(a || b"), "expected )");
+        assert_eq!(error("a in 'x'"), "expected [ after in");
+        assert_eq!(error("a in [1 2]"), "expected , or ] in the list");
+        assert_eq!(error("a in [b]"), "in lists take literals only");
+        assert_eq!(error("a &&"), "the condition ends early");
+        assert_eq!(error(""), "the condition ends early");
+        assert_eq!(error("a b"), "unexpected text after the condition");
+        assert_eq!(error("a == )"), "expected a field or a value");
+    }
+}
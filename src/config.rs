@@ -0,0 +1,1124 @@
+use crate::calendar::{parse_calendar, CalendarEvent};
+use crate::custom::SubstitutionDef;
+use crate::logging::LogLevel;
+use crate::oversize::OversizeStrategy;
+use crate::payload::PayloadFormat;
+use crate::plugins::PluginConfig;
+use crate::protobuf::Descriptors;
+use crate::quotas::QuotaAction;
+use crate::scenario::PhaseDef;
+use crate::schedule::parse_at;
+use crate::schema::shape_from_schema;
+use crate::spec::spec_streams;
+use crate::stats::Period;
+use crate::template::{Locale, Normalization};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+const CONFIG_PATH: &str = "sse-proto.toml";
+const ENV_PREFIX: &str = "SSE_PROTO_";
+// names the config file, in place of sse-proto.toml, rather than setting a config path
+const CONFIG_ENV: &str = "SSE_PROTO_CONFIG";
+
+// a dotted config path and a raw value, from an environment variable or a CLI flag
+pub type Override = (String, String);
+
+#[derive(Deserialize, Validate, Default)]
+#[serde(default)]
+pub struct Config {
+    pub server: Server,
+    #[validate(nested)]
+    pub defaults: Defaults,
+    pub limits: Limits,
+    pub packs: Packs,
+    pub templates: TemplatesConfig,
+    pub cors: CorsConfig,
+    pub presets: PresetsConfig,
+    pub recordings: RecordingsConfig,
+    pub fixtures: FixturesConfig,
+    pub spec: SpecConfig,
+    pub protobuf: ProtobufConfig,
+    // when any are set, stream requests need one of these keys and are held to its quota
+    pub api_keys: HashMap<String, ApiKeyConfig>,
+    pub jwt: Option<JwtConfig>,
+    pub auth: Option<AuthConfig>,
+    pub pools: HashMap<String, PoolConfig>,
+    pub substitutions: BTreeMap<String, SubstitutionDef>,
+    pub plugins: BTreeMap<String, PluginConfig>,
+    pub scenarios: BTreeMap<String, ScenarioConfig>,
+    pub schedule: Vec<ScheduleConfig>,
+    pub maintenance: Vec<MaintenanceConfig>,
+    pub calendar: Option<CalendarConfig>,
+    pub streams: HashMap<String, StreamConfig>,
+}
+
+// a scenario's phases inline, or read from a JSON file of {"phases": [...]}
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScenarioConfig {
+    pub phases: Vec<PhaseDef>,
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    pub host: Hosts,
+    pub port: u16,
+    // unset, IPv6 addresses also take IPv4 unless an IPv4 address is bound alongside them
+    pub v6_only: Option<bool>,
+    // lets a new instance bind the same port while this one drains
+    pub reuse_port: bool,
+    // on shutdown, live streams are closed at random points over this long
+    pub drain_ms: u64,
+    // exits this long after the first signal even if connections are still open
+    pub drain_timeout_ms: u64,
+    // live streams across all routes; past it new ones get a 503
+    pub max_connections: Option<usize>,
+    // live streams per client address
+    pub max_connections_per_ip: Option<usize>,
+    // gzip or deflate streams for clients that send Accept-Encoding
+    pub compression: bool,
+    pub log_level: LogLevel,
+    // sent on every response, over the built-in value on streams
+    #[serde(rename = "headers")]
+    raw_headers: BTreeMap<String, String>,
+    #[serde(skip)]
+    pub headers: HeaderMap,
+}
+
+// one address to listen on, or several, e.g. ["0.0.0.0", "::"]
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Hosts {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Hosts {
+    pub fn list(&self) -> &[String] {
+        match self {
+            Hosts::One(host) => std::slice::from_ref(host),
+            Hosts::Many(hosts) => hosts,
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            host: Hosts::One("0.0.0.0".to_string()),
+            port: 3000,
+            v6_only: None,
+            reuse_port: false,
+            drain_ms: 5000,
+            drain_timeout_ms: 10000,
+            max_connections: None,
+            max_connections_per_ip: None,
+            compression: false,
+            log_level: LogLevel::default(),
+            raw_headers: BTreeMap::new(),
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(default)]
+pub struct Defaults {
+    pub interval_min: u64,
+    pub interval_max: u64,
+    pub shape: Value,
+    pub shape_file: Option<PathBuf>,
+    // a JSON Schema file the shape is derived from
+    pub schema_file: Option<PathBuf>,
+    // a .jsonl file streamed on / in place of the shape
+    pub fixture: Option<PathBuf>,
+    // a command, with its arguments, rewriting each event sent on /
+    pub script: Option<Vec<String>>,
+    #[validate(range(min = 100, message = "heartbeat_ms must be >= 100ms"))]
+    pub heartbeat_ms: Option<u64>,
+    // idle time before a keep-alive comment, so proxies don't drop a quiet connection
+    #[validate(range(min = 100, message = "keepalive_ms must be >= 100ms"))]
+    pub keepalive_ms: Option<u64>,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Defaults {
+            interval_min: 1000,
+            interval_max: 2000,
+            shape: json!({
+                "id": "{uuid}",
+                "user": {
+                    "name": "{name}",
+                    "email": "{email}",
+                    "city": "{city}"
+                },
+                "message": "{words}",
+                "sent_at": "{datetime}"
+            }),
+            shape_file: None,
+            schema_file: None,
+            fixture: None,
+            script: None,
+            heartbeat_ms: None,
+            keepalive_ms: None,
+        }
+    }
+}
+
+// a named stream served at /streams/{name}; unset fields fall back to [defaults]
+#[derive(Deserialize, Validate, Default)]
+#[serde(default)]
+pub struct StreamConfig {
+    pub interval_min: Option<u64>,
+    pub interval_max: Option<u64>,
+    pub shape: Option<Value>,
+    pub shape_file: Option<PathBuf>,
+    pub schema_file: Option<PathBuf>,
+    // a .jsonl file streamed in place of the shape
+    pub fixture: Option<PathBuf>,
+    // a command, with its arguments, that each connection starts to rewrite its events
+    pub script: Option<Vec<String>>,
+    pub event: Option<String>,
+    pub payload_encoding: Option<PayloadFormat>,
+    // the full name of the [protobuf] message events are encoded as
+    pub proto_message: Option<String>,
+    // every connection to the stream sees the same events
+    #[validate(range(max = 9007199254740991_u64, message = "seed must be at most 2^53 - 1"))]
+    pub seed: Option<u64>,
+    #[validate(range(min = 100, message = "heartbeat_ms must be >= 100ms"))]
+    pub heartbeat_ms: Option<u64>,
+    // idle time before a keep-alive comment, so proxies don't drop a quiet connection
+    #[validate(range(min = 100, message = "keepalive_ms must be >= 100ms"))]
+    pub keepalive_ms: Option<u64>,
+    #[validate(range(min = 64, message = "max_event_bytes must be >= 64"))]
+    pub max_event_bytes: Option<usize>,
+    pub oversize: Option<OversizeStrategy>,
+    #[validate(range(min = 1, message = "quota_after must be at least 1"))]
+    pub quota_after: Option<u64>,
+    pub on_quota: Option<QuotaAction>,
+    pub normalization: Option<Normalization>,
+    pub locale: Option<Locale>,
+    pub stats_every: Option<Period>,
+    #[validate(range(min = 1, message = "max_events must be at least 1"))]
+    pub max_events: Option<u64>,
+    #[validate(range(min = 1, message = "duration_secs must be at least 1"))]
+    pub duration_secs: Option<u64>,
+    pub produces: Option<Produces>,
+    // dotted field path => pool whose entries fill it
+    pub references: BTreeMap<String, String>,
+    pub crud: Option<bool>,
+    pub crud_key: Option<String>,
+    #[validate(range(min = 1, message = "crud_entities must be at least 1"))]
+    pub crud_entities: Option<usize>,
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "crud_update_rate must be between 0 and 1"
+    ))]
+    pub crud_update_rate: Option<f64>,
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "crud_delete_rate must be between 0 and 1"
+    ))]
+    pub crud_delete_rate: Option<f64>,
+    pub snapshot: Option<SnapshotConfig>,
+    // event types multiplexed onto the stream, each with its own shape and priority
+    pub lanes: BTreeMap<String, LaneConfig>,
+    // also served at this path, ahead of the built-in routes
+    pub path: Option<String>,
+    #[serde(rename = "headers")]
+    raw_headers: BTreeMap<String, String>,
+    #[serde(skip)]
+    pub headers: HeaderMap,
+    // [cors] with only its origins replaced
+    pub cors_origins: Vec<String>,
+    // CORS for the stream's own path, in place of [cors]
+    pub cors: Option<CorsConfig>,
+}
+
+// a named value pool shared by every stream's {pool:<name>} placeholders; either explicit
+// `values` or `size` renderings of the `value` template
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    pub size: usize,
+    pub value: String,
+    pub values: Option<Vec<Value>>,
+    pub locale: Option<Locale>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            size: 100,
+            value: "{uuid}".to_string(),
+            values: None,
+            locale: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Validate, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaneConfig {
+    pub shape: Value,
+    // higher goes first when events queue up
+    pub priority: i64,
+    pub interval_min: Option<u64>,
+    pub interval_max: Option<u64>,
+    // events generated per tick
+    #[validate(range(min = 1, max = 1000, message = "burst must be between 1 and 1000"))]
+    pub burst: usize,
+    // queued events kept per lane before the oldest are dropped
+    #[validate(range(min = 1, message = "queue must be at least 1"))]
+    pub queue: usize,
+}
+
+impl Default for LaneConfig {
+    fn default() -> Self {
+        LaneConfig {
+            shape: Value::Null,
+            priority: 0,
+            interval_min: None,
+            interval_max: None,
+            burst: 1,
+            queue: 1000,
+        }
+    }
+}
+
+// stream requests need a bearer JWT that hasn't expired
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    // end streams with a token_expired event when the token's exp passes
+    pub expire_streams: bool,
+}
+
+// a simulated auth gate: stream requests need one of `tokens`, passed the way `mode` says
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub mode: AuthMode,
+    pub tokens: Vec<String>,
+    // where mode = "api_key" looks
+    pub header: String,
+    pub on_failure: AuthFailure,
+    // how long a stream with a bad token runs under on_failure = "expire"
+    pub expire_after_secs: u64,
+    // streams with a good token get auth_expired after this long
+    pub session_secs: Option<u64>,
+}
+
+// expire_after_secs and session_secs past this are surely typos
+const MAX_AUTH_SECS: u64 = 366 * 24 * 60 * 60;
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            mode: AuthMode::default(),
+            tokens: Vec::new(),
+            header: "x-api-key".to_string(),
+            on_failure: AuthFailure::default(),
+            expire_after_secs: 5,
+            session_secs: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    // Authorization: Bearer <token>
+    #[default]
+    Bearer,
+    ApiKey,
+    // ?token=<token>
+    Query,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailure {
+    // 401 before the stream starts
+    #[default]
+    Reject,
+    // stream, then end with an auth_expired event
+    Expire,
+    // close the connection right after the response head
+    Drop,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    pub max_connections: Option<usize>,
+    pub events_per_minute: Option<u32>,
+    pub on_limit: LimitMode,
+}
+
+// how a key over its event quota is treated
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitMode {
+    // 429 for new connections, and live streams end with a rate-limited event
+    #[default]
+    Reject,
+    // connections stay open, data pauses behind a throttled notice
+    Throttle,
+}
+
+// a one-off event injected into live streams at `at`, from [[schedule]] or POST /schedule
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    #[serde(rename = "at")]
+    raw_at: String,
+    #[serde(skip)]
+    pub at: DateTime<Utc>,
+    pub event: String,
+    #[serde(default)]
+    pub data: Value,
+    // only clients of /streams/<stream>; every live stream when unset
+    pub stream: Option<String>,
+}
+
+impl ScheduleConfig {
+    pub fn load(&mut self) -> Result<(), String> {
+        self.at = parse_at(&self.raw_at, Utc::now())?;
+        Ok(())
+    }
+}
+
+// pauses every stream for `duration_ms`, from [[maintenance]] or POST /maintenance
+#[derive(Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    #[serde(rename = "at")]
+    raw_at: Option<String>,
+    // unset starts the window right away
+    #[serde(skip)]
+    pub at: Option<DateTime<Utc>>,
+    #[validate(range(
+        min = 1,
+        max = 31_622_400_000u64,
+        message = "duration_ms must be between 1 and a year"
+    ))]
+    pub duration_ms: u64,
+    // answer new stream requests with 503 while the window lasts
+    #[serde(default)]
+    pub reject_new_connections: bool,
+    pub message: Option<String>,
+}
+
+impl MaintenanceConfig {
+    pub fn load(&mut self) -> Result<(), String> {
+        self.validate().map_err(|e| e.to_string())?;
+        self.at = self
+            .raw_at
+            .as_deref()
+            .map(|at| parse_at(at, Utc::now()))
+            .transpose()
+            .map_err(|e| format!("at: {e}"))?;
+        Ok(())
+    }
+}
+
+// an iCalendar file whose VEVENTs are emitted like [[schedule]] entries, recurring per RRULE
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalendarConfig {
+    pub file: PathBuf,
+    pub stream: Option<String>,
+    #[serde(skip)]
+    pub events: Vec<CalendarEvent>,
+}
+
+// serve the stream as one shared simulated state: a snapshot per subscriber, then deltas
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    pub key: String,
+    pub max_records: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            key: "id".to_string(),
+            max_records: 100,
+        }
+    }
+}
+
+// every event of the stream adds the value at `field` to `pool`
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Produces {
+    pub pool: String,
+    pub field: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_shape_bytes: usize,
+    pub max_pool_entries: usize,
+    // the shortest interval_min or interval_max accepted anywhere
+    pub interval_floor_ms: u64,
+    // faster streams are slowed to this, and told so in a config event
+    pub max_events_per_sec: Option<f64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_shape_bytes: 1024 * 1024,
+            max_pool_entries: 1000,
+            interval_floor_ms: 1000,
+            max_events_per_sec: None,
+        }
+    }
+}
+
+impl Limits {
+    // each interval set, at least the floor, and the pair in order when both are
+    pub fn check_intervals(
+        &self,
+        interval_min: Option<u64>,
+        interval_max: Option<u64>,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        for (name, interval) in [
+            ("interval_min", interval_min),
+            ("interval_max", interval_max),
+        ] {
+            if let Some(interval) = interval.filter(|interval| *interval < self.interval_floor_ms) {
+                let mut error = ValidationError::new("range").with_message(
+                    format!(
+                        "must be at least the [limits] interval_floor_ms of {}ms",
+                        self.interval_floor_ms
+                    )
+                    .into(),
+                );
+                error.add_param("value".into(), &interval);
+                error.add_param("min".into(), &self.interval_floor_ms);
+                errors.add(name, error);
+            }
+        }
+        // equal intervals are allowed, for a fixed delay between events
+        if let (Some(interval_min), Some(interval_max)) = (interval_min, interval_max) {
+            if interval_min > interval_max {
+                let mut error = ValidationError::new("interval_order").with_message(
+                    format!("must not exceed interval_max ({interval_max}ms)").into(),
+                );
+                error.add_param("value".into(), &interval_min);
+                error.add_param("max".into(), &interval_max);
+                errors.add("interval_min", error);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresetsConfig {
+    // keeps /shapes presets across restarts
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingsConfig {
+    // keeps /recordings across restarts, one JSON file each
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixturesConfig {
+    // where ?fixture=<file> looks
+    pub dir: PathBuf,
+}
+
+impl Default for FixturesConfig {
+    fn default() -> Self {
+        FixturesConfig {
+            dir: PathBuf::from("fixtures"),
+        }
+    }
+}
+
+// an OpenAPI or AsyncAPI document whose streaming operations are served as named streams
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpecConfig {
+    pub file: Option<PathBuf>,
+}
+
+// a FileDescriptorSet, from protoc --descriptor_set_out, for payload_encoding = "protobuf"
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtobufConfig {
+    pub descriptor: Option<PathBuf>,
+    #[serde(skip)]
+    pub descriptors: Option<Arc<Descriptors>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplatesConfig {
+    // unknown placeholders and unclosed braces fail validation instead of being sent as written
+    pub strict: bool,
+}
+
+// opt-in placeholder packs, off unless enabled in [packs]
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Packs {
+    pub injection: bool,
+}
+
+fn load_shape(
+    shape: Value,
+    shape_file: &Option<PathBuf>,
+    schema_file: &Option<PathBuf>,
+    table: &str,
+) -> Result<Value, String> {
+    let shape = if let Some(schema_file) = schema_file {
+        if shape_file.is_some() {
+            return Err(format!("{table} takes one of shape_file and schema_file"));
+        }
+        let error = |e: String| format!("{table}.schema_file {}: {e}", schema_file.display());
+        let source = fs::read_to_string(schema_file).map_err(|e| error(e.to_string()))?;
+        let schema = serde_json::from_str(&source).map_err(|e| error(e.to_string()))?;
+        shape_from_schema(&schema).map_err(error)?
+    } else if let Some(shape_file) = shape_file {
+        let source = fs::read_to_string(shape_file)
+            .map_err(|e| format!("{table}.shape_file {}: {e}", shape_file.display()))?;
+        serde_json::from_str(&source)
+            .map_err(|e| format!("{table}.shape_file {}: {e}", shape_file.display()))?
+    } else if let Value::String(source) = &shape {
+        // shapes may be written as inline tables or as JSON strings
+        serde_json::from_str(source).map_err(|e| format!("{table}.shape: {e}"))?
+    } else {
+        shape
+    };
+
+    if !shape.is_object() {
+        return Err(format!("{table}.shape must be a JSON object"));
+    }
+
+    Ok(shape)
+}
+
+impl StreamConfig {
+    fn load(&mut self, name: &str, limits: &Limits, defaults: &Defaults) -> Result<(), String> {
+        let table = format!("streams.{name}");
+        self.validate().map_err(|e| format!("{table}: {e}"))?;
+        // as resolved for a request that sets neither, so one-sided overrides are checked too
+        limits
+            .check_intervals(
+                Some(self.interval_min.unwrap_or(defaults.interval_min)),
+                Some(self.interval_max.unwrap_or(defaults.interval_max)),
+            )
+            .map_err(|e| format!("{table}: {e}"))?;
+
+        if self.shape.is_some() || self.shape_file.is_some() || self.schema_file.is_some() {
+            let shape = self.shape.take().unwrap_or_default();
+            self.shape = Some(load_shape(
+                shape,
+                &self.shape_file,
+                &self.schema_file,
+                &table,
+            )?);
+        }
+
+        if self.script.as_ref().is_some_and(Vec::is_empty) {
+            return Err(format!("{table}.script needs a command"));
+        }
+
+        for (name, lane) in &mut self.lanes {
+            let table = format!("{table}.lanes.{name}");
+            lane.validate().map_err(|e| format!("{table}: {e}"))?;
+            limits
+                .check_intervals(lane.interval_min, lane.interval_max)
+                .map_err(|e| format!("{table}: {e}"))?;
+            lane.shape = load_shape(lane.shape.take(), &None, &None, &table)?;
+        }
+
+        self.headers = parse_headers(&self.raw_headers, &table)?;
+
+        if let Some(path) = &self.path {
+            if !path.starts_with('/') || path.contains([':', '*', '{', '}']) {
+                return Err(format!(
+                    "{table}.path must start with / and have no : * {{ or }} segments, got {path}"
+                ));
+            }
+        }
+        if let Some(cors) = &mut self.cors {
+            if !self.cors_origins.is_empty() {
+                return Err(format!("{table} takes one of cors and cors_origins"));
+            }
+            cors.load(&format!("{table}.cors"))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_headers(raw: &BTreeMap<String, String>, table: &str) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in raw {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("{table}.headers: {name}: {e}"))?;
+        let header_value =
+            HeaderValue::from_str(value).map_err(|e| format!("{table}.headers: {name}: {e}"))?;
+        headers.append(header_name, header_value);
+    }
+    Ok(headers)
+}
+
+// which browser origins may read responses: [cors] for the built-in routes and, unless they
+// have their own, streams at a path
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // any when empty; with credentials, the request's own origin is allowed instead
+    #[serde(rename = "origins")]
+    raw_origins: Vec<String>,
+    #[serde(rename = "methods")]
+    raw_methods: Vec<String>,
+    // request headers a preflight allows, e.g. Authorization or Last-Event-ID
+    #[serde(rename = "headers")]
+    raw_headers: Vec<String>,
+    // response headers scripts may read
+    #[serde(rename = "expose_headers")]
+    raw_expose_headers: Vec<String>,
+    // lets EventSource's withCredentials and fetch's credentials: "include" through
+    pub credentials: bool,
+    pub max_age_secs: Option<u64>,
+    #[serde(skip)]
+    pub origins: Vec<HeaderValue>,
+    #[serde(skip)]
+    pub methods: Vec<Method>,
+    #[serde(skip)]
+    pub headers: Vec<HeaderName>,
+    #[serde(skip)]
+    pub expose_headers: Vec<HeaderName>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            raw_origins: Vec::new(),
+            raw_methods: ["HEAD", "GET", "POST", "DELETE", "OPTIONS"]
+                .map(str::to_string)
+                .to_vec(),
+            raw_headers: Vec::new(),
+            raw_expose_headers: Vec::new(),
+            credentials: false,
+            max_age_secs: None,
+            origins: Vec::new(),
+            methods: Vec::new(),
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+        }
+    }
+}
+
+impl CorsConfig {
+    fn load(&mut self, table: &str) -> Result<(), String> {
+        self.origins = self
+            .raw_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin).map_err(|e| format!("{table}.origins: {origin}: {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+        self.methods = self
+            .raw_methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|e| format!("{table}.methods: {method}: {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+        let header_names = |raw: &[String], key: &str| {
+            raw.iter()
+                .map(|name| {
+                    HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|e| format!("{table}.{key}: {name}: {e}"))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        self.headers = header_names(&self.raw_headers, "headers")?;
+        self.expose_headers = header_names(&self.raw_expose_headers, "expose_headers")?;
+        Ok(())
+    }
+
+    fn with_origins(&self, origins: &[String], table: &str) -> Result<Self, String> {
+        let mut cors = CorsConfig {
+            raw_origins: origins.to_vec(),
+            ..self.clone()
+        };
+        cors.load(table)?;
+        Ok(cors)
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, String> {
+    let config_file = path.display();
+    let source =
+        fs::read_to_string(path).map_err(|e| format!("failed to read {config_file}: {e}"))?;
+    toml::from_str(&source).map_err(|e| format!("failed to parse {config_file}: {e}"))
+}
+
+// values are read as TOML where they parse (numbers, booleans, arrays, inline tables) and
+// as plain strings otherwise
+fn set_path(table: &mut toml::Table, path: &str, raw: &str) -> Result<(), String> {
+    let value = toml::from_str::<toml::Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+
+    let mut segments = path.split('.').peekable();
+    let mut table = table;
+    while let Some(segment) = segments.next() {
+        if segment.is_empty() {
+            return Err(format!("{path} is not a config path"));
+        }
+        if segments.peek().is_none() {
+            table.insert(segment.to_string(), value);
+            return Ok(());
+        }
+        table = match table
+            .entry(segment.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            toml::Value::Table(table) => table,
+            _ => return Err(format!("{path}: {segment} is not a table")),
+        };
+    }
+    Err(format!("{path} is not a config path"))
+}
+
+impl Config {
+    // SSE_PROTO_SERVER__PORT=8080 sets server.port; segments are lowercased
+    pub fn env_overrides() -> Vec<Override> {
+        let mut overrides = env::vars()
+            .filter(|(name, _)| name != CONFIG_ENV)
+            .filter_map(|(name, value)| {
+                let path = name.strip_prefix(ENV_PREFIX)?;
+                Some((path.to_lowercase().replace("__", "."), value))
+            })
+            .collect::<Vec<_>>();
+        overrides.sort();
+        overrides
+    }
+
+    // the config file, then environment variables, then `cli` overrides, each over the last;
+    // the file is `file`, else $SSE_PROTO_CONFIG, else sse-proto.toml if it exists
+    pub fn load(cli: &[Override], file: Option<&Path>) -> Result<Config, String> {
+        let named = file
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from));
+        let path = named.as_deref().unwrap_or(Path::new(CONFIG_PATH));
+        let mut table = if named.is_some() || path.exists() {
+            read_table(path)?
+        } else {
+            toml::Table::new()
+        };
+        for (path, value) in Config::env_overrides().iter().chain(cli) {
+            set_path(&mut table, path, value)?;
+        }
+        Config::from_table(table, path)
+    }
+
+    // for a server embedded in another program's tests: `file`, if any, then `overrides`,
+    // leaving out sse-proto.toml and the environment; unless they say otherwise it listens on
+    // an ephemeral loopback port and logs only warnings
+    pub fn embedded(file: Option<&Path>, overrides: &[Override]) -> Result<Config, String> {
+        let mut table = file.map(read_table).transpose()?.unwrap_or_default();
+        if let toml::Value::Table(server) = table
+            .entry("server")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            server.entry("host").or_insert("127.0.0.1".into());
+            server.entry("port").or_insert(0.into());
+            server.entry("log_level").or_insert("warn".into());
+        }
+        for (path, value) in overrides {
+            set_path(&mut table, path, value)?;
+        }
+        Config::from_table(table, file.unwrap_or(Path::new(CONFIG_PATH)))
+    }
+
+    fn from_table(table: toml::Table, path: &Path) -> Result<Config, String> {
+        let config_file = path.display();
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("invalid configuration: {e}"))?;
+        config
+            .validate()
+            .map_err(|e| format!("invalid {config_file}: {e}"))?;
+        if config.limits.interval_floor_ms == 0 {
+            return Err(format!(
+                "invalid {config_file}: limits.interval_floor_ms must be at least 1"
+            ));
+        }
+        if config
+            .limits
+            .max_events_per_sec
+            .is_some_and(|rate| !(rate.is_finite() && rate > 0.0))
+        {
+            return Err(format!(
+                "invalid {config_file}: limits.max_events_per_sec must be above 0"
+            ));
+        }
+        config
+            .limits
+            .check_intervals(
+                Some(config.defaults.interval_min),
+                Some(config.defaults.interval_max),
+            )
+            .map_err(|e| format!("invalid {config_file}: defaults: {e}"))?;
+
+        let defaults_shape = config.defaults.shape.take();
+        config.defaults.shape = load_shape(
+            defaults_shape,
+            &config.defaults.shape_file,
+            &config.defaults.schema_file,
+            "defaults",
+        )
+        .map_err(|e| format!("invalid {config_file}: {e}"))?;
+        if config.defaults.script.as_ref().is_some_and(Vec::is_empty) {
+            return Err(format!(
+                "invalid {config_file}: defaults.script needs a command"
+            ));
+        }
+        for (name, stream) in &mut config.streams {
+            stream
+                .load(name, &config.limits, &config.defaults)
+                .map_err(|e| format!("invalid {config_file}: {e}"))?;
+        }
+
+        config.server.headers = parse_headers(&config.server.raw_headers, "server")
+            .map_err(|e| format!("invalid {config_file}: {e}"))?;
+        config
+            .cors
+            .load("cors")
+            .map_err(|e| format!("invalid {config_file}: {e}"))?;
+        for (name, stream) in &mut config.streams {
+            if stream.cors.is_none() && !stream.cors_origins.is_empty() {
+                let table = format!("streams.{name}.cors");
+                stream.cors = Some(
+                    config
+                        .cors
+                        .with_origins(&stream.cors_origins, &table)
+                        .map_err(|e| format!("invalid {config_file}: {e}"))?,
+                );
+            }
+        }
+
+        if config.server.drain_timeout_ms < config.server.drain_ms {
+            return Err(format!(
+                "invalid {config_file}: server.drain_timeout_ms must be at least server.drain_ms"
+            ));
+        }
+
+        if let Some(auth) = &config.auth {
+            if auth.tokens.is_empty() {
+                return Err(format!(
+                    "invalid {config_file}: auth.tokens needs at least one token"
+                ));
+            }
+            if HeaderName::from_bytes(auth.header.as_bytes()).is_err() {
+                return Err(format!(
+                    "invalid {config_file}: auth.header {} is not a header name",
+                    auth.header
+                ));
+            }
+            for (name, secs) in [
+                ("expire_after_secs", Some(auth.expire_after_secs)),
+                ("session_secs", auth.session_secs),
+            ] {
+                if secs.is_some_and(|secs| secs > MAX_AUTH_SECS) {
+                    return Err(format!(
+                        "invalid {config_file}: auth.{name} must be at most {MAX_AUTH_SECS} (a year)"
+                    ));
+                }
+            }
+        }
+
+        if let Some(file) = &config.protobuf.descriptor {
+            let error = |e: String| {
+                format!(
+                    "invalid {config_file}: protobuf.descriptor {}: {e}",
+                    file.display()
+                )
+            };
+            let bytes = fs::read(file).map_err(|e| error(e.to_string()))?;
+            config.protobuf.descriptors =
+                Some(Arc::new(Descriptors::parse(&bytes).map_err(error)?));
+        }
+
+        if let Some(file) = &config.spec.file {
+            let error =
+                |e: String| format!("invalid {config_file}: spec.file {}: {e}", file.display());
+            let source = fs::read_to_string(file).map_err(|e| error(e.to_string()))?;
+            let document = serde_json::from_str(&source)
+                .map_err(|e| error(format!("{e}, only JSON documents are read")))?;
+            for stream in spec_streams(&document).map_err(error)? {
+                if config.streams.contains_key(&stream.name) {
+                    return Err(error(format!("{} is also a [streams] table", stream.name)));
+                }
+                config.streams.insert(
+                    stream.name,
+                    StreamConfig {
+                        shape: stream.shape,
+                        event: stream.event,
+                        path: Some(stream.path),
+                        ..StreamConfig::default()
+                    },
+                );
+            }
+        }
+
+        let streams = &config.streams;
+        let mut paths = HashMap::new();
+        for (name, path) in streams
+            .iter()
+            .filter_map(|(name, stream)| Some((name, stream.path.as_ref()?)))
+        {
+            if let Some(other) = paths.insert(path, name) {
+                return Err(format!(
+                    "invalid {config_file}: streams {other} and {name} are both at {path}"
+                ));
+            }
+        }
+        for (index, entry) in config.schedule.iter_mut().enumerate() {
+            entry
+                .load()
+                .map_err(|e| format!("invalid {config_file}: schedule[{index}].at: {e}"))?;
+            if let Some(name) = entry
+                .stream
+                .as_ref()
+                .filter(|name| !streams.contains_key(*name))
+            {
+                return Err(format!(
+                    "invalid {config_file}: schedule[{index}].stream: no stream named {name}"
+                ));
+            }
+        }
+
+        for (index, window) in config.maintenance.iter_mut().enumerate() {
+            window
+                .load()
+                .map_err(|e| format!("invalid {config_file}: maintenance[{index}]: {e}"))?;
+        }
+
+        if let Some(calendar) = &mut config.calendar {
+            let file = calendar.file.display();
+            let source = fs::read_to_string(&calendar.file)
+                .map_err(|e| format!("invalid {config_file}: calendar.file {file}: {e}"))?;
+            calendar.events = parse_calendar(&source)
+                .map_err(|e| format!("invalid {config_file}: calendar.file {file}: {e}"))?;
+            if let Some(name) = calendar
+                .stream
+                .as_ref()
+                .filter(|name| !streams.contains_key(*name))
+            {
+                return Err(format!(
+                    "invalid {config_file}: calendar.stream: no stream named {name}"
+                ));
+            }
+        }
+
+        for (name, stream) in &config.streams {
+            for (field, pool) in &stream.references {
+                let produced = config.streams.values().any(|stream| {
+                    stream
+                        .produces
+                        .as_ref()
+                        .is_some_and(|produces| produces.pool == *pool)
+                });
+                if !produced {
+                    return Err(format!(
+                        "invalid {config_file}: streams.{name}.references.{field}: no stream produces pool {pool}"
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(source: &str) -> Result<Config, String> {
+        Config::from_table(source.parse().unwrap(), Path::new("test.toml"))
+    }
+
+    #[test]
+    fn stream_intervals_are_checked_against_the_defaults() {
+        let error = from_toml("[streams.slow]\ninterval_min = 3000\n")
+            .err()
+            .unwrap();
+        assert!(error.contains("streams.slow"), "{error}");
+        assert!(from_toml("[streams.slow]\ninterval_min = 3000\ninterval_max = 3000\n").is_ok());
+    }
+
+    #[test]
+    fn auth_durations_are_capped() {
+        let error = from_toml("[auth]\ntokens = [\"t\"]\nsession_secs = 99999999999\n")
+            .err()
+            .unwrap();
+        assert!(error.contains("auth.session_secs"), "{error}");
+        assert!(from_toml("[auth]\ntokens = [\"t\"]\nsession_secs = 3600\n").is_ok());
+    }
+
+    #[test]
+    fn interval_errors_are_structured() {
+        let errors = Limits::default()
+            .check_intervals(Some(500), Some(400))
+            .unwrap_err();
+        let fields = errors.field_errors();
+        assert_eq!(fields["interval_min"].len(), 2);
+        assert_eq!(fields["interval_max"][0].code, "range");
+
+        let errors = Limits::default()
+            .check_intervals(Some(3000), Some(2000))
+            .unwrap_err();
+        assert_eq!(
+            errors.to_string(),
+            "interval_min: must not exceed interval_max (2000ms)"
+        );
+        let errors = Limits::default()
+            .check_intervals(None, Some(500))
+            .unwrap_err();
+        assert_eq!(
+            errors.to_string(),
+            "interval_max: must be at least the [limits] interval_floor_ms of 1000ms"
+        );
+    }
+}
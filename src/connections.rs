@@ -0,0 +1,266 @@
+use crate::crud::Collection;
+use crate::error::AppError;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+// instructions delivered to a live stream from outside its connection
+pub enum Command {
+    Redact {
+        fields: Vec<String>,
+    },
+    Emit {
+        event: String,
+        data: Value,
+    },
+    // stop sending data events until resumed; heartbeats carry on
+    Pause,
+    Resume,
+    // send the next data event now, leaving a paused stream paused
+    Step,
+    SetRate {
+        interval_min: u64,
+        interval_max: u64,
+    },
+}
+
+// live streams by stream id, so control endpoints can reach them
+#[derive(Default)]
+pub struct Connections {
+    senders: Mutex<HashMap<Uuid, Live>>,
+    collections: Mutex<HashMap<Uuid, Arc<Mutex<Collection>>>>,
+    // every admitted stream, whatever route it is on
+    open: Mutex<HashMap<Uuid, Open>>,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+struct Open {
+    client: Option<IpAddr>,
+    opened_at: DateTime<Utc>,
+    events: Arc<AtomicU64>,
+    kick: Arc<Notify>,
+}
+
+// one row of GET /connections
+#[derive(Serialize)]
+pub struct OpenStream {
+    stream_id: Uuid,
+    client: Option<IpAddr>,
+    opened_at: DateTime<Utc>,
+    events: u64,
+}
+
+// a stream's share of its client's connection cap, given back on drop
+pub struct IpSlot {
+    connections: Arc<Connections>,
+    client: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut per_ip = self.connections.per_ip.lock().unwrap();
+        if let Some(count) = per_ip.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.client);
+            }
+        }
+    }
+}
+
+// a stream's entry in GET /connections; dropping it takes the stream off the list
+pub struct Tracked {
+    connections: Arc<Connections>,
+    stream_id: Uuid,
+    pub events: Arc<AtomicU64>,
+    pub kick: Arc<Notify>,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let mut open = self.connections.open.lock().unwrap();
+        if open
+            .get(&self.stream_id)
+            .is_some_and(|entry| Arc::ptr_eq(&entry.kick, &self.kick))
+        {
+            open.remove(&self.stream_id);
+        }
+    }
+}
+
+impl Connections {
+    // `name` is the named stream being served, if any, for commands aimed at one stream's clients
+    pub fn register(self: &Arc<Self>, stream_id: Uuid, name: Option<&str>) -> Control {
+        let (sender, commands) = unbounded_channel();
+        self.senders.lock().unwrap().insert(
+            stream_id,
+            Live {
+                name: name.map(str::to_string),
+                sender: sender.clone(),
+            },
+        );
+
+        Control {
+            commands,
+            registration: Registration {
+                connections: self.clone(),
+                stream_id,
+                sender,
+            },
+        }
+    }
+
+    // None once the client already has `max` streams open
+    pub fn take_ip_slot(self: &Arc<Self>, client: IpAddr, max: usize) -> Option<IpSlot> {
+        let mut per_ip = self.per_ip.lock().unwrap();
+        let count = per_ip.entry(client).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(IpSlot {
+            connections: self.clone(),
+            client,
+        })
+    }
+
+    pub fn track(self: &Arc<Self>, stream_id: Uuid, client: Option<IpAddr>) -> Tracked {
+        let events = Arc::new(AtomicU64::new(0));
+        let kick = Arc::new(Notify::new());
+        self.open.lock().unwrap().insert(
+            stream_id,
+            Open {
+                client,
+                opened_at: Utc::now(),
+                events: events.clone(),
+                kick: kick.clone(),
+            },
+        );
+        Tracked {
+            connections: self.clone(),
+            stream_id,
+            events,
+            kick,
+        }
+    }
+
+    pub fn list(&self) -> Vec<OpenStream> {
+        let mut streams = self
+            .open
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(stream_id, open)| OpenStream {
+                stream_id: *stream_id,
+                client: open.client,
+                opened_at: open.opened_at,
+                events: open.events.load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>();
+        streams.sort_by_key(|stream| stream.opened_at);
+        streams
+    }
+
+    pub fn count(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+
+    // closes the stream's connection as if the network dropped it
+    pub fn kick(&self, stream_id: Uuid) -> Result<(), AppError> {
+        self.open
+            .lock()
+            .unwrap()
+            .get(&stream_id)
+            .map(|open| open.kick.notify_one())
+            .ok_or_else(|| AppError::not_found(format!("no live stream with id {stream_id}")))
+    }
+
+    // exposes a CRUD stream's collection for as long as the stream stays registered
+    fn attach_collection(&self, stream_id: Uuid, collection: Arc<Mutex<Collection>>) {
+        self.collections
+            .lock()
+            .unwrap()
+            .insert(stream_id, collection);
+    }
+
+    pub fn collection(&self, stream_id: Uuid) -> Result<Arc<Mutex<Collection>>, AppError> {
+        self.collections
+            .lock()
+            .unwrap()
+            .get(&stream_id)
+            .cloned()
+            .ok_or_else(|| AppError::not_found(format!("no live CRUD stream with id {stream_id}")))
+    }
+
+    pub fn send(&self, stream_id: Uuid, command: Command) -> Result<(), AppError> {
+        self.senders
+            .lock()
+            .unwrap()
+            .get(&stream_id)
+            .and_then(|live| live.sender.send(command).ok())
+            .ok_or_else(|| AppError::not_found(format!("no live stream with id {stream_id}")))
+    }
+
+    // sends to every live stream, or only those serving the named stream `name`
+    pub fn broadcast(&self, name: Option<&str>, command: impl Fn() -> Command) {
+        for live in self.senders.lock().unwrap().values() {
+            if name.is_none() || live.name.as_deref() == name {
+                let _ = live.sender.send(command());
+            }
+        }
+    }
+}
+
+// a stream's end of the registry; dropping it (when the client goes away) unregisters the stream
+pub struct Control {
+    commands: UnboundedReceiver<Command>,
+    registration: Registration,
+}
+
+impl Control {
+    pub fn attach_collection(&self, collection: Arc<Mutex<Collection>>) {
+        self.registration
+            .connections
+            .attach_collection(self.registration.stream_id, collection);
+    }
+
+    pub async fn recv(&mut self) -> Option<Command> {
+        self.commands.recv().await
+    }
+}
+
+struct Live {
+    name: Option<String>,
+    sender: UnboundedSender<Command>,
+}
+
+struct Registration {
+    connections: Arc<Connections>,
+    stream_id: Uuid,
+    sender: UnboundedSender<Command>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut senders = self.connections.senders.lock().unwrap();
+        // a resumed connection may have taken over the id since
+        if senders
+            .get(&self.stream_id)
+            .is_some_and(|live| live.sender.same_channel(&self.sender))
+        {
+            senders.remove(&self.stream_id);
+            self.connections
+                .collections
+                .lock()
+                .unwrap()
+                .remove(&self.stream_id);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::projection::{lookup, lookup_mut};
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+// operation mix once the collection is non-empty; the first event is always a create
+const UPDATE_RATE: f64 = 0.35;
+const DELETE_RATE: f64 = 0.15;
+
+// the share of events that update or delete an existing record rather than create one
+#[derive(Clone, Copy)]
+pub struct Churn {
+    pub update: f64,
+    pub delete: f64,
+}
+
+impl Default for Churn {
+    fn default() -> Self {
+        Churn {
+            update: UPDATE_RATE,
+            delete: DELETE_RATE,
+        }
+    }
+}
+
+// what a CRUD stream's events operate on
+#[derive(Clone)]
+pub struct Crud {
+    pub key: String,
+    // a lifecycle stream keeps at most this many entities and names its events after the op
+    pub entities: Option<usize>,
+    pub churn: Churn,
+}
+
+// created, updated or deleted, for a lifecycle stream's event names
+pub fn lifecycle_event(envelope: &Value) -> Option<String> {
+    envelope
+        .get("op")
+        .and_then(Value::as_str)
+        .map(|op| format!("{op}d"))
+}
+
+// the in-memory collection behind a CRUD stream; events are applied to it as they go out, so
+// updates and deletes only ever name records that exist
+pub struct Collection {
+    key: Vec<String>,
+    records: BTreeMap<String, Value>,
+    // once full, only updates and deletes go out until a delete makes room
+    max_records: Option<usize>,
+    churn: Churn,
+}
+
+impl Collection {
+    pub fn new(key: &str, max_records: Option<usize>, churn: Churn) -> Self {
+        Collection {
+            key: key.split('.').map(str::to_string).collect(),
+            records: BTreeMap::new(),
+            max_records,
+            churn,
+        }
+    }
+
+    // turns a freshly generated record into a create, update or delete envelope
+    pub fn apply(&mut self, mut record: Value, rng: &mut impl Rng) -> Value {
+        let existing = self.records.keys().choose(rng).cloned();
+        let full = self
+            .max_records
+            .is_some_and(|max_records| self.records.len() >= max_records);
+        let Churn { update, delete } = self.churn;
+        let roll = if full {
+            rng.gen::<f64>() * (delete + update)
+        } else {
+            rng.gen::<f64>()
+        };
+
+        match existing {
+            Some(key) if roll < delete => {
+                let record = self.records.remove(&key);
+                json!({ "op": "delete", "key": self.key_of(record.as_ref()), "record": Value::Null })
+            }
+            // a full collection with no churn at all can still only update
+            Some(key) if full || roll < delete + update => {
+                let key_value = self.key_of(self.records.get(&key));
+                if let Some(field) = lookup_mut(&mut record, &self.key) {
+                    *field = key_value.clone();
+                }
+                self.records.insert(key, record.clone());
+                json!({ "op": "update", "key": key_value, "record": record })
+            }
+            _ => {
+                let key_value = self.key_of(Some(&record));
+                self.records.insert(key_string(&key_value), record.clone());
+                json!({ "op": "create", "key": key_value, "record": record })
+            }
+        }
+    }
+
+    fn key_of(&self, record: Option<&Value>) -> Value {
+        record
+            .and_then(|record| lookup(record, &self.key))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn records(&self) -> Vec<Value> {
+        self.records.values().cloned().collect()
+    }
+}
+
+pub fn key_string(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,280 @@
+use crate::config::Packs;
+use crate::error::AppError;
+use crate::plugins::Plugin;
+use crate::template::{placeholders, substitution, CompiledString, GenContext, PARAMETERIZED};
+use axum::http::StatusCode;
+use rand::Rng;
+use regex_syntax::hir::{Class, ClassUnicode, ClassUnicodeRange, Hir, HirKind};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+// extra repetitions past the minimum for open-ended quantifiers such as + and *
+const OPEN_REPEAT: u32 = 8;
+const MAX_REPEAT: u32 = 1000;
+
+// a user-defined placeholder: exactly one of a value list, a regex its values match, or a
+// format string over the built-in placeholders
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct SubstitutionDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    // the [plugins] entry providing it; plugins register their placeholders at startup
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub plugin: Option<String>,
+}
+
+enum Generator {
+    Values(Vec<String>),
+    Pattern(Hir),
+    Format(CompiledString),
+    // the plugin and the name of the generator it calls
+    Plugin(Arc<Plugin>, String),
+}
+
+pub struct CustomSubstitution {
+    pub def: SubstitutionDef,
+    generator: Generator,
+}
+
+impl CustomSubstitution {
+    fn compile(name: &str, def: SubstitutionDef) -> Result<Self, String> {
+        check_name(name)?;
+        let generator = match (&def.values, &def.pattern, &def.format) {
+            (Some(values), None, None) if values.is_empty() => {
+                return Err(format!("{{{name}}} needs at least one value"));
+            }
+            (Some(values), None, None) => Generator::Values(values.clone()),
+            (None, Some(pattern), None) => Generator::Pattern(
+                regex_syntax::parse(pattern)
+                    .map_err(|e| format!("{{{name}}} pattern is not a regex: {e}"))?,
+            ),
+            (None, None, Some(format)) => {
+                if let Some(placeholder) = unknown_placeholder(format) {
+                    return Err(format!(
+                        "{{{name}}} format uses {{{placeholder}}}, formats can only use built-in placeholders"
+                    ));
+                }
+                Generator::Format(CompiledString::compile(format))
+            }
+            _ => {
+                return Err(format!(
+                    "{{{name}}} needs exactly one of values, pattern and format"
+                ))
+            }
+        };
+        Ok(CustomSubstitution { def, generator })
+    }
+
+    fn plugin(name: &str, plugin: &Arc<Plugin>) -> Result<Self, String> {
+        check_name(name)?;
+        Ok(CustomSubstitution {
+            def: SubstitutionDef {
+                plugin: Some(plugin.name.clone()),
+                ..SubstitutionDef::default()
+            },
+            generator: Generator::Plugin(Arc::clone(plugin), name.to_string()),
+        })
+    }
+
+    pub fn generate(&self, ctx: &mut GenContext) -> String {
+        match &self.generator {
+            Generator::Values(values) => values[ctx.rng.gen_range(0..values.len())].clone(),
+            Generator::Pattern(hir) => {
+                let mut bytes = Vec::new();
+                render(hir, &mut ctx.rng, &mut bytes);
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+            Generator::Format(format) => match format.fill(ctx) {
+                Value::String(string) => string,
+                other => other.to_string(),
+            },
+            // the seed comes from the stream's rng, so seeded streams can stay reproducible
+            Generator::Plugin(plugin, generator) => plugin.call(generator, ctx.rng.gen()),
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(format!(
+            "{name:?} is not a valid name, use lowercase letters, digits and _"
+        ));
+    }
+    if built_in(name) {
+        return Err(format!("{{{name}}} is built in"));
+    }
+    Ok(())
+}
+
+// the first placeholder in a format that isn't built in; custom ones are ruled out so formats
+// can't refer to each other in a loop
+fn unknown_placeholder(format: &str) -> Option<String> {
+    placeholders(format).0.into_iter().find(|placeholder| {
+        let name = placeholder
+            .split_once(':')
+            .map_or(placeholder.as_str(), |(name, _)| name);
+        !built_in(name)
+    })
+}
+
+// opt-in packs included, so enabling one later can't shadow a custom placeholder
+fn built_in(name: &str) -> bool {
+    substitution(name, Packs { injection: true }).is_some() || PARAMETERIZED.contains(&name)
+}
+
+// where a class has printable ASCII members, only those are drawn, so \d and \w stay ASCII
+fn printable(class: &ClassUnicode) -> ClassUnicode {
+    let mut ascii = ClassUnicode::new([ClassUnicodeRange::new(' ', '~')]);
+    ascii.intersect(class);
+    if ascii.ranges().is_empty() {
+        class.clone()
+    } else {
+        ascii
+    }
+}
+
+fn render(hir: &Hir, rng: &mut impl Rng, out: &mut Vec<u8>) {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => {}
+        HirKind::Literal(literal) => out.extend_from_slice(&literal.0),
+        HirKind::Class(Class::Unicode(class)) => {
+            let class = printable(class);
+            let total: u32 = class
+                .ranges()
+                .iter()
+                .map(|range| range.end() as u32 - range.start() as u32 + 1)
+                .sum();
+            if total == 0 {
+                return;
+            }
+            let mut pick = rng.gen_range(0..total);
+            for range in class.ranges() {
+                let size = range.end() as u32 - range.start() as u32 + 1;
+                if pick < size {
+                    // a range spanning the surrogates counts them, but they aren't chars
+                    let c = char::from_u32(range.start() as u32 + pick).unwrap_or(range.end());
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    return;
+                }
+                pick -= size;
+            }
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            let total: u32 = class
+                .ranges()
+                .iter()
+                .map(|range| range.end() as u32 - range.start() as u32 + 1)
+                .sum();
+            if total == 0 {
+                return;
+            }
+            let mut pick = rng.gen_range(0..total);
+            for range in class.ranges() {
+                let size = range.end() as u32 - range.start() as u32 + 1;
+                if pick < size {
+                    out.push(range.start() + pick as u8);
+                    return;
+                }
+                pick -= size;
+            }
+        }
+        HirKind::Repetition(repetition) => {
+            let min = repetition.min.min(MAX_REPEAT);
+            let max = repetition
+                .max
+                .unwrap_or(min + OPEN_REPEAT)
+                .clamp(min, MAX_REPEAT);
+            for _ in 0..rng.gen_range(min..=max) {
+                render(&repetition.sub, rng, out);
+            }
+        }
+        HirKind::Capture(capture) => render(&capture.sub, rng, out),
+        HirKind::Concat(parts) => parts.iter().for_each(|part| render(part, rng, out)),
+        HirKind::Alternation(branches) => {
+            render(&branches[rng.gen_range(0..branches.len())], rng, out)
+        }
+    }
+}
+
+// placeholders registered at runtime over /substitutions, under [substitutions] in config or
+// by [plugins]
+#[derive(Default)]
+pub struct CustomSubstitutions {
+    custom: RwLock<BTreeMap<String, Arc<CustomSubstitution>>>,
+}
+
+impl CustomSubstitutions {
+    pub fn from_config(
+        defs: &BTreeMap<String, SubstitutionDef>,
+        plugins: &[Arc<Plugin>],
+    ) -> Result<Self, String> {
+        let mut custom = defs
+            .iter()
+            .map(|(name, def)| {
+                CustomSubstitution::compile(name, def.clone())
+                    .map(|custom| (name.clone(), Arc::new(custom)))
+                    .map_err(|e| format!("substitutions.{name}: {e}"))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+        for plugin in plugins {
+            for name in &plugin.generators {
+                let substitution = CustomSubstitution::plugin(name, plugin)
+                    .map_err(|e| format!("plugins.{}: {e}", plugin.name))?;
+                if let Some(existing) = custom.insert(name.clone(), Arc::new(substitution)) {
+                    let owner = existing
+                        .def
+                        .plugin
+                        .as_ref()
+                        .map_or("[substitutions]".to_string(), |owner| {
+                            format!("plugin {owner}")
+                        });
+                    return Err(format!(
+                        "plugins.{}: {{{name}}} is already provided by {owner}",
+                        plugin.name
+                    ));
+                }
+            }
+        }
+        Ok(CustomSubstitutions {
+            custom: RwLock::new(custom),
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.custom.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<CustomSubstitution>> {
+        self.custom.read().unwrap().get(name).cloned()
+    }
+
+    pub fn create(&self, name: &str, def: SubstitutionDef) -> Result<(), AppError> {
+        let custom = CustomSubstitution::compile(name, def).map_err(AppError::bad_request)?;
+        let mut registered = self.custom.write().unwrap();
+        if registered.contains_key(name) {
+            return Err(AppError::new(StatusCode::CONFLICT)
+                .with_detail(format!("{{{name}}} is already registered, DELETE it first")));
+        }
+        registered.insert(name.to_string(), Arc::new(custom));
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), AppError> {
+        self.custom
+            .write()
+            .unwrap()
+            .remove(name)
+            .map(drop)
+            .ok_or_else(|| AppError::not_found(format!("no custom substitution named {name}")))
+    }
+}
@@ -0,0 +1,263 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+pub const KEY_REUSE: &str = "$key_reuse";
+pub const EVOLUTION: &str = "$evolution";
+pub const MIGRATION: &str = "$migration";
+pub const EVENTS: &str = "$events";
+pub const SAGA: &str = "$saga";
+
+const STREAM_DIRECTIVES: [&str; 5] = [KEY_REUSE, EVOLUTION, MIGRATION, EVENTS, SAGA];
+const MAX_CONCURRENCY: usize = 1000;
+
+// makes `field` cycle through a small pool of recent values so dedup/upsert logic sees collisions
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KeyReuse {
+    pub field: String,
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    #[serde(default = "default_reuse")]
+    pub reuse: f64,
+}
+
+fn default_pool_size() -> usize {
+    10
+}
+
+fn default_reuse() -> f64 {
+    0.5
+}
+
+// schema drift over the stream's lifetime: each step applies once the sequence reaches `at`
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Evolution {
+    // when set, every event carries the schema version, starting at 1 and bumped per step
+    pub version_field: Option<String>,
+    pub steps: Vec<EvolutionStep>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EvolutionStep {
+    pub at: u64,
+    // dotted path => template value
+    #[serde(default)]
+    pub add: Map<String, Value>,
+    // dotted path => new dotted path
+    #[serde(default)]
+    pub rename: Map<String, Value>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+// a v1 -> v2 rollout: the surrounding shape is v1, `to` is v2, cut over at sequence `at`
+// with both versions emitted for each of the `overlap` sequences starting there
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Migration {
+    pub to: Map<String, Value>,
+    pub at: u64,
+    #[serde(default)]
+    pub overlap: u64,
+    pub version_field: Option<String>,
+}
+
+// one of several named event types interleaved on a stream, picked per tick in proportion to
+// its weight; its shape replaces the surrounding one
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EventType {
+    pub name: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    pub shape: Map<String, Value>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+// transactions told as sequences of named events, several in flight at once and interleaved;
+// every step of one transaction reads the same {saga:...} context
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Saga {
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    // filled once per transaction
+    #[serde(default)]
+    pub context: Map<String, Value>,
+    pub steps: Vec<SagaStep>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SagaStep {
+    pub name: String,
+    // the chance a transaction goes on to this step rather than being abandoned
+    #[serde(default = "default_probability")]
+    pub probability: f64,
+    pub shape: Map<String, Value>,
+}
+
+fn default_concurrency() -> usize {
+    3
+}
+
+fn default_probability() -> f64 {
+    1.0
+}
+
+fn check_name(directive: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(['\r', '\n']) {
+        return Err(format!(
+            "{directive}: names must be non-empty single lines, found {name:?}"
+        ));
+    }
+    Ok(())
+}
+
+// top-level `$` keys of a shape that configure the stream rather than describe a field
+#[derive(Default, Clone)]
+pub struct StreamDirectives {
+    pub key_reuse: Option<KeyReuse>,
+    pub evolution: Option<Evolution>,
+    pub migration: Option<Migration>,
+    pub events: Option<Vec<EventType>>,
+    pub saga: Option<Saga>,
+}
+
+impl StreamDirectives {
+    pub fn parse(shape: &Map<String, Value>) -> Result<Self, String> {
+        let key_reuse = shape
+            .get(KEY_REUSE)
+            .map(|value| {
+                let key_reuse =
+                    KeyReuse::deserialize(value).map_err(|e| format!("{KEY_REUSE}: {e}"))?;
+                if key_reuse.pool_size == 0 {
+                    return Err(format!("{KEY_REUSE}: pool_size must be at least 1"));
+                }
+                if !(0.0..=1.0).contains(&key_reuse.reuse) {
+                    return Err(format!("{KEY_REUSE}: reuse must be between 0 and 1"));
+                }
+                Ok(key_reuse)
+            })
+            .transpose()?;
+
+        let evolution = shape
+            .get(EVOLUTION)
+            .map(|value| {
+                let mut evolution =
+                    Evolution::deserialize(value).map_err(|e| format!("{EVOLUTION}: {e}"))?;
+                if let Some((_, target)) = evolution
+                    .steps
+                    .iter()
+                    .flat_map(|step| &step.rename)
+                    .find(|(_, target)| !target.is_string())
+                {
+                    return Err(format!(
+                        "{EVOLUTION}: rename targets must be strings, found {target}"
+                    ));
+                }
+                evolution.steps.sort_by_key(|step| step.at);
+                Ok(evolution)
+            })
+            .transpose()?;
+
+        let migration = shape
+            .get(MIGRATION)
+            .map(|value| {
+                let migration =
+                    Migration::deserialize(value).map_err(|e| format!("{MIGRATION}: {e}"))?;
+                if migration.at == 0 {
+                    return Err(format!("{MIGRATION}: at must be at least 1"));
+                }
+                Ok(migration)
+            })
+            .transpose()?;
+
+        let events = shape
+            .get(EVENTS)
+            .map(|value| {
+                let events =
+                    Vec::<EventType>::deserialize(value).map_err(|e| format!("{EVENTS}: {e}"))?;
+                if events.is_empty() {
+                    return Err(format!("{EVENTS}: needs at least one event type"));
+                }
+                for event in &events {
+                    check_name(EVENTS, &event.name)?;
+                    if event.weight == 0 {
+                        return Err(format!(
+                            "{EVENTS}: {} needs a weight of at least 1",
+                            event.name
+                        ));
+                    }
+                }
+                if evolution.is_some() || migration.is_some() {
+                    return Err(format!(
+                        "{EVENTS} can't be combined with {EVOLUTION} or {MIGRATION}"
+                    ));
+                }
+                Ok(events)
+            })
+            .transpose()?;
+
+        let saga = shape
+            .get(SAGA)
+            .map(|value| {
+                let saga = Saga::deserialize(value).map_err(|e| format!("{SAGA}: {e}"))?;
+                if !(1..=MAX_CONCURRENCY).contains(&saga.concurrency) {
+                    return Err(format!(
+                        "{SAGA}: concurrency must be between 1 and {MAX_CONCURRENCY}"
+                    ));
+                }
+                let Some(first) = saga.steps.first() else {
+                    return Err(format!("{SAGA}: needs at least one step"));
+                };
+                if first.probability != 1.0 {
+                    return Err(format!(
+                        "{SAGA}: the first step always happens, so it takes no probability"
+                    ));
+                }
+                for step in &saga.steps {
+                    check_name(SAGA, &step.name)?;
+                    if !(0.0..=1.0).contains(&step.probability) {
+                        return Err(format!(
+                            "{SAGA}: {} needs a probability between 0 and 1",
+                            step.name
+                        ));
+                    }
+                }
+                if evolution.is_some() || migration.is_some() || events.is_some() {
+                    return Err(format!(
+                        "{SAGA} can't be combined with {EVOLUTION}, {MIGRATION} or {EVENTS}"
+                    ));
+                }
+                Ok(saga)
+            })
+            .transpose()?;
+
+        Ok(StreamDirectives {
+            key_reuse,
+            evolution,
+            migration,
+            events,
+            saga,
+        })
+    }
+}
+
+pub fn is_stream_directive(key: &str) -> bool {
+    STREAM_DIRECTIVES.contains(&key)
+}
+
+// the shape with its stream directives removed, i.e. what actually gets filled per event
+pub fn template(shape: &Map<String, Value>) -> Map<String, Value> {
+    shape
+        .iter()
+        .filter(|(key, _)| !is_stream_directive(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
@@ -0,0 +1,171 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::{header, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum_valid::ValidRejection;
+use serde_json::{json, Map, Value};
+use validator::ValidationErrors;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+// RFC 7807 problem details, rendered as application/problem+json
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    problem_type: &'static str,
+    title: String,
+    detail: Option<String>,
+    extensions: Map<String, Value>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode) -> Self {
+        AppError {
+            status,
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            detail: None,
+            extensions: Map::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        AppError::new(StatusCode::BAD_REQUEST).with_detail(detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        AppError::new(StatusCode::NOT_FOUND).with_detail(detail)
+    }
+
+    pub fn with_type(mut self, problem_type: &'static str, title: impl Into<String>) -> Self {
+        self.problem_type = problem_type;
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_extension(mut self, key: &str, value: Value) -> Self {
+        self.extensions.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let mut body = Map::new();
+        body.insert("type".to_string(), json!(self.problem_type));
+        body.insert("title".to_string(), json!(self.title));
+        body.insert("status".to_string(), json!(self.status.as_u16()));
+        if let Some(detail) = self.detail {
+            body.insert("detail".to_string(), json!(detail));
+        }
+        body.extend(self.extensions);
+
+        let mut response = (self.status, Value::Object(body).to_string()).into_response();
+        response.headers_mut().extend(self.headers);
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+        );
+        response
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::new(rejection.status())
+            .with_type("/problems/invalid-query", "Invalid query string")
+            .with_detail(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::new(rejection.status())
+            .with_type("/problems/invalid-body", "Invalid request body")
+            .with_detail(rejection.body_text())
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut field_errors = errors.field_errors().into_iter().collect::<Vec<_>>();
+        field_errors.sort_by_key(|(field, _)| *field);
+
+        let entries = field_errors
+            .into_iter()
+            .flat_map(|(field, field_errors)| {
+                field_errors.iter().map(move |error| {
+                    let mut entry = Map::new();
+                    entry.insert("field".to_string(), json!(field));
+                    entry.insert("code".to_string(), json!(error.code));
+                    if let Some(message) = &error.message {
+                        entry.insert("message".to_string(), json!(message));
+                    }
+                    if let Some(value) = error.params.get("value") {
+                        entry.insert("value".to_string(), value.clone());
+                    }
+
+                    let allowed = error
+                        .params
+                        .iter()
+                        .filter(|(param, _)| *param != "value")
+                        .map(|(param, value)| (param.to_string(), value.clone()))
+                        .collect::<Map<String, Value>>();
+                    if !allowed.is_empty() {
+                        entry.insert("allowed".to_string(), Value::Object(allowed));
+                    }
+
+                    Value::Object(entry)
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        AppError::new(StatusCode::BAD_REQUEST)
+            .with_type("/problems/validation", "Request validation failed")
+            .with_detail(errors.to_string())
+            .with_extension("errors", Value::Array(entries))
+    }
+}
+
+impl<E> From<ValidRejection<E>> for AppError
+where
+    E: Into<AppError>,
+{
+    fn from(rejection: ValidRejection<E>) -> Self {
+        match rejection {
+            ValidRejection::Valid(errors) => errors.into(),
+            ValidRejection::Inner(inner) => inner.into(),
+        }
+    }
+}
+
+// axum answers unmatched methods with a bare 405, so rewrite those into problems too
+pub async fn problem_for_bare_errors(response: Response) -> Response {
+    if response.status() == StatusCode::METHOD_NOT_ALLOWED
+        && !response.headers().contains_key(header::CONTENT_TYPE)
+    {
+        let allow = response.headers().get(header::ALLOW).cloned();
+        let mut problem = AppError::new(StatusCode::METHOD_NOT_ALLOWED).into_response();
+        if let Some(allow) = allow {
+            problem.headers_mut().insert(header::ALLOW, allow);
+        }
+        return problem;
+    }
+
+    response
+}
+
+pub async fn not_found() -> AppError {
+    AppError::not_found("no route matches this path")
+}
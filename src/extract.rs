@@ -0,0 +1,122 @@
+use crate::error::AppError;
+use axum::async_trait;
+use axum::body::HttpBody;
+use axum::extract::{FromRequest, FromRequestParts, Query, Request};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum_valid::Valid;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use validator::Validate;
+
+// Valid<Query<T>> whose rejections render as problem+json
+pub struct ValidQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Valid(Query(value)) = Valid::<Query<T>>::from_request_parts(parts, state).await?;
+        Ok(ValidQuery(value))
+    }
+}
+
+// axum::Json whose rejections render as problem+json, and which responds the same way
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::<T>::from_request(request, state).await?;
+        Ok(Json(value))
+    }
+}
+
+// a JSON body that may be left off, standing for T::default(); Option<Json<T>> would read a
+// malformed body as a missing one
+pub struct OptionalJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for OptionalJson<T>
+where
+    T: DeserializeOwned + Default,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if request.body().is_end_stream() {
+            return Ok(OptionalJson(T::default()));
+        }
+        let Json(value) = Json::<T>::from_request(request, state).await?;
+        Ok(OptionalJson(value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn post_to(app: Router, content_type: Option<&str>, body: &'static str) -> Response {
+        let mut request = Request::post("/");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        app.oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn json_rejections_are_problems() {
+        let app = Router::new().route("/", post(|Json(value): Json<Value>| async { Json(value) }));
+        for (content_type, body, status) in [
+            (Some("application/json"), "{bad", StatusCode::BAD_REQUEST),
+            (None, "{}", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        ] {
+            let response = post_to(app.clone(), content_type, body).await;
+            assert_eq!(response.status(), status);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/problem+json"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn optional_json_defaults_only_without_a_body() {
+        let app = Router::new().route(
+            "/",
+            post(|OptionalJson(value): OptionalJson<Value>| async { Json(value) }),
+        );
+        assert_eq!(
+            post_to(app.clone(), None, "").await.status(),
+            StatusCode::OK
+        );
+        let response = post_to(app, Some("application/json"), "{bad").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::crud::{key_string, Churn, Collection};
+use crate::pools::Pools;
+use crate::stream::{EventGenerator, StreamSettings};
+use crate::template::GenContext;
+use crate::transport::Message;
+use futures::Stream;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+// deltas a subscriber may fall behind by before it is re-snapshotted
+const FEED_BUFFER: usize = 256;
+
+// server-side simulated state for a snapshot stream, shared by all of its subscribers
+pub struct SnapshotFeed {
+    state: Mutex<FeedState>,
+    deltas: Sender<(u64, Value)>,
+}
+
+struct FeedState {
+    sequence: u64,
+    collection: Collection,
+}
+
+impl SnapshotFeed {
+    // starts the producer: it applies one CRUD operation per tick and broadcasts it as a delta
+    fn spawn(
+        settings: StreamSettings,
+        key: &str,
+        max_records: usize,
+        pools: Arc<Pools>,
+    ) -> Arc<Self> {
+        let (deltas, _) = broadcast::channel(FEED_BUFFER);
+        let feed = Arc::new(SnapshotFeed {
+            state: Mutex::new(FeedState {
+                sequence: 0,
+                collection: Collection::new(key, Some(max_records), Churn::default()),
+            }),
+            deltas,
+        });
+
+        let producer = feed.clone();
+        tokio::spawn(async move {
+            let mut generator = EventGenerator::new(
+                &settings.shape,
+                &settings.directives,
+                GenContext::new(settings.seed, settings.packs, settings.clock, pools.clone())
+                    .with_normalization(settings.normalization)
+                    .with_locale(settings.locale)
+                    .with_custom(settings.custom.clone()),
+                settings.position,
+            );
+
+            loop {
+                sleep(generator.next_delay(settings.interval_min, settings.interval_max)).await;
+                let (sequence, events) = generator.next_events();
+                for mut event in events {
+                    settings.links.apply(&mut event, &pools, generator.rng());
+                    let event = settings.projection.apply(event);
+
+                    // deltas go out under the state lock so subscribe() never misses or repeats one
+                    let mut state = producer.state.lock().unwrap();
+                    let delta = state.collection.apply(event, generator.rng());
+                    state.sequence = sequence;
+                    let _ = producer.deltas.send((sequence, delta));
+                }
+            }
+        });
+
+        feed
+    }
+
+    // the current records and a receiver for every delta after them
+    fn subscribe(&self) -> (u64, Vec<Value>, Receiver<(u64, Value)>) {
+        let state = self.state.lock().unwrap();
+        (
+            state.sequence,
+            state.collection.records(),
+            self.deltas.subscribe(),
+        )
+    }
+}
+
+// keeps only the latest pending delta per key for a subscriber that reads slower than the feed
+// produces, so its backlog is bounded by the number of keys rather than the number of updates
+struct Conflator {
+    pending: Arc<Mutex<Pending>>,
+    ready: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Pending {
+    deltas: HashMap<String, (u64, Value)>,
+    lagged: bool,
+}
+
+impl Conflator {
+    fn spawn(mut deltas: Receiver<(u64, Value)>) -> Self {
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let ready = Arc::new(Notify::new());
+
+        let task = tokio::spawn({
+            let pending = pending.clone();
+            let ready = ready.clone();
+            async move {
+                loop {
+                    match deltas.recv().await {
+                        Ok((sequence, delta)) => {
+                            let key = key_string(&delta["key"]);
+                            pending
+                                .lock()
+                                .unwrap()
+                                .deltas
+                                .insert(key, (sequence, delta));
+                        }
+                        Err(RecvError::Lagged(_)) => pending.lock().unwrap().lagged = true,
+                        Err(RecvError::Closed) => break,
+                    }
+                    ready.notify_one();
+                }
+            }
+        });
+
+        Conflator {
+            pending,
+            ready,
+            task,
+        }
+    }
+
+    // everything pending, oldest first; None once the conflator itself fell behind
+    async fn next_batch(&self) -> Option<Vec<(u64, Value)>> {
+        loop {
+            {
+                let mut pending = self.pending.lock().unwrap();
+                if pending.lagged {
+                    return None;
+                }
+                if !pending.deltas.is_empty() {
+                    let mut batch = pending
+                        .deltas
+                        .drain()
+                        .map(|(_, delta)| delta)
+                        .collect::<Vec<_>>();
+                    batch.sort_by_key(|(sequence, _)| *sequence);
+                    return Some(batch);
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+impl Drop for Conflator {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Default)]
+pub struct Feeds {
+    feeds: Mutex<HashMap<String, Arc<SnapshotFeed>>>,
+}
+
+impl Feeds {
+    // feeds start with their first subscriber and keep running for the life of the server
+    pub fn get_or_spawn(
+        &self,
+        name: &str,
+        settings: impl FnOnce() -> StreamSettings,
+        key: &str,
+        max_records: usize,
+        pools: Arc<Pools>,
+    ) -> Arc<SnapshotFeed> {
+        self.feeds
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| SnapshotFeed::spawn(settings(), key, max_records, pools))
+            .clone()
+    }
+}
+
+fn snapshot_message(sequence: u64, records: Vec<Value>) -> Message {
+    Message::data(
+        Some("snapshot".to_string()),
+        json!({ "sequence": sequence, "records": records }),
+    )
+    .with_id(sequence)
+}
+
+pub fn snapshot_events(
+    settings: StreamSettings,
+    feed: Arc<SnapshotFeed>,
+) -> impl Stream<Item = Message> {
+    async_stream::stream! {
+        yield Message::control("handshake", settings.handshake());
+
+        let (sequence, records, mut deltas) = feed.subscribe();
+        yield snapshot_message(sequence, records);
+
+        if settings.conflate {
+            let mut conflator = Conflator::spawn(deltas);
+            loop {
+                match conflator.next_batch().await {
+                    Some(batch) => {
+                        for (sequence, delta) in batch {
+                            yield Message::data(Some("delta".to_string()), delta).with_id(sequence);
+                        }
+                    }
+                    None => {
+                        let (sequence, records, receiver) = feed.subscribe();
+                        conflator = Conflator::spawn(receiver);
+                        yield snapshot_message(sequence, records);
+                    }
+                }
+            }
+        }
+
+        loop {
+            match deltas.recv().await {
+                Ok((sequence, delta)) => {
+                    yield Message::data(Some("delta".to_string()), delta).with_id(sequence);
+                }
+                // too far behind to catch up delta by delta, so start over from current state
+                Err(RecvError::Lagged(_)) => {
+                    let (sequence, records, receiver) = feed.subscribe();
+                    deltas = receiver;
+                    yield snapshot_message(sequence, records);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
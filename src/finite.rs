@@ -0,0 +1,161 @@
+use crate::transport::Message;
+use chrono::Utc;
+use futures::{future, Stream, StreamExt};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+const END_EVENT: &str = "end";
+
+// when a stream stops on its own instead of running until the client leaves
+#[derive(Clone)]
+pub struct Finish {
+    pub max_events: Option<u64>,
+    pub duration: Option<Duration>,
+    // announce the end with a final end event
+    pub end_event: bool,
+    pub stop: Stop,
+}
+
+// lets whatever ends a stream early ask its generator to send what it is still holding back and
+// end on its own first; sources that never listen are simply cut off
+#[derive(Clone)]
+pub struct Stop(Arc<StopState>);
+
+struct StopState {
+    listening: AtomicBool,
+    requested: watch::Sender<bool>,
+}
+
+impl Default for Stop {
+    fn default() -> Self {
+        Stop(Arc::new(StopState {
+            listening: AtomicBool::new(false),
+            requested: watch::Sender::new(false),
+        }))
+    }
+}
+
+impl Stop {
+    // called by a generator that flushes and ends once the watched value turns true
+    pub fn listen(&self) -> watch::Receiver<bool> {
+        self.0.listening.store(true, Ordering::Relaxed);
+        self.0.requested.subscribe()
+    }
+
+    // true when a generator is listening, so the caller should drain the stream until it ends
+    pub fn request(&self) -> bool {
+        self.0.requested.send_replace(true);
+        self.0.listening.load(Ordering::Relaxed)
+    }
+}
+
+// passes messages through until max_events data events have gone out or duration has passed,
+// whichever comes first, then ends the stream once the generator has sent what it held back
+pub fn until_finished(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    finish: Finish,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        let deadline = async {
+            match finish.duration {
+                Some(duration) => sleep(duration).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::pin!(deadline);
+        let mut events = 0u64;
+
+        let reason = loop {
+            if finish.max_events.is_some_and(|max| events >= max) {
+                break "max_events";
+            }
+            tokio::select! {
+                message = messages.next() => match message {
+                    Some(message) => {
+                        if !message.control {
+                            events += 1;
+                        }
+                        yield message;
+                    }
+                    None => return,
+                },
+                _ = &mut deadline => break "duration",
+            }
+        };
+
+        // after max_events only the controls trailing the last event, like its pii sidecar, go out
+        if finish.stop.request() {
+            while let Some(message) = messages.next().await {
+                if !message.control {
+                    if reason == "max_events" {
+                        continue;
+                    }
+                    events += 1;
+                }
+                yield message;
+            }
+        }
+
+        if finish.end_event {
+            yield Message::control(
+                END_EVENT,
+                json!({ "reason": reason, "events": events, "ts": Utc::now().to_rfc3339() }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // data events each followed by their pii sidecar, ending when asked to stop
+    fn with_sidecars(stop: Stop) -> impl Stream<Item = Message> + Send + 'static {
+        let requested = stop.listen();
+        async_stream::stream! {
+            for sequence in 1.. {
+                if *requested.borrow() {
+                    return;
+                }
+                yield Message::data(None, json!({ "sequence": sequence }));
+                yield Message::control("pii", json!({ "sequence": sequence }));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn max_events_keeps_the_last_event_sidecar() {
+        let stop = Stop::default();
+        let finish = Finish {
+            max_events: Some(2),
+            duration: None,
+            end_event: true,
+            stop: stop.clone(),
+        };
+        let messages: Vec<_> = until_finished(with_sidecars(stop), finish).collect().await;
+        let events: Vec<_> = messages
+            .iter()
+            .map(|message| message.event.as_deref().unwrap_or("message"))
+            .collect();
+        assert_eq!(events, ["message", "pii", "message", "pii", "end"]);
+        assert_eq!(messages[3].data["sequence"], 2);
+        assert_eq!(messages[4].data["events"], 2);
+    }
+
+    #[tokio::test]
+    async fn sources_that_do_not_listen_are_cut_off() {
+        let finish = Finish {
+            max_events: Some(1),
+            duration: None,
+            end_event: false,
+            stop: Stop::default(),
+        };
+        let endless = futures::stream::repeat_with(|| Message::data(None, json!({})));
+        assert_eq!(until_finished(endless, finish).count().await, 1);
+    }
+}
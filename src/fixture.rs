@@ -0,0 +1,139 @@
+use crate::error::AppError;
+use crate::stream::{SSEQuery, StreamSettings};
+use crate::template::lookup;
+use crate::transport::Message;
+use chrono::DateTime;
+use futures::Stream;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+const DEFAULT_TIMESTAMP: &str = "timestamp";
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Pace {
+    // one line every fixture_interval_ms
+    #[default]
+    Interval,
+    // the gaps between the lines' own timestamps
+    Timestamps,
+}
+
+// the lines of a .jsonl file, each sent as one event's data
+#[derive(Clone)]
+pub struct Fixture {
+    events: Vec<Value>,
+    // from the first line, when paced by timestamps
+    offsets: Option<Vec<Duration>>,
+    interval: Duration,
+    speed: f64,
+    looped: bool,
+}
+
+// a timestamp field as RFC 3339 or as milliseconds since the epoch
+fn timestamp_ms(value: &Value) -> Option<i64> {
+    match value {
+        Value::String(string) => DateTime::parse_from_rfc3339(string)
+            .ok()
+            .map(|timestamp| timestamp.timestamp_millis()),
+        value => value.as_f64().map(|ms| ms as i64),
+    }
+}
+
+impl Fixture {
+    pub fn load(path: &Path, query: &SSEQuery) -> Result<Self, AppError> {
+        let source = fs::read_to_string(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                AppError::not_found(format!("no fixture at {}", path.display()))
+            }
+            _ => AppError::bad_request(format!("fixture {}: {e}", path.display())),
+        })?;
+        let events = source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    AppError::bad_request(format!("fixture line {} is not JSON: {e}", index + 1))
+                })
+            })
+            .collect::<Result<Vec<Value>, _>>()?;
+        if events.is_empty() {
+            return Err(AppError::bad_request(format!(
+                "fixture {} has no lines",
+                path.display()
+            )));
+        }
+
+        let offsets = match query.fixture_pace.unwrap_or_default() {
+            Pace::Interval => None,
+            Pace::Timestamps => {
+                let field = query
+                    .fixture_timestamp
+                    .as_deref()
+                    .unwrap_or(DEFAULT_TIMESTAMP);
+                let stamps = events
+                    .iter()
+                    .enumerate()
+                    .map(|(index, event)| {
+                        lookup(event, field).and_then(timestamp_ms).ok_or_else(|| {
+                            AppError::bad_request(format!(
+                                "fixture event {} has no RFC 3339 or epoch milliseconds {field}",
+                                index + 1
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // lines earlier than the first go out straight away
+                Some(
+                    stamps
+                        .iter()
+                        .map(|stamp| Duration::from_millis((stamp - stamps[0]).max(0) as u64))
+                        .collect(),
+                )
+            }
+        };
+        Ok(Fixture {
+            events,
+            offsets,
+            interval: Duration::from_millis(
+                query.fixture_interval_ms.unwrap_or(DEFAULT_INTERVAL_MS),
+            ),
+            speed: query.speed.unwrap_or(1.0),
+            looped: query.fixture_loop.unwrap_or(false),
+        })
+    }
+}
+
+// the fixture's lines in order, over and over when looped, with ids running across passes
+pub fn fixture_events(settings: StreamSettings, fixture: Fixture) -> impl Stream<Item = Message> {
+    async_stream::stream! {
+        yield Message::control("handshake", settings.handshake());
+        let mut sequence = 0u64;
+        let mut pass = Instant::now();
+
+        loop {
+            for (index, event) in fixture.events.iter().enumerate() {
+                let at = match &fixture.offsets {
+                    Some(offsets) => offsets[index].div_f64(fixture.speed),
+                    None => fixture.interval * index as u32,
+                };
+                sleep_until(pass + at).await;
+                sequence += 1;
+                let event = settings.projection.apply(event.clone());
+                yield settings.data_message(sequence, None, event);
+            }
+            if !fixture.looped {
+                break;
+            }
+            // the next pass starts one interval after the last line
+            pass = Instant::now() + fixture.interval;
+        }
+    }
+}
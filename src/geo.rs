@@ -0,0 +1,113 @@
+use crate::template::numeric_settings;
+use rand::Rng;
+
+// metres in a degree of latitude, and of longitude at the equator
+const METRES_PER_DEGREE: f64 = 111_320.0;
+// keeps tracks off the poles, where longitude stops meaning much
+const MAX_LATITUDE: f64 = 85.0;
+const PLACES: usize = 6;
+
+pub fn latitude(rng: &mut impl Rng) -> f64 {
+    rng.gen_range(-MAX_LATITUDE..=MAX_LATITUDE)
+}
+
+pub fn longitude(rng: &mut impl Rng) -> f64 {
+    rng.gen_range(-180.0..180.0)
+}
+
+pub fn coordinate(value: f64) -> String {
+    format!("{value:.PLACES$}")
+}
+
+// where a track stands and which way it is heading, in degrees clockwise from north
+#[derive(Clone, Copy)]
+pub struct Fix {
+    pub lat: f64,
+    pub lng: f64,
+    pub heading: f64,
+}
+
+impl Fix {
+    pub fn to_json(self) -> String {
+        format!(
+            r#"{{"lat":{},"lng":{}}}"#,
+            coordinate(self.lat),
+            coordinate(self.lng)
+        )
+    }
+}
+
+// a {geo_walk:lat=52.52,lng=13.40,speed=15,heading=90,turn=20} argument; the track moves speed
+// metres each event, turning by up to turn degrees either way; an unset start or heading is random
+#[derive(Clone, Copy)]
+pub struct GeoWalk {
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub speed: f64,
+    pub heading: Option<f64>,
+    pub turn: f64,
+}
+
+impl Default for GeoWalk {
+    fn default() -> Self {
+        GeoWalk {
+            lat: None,
+            lng: None,
+            speed: 10.0,
+            heading: None,
+            turn: 15.0,
+        }
+    }
+}
+
+impl GeoWalk {
+    pub fn parse(argument: &str) -> Result<Self, String> {
+        let mut walk = GeoWalk::default();
+        for (key, number) in numeric_settings(argument)? {
+            match key {
+                "lat" if number.abs() <= MAX_LATITUDE => walk.lat = Some(number),
+                "lat" => return Err(format!("lat {number} is outside -85-85")),
+                "lng" if (-180.0..=180.0).contains(&number) => walk.lng = Some(number),
+                "lng" => return Err(format!("lng {number} is outside -180-180")),
+                "speed" if number >= 0.0 => walk.speed = number,
+                "speed" => return Err(format!("speed {number} is negative")),
+                "heading" => walk.heading = Some(number.rem_euclid(360.0)),
+                "turn" if (0.0..=180.0).contains(&number) => walk.turn = number,
+                "turn" => return Err(format!("turn {number} is outside 0-180")),
+                other => {
+                    return Err(format!(
+                        "has no setting {other}, use lat, lng, speed, heading and turn"
+                    ))
+                }
+            }
+        }
+        Ok(walk)
+    }
+
+    pub fn start(self, rng: &mut impl Rng) -> Fix {
+        Fix {
+            lat: self.lat.unwrap_or_else(|| latitude(rng)),
+            lng: self.lng.unwrap_or_else(|| longitude(rng)),
+            heading: self.heading.unwrap_or_else(|| rng.gen_range(0.0..360.0)),
+        }
+    }
+
+    pub fn advance(self, fix: Fix, rng: &mut impl Rng) -> Fix {
+        let heading = (fix.heading + rng.gen_range(-self.turn..=self.turn)).rem_euclid(360.0);
+        let (sin, cos) = heading.to_radians().sin_cos();
+        let mut lat = fix.lat + self.speed * cos / METRES_PER_DEGREE;
+        let mut heading = heading;
+        // bounce off the polar limit rather than wrapping over the pole
+        if lat.abs() > MAX_LATITUDE {
+            lat = lat.signum() * (2.0 * MAX_LATITUDE - lat.abs());
+            heading = (180.0 - heading).rem_euclid(360.0);
+        }
+        let lng = (fix.lng + self.speed * sin / (METRES_PER_DEGREE * fix.lat.to_radians().cos()))
+            .rem_euclid(360.0);
+        Fix {
+            lat,
+            lng: if lng >= 180.0 { lng - 360.0 } else { lng },
+            heading,
+        }
+    }
+}
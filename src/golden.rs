@@ -0,0 +1,97 @@
+use crate::config::Packs;
+use crate::directives::StreamDirectives;
+use crate::pools::Pools;
+use crate::resume::Position;
+use crate::stream::EventGenerator;
+use crate::template::{Clock, GenContext};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+// {datetime} and friends read this instead of the wall clock
+const GOLDEN_EPOCH: &str = "2000-01-01T00:00:00Z";
+
+// the first events a seed and shape generate, as committed by downstream projects
+#[derive(Serialize, Deserialize)]
+pub struct Golden {
+    pub generator: String,
+    pub seed: u64,
+    pub shape: Map<String, Value>,
+    pub events: Vec<Value>,
+}
+
+impl Golden {
+    pub fn generate(
+        shape: Map<String, Value>,
+        seed: u64,
+        count: usize,
+        packs: Packs,
+    ) -> Result<Golden, String> {
+        let directives = StreamDirectives::parse(&shape)?;
+        let clock = Clock {
+            frozen: DateTime::parse_from_rfc3339(GOLDEN_EPOCH)
+                .ok()
+                .map(|epoch| epoch.to_utc()),
+            ..Clock::default()
+        };
+        let ctx = GenContext::new(seed, packs, clock, Arc::new(Pools::new(0)));
+        let mut generator = EventGenerator::new(&shape, &directives, ctx, Position::default());
+
+        let mut events = Vec::with_capacity(count);
+        while events.len() < count {
+            events.extend(generator.next_events().1);
+        }
+        events.truncate(count);
+
+        Ok(Golden {
+            generator: format!("sse-proto/{}", env!("CARGO_PKG_VERSION")),
+            seed,
+            shape,
+            events,
+        })
+    }
+
+    // pretty-printed with sorted keys, so files diff cleanly
+    pub fn canonical(&self) -> String {
+        let mut canonical = serde_json::to_string_pretty(self).unwrap_or_default();
+        canonical.push('\n');
+        canonical
+    }
+}
+
+// one line per differing field, e.g. `events[3].user.name: expected "a", got "b"`
+pub fn diff(expected: &Value, actual: &Value, path: &str, differences: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected) in expected {
+                let path = format!("{path}.{key}");
+                match actual.get(key) {
+                    Some(actual) => diff(expected, actual, &path, differences),
+                    None => differences.push(format!("{path}: missing, expected {expected}")),
+                }
+            }
+            for (key, actual) in actual {
+                if !expected.contains_key(key) {
+                    differences.push(format!("{path}.{key}: unexpected, got {actual}"));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (index, pair) in expected.iter().zip(actual).enumerate() {
+                diff(pair.0, pair.1, &format!("{path}[{index}]"), differences);
+            }
+            if expected.len() != actual.len() {
+                differences.push(format!(
+                    "{path}: expected {} items, got {}",
+                    expected.len(),
+                    actual.len()
+                ));
+            }
+        }
+        (expected, actual) if expected != actual => {
+            differences.push(format!("{path}: expected {expected}, got {actual}"))
+        }
+        _ => {}
+    }
+}
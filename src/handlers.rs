@@ -0,0 +1,1068 @@
+use crate::auth::{admit, Admission};
+use crate::calendar::parse_calendar;
+use crate::channels::channel_events;
+use crate::chaos::{with_chaos, Chaos};
+use crate::config::{MaintenanceConfig, PoolConfig, ScheduleConfig, StreamConfig};
+use crate::connections::{Command, OpenStream};
+use crate::custom::SubstitutionDef;
+use crate::directives::StreamDirectives;
+use crate::error::AppError;
+use crate::extract::{Json, OptionalJson, ValidQuery};
+use crate::feeds::snapshot_events;
+use crate::finite::{until_finished, Finish};
+use crate::fixture::{fixture_events, Fixture};
+use crate::lanes::lane_events;
+use crate::lint::{lint_shape, LintReport};
+use crate::maintenance::Window;
+use crate::network::{throttle, Network};
+use crate::payload::{with_payload_encoding, PayloadEncoding};
+use crate::pools::PoolSnapshot;
+use crate::presets::check_shape;
+use crate::recording::{recorded_events, NewRecording, Recording, RecordingSummary};
+use crate::replay::last_event_id;
+use crate::resume::Position;
+use crate::scenario::{scenario_events, FixedEvent, ScenarioDef};
+use crate::schedule::ScheduledEvent;
+use crate::schema;
+use crate::script::with_script;
+use crate::state::AppState;
+use crate::stats::{with_stats, Period};
+use crate::stream::{clamp_intervals, fake_events, EventGenerator, SSEQuery, StreamSettings};
+use crate::template::{Clock, GenContext};
+use crate::trace::Correlation;
+use crate::transport::{Framing, Message, Transport};
+use crate::websocket::WebSocketUpgrade;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, MethodRouter};
+use futures::{Stream, StreamExt};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use uuid::Uuid;
+use validator::Validate;
+
+// the peer address, missing only when the app is served without connect info
+type Client = Option<ConnectInfo<SocketAddr>>;
+
+fn resolve(
+    state: &AppState,
+    name: Option<&str>,
+    mut query: SSEQuery,
+) -> Result<StreamSettings, AppError> {
+    if let Some(preset) = query.preset.take() {
+        if query.shape.is_some() || query.shape_b64.is_some() || query.shape_gz.is_some() {
+            return Err(AppError::bad_request(
+                "pass either a preset or a shape, not both",
+            ));
+        }
+        let shape = state
+            .presets
+            .get(&preset)
+            .ok_or_else(|| AppError::not_found(format!("no preset named {preset}")))?;
+        query.shape = Some(Value::Object(shape).to_string());
+    }
+
+    let stream = match name {
+        Some(name) => Some(
+            state
+                .config
+                .streams
+                .get(name)
+                .ok_or_else(|| AppError::not_found(format!("no stream named {name}")))?,
+        ),
+        None => None,
+    };
+
+    let sources = [
+        query.scenario.is_some(),
+        query.replay.is_some(),
+        query.fixture.is_some(),
+    ];
+    if sources.into_iter().filter(|&set| set).count() > 1 {
+        return Err(AppError::bad_request(
+            "pass only one of scenario, replay and fixture",
+        ));
+    }
+    let fixture = match &query.fixture {
+        // a bare file name, so requests can't read outside the fixtures dir
+        Some(file) if file.is_empty() || file.starts_with('.') || file.contains(['/', '\\']) => {
+            return Err(AppError::bad_request(format!(
+                "{file:?} is not a file name in the fixtures dir"
+            )))
+        }
+        Some(file) => Some(state.config.fixtures.dir.join(file)),
+        None if query.scenario.is_some()
+            || query.replay.is_some()
+            || query.shape.is_some()
+            || query.shape_b64.is_some()
+            || query.shape_gz.is_some() =>
+        {
+            None
+        }
+        None => match stream {
+            Some(stream) => stream.fixture.clone(),
+            None => state.config.defaults.fixture.clone(),
+        },
+    }
+    .map(|path| Fixture::load(&path, &query))
+    .transpose()?;
+    let recording = query
+        .replay
+        .as_deref()
+        .map(|name| {
+            state
+                .recordings
+                .get(name)
+                .map(|recording| (recording, query.speed.unwrap_or(1.0)))
+                .ok_or_else(|| AppError::not_found(format!("no recording named {name}")))
+        })
+        .transpose()?;
+    let scenario = query
+        .scenario
+        .as_deref()
+        .map(|name| {
+            state
+                .scenarios
+                .get(name)
+                .ok_or_else(|| AppError::not_found(format!("no scenario named {name}")))
+        })
+        .transpose()?;
+    let mut settings = StreamSettings::resolve(query, &state.config, stream, &state.custom)?;
+    settings.scenario = scenario;
+    settings.recording = recording;
+    settings.fixture = fixture;
+    Ok(settings)
+}
+
+fn stream_metadata(settings: &StreamSettings) -> HeaderMap {
+    let mut metadata = settings.metadata_headers();
+    metadata.extend(settings.headers.clone());
+    if let Ok(stream_id) = HeaderValue::from_str(&settings.stream_id.to_string()) {
+        metadata.insert("x-stream-id", stream_id);
+    }
+    metadata
+}
+
+// how a stream's messages are put on the wire, taken from its settings before they are moved
+#[derive(Clone)]
+struct Delivery {
+    framing: Framing,
+    stats_every: Option<Period>,
+    finish: Finish,
+    chaos: Option<Chaos>,
+    network: Option<Network>,
+    encoding: Option<Transport>,
+    payload: Option<PayloadEncoding>,
+    script: Option<Vec<String>>,
+    keepalive: Option<Duration>,
+    config_notice: Option<Value>,
+}
+
+impl Delivery {
+    fn of(settings: &StreamSettings) -> Self {
+        Delivery {
+            framing: settings.framing,
+            stats_every: settings.stats_every,
+            finish: settings.finish.clone(),
+            chaos: settings.chaos,
+            network: settings.network,
+            encoding: settings.encoding,
+            payload: settings.payload.clone(),
+            script: settings.script.clone(),
+            keepalive: settings.keepalive,
+            config_notice: settings.config_notice(),
+        }
+    }
+}
+
+// the config event right after the handshake every stream opens with
+fn with_config_notice(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    notice: Option<Value>,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        if let Some(notice) = notice {
+            if let Some(handshake) = messages.next().await {
+                yield handshake;
+            }
+            yield Message::control("config", notice);
+        }
+        while let Some(message) = messages.next().await {
+            yield message;
+        }
+    }
+}
+
+fn respond(
+    metadata: HeaderMap,
+    delivery: Delivery,
+    headers: &HeaderMap,
+    upgrade: Option<WebSocketUpgrade>,
+    messages: impl Stream<Item = Message> + Send + 'static,
+    admission: Admission,
+) -> Response {
+    let stream_id = metadata
+        .get("x-stream-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok());
+    let messages = with_config_notice(messages, delivery.config_notice);
+    let messages = with_script(messages, delivery.script);
+    let messages = until_finished(
+        admission.guard(stream_id, messages, delivery.finish.stop.clone()),
+        delivery.finish,
+    );
+    let messages = with_chaos(messages, delivery.chaos);
+    let messages = with_stats(messages, delivery.stats_every);
+    let (messages, trace_headers) = match Correlation::from_headers(headers) {
+        Some(correlation) => {
+            let stream_id = metadata
+                .get("x-stream-id")
+                .and_then(|id| id.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let trace_headers = correlation.response_headers();
+            (
+                correlation.annotate(stream_id, messages).boxed(),
+                trace_headers,
+            )
+        }
+        None => (messages.boxed(), HeaderMap::new()),
+    };
+    let messages = with_payload_encoding(messages, delivery.payload);
+
+    let mut response = match upgrade {
+        Some(upgrade) => upgrade.respond(delivery.keepalive, messages),
+        None => throttle(
+            Transport::select(delivery.encoding, headers).respond(
+                delivery.framing,
+                delivery.keepalive,
+                messages,
+            ),
+            delivery.network,
+        ),
+    };
+    response.headers_mut().extend(trace_headers);
+    response.headers_mut().extend(metadata);
+    response
+}
+
+fn stream(
+    state: &AppState,
+    name: Option<&str>,
+    settings: StreamSettings,
+    headers: &HeaderMap,
+    upgrade: Option<WebSocketUpgrade>,
+    admission: Admission,
+) -> Response {
+    let mut settings = settings;
+    if let Some(fixture) = settings.fixture.take() {
+        let metadata = stream_metadata(&settings);
+        return respond(
+            metadata,
+            Delivery::of(&settings),
+            headers,
+            upgrade,
+            fixture_events(settings, fixture),
+            admission,
+        );
+    }
+    if let Some((recording, speed)) = settings.recording.take() {
+        let metadata = stream_metadata(&settings);
+        return respond(
+            metadata,
+            Delivery::of(&settings),
+            headers,
+            upgrade,
+            recorded_events(settings, recording, speed),
+            admission,
+        );
+    }
+    if let Some(scenario) = settings.scenario.clone() {
+        let metadata = stream_metadata(&settings);
+        return respond(
+            metadata,
+            Delivery::of(&settings),
+            headers,
+            upgrade,
+            scenario_events(settings, scenario, state.pools.clone()),
+            admission,
+        );
+    }
+
+    let key = settings
+        .stream_key
+        .clone()
+        .unwrap_or_else(|| name.unwrap_or("/").to_string());
+    settings.replay = Some(
+        state
+            .replays
+            .start(key, &mut settings, last_event_id(headers)),
+    );
+    let metadata = stream_metadata(&settings);
+    let control = state.connections.register(settings.stream_id, name);
+    respond(
+        metadata,
+        Delivery::of(&settings),
+        headers,
+        upgrade,
+        fake_events(settings, control, state.pools.clone()),
+        admission,
+    )
+}
+
+fn head(settings: StreamSettings, headers: &HeaderMap) -> Response {
+    let mut response = Transport::select(settings.encoding, headers).head();
+    response.headers_mut().extend(settings.metadata_headers());
+    response.headers_mut().extend(settings.headers);
+    response
+}
+
+fn options(settings: StreamSettings) -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::ALLOW,
+        HeaderValue::from_static("GET, HEAD, POST, OPTIONS"),
+    );
+    headers.extend(settings.metadata_headers());
+    response
+}
+
+// the query parameters as a JSON body, with `shape` and `events` as JSON rather than encoded strings
+#[derive(Deserialize)]
+pub struct StreamBody {
+    shape: Option<Value>,
+    events: Option<Value>,
+    #[serde(flatten)]
+    query: SSEQuery,
+}
+
+impl StreamBody {
+    fn into_query(self) -> Result<SSEQuery, AppError> {
+        let mut query = self.query;
+        query.validate()?;
+        query.shape = match self.shape {
+            Some(Value::String(source)) => Some(source),
+            Some(shape) => Some(shape.to_string()),
+            None => None,
+        };
+        query.events = match self.events {
+            Some(Value::String(source)) => Some(source),
+            Some(events) => Some(events.to_string()),
+            None => None,
+        };
+        Ok(query)
+    }
+}
+
+pub async fn sse(
+    State(state): State<AppState>,
+    client: Client,
+    upgrade: Option<WebSocketUpgrade>,
+    headers: HeaderMap,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    let admission = admit(&state, client, &headers, &query)?;
+    Ok(stream(
+        &state,
+        None,
+        resolve(&state, None, query)?,
+        &headers,
+        upgrade,
+        admission,
+    ))
+}
+
+// the default stream over WebSocket only; any stream route also upgrades when asked to
+pub async fn ws(
+    state: State<AppState>,
+    client: Client,
+    upgrade: WebSocketUpgrade,
+    headers: HeaderMap,
+    query: ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    sse(state, client, Some(upgrade), headers, query).await
+}
+
+pub async fn sse_post(
+    state: State<AppState>,
+    client: Client,
+    headers: HeaderMap,
+    Json(body): Json<StreamBody>,
+) -> Result<Response, AppError> {
+    sse(state, client, None, headers, ValidQuery(body.into_query()?)).await
+}
+
+pub async fn sse_head(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    Ok(head(resolve(&state, None, query)?, &headers))
+}
+
+pub async fn sse_options(
+    State(state): State<AppState>,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    Ok(options(resolve(&state, None, query)?))
+}
+
+pub async fn named_sse(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    client: Client,
+    upgrade: Option<WebSocketUpgrade>,
+    headers: HeaderMap,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    let admission = admit(&state, client, &headers, &query)?;
+    let settings = resolve(&state, Some(&name), query)?;
+
+    match state.config.streams.get(&name) {
+        Some(
+            stream_config @ StreamConfig {
+                snapshot: Some(snapshot),
+                ..
+            },
+        ) => {
+            // the feed is shared, so its generation settings come from config alone
+            let feed_settings = StreamSettings::resolve(
+                SSEQuery::default(),
+                &state.config,
+                Some(stream_config),
+                &state.custom,
+            )?;
+            let feed = state.feeds.get_or_spawn(
+                &name,
+                || feed_settings,
+                &snapshot.key,
+                snapshot.max_records,
+                state.pools.clone(),
+            );
+            Ok(respond(
+                stream_metadata(&settings),
+                Delivery::of(&settings),
+                &headers,
+                upgrade,
+                snapshot_events(settings, feed),
+                admission,
+            ))
+        }
+        Some(stream_config) if !stream_config.lanes.is_empty() => Ok(respond(
+            stream_metadata(&settings),
+            Delivery::of(&settings),
+            &headers,
+            upgrade,
+            lane_events(settings, &stream_config.lanes, state.pools.clone()),
+            admission,
+        )),
+        _ => Ok(stream(
+            &state,
+            Some(&name),
+            settings,
+            &headers,
+            upgrade,
+            admission,
+        )),
+    }
+}
+
+pub async fn named_sse_post(
+    state: State<AppState>,
+    name: Path<String>,
+    client: Client,
+    headers: HeaderMap,
+    Json(body): Json<StreamBody>,
+) -> Result<Response, AppError> {
+    named_sse(
+        state,
+        name,
+        client,
+        None,
+        headers,
+        ValidQuery(body.into_query()?),
+    )
+    .await
+}
+
+// a shared stream: the first subscriber's shape and timing drive it for everyone after
+pub async fn channel(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    client: Client,
+    upgrade: Option<WebSocketUpgrade>,
+    headers: HeaderMap,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    if query.scenario.is_some() || query.replay.is_some() || query.fixture.is_some() {
+        return Err(AppError::bad_request(
+            "channels generate from a shape, not a scenario, replay or fixture",
+        ));
+    }
+    let admission = admit(&state, client, &headers, &query)?;
+    let mut settings = resolve(&state, None, query)?;
+    settings.fixture = None;
+
+    // the clone only drives the channel when this request is the one that starts it
+    let channel = state
+        .channels
+        .get_or_spawn(&name, || settings.clone(), state.pools.clone());
+    Ok(respond(
+        stream_metadata(&settings),
+        Delivery::of(&settings),
+        &headers,
+        upgrade,
+        channel_events(settings, name, channel),
+        admission,
+    ))
+}
+
+// sends one event to everyone on a running channel, between its generated ones
+pub async fn publish(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(event): Json<FixedEvent>,
+) -> Result<Json<Value>, AppError> {
+    let subscribers = state.channels.publish(&name, event)?;
+    Ok(Json(json!({ "subscribers": subscribers })))
+}
+
+pub async fn list_channels(State(state): State<AppState>) -> Json<BTreeMap<String, usize>> {
+    Json(state.channels.subscribers())
+}
+
+pub async fn named_sse_head(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    Ok(head(resolve(&state, Some(&name), query)?, &headers))
+}
+
+pub async fn named_sse_options(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ValidQuery(query): ValidQuery<SSEQuery>,
+) -> Result<Response, AppError> {
+    Ok(options(resolve(&state, Some(&name), query)?))
+}
+
+// a named stream at its configured path, answering the same methods as /streams/{name}
+pub fn stream_route(name: &str) -> MethodRouter<AppState> {
+    let [get_name, head_name, post_name, options_name] = [(); 4].map(|()| name.to_string());
+    get(
+        move |state, client, upgrade: Option<WebSocketUpgrade>, headers, query| {
+            named_sse(
+                state,
+                Path(get_name.clone()),
+                client,
+                upgrade,
+                headers,
+                query,
+            )
+        },
+    )
+    .head(move |state, headers, query| {
+        named_sse_head(state, Path(head_name.clone()), headers, query)
+    })
+    .post(move |state, client, headers, body| {
+        named_sse_post(state, Path(post_name.clone()), client, headers, body)
+    })
+    .options(move |state, query| named_sse_options(state, Path(options_name.clone()), query))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RedactRequest {
+    // falls back to the stream's own `redact` list when empty
+    fields: Vec<String>,
+}
+
+fn parse_stream_id(stream_id: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(stream_id)
+        .map_err(|e| AppError::bad_request(format!("{stream_id} is not a stream id: {e}")))
+}
+
+pub async fn redact(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+    OptionalJson(request): OptionalJson<RedactRequest>,
+) -> Result<StatusCode, AppError> {
+    let stream_id = parse_stream_id(&stream_id)?;
+
+    state.connections.send(
+        stream_id,
+        Command::Redact {
+            fields: request.fields,
+        },
+    )?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+fn control(state: &AppState, stream_id: &str, command: Command) -> Result<StatusCode, AppError> {
+    state
+        .connections
+        .send(parse_stream_id(stream_id)?, command)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn pause_connection(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    control(&state, &stream_id, Command::Pause)
+}
+
+pub async fn resume_connection(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    control(&state, &stream_id, Command::Resume)
+}
+
+pub async fn step_connection(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    control(&state, &stream_id, Command::Step)
+}
+
+pub async fn set_connection_rate(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+    Json(rate): Json<SetRateRequest>,
+) -> Result<StatusCode, AppError> {
+    let limits = &state.config.limits;
+    limits.check_intervals(Some(rate.interval_min), Some(rate.interval_max))?;
+    // slowed to the ceiling the way a new stream would be
+    let (interval_min, interval_max) = clamp_intervals(
+        limits.max_events_per_sec,
+        rate.interval_min,
+        rate.interval_max,
+    )
+    .unwrap_or((rate.interval_min, rate.interval_max));
+    control(
+        &state,
+        &stream_id,
+        Command::SetRate {
+            interval_min,
+            interval_max,
+        },
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetRateRequest {
+    interval_min: u64,
+    interval_max: u64,
+}
+
+pub async fn list_connections(State(state): State<AppState>) -> Json<Vec<OpenStream>> {
+    Json(state.connections.list())
+}
+
+pub async fn close_connection(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.connections.kick(parse_stream_id(&stream_id)?)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// the materialized state of a CRUD stream, as of the last event sent
+pub async fn crud_state(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let stream_id = parse_stream_id(&id)?;
+    let records = state
+        .connections
+        .collection(stream_id)?
+        .lock()
+        .unwrap()
+        .records();
+
+    Ok(Json(json!({
+        "stream_id": stream_id,
+        "count": records.len(),
+        "records": records,
+    })))
+}
+
+pub async fn list_pools(State(state): State<AppState>) -> Json<BTreeMap<String, usize>> {
+    Json(state.pools.sizes())
+}
+
+pub async fn get_pool(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<PoolSnapshot>, AppError> {
+    state
+        .pools
+        .snapshot(&name)
+        .map(Json)
+        .ok_or_else(|| AppError::not_found(format!("no pool named {name}")))
+}
+
+// creates the pool or appends to it; the body takes the same fields as a [pools.<name>] table
+pub async fn extend_pool(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    OptionalJson(pool_config): OptionalJson<PoolConfig>,
+) -> Result<Json<PoolSnapshot>, AppError> {
+    let max_entries = state.config.limits.max_pool_entries;
+    if pool_config.values.is_none() && pool_config.size > max_entries {
+        return Err(
+            AppError::new(StatusCode::PAYLOAD_TOO_LARGE).with_detail(format!(
+                "generated pools are limited to {max_entries} entries"
+            )),
+        );
+    }
+
+    let mut ctx = GenContext::new(
+        thread_rng().gen(),
+        state.config.packs,
+        Clock::default(),
+        state.pools.clone(),
+    )
+    .with_custom(state.custom.clone());
+    let values = pool_config.render(&mut ctx);
+    state.pools.extend(&name, values);
+
+    get_pool(State(state), Path(name)).await
+}
+
+pub async fn delete_pool(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !state.pools.remove(&name) {
+        return Err(AppError::not_found(format!("no pool named {name}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_schedule(State(state): State<AppState>) -> Json<Vec<ScheduledEvent>> {
+    Json(state.scheduler.pending())
+}
+
+// takes the same fields as a [[schedule]] entry
+pub async fn schedule_event(
+    State(state): State<AppState>,
+    Json(mut entry): Json<ScheduleConfig>,
+) -> Result<(StatusCode, Json<ScheduledEvent>), AppError> {
+    entry
+        .load()
+        .map_err(|e| AppError::bad_request(format!("at: {e}")))?;
+    if let Some(name) = &entry.stream {
+        if !state.config.streams.contains_key(name) {
+            return Err(AppError::not_found(format!("no stream named {name}")));
+        }
+    }
+
+    Ok((StatusCode::CREATED, Json(state.scheduler.add(entry))))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CalendarQuery {
+    stream: Option<String>,
+}
+
+// schedules every VEVENT of an iCalendar body, like a [calendar] file
+pub async fn schedule_calendar(
+    State(state): State<AppState>,
+    ValidQuery(query): ValidQuery<CalendarQuery>,
+    body: String,
+) -> Result<(StatusCode, Json<Vec<ScheduledEvent>>), AppError> {
+    let events = parse_calendar(&body)
+        .map_err(|e| AppError::bad_request(format!("invalid calendar: {e}")))?;
+    if let Some(name) = &query.stream {
+        if !state.config.streams.contains_key(name) {
+            return Err(AppError::not_found(format!("no stream named {name}")));
+        }
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(state.scheduler.add_calendar(&events, query.stream)),
+    ))
+}
+
+pub async fn cancel_scheduled(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let id = Uuid::parse_str(&id)
+        .map_err(|e| AppError::bad_request(format!("{id} is not a scheduled event id: {e}")))?;
+    if !state.scheduler.cancel(id) {
+        return Err(AppError::not_found(format!(
+            "no scheduled event with id {id}"
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_maintenance(State(state): State<AppState>) -> Json<Option<Window>> {
+    Json(state.maintenance.active())
+}
+
+// takes the same fields as a [[maintenance]] entry; without `at` the window starts now
+pub async fn start_maintenance(
+    State(state): State<AppState>,
+    Json(mut window): Json<MaintenanceConfig>,
+) -> Result<(StatusCode, Json<Window>), AppError> {
+    window.load().map_err(AppError::bad_request)?;
+    let window = state
+        .maintenance
+        .schedule(&window)
+        .map_err(AppError::bad_request)?;
+    Ok((StatusCode::ACCEPTED, Json(window)))
+}
+
+pub async fn end_maintenance(State(state): State<AppState>) -> Result<StatusCode, AppError> {
+    if !state.maintenance.end() {
+        return Err(AppError::not_found("no maintenance window is active"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// a shape, or a JSON Schema to derive one from
+#[derive(Deserialize)]
+pub struct NewPreset {
+    name: String,
+    shape: Option<Value>,
+    schema: Option<Value>,
+}
+
+pub async fn list_presets(State(state): State<AppState>) -> Json<Vec<String>> {
+    Json(state.presets.names())
+}
+
+pub async fn create_preset(
+    State(state): State<AppState>,
+    Json(preset): Json<NewPreset>,
+) -> Result<(StatusCode, Json<Map<String, Value>>), AppError> {
+    let shape = match (preset.shape, preset.schema) {
+        (Some(shape), None) => shape,
+        (None, Some(schema)) => {
+            schema::shape_from_schema(&schema).map_err(AppError::bad_request)?
+        }
+        _ => return Err(AppError::bad_request("pass one of shape and schema")),
+    };
+    let shape = check_shape(shape, &state)?;
+    state.presets.create(&preset.name, shape.clone())?;
+    Ok((StatusCode::CREATED, Json(shape)))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreviewRequest {
+    shape: Value,
+    seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Preview {
+    seed: u64,
+    event: Value,
+    #[serde(flatten)]
+    lint: LintReport,
+}
+
+// the first event a shape renders to, alongside what /validate would report about it; unknown
+// placeholders still render, as written the way a stream would send them
+pub async fn preview(
+    State(state): State<AppState>,
+    Json(request): Json<PreviewRequest>,
+) -> Result<Json<Preview>, AppError> {
+    let lint = LintReport::new(lint_shape(
+        &request.shape,
+        state.config.packs,
+        &state.custom,
+        state.config.templates.strict,
+    ));
+    let Value::Object(shape) = request.shape else {
+        return Err(AppError::bad_request("shape must be a JSON object"));
+    };
+    let directives = StreamDirectives::parse(&shape).map_err(AppError::bad_request)?;
+    let seed = request.seed.unwrap_or_else(|| thread_rng().gen());
+
+    let ctx = GenContext::new(
+        seed,
+        state.config.packs,
+        Clock::default(),
+        state.pools.clone(),
+    )
+    .with_custom(state.custom.clone());
+    let mut generator = EventGenerator::new(&shape, &directives, ctx, Position::default());
+    let event = generator
+        .next_events()
+        .1
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+    Ok(Json(Preview { seed, event, lint }))
+}
+
+// the shape a JSON Schema body derives, to check or tweak before registering it
+pub async fn shape_from_schema(
+    State(state): State<AppState>,
+    Json(schema): Json<Value>,
+) -> Result<Json<Map<String, Value>>, AppError> {
+    let shape = schema::shape_from_schema(&schema).map_err(AppError::bad_request)?;
+    Ok(Json(check_shape(shape, &state)?))
+}
+
+pub async fn get_preset(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Map<String, Value>>, AppError> {
+    state
+        .presets
+        .get(&name)
+        .map(Json)
+        .ok_or_else(|| AppError::not_found(format!("no preset named {name}")))
+}
+
+// the body is the shape itself; 201 when the preset is new
+pub async fn put_preset(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(shape): Json<Value>,
+) -> Result<(StatusCode, Json<Map<String, Value>>), AppError> {
+    let shape = check_shape(shape, &state)?;
+    let status = if state.presets.put(&name, shape.clone())? {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(shape)))
+}
+
+pub async fn delete_preset(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.presets.delete(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct NewSubstitution {
+    name: String,
+    #[serde(flatten)]
+    def: SubstitutionDef,
+}
+
+pub async fn create_substitution(
+    State(state): State<AppState>,
+    Json(substitution): Json<NewSubstitution>,
+) -> Result<(StatusCode, Json<SubstitutionDef>), AppError> {
+    state
+        .custom
+        .create(&substitution.name, substitution.def.clone())?;
+    Ok((StatusCode::CREATED, Json(substitution.def)))
+}
+
+pub async fn get_substitution(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<SubstitutionDef>, AppError> {
+    state
+        .custom
+        .get(&name)
+        .map(|custom| Json(custom.def.clone()))
+        .ok_or_else(|| AppError::not_found(format!("no custom substitution named {name}")))
+}
+
+pub async fn delete_substitution(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.custom.delete(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_scenarios(State(state): State<AppState>) -> Json<Vec<String>> {
+    Json(state.scenarios.names())
+}
+
+#[derive(Deserialize)]
+pub struct NewScenario {
+    name: String,
+    #[serde(flatten)]
+    def: ScenarioDef,
+}
+
+pub async fn create_scenario(
+    State(state): State<AppState>,
+    Json(scenario): Json<NewScenario>,
+) -> Result<(StatusCode, Json<ScenarioDef>), AppError> {
+    state.scenarios.create(
+        &scenario.name,
+        scenario.def.clone(),
+        state.config.packs,
+        &state.custom,
+        state.config.templates.strict,
+    )?;
+    Ok((StatusCode::CREATED, Json(scenario.def)))
+}
+
+pub async fn get_scenario(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ScenarioDef>, AppError> {
+    state
+        .scenarios
+        .get(&name)
+        .map(|scenario| Json(scenario.def.clone()))
+        .ok_or_else(|| AppError::not_found(format!("no scenario named {name}")))
+}
+
+pub async fn delete_scenario(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.scenarios.delete(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_recordings(State(state): State<AppState>) -> Json<Vec<RecordingSummary>> {
+    Json(state.recordings.summaries())
+}
+
+pub async fn create_recording(
+    State(state): State<AppState>,
+    Json(request): Json<NewRecording>,
+) -> Result<(StatusCode, Json<RecordingSummary>), AppError> {
+    Ok((StatusCode::CREATED, Json(state.recordings.start(request)?)))
+}
+
+pub async fn get_recording(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Recording>, AppError> {
+    state
+        .recordings
+        .get(&name)
+        .map(Json)
+        .ok_or_else(|| AppError::not_found(format!("no recording named {name}")))
+}
+
+pub async fn delete_recording(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.recordings.delete(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
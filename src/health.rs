@@ -0,0 +1,74 @@
+use crate::shape::ShapeTemplate;
+use crate::state::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::task;
+use tokio::time::Instant;
+
+// rendered on every readiness probe, so a broken generator takes the instance out of rotation
+const SELF_CHECK: &str = r#"{"id": "{uuid}", "n": "{int:1-9}", "at": "{datetime}"}"#;
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    version: &'static str,
+    uptime_secs: u64,
+    // live streams on every route
+    streams: usize,
+    self_check_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// the process is up and answering
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+// ready while a sample event renders, until shutdown starts draining streams
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let started = Instant::now();
+    let check = task::spawn_blocking(self_check)
+        .await
+        .unwrap_or_else(|_| Err("the generator panicked".to_string()));
+    let self_check_ms = (started.elapsed().as_secs_f64() * 100_000.0).round() / 100.0;
+    let error = if state.shutdown.is_stopping() {
+        Some("shutting down".to_string())
+    } else {
+        check.err()
+    };
+    let status = match error {
+        Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+        None => StatusCode::OK,
+    };
+    let readiness = Readiness {
+        ready: error.is_none(),
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: state.started.elapsed().as_secs(),
+        streams: state.connections.count(),
+        self_check_ms,
+        error,
+    };
+    (status, Json(readiness))
+}
+
+fn self_check() -> Result<(), String> {
+    let template = ShapeTemplate::parse(SELF_CHECK)?;
+    let (_, events) = template.generator(0).next_events();
+    match events.first() {
+        Some(Value::Object(event))
+            if event.get("n").is_some_and(Value::is_u64)
+                && event
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .is_some_and(|id| !id.is_empty()) =>
+        {
+            Ok(())
+        }
+        Some(event) => Err(format!("the sample event rendered as {event}")),
+        None => Err("the sample shape rendered no event".to_string()),
+    }
+}
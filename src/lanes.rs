@@ -0,0 +1,150 @@
+use crate::config::LaneConfig;
+use crate::directives::StreamDirectives;
+use crate::pools::Pools;
+use crate::resume::Position;
+use crate::shape::shape_object;
+use crate::stream::{clamp_intervals, EventGenerator, StreamSettings};
+use crate::template::GenContext;
+use crate::transport::Message;
+use futures::Stream;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+// per-lane queues between the producers and the connection, highest priority first
+struct Lanes {
+    queues: Mutex<Vec<Lane>>,
+    ready: Notify,
+    sequence: AtomicU64,
+}
+
+struct Lane {
+    capacity: usize,
+    queue: VecDeque<Message>,
+}
+
+impl Lanes {
+    fn push(&self, lane: usize, message: Message) {
+        let mut queues = self.queues.lock().unwrap();
+        let lane = &mut queues[lane];
+        // a lane that outgrows its queue sheds its oldest events
+        if lane.queue.len() >= lane.capacity {
+            lane.queue.pop_front();
+        }
+        lane.queue.push_back(message);
+        self.ready.notify_one();
+    }
+
+    async fn pop(&self) -> Message {
+        loop {
+            if let Some(message) = self
+                .queues
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .find_map(|lane| lane.queue.pop_front())
+            {
+                return message;
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+struct Producers(Vec<JoinHandle<()>>);
+
+impl Drop for Producers {
+    fn drop(&mut self) {
+        self.0.iter().for_each(JoinHandle::abort);
+    }
+}
+
+// a multiplexed stream: each lane generates on its own schedule, and whatever is queued goes out
+// strictly by lane priority, so bursts of bulk events never hold back an alert
+pub fn lane_events(
+    settings: StreamSettings,
+    lanes: &BTreeMap<String, LaneConfig>,
+    pools: Arc<Pools>,
+) -> impl Stream<Item = Message> {
+    let mut lanes = lanes.iter().collect::<Vec<_>>();
+    lanes.sort_by_key(|(_, lane)| -lane.priority);
+
+    let queues = Arc::new(Lanes {
+        queues: Mutex::new(
+            lanes
+                .iter()
+                .map(|(_, lane)| Lane {
+                    capacity: lane.queue,
+                    queue: VecDeque::new(),
+                })
+                .collect(),
+        ),
+        ready: Notify::new(),
+        sequence: AtomicU64::new(0),
+    });
+
+    let settings = Arc::new(settings);
+    let producers = Producers(
+        lanes
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, lane))| {
+                let (name, lane) = (name.clone(), lane.clone());
+                let (settings, queues, pools) = (settings.clone(), queues.clone(), pools.clone());
+                tokio::spawn(async move {
+                    let shape = shape_object(lane.shape.clone()).unwrap_or_default();
+                    let mut generator = EventGenerator::new(
+                        &shape,
+                        &StreamDirectives::default(),
+                        GenContext::new(
+                            settings.seed.wrapping_add(index as u64),
+                            settings.packs,
+                            settings.clock,
+                            pools,
+                        )
+                        .with_normalization(settings.normalization)
+                        .with_locale(settings.locale)
+                        .with_custom(settings.custom.clone()),
+                        Position::default(),
+                    );
+                    let interval_min = lane.interval_min.unwrap_or(settings.interval_min);
+                    // a lane's own interval_min may be above the stream's interval_max
+                    let interval_max = lane
+                        .interval_max
+                        .unwrap_or(settings.interval_max)
+                        .max(interval_min);
+                    let (interval_min, interval_max) =
+                        clamp_intervals(settings.max_events_per_sec, interval_min, interval_max)
+                            .unwrap_or((interval_min, interval_max));
+
+                    loop {
+                        sleep(generator.next_delay(interval_min, interval_max)).await;
+                        for _ in 0..lane.burst {
+                            let (_, events) = generator.next_events();
+                            for event in events {
+                                let sequence = queues.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+                                let event = settings.projection.apply(event);
+                                queues.push(
+                                    index,
+                                    Message::data(Some(name.clone()), event).with_id(sequence),
+                                );
+                            }
+                        }
+                    }
+                })
+            })
+            .collect(),
+    );
+
+    async_stream::stream! {
+        let _producers = producers;
+        yield Message::control("handshake", settings.handshake());
+
+        loop {
+            yield queues.pop().await;
+        }
+    }
+}
@@ -0,0 +1,481 @@
+mod auth;
+mod calendar;
+mod channels;
+mod chaos;
+pub mod cli;
+mod compression;
+mod condition;
+mod config;
+mod connections;
+mod crud;
+mod custom;
+mod directives;
+mod error;
+mod extract;
+mod feeds;
+mod finite;
+mod fixture;
+mod geo;
+mod golden;
+mod handlers;
+mod health;
+mod lanes;
+mod lint;
+mod loadtest;
+mod logging;
+mod maintenance;
+mod network;
+mod oversize;
+mod packs;
+mod pattern;
+mod payload;
+mod pii;
+mod plugins;
+mod pools;
+mod presets;
+mod projection;
+mod protobuf;
+mod quotas;
+mod recording;
+mod replay;
+mod resume;
+mod scenario;
+mod schedule;
+mod schema;
+mod script;
+mod server;
+mod shape;
+mod spec;
+mod state;
+mod stats;
+mod stream;
+mod template;
+mod trace;
+mod transport;
+mod websocket;
+
+pub use crate::config::{Config, Override};
+pub use crate::server::signal;
+pub use crate::shape::ShapeTemplate;
+pub use crate::stream::EventGenerator;
+
+use crate::config::CorsConfig;
+use crate::custom::CustomSubstitutions;
+use crate::error::AppError;
+use crate::lint::{lint_shape_source, LintReport};
+use crate::maintenance::Maintenance;
+use crate::plugins::start_plugins;
+use crate::pools::Pools;
+use crate::presets::Presets;
+use crate::recording::Recordings;
+use crate::scenario::Scenarios;
+use crate::schedule::Scheduler;
+use crate::server::Shutdown;
+use crate::state::AppState;
+use crate::template::substitution_names;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method};
+use axum::response::{Html, Response};
+use axum::routing::{delete, get, post};
+use axum::{middleware, Json, Router};
+use futures::future::join_all;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tower::{service_fn, ServiceExt};
+use tower_http::cors::{self, AllowOrigin, CorsLayer};
+use tracing::{info, warn};
+
+async fn get_available_substitutions(
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut names = substitution_names(state.config.packs)
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    names.extend(state.custom.names());
+    Ok(Json(json!(names)))
+}
+
+// a page for building shapes over /substitutions and /preview and watching the stream they make
+async fn ui() -> Html<&'static str> {
+    Html(include_str!("ui.html"))
+}
+
+#[derive(Deserialize)]
+struct ValidateQuery {
+    // [templates] strict unless given
+    strict: Option<bool>,
+}
+
+async fn validate_shape(
+    State(state): State<AppState>,
+    Query(query): Query<ValidateQuery>,
+    shape: String,
+) -> Json<LintReport> {
+    Json(lint_shape_source(
+        &shape,
+        state.config.packs,
+        &state.custom,
+        query.strict.unwrap_or(state.config.templates.strict),
+    ))
+}
+
+// the router as it is, and behind CORS as configured
+fn with_cors(app: Router, config: &CorsConfig) -> (Router, Router) {
+    let cors_layer = CorsLayer::new()
+        .allow_methods(config.methods.clone())
+        .allow_headers(config.headers.clone())
+        .expose_headers(config.expose_headers.clone())
+        .allow_credentials(config.credentials);
+    // browsers refuse a wildcard origin on credentialed requests
+    let cors_layer = match (config.origins.is_empty(), config.credentials) {
+        (true, false) => cors_layer.allow_origin(cors::Any),
+        (true, true) => cors_layer.allow_origin(AllowOrigin::mirror_request()),
+        (false, _) => cors_layer.allow_origin(config.origins.clone()),
+    };
+    let cors_layer = match config.max_age_secs {
+        Some(secs) => cors_layer.max_age(Duration::from_secs(secs)),
+        None => cors_layer,
+    };
+    (app.clone(), app.layer(cors_layer))
+}
+
+// [server] headers, on responses that haven't set them already
+async fn static_headers(State(headers): State<HeaderMap>, mut response: Response) -> Response {
+    for (name, value) in &headers {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name, value.clone());
+        }
+    }
+    response
+}
+
+// /orders/:id/events matches /orders/7/events
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let (mut pattern, mut path) = (pattern.split('/'), path.split('/'));
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment))
+                if expected == segment || (expected.starts_with(':') && !segment.is_empty()) => {}
+            _ => return false,
+        }
+    }
+}
+
+fn state(config: Config, shutdown: Arc<Shutdown>) -> Result<AppState, String> {
+    let presets = Presets::load(&config)?;
+    let plugins = start_plugins(&config.plugins)?;
+    let custom = CustomSubstitutions::from_config(&config.substitutions, &plugins)?;
+    let scenarios = Scenarios::from_config(
+        &config.scenarios,
+        config.packs,
+        &custom,
+        config.templates.strict,
+    )?;
+    let recordings = Recordings::load(config.recordings.dir.as_deref())?;
+    let custom = Arc::new(custom);
+    let connection_slots = config
+        .server
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let connections = Arc::default();
+    Ok(AppState {
+        pools: Arc::new(Pools::from_config(&config, &custom)),
+        scheduler: Scheduler::spawn(Arc::clone(&connections), &config),
+        maintenance: Maintenance::spawn(&config.maintenance)?,
+        config: Arc::new(config),
+        connections,
+        feeds: Arc::default(),
+        quotas: Arc::default(),
+        shutdown,
+        presets: Arc::new(presets),
+        replays: Arc::default(),
+        custom,
+        scenarios: Arc::new(scenarios),
+        recordings: Arc::new(recordings),
+        channels: Arc::default(),
+        connection_slots,
+        started: Instant::now(),
+    })
+}
+
+fn app(state: AppState) -> Router {
+    let compression = state.config.server.compression;
+    let app = Router::new()
+        .route(
+            "/",
+            get(handlers::sse)
+                .head(handlers::sse_head)
+                .post(handlers::sse_post)
+                .options(handlers::sse_options),
+        )
+        .route(
+            "/streams/:name",
+            get(handlers::named_sse)
+                .head(handlers::named_sse_head)
+                .post(handlers::named_sse_post)
+                .options(handlers::named_sse_options),
+        )
+        .route("/channels", get(handlers::list_channels))
+        .route("/channel/:name", get(handlers::channel))
+        .route("/channel/:name/publish", post(handlers::publish))
+        .route("/ws", get(handlers::ws))
+        .route("/connections", get(handlers::list_connections))
+        .route("/connections/:id", delete(handlers::close_connection))
+        .route("/connections/:id/redact", post(handlers::redact))
+        .route("/connections/:id/state", get(handlers::crud_state))
+        .route("/connections/:id/pause", post(handlers::pause_connection))
+        .route("/connections/:id/resume", post(handlers::resume_connection))
+        .route("/connections/:id/step", post(handlers::step_connection))
+        .route(
+            "/connections/:id/set-rate",
+            post(handlers::set_connection_rate),
+        )
+        .route("/pools", get(handlers::list_pools))
+        .route(
+            "/pools/:name",
+            get(handlers::get_pool)
+                .post(handlers::extend_pool)
+                .delete(handlers::delete_pool),
+        )
+        .route(
+            "/schedule",
+            get(handlers::list_schedule).post(handlers::schedule_event),
+        )
+        .route("/schedule/calendar", post(handlers::schedule_calendar))
+        .route("/schedule/:id", delete(handlers::cancel_scheduled))
+        .route(
+            "/maintenance",
+            get(handlers::get_maintenance)
+                .post(handlers::start_maintenance)
+                .delete(handlers::end_maintenance),
+        )
+        .route(
+            "/shapes",
+            get(handlers::list_presets).post(handlers::create_preset),
+        )
+        .route("/shapes/from-schema", post(handlers::shape_from_schema))
+        .route(
+            "/shapes/:name",
+            get(handlers::get_preset)
+                .put(handlers::put_preset)
+                .delete(handlers::delete_preset),
+        )
+        .route(
+            "/substitutions",
+            get(get_available_substitutions).post(handlers::create_substitution),
+        )
+        .route(
+            "/substitutions/:name",
+            get(handlers::get_substitution).delete(handlers::delete_substitution),
+        )
+        .route(
+            "/recordings",
+            get(handlers::list_recordings).post(handlers::create_recording),
+        )
+        .route(
+            "/recordings/:name",
+            get(handlers::get_recording).delete(handlers::delete_recording),
+        )
+        .route(
+            "/scenarios",
+            get(handlers::list_scenarios).post(handlers::create_scenario),
+        )
+        .route(
+            "/scenarios/:name",
+            get(handlers::get_scenario).delete(handlers::delete_scenario),
+        )
+        .route("/validate", post(validate_shape))
+        .route("/preview", post(handlers::preview))
+        .route("/ui", get(ui))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .fallback(error::not_found)
+        .layer(middleware::map_response(error::problem_for_bare_errors))
+        .with_state(state.clone());
+    let app = with_cors(app, &state.config.cors);
+
+    // streams with a path of their own, each with its own CORS origins; paths from a [spec]
+    // document may have :param segments, matched after the exact paths
+    let (patterns, routes): (Vec<_>, Vec<_>) = state
+        .config
+        .streams
+        .iter()
+        .filter_map(|(name, stream)| {
+            let path = stream.path.as_ref()?;
+            let route = Router::new()
+                .route(path, handlers::stream_route(name))
+                .layer(middleware::map_response(error::problem_for_bare_errors))
+                .with_state(state.clone());
+            let cors = stream.cors.as_ref().unwrap_or(&state.config.cors);
+            Some((path.clone(), with_cors(route, cors)))
+        })
+        .partition(|(path, _)| path.contains(':'));
+    let routes: HashMap<String, (Router, Router)> = routes.into_iter().collect();
+
+    // CorsLayer answers every OPTIONS as a preflight, so plain OPTIONS skip it
+    let app = Router::new().fallback_service(service_fn(move |request: Request| {
+        let is_plain_options = request.method() == Method::OPTIONS
+            && !request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        let path = request.uri().path();
+        let (plain, cors_app) = routes
+            .get(path)
+            .or_else(|| {
+                patterns
+                    .iter()
+                    .find(|(pattern, _)| matches_pattern(pattern, path))
+                    .map(|(_, routers)| routers)
+            })
+            .unwrap_or(&app);
+        let router = if is_plain_options {
+            plain.clone()
+        } else {
+            cors_app.clone()
+        };
+        router.oneshot(request)
+    }));
+    // inside the logging layer, so logged byte counts are what went over the wire
+    let app = if compression {
+        app.layer(middleware::from_fn(compression::compress))
+    } else {
+        app
+    };
+    app.layer(middleware::map_response_with_state(
+        state.config.server.headers.clone(),
+        static_headers,
+    ))
+    .layer(middleware::from_fn(logging::connections))
+}
+
+// a running server, e.g. on an ephemeral port inside a #[tokio::test]; dropping it stops it
+pub struct MockSseServer {
+    addrs: Vec<SocketAddr>,
+    shutdown: Arc<Shutdown>,
+    drain_timeout: Duration,
+    serving: JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct MockSseServerBuilder {
+    config_file: Option<PathBuf>,
+    overrides: Vec<Override>,
+}
+
+impl MockSseServerBuilder {
+    // a config file as for --config; without one only the defaults and set() apply
+    pub fn config_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.config_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    // any config value, as for --set, e.g. set("defaults.interval_min", "1000")
+    pub fn set(mut self, path: &str, value: impl ToString) -> Self {
+        self.overrides.push((path.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn port(self, port: u16) -> Self {
+        self.set("server.port", port)
+    }
+
+    pub async fn start(self) -> Result<MockSseServer, String> {
+        let config = Config::embedded(self.config_file.as_deref(), &self.overrides)?;
+        MockSseServer::start(config).await
+    }
+}
+
+impl MockSseServer {
+    pub fn builder() -> MockSseServerBuilder {
+        MockSseServerBuilder::default()
+    }
+
+    // binds every [server] host and serves on them until shutdown() or drop
+    pub async fn start(config: Config) -> Result<Self, String> {
+        logging::set_level(config.server.log_level);
+        let listeners = server::listeners(&config.server)
+            .await
+            .map_err(|e| format!("failed to bind: {e}"))?;
+        let addrs = listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect::<Vec<_>>();
+        for address in &addrs {
+            info!("listening on {address}");
+        }
+        let shutdown = Arc::new(Shutdown::new(Duration::from_millis(config.server.drain_ms)));
+        let drain_timeout = Duration::from_millis(config.server.drain_timeout_ms);
+        let app = app(state(config, Arc::clone(&shutdown))?);
+
+        let serving = tokio::spawn({
+            let shutdown = Arc::clone(&shutdown);
+            async move {
+                join_all(listeners.into_iter().map(|listener| {
+                    let shutdown = Arc::clone(&shutdown);
+                    axum::serve(
+                        listener,
+                        app.clone()
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .with_graceful_shutdown(async move { shutdown.started().await })
+                    .into_future()
+                }))
+                .await
+                .into_iter()
+                .for_each(Result::unwrap);
+            }
+        });
+        Ok(MockSseServer {
+            addrs,
+            shutdown,
+            drain_timeout,
+            serving,
+        })
+    }
+
+    // the first bound address, with the port the OS picked when it was 0
+    pub fn addr(&self) -> SocketAddr {
+        self.addrs[0]
+    }
+
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr())
+    }
+
+    // stops accepting and drains live streams with a server_shutdown event, returning once
+    // they are closed or [server] drain_timeout_ms has passed
+    pub async fn shutdown(mut self) {
+        info!("shutting down, draining live streams");
+        self.shutdown.begin();
+        if tokio::time::timeout(self.drain_timeout, &mut self.serving)
+            .await
+            .is_err()
+        {
+            warn!(
+                "connections still open after {}ms, exiting",
+                self.drain_timeout.as_millis()
+            );
+        }
+    }
+}
+
+impl Drop for MockSseServer {
+    fn drop(&mut self) {
+        self.serving.abort();
+    }
+}
@@ -0,0 +1,416 @@
+use crate::config::Packs;
+use crate::custom::CustomSubstitutions;
+use crate::directives::{is_stream_directive, StreamDirectives, EVENTS, MIGRATION, SAGA};
+use crate::template::{
+    is_presence_directive, modifiers, placeholders, substitution, If, OneOf, Repeat,
+    INJECTION_SUBSTITUTIONS, PARAMETERIZED,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub path: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, code: &'static str, path: &str, message: String) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            path: path.to_string(),
+            message,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LintReport {
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LintReport {
+    pub fn new(diagnostics: Vec<Diagnostic>) -> Self {
+        LintReport {
+            valid: !diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == Severity::Error),
+            diagnostics,
+        }
+    }
+}
+
+pub fn lint_shape_source(
+    source: &str,
+    packs: Packs,
+    custom: &CustomSubstitutions,
+    strict: bool,
+) -> LintReport {
+    let diagnostics = match serde_json::from_str::<Value>(source) {
+        Ok(shape) => lint_shape(&shape, packs, custom, strict),
+        Err(error) => vec![Diagnostic {
+            line: Some(error.line()),
+            column: Some(error.column()),
+            ..Diagnostic::new(Severity::Error, "invalid_json", "$", error.to_string())
+        }],
+    };
+    LintReport::new(diagnostics)
+}
+
+// strict, text that looks like a placeholder but isn't one is an error rather than sent as written
+pub fn lint_shape(
+    shape: &Value,
+    packs: Packs,
+    custom: &CustomSubstitutions,
+    strict: bool,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    match shape {
+        Value::Object(object) => {
+            if let Err(message) = StreamDirectives::parse(object) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    "invalid_directive",
+                    "$",
+                    message,
+                ));
+            }
+            lint_object(object, "$", packs, custom, &mut diagnostics)
+        }
+        other => diagnostics.push(Diagnostic::new(
+            Severity::Error,
+            "type_mismatch",
+            "$",
+            format!("shape must be a JSON object, found {}", type_name(other)),
+        )),
+    }
+
+    if strict {
+        for diagnostic in &mut diagnostics {
+            if matches!(
+                diagnostic.code,
+                "unknown_placeholder" | "unterminated_placeholder"
+            ) {
+                diagnostic.severity = Severity::Error;
+            }
+        }
+    }
+    diagnostics
+}
+
+fn lint_object(
+    object: &Map<String, Value>,
+    path: &str,
+    packs: Packs,
+    custom: &CustomSubstitutions,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (key, value) in object {
+        let field_path = child_path(path, key);
+
+        if path == "$" && is_stream_directive(key) {
+            if let (MIGRATION, Some(Value::Object(to))) = (key.as_str(), value.get("to")) {
+                lint_object(
+                    to,
+                    &child_path(&field_path, "to"),
+                    packs,
+                    custom,
+                    diagnostics,
+                );
+            }
+            if let (EVENTS, Value::Array(events)) = (key.as_str(), value) {
+                for (index, event) in events.iter().enumerate() {
+                    if let Some(Value::Object(shape)) = event.get("shape") {
+                        let path = child_path(&format!("{field_path}[{index}]"), "shape");
+                        lint_object(shape, &path, packs, custom, diagnostics);
+                    }
+                }
+            }
+            if key == SAGA {
+                if let Some(Value::Object(context)) = value.get("context") {
+                    let path = child_path(&field_path, "context");
+                    lint_object(context, &path, packs, custom, diagnostics);
+                }
+                if let Some(Value::Array(steps)) = value.get("steps") {
+                    for (index, step) in steps.iter().enumerate() {
+                        if let Some(Value::Object(shape)) = step.get("shape") {
+                            let path = child_path(&format!("{field_path}.steps[{index}]"), "shape");
+                            lint_object(shape, &path, packs, custom, diagnostics);
+                        }
+                    }
+                }
+            }
+            continue;
+        }
+
+        if is_presence_directive(key) {
+            lint_presence(object, key, value, &field_path, diagnostics);
+            continue;
+        }
+
+        if key.starts_with('$') {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                "unsupported_directive",
+                &field_path,
+                format!("{key} is not a recognized directive and will be emitted verbatim"),
+            ));
+        }
+
+        lint_value(value, &field_path, true, packs, custom, diagnostics);
+    }
+}
+
+fn lint_value(
+    value: &Value,
+    path: &str,
+    filled: bool,
+    packs: Packs,
+    custom: &CustomSubstitutions,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    match value {
+        Value::Object(object) if filled => match Repeat::parse(object) {
+            Some(Ok(repeat)) => lint_value(
+                repeat.shape,
+                &format!("{path}[*]"),
+                true,
+                packs,
+                custom,
+                diagnostics,
+            ),
+            Some(Err(message)) => diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                "invalid_directive",
+                path,
+                message,
+            )),
+            None => match If::parse(object) {
+                Some(Ok(conditional)) => {
+                    for branch in [Some(conditional.then), conditional.otherwise]
+                        .into_iter()
+                        .flatten()
+                    {
+                        lint_value(branch, path, true, packs, custom, diagnostics);
+                    }
+                }
+                Some(Err(message)) => diagnostics.push(Diagnostic::new(
+                    Severity::Error,
+                    "invalid_directive",
+                    path,
+                    message,
+                )),
+                None => lint_object(object, path, packs, custom, diagnostics),
+            },
+        },
+        Value::String(subject_string) => {
+            if filled {
+                match modifiers(subject_string) {
+                    Some(Ok((source, _))) => lint_string(&source, path, packs, custom, diagnostics),
+                    Some(Err(message)) => diagnostics.push(Diagnostic::new(
+                        Severity::Error,
+                        "malformed_modifier",
+                        path,
+                        message,
+                    )),
+                    None => lint_string(subject_string, path, packs, custom, diagnostics),
+                }
+            } else if subject_string.contains('{') {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    "not_substituted",
+                    path,
+                    "placeholders inside arrays are emitted verbatim".to_string(),
+                ));
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                lint_value(
+                    item,
+                    &format!("{path}[{index}]"),
+                    false,
+                    packs,
+                    custom,
+                    diagnostics,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+// $optional and $nullable take a probability, or an object of one per field of their object
+fn lint_presence(
+    object: &Map<String, Value>,
+    directive: &str,
+    value: &Value,
+    path: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let is_probability = |value: &Value| value.as_f64().is_some_and(|p| (0.0..=1.0).contains(&p));
+    match value {
+        Value::Object(fields) => {
+            for (field, probability) in fields {
+                let field_path = child_path(path, field);
+                if !is_probability(probability) {
+                    diagnostics.push(Diagnostic::new(
+                        Severity::Error,
+                        "invalid_directive",
+                        &field_path,
+                        format!("{directive} probabilities must be between 0 and 1"),
+                    ));
+                } else if !object.contains_key(field) {
+                    diagnostics.push(Diagnostic::new(
+                        Severity::Warning,
+                        "unknown_field",
+                        &field_path,
+                        format!("{directive} names {field}, which the object doesn't have"),
+                    ));
+                }
+            }
+        }
+        value if is_probability(value) => {}
+        _ => diagnostics.push(Diagnostic::new(
+            Severity::Error,
+            "invalid_directive",
+            path,
+            format!(
+                "{directive} takes a probability between 0 and 1, or an object of them by field"
+            ),
+        )),
+    }
+}
+
+fn lint_string(
+    subject_string: &str,
+    path: &str,
+    packs: Packs,
+    custom: &CustomSubstitutions,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let (placeholders, unclosed) = placeholders(subject_string);
+    if unclosed > 0 {
+        diagnostics.push(Diagnostic::new(
+            Severity::Warning,
+            "unterminated_placeholder",
+            path,
+            "'{' is never closed and is sent as written, escape it as \\{ if that's intended"
+                .to_string(),
+        ));
+    }
+    for placeholder in &placeholders {
+        if let Some(diagnostic) = lint_placeholder(placeholder, path, packs, custom) {
+            diagnostics.push(diagnostic);
+        }
+    }
+}
+
+fn lint_placeholder(
+    placeholder: &str,
+    path: &str,
+    packs: Packs,
+    custom: &CustomSubstitutions,
+) -> Option<Diagnostic> {
+    if let Some((name, argument)) = placeholder.split_once(':') {
+        if PARAMETERIZED.contains(&name) {
+            if argument.is_empty() {
+                return Some(Diagnostic::new(
+                    Severity::Error,
+                    "malformed_arguments",
+                    path,
+                    format!("{{{name}:...}} needs an argument"),
+                ));
+            }
+            if name == "oneof" {
+                return OneOf::parse(argument).err().map(|error| {
+                    Diagnostic::new(
+                        Severity::Error,
+                        "malformed_arguments",
+                        path,
+                        format!("{{{placeholder}}}: {{{name}}} {error}"),
+                    )
+                });
+            }
+            return None;
+        }
+        if custom.get(name).is_some() {
+            return Some(Diagnostic::new(
+                Severity::Error,
+                "malformed_arguments",
+                path,
+                format!("{{{placeholder}}}: {{{name}}} takes no arguments"),
+            ));
+        }
+        if let Some(substitution) = substitution(name, packs) {
+            return substitution
+                .parse_argument(Some(argument))
+                .err()
+                .map(|error| {
+                    Diagnostic::new(
+                        Severity::Error,
+                        "malformed_arguments",
+                        path,
+                        format!("{{{placeholder}}}: {{{name}}} {error}"),
+                    )
+                });
+        }
+    }
+
+    if substitution(placeholder, packs).is_some() || custom.get(placeholder).is_some() {
+        return None;
+    }
+
+    if INJECTION_SUBSTITUTIONS.contains_key(placeholder) {
+        return Some(Diagnostic::new(
+            Severity::Error,
+            "pack_disabled",
+            path,
+            format!("{{{placeholder}}} is in the injection pack, enable it with [packs] injection = true"),
+        ));
+    }
+
+    Some(Diagnostic::new(
+        Severity::Warning,
+        "unknown_placeholder",
+        path,
+        format!("{{{placeholder}}} is not a known substitution and is sent as written"),
+    ))
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{path}.{key}")
+    } else {
+        format!("{path}[{}]", Value::String(key.to_string()))
+    }
+}
+
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
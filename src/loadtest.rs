@@ -0,0 +1,148 @@
+use crate::recording::Upstream;
+use crate::server::signal;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{interval, sleep, Instant};
+
+// how often the running totals are printed
+const PROGRESS_EVERY: Duration = Duration::from_secs(1);
+
+pub struct LoadTest {
+    pub url: String,
+    pub connections: usize,
+    // until interrupted when unset
+    pub duration: Option<Duration>,
+    pub reconnect_delay: Duration,
+}
+
+#[derive(Default)]
+struct Totals {
+    open: AtomicUsize,
+    events: AtomicU64,
+    reconnects: AtomicU64,
+    failed_connects: AtomicU64,
+    // ms between consecutive events on one connection, and from connecting to the first event
+    gaps: Mutex<Vec<f64>>,
+    first_events: Mutex<Vec<f64>>,
+}
+
+// opens the connections, prints totals every second, then a summary at the end
+pub async fn run(test: LoadTest) -> Result<(), String> {
+    let upstream = Arc::new(Upstream::parse(&test.url)?);
+    let totals = Arc::new(Totals::default());
+    let started = Instant::now();
+
+    let mut connections = JoinSet::new();
+    for _ in 0..test.connections {
+        connections.spawn(connection(
+            Arc::clone(&upstream),
+            Arc::clone(&totals),
+            test.reconnect_delay,
+        ));
+    }
+
+    let finished = async {
+        match test.duration {
+            Some(duration) => sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    let interrupted = signal();
+    tokio::pin!(finished, interrupted);
+    let mut progress = interval(PROGRESS_EVERY);
+    progress.tick().await;
+    loop {
+        tokio::select! {
+            () = &mut finished => break,
+            () = &mut interrupted => break,
+            _ = progress.tick() => eprintln!(
+                "{:.0}s: {} open, {} events, {} reconnects",
+                started.elapsed().as_secs_f64(),
+                totals.open.load(Ordering::Relaxed),
+                totals.events.load(Ordering::Relaxed),
+                totals.reconnects.load(Ordering::Relaxed),
+            ),
+        }
+    }
+    connections.shutdown().await;
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let events = totals.events.load(Ordering::Relaxed);
+    println!(
+        "{} with {} connections for {elapsed:.1}s",
+        test.url, test.connections
+    );
+    println!("events: {events} ({:.1}/s)", events as f64 / elapsed);
+    println!(
+        "between events: {}",
+        summary(&mut totals.gaps.lock().unwrap())
+    );
+    println!(
+        "to first event: {}",
+        summary(&mut totals.first_events.lock().unwrap())
+    );
+    println!(
+        "reconnects: {}, failed connects: {}",
+        totals.reconnects.load(Ordering::Relaxed),
+        totals.failed_connects.load(Ordering::Relaxed),
+    );
+    Ok(())
+}
+
+// one client, reconnecting with the last event id it saw whenever the stream ends or fails
+async fn connection(upstream: Arc<Upstream>, totals: Arc<Totals>, reconnect_delay: Duration) {
+    let mut last_event_id = None;
+    let mut first = true;
+    loop {
+        if !first {
+            totals.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+        first = false;
+
+        let connected = Instant::now();
+        match upstream.connect(last_event_id.as_deref()).await {
+            Ok(mut feed) => {
+                totals.open.fetch_add(1, Ordering::Relaxed);
+                let mut previous: Option<Instant> = None;
+                while let Ok(Some(event)) = feed.next_event().await {
+                    let now = Instant::now();
+                    let (since, samples) = match previous {
+                        Some(previous) => (previous, &totals.gaps),
+                        None => (connected, &totals.first_events),
+                    };
+                    samples
+                        .lock()
+                        .unwrap()
+                        .push(now.duration_since(since).as_secs_f64() * 1000.0);
+                    previous = Some(now);
+                    totals.events.fetch_add(1, Ordering::Relaxed);
+                    if event.id.is_some() {
+                        last_event_id = event.id;
+                    }
+                }
+                totals.open.fetch_sub(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                totals.failed_connects.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        sleep(reconnect_delay).await;
+    }
+}
+
+fn summary(samples: &mut [f64]) -> String {
+    if samples.is_empty() {
+        return "no samples".to_string();
+    }
+    samples.sort_by(f64::total_cmp);
+    let at = |quantile: f64| samples[((samples.len() - 1) as f64 * quantile).round() as usize];
+    format!(
+        "p50 {:.1}ms, p95 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+        at(0.5),
+        at(0.95),
+        at(0.99),
+        samples[samples.len() - 1]
+    )
+}
@@ -0,0 +1,298 @@
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+use tokio::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{debug, info, Event, Level, Metadata, Span, Subscriber};
+
+// how much of each chunk a debug line shows
+const PREVIEW_CHARS: usize = 120;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, PartialOrd, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+// the [server] log_level, and the stderr subscriber the first time; an embedding
+// application that installed its own subscriber keeps it
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    let _ = tracing::subscriber::set_global_default(Stderr::default());
+}
+
+fn enabled(level: &Level) -> bool {
+    let level = match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        _ => LogLevel::Debug,
+    };
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// the fields of a span or event as ` name=value`, with the message kept apart
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            let _ = write!(self.rest, " {}={value:?}", field.name());
+        }
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    parent: Option<Id>,
+    references: usize,
+}
+
+thread_local! {
+    // the spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+// one line per event on stderr, prefixed with its spans as name{fields}:, at the [server]
+// log_level; tracing-subscriber isn't available offline, and this is all the server needs
+#[derive(Default)]
+struct Stderr {
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+impl Stderr {
+    fn current() -> Option<Id> {
+        ENTERED.with(|entered| entered.borrow().last().cloned())
+    }
+
+    // outermost first
+    fn context(&self, mut span: Option<Id>) -> String {
+        let spans = self.spans.lock().unwrap();
+        let mut context = Vec::new();
+        while let Some(data) = span.and_then(|id| spans.get(&id.into_u64())) {
+            context.push(format!("{}{{{}}}: ", data.name, data.fields.trim_start()));
+            span = data.parent.clone();
+        }
+        context.reverse();
+        context.concat()
+    }
+}
+
+impl Subscriber for Stderr {
+    // asked every time, so the level can change after a callsite was first seen
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    // only this crate's own events; axum and hyper emit theirs through tracing too
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME")) && enabled(metadata.level())
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        let parent = match attributes.parent() {
+            Some(parent) => Some(parent.clone()),
+            None if attributes.is_contextual() => Self::current(),
+            None => None,
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.spans.lock().unwrap().insert(
+            id,
+            SpanData {
+                name: attributes.metadata().name(),
+                fields: fields.rest,
+                parent,
+                references: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.fields.push_str(&fields.rest);
+        }
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let parent = match event.parent() {
+            Some(parent) => Some(parent.clone()),
+            None if event.is_contextual() => Self::current(),
+            None => None,
+        };
+        eprintln!("{}{}{}", self.context(parent), fields.message, fields.rest);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(at) = entered.iter().rposition(|id| id == span) {
+                entered.remove(at);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.references -= 1;
+        if data.references > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+// the stream a response carries and who it is for
+fn stream_span(client: &str, response: &Response) -> Option<Span> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    Some(tracing::info_span!(
+        "stream",
+        id = header("x-stream-id")?,
+        client,
+        shape = header("x-shape-hash").unwrap_or("-"),
+        interval_ms = header("x-stream-interval-ms").unwrap_or("-"),
+    ))
+}
+
+// logs the close, with what was sent, even when the client goes away mid-stream
+struct Closed {
+    span: Span,
+    opened: Instant,
+    chunks: u64,
+    bytes: usize,
+}
+
+impl Drop for Closed {
+    fn drop(&mut self) {
+        info!(
+            parent: &self.span,
+            chunks = self.chunks,
+            bytes = self.bytes,
+            "closed after {:.1}s",
+            self.opened.elapsed().as_secs_f64()
+        );
+    }
+}
+
+// one info line per request that opens a stream, with the client and stream settings, one per
+// close, and at debug level one for every chunk written and for every other request
+pub async fn connections(request: Request, next: Next) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(client)| *client);
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let response = next.run(request).await;
+
+    let client = client.map_or("unknown".to_string(), |client| client.ip().to_string());
+
+    let streaming = matches!(method, Method::GET | Method::POST)
+        && (response.status().is_success() || response.status() == StatusCode::SWITCHING_PROTOCOLS);
+    let Some(span) = stream_span(&client, &response).filter(|_| streaming) else {
+        debug!(client, "{method} {uri} {}", response.status().as_u16());
+        return response;
+    };
+    info!(parent: &span, "opened by {method} {uri}");
+    if span.is_disabled() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let mut data = body.into_data_stream();
+    let body = Body::from_stream(async_stream::stream! {
+        let mut closed = Closed { span, opened: Instant::now(), chunks: 0, bytes: 0 };
+        while let Some(chunk) = data.next().await {
+            if let Ok(bytes) = &chunk {
+                closed.chunks += 1;
+                closed.bytes += bytes.len();
+                if tracing::enabled!(Level::DEBUG) {
+                    let text = String::from_utf8_lossy(bytes);
+                    let preview = text.trim_end().replace('\n', " | ");
+                    let preview = preview.chars().take(PREVIEW_CHARS).collect::<String>();
+                    debug!(parent: &closed.span, bytes = bytes.len(), "sent {preview}");
+                }
+            }
+            yield chunk;
+        }
+    });
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Dispatch;
+
+    #[test]
+    fn spans_render_as_context_prefixes() {
+        set_level(LogLevel::Info);
+        let dispatch = Dispatch::new(Stderr::default());
+        tracing::dispatcher::with_default(&dispatch, || {
+            let span = tracing::info_span!("stream", id = "7", client = "127.0.0.1");
+            let stderr = dispatch.downcast_ref::<Stderr>().unwrap();
+            assert_eq!(stderr.context(span.id()), "stream{id=7 client=127.0.0.1}: ");
+            drop(span);
+            assert!(stderr.spans.lock().unwrap().is_empty());
+        });
+    }
+}
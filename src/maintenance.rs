@@ -0,0 +1,117 @@
+use crate::config::MaintenanceConfig;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+#[derive(Serialize, Clone)]
+pub struct Window {
+    #[serde(skip)]
+    id: u64,
+    pub starts_at: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub reject_new_connections: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// the maintenance window in effect, watched by every live stream so they pause together
+pub struct Maintenance {
+    active: watch::Sender<Option<Window>>,
+    next_id: AtomicU64,
+}
+
+impl Maintenance {
+    pub fn spawn(windows: &[MaintenanceConfig]) -> Result<Arc<Self>, String> {
+        let maintenance = Arc::new(Maintenance {
+            active: watch::Sender::new(None),
+            next_id: AtomicU64::new(0),
+        });
+        for (index, window) in windows.iter().enumerate() {
+            maintenance
+                .schedule(window)
+                .map_err(|e| format!("maintenance[{index}]: {e}"))?;
+        }
+        Ok(maintenance)
+    }
+
+    // starts the window at its `at`, or right away without one
+    pub fn schedule(self: &Arc<Self>, config: &MaintenanceConfig) -> Result<Window, String> {
+        let starts_at = config.at.unwrap_or_else(Utc::now);
+        let until = i64::try_from(config.duration_ms)
+            .ok()
+            .and_then(|ms| starts_at.checked_add_signed(Duration::milliseconds(ms)))
+            .ok_or_else(|| format!("{starts_at} plus duration_ms is past the end of time"))?;
+        let window = Window {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            starts_at,
+            until,
+            reject_new_connections: config.reject_new_connections,
+            message: config.message.clone(),
+        };
+
+        let maintenance = self.clone();
+        let scheduled = window.clone();
+        tokio::spawn(async move {
+            if let Ok(wait) = (scheduled.starts_at - Utc::now()).to_std() {
+                sleep(wait).await;
+            }
+            let id = scheduled.id;
+            let duration = (scheduled.until - scheduled.starts_at)
+                .to_std()
+                .unwrap_or_default();
+            maintenance.active.send_replace(Some(scheduled));
+
+            sleep(duration).await;
+            // a later window may have replaced this one since
+            maintenance.active.send_if_modified(|active| match active {
+                Some(window) if window.id == id => {
+                    *active = None;
+                    true
+                }
+                _ => false,
+            });
+        });
+
+        Ok(window)
+    }
+
+    pub fn end(&self) -> bool {
+        self.active.send_replace(None).is_some()
+    }
+
+    pub fn active(&self) -> Option<Window> {
+        self.active.borrow().clone()
+    }
+
+    pub fn watch(&self) -> watch::Receiver<Option<Window>> {
+        self.active.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn window(duration_ms: u64) -> MaintenanceConfig {
+        serde_json::from_value(json!({ "duration_ms": duration_ms })).unwrap()
+    }
+
+    #[test]
+    fn rejects_durations_over_a_year() {
+        assert!(window(9_000_000_000_000_000_000).load().is_err());
+        assert!(window(1000).load().is_ok());
+    }
+
+    #[tokio::test]
+    async fn schedule_fails_past_the_end_of_time() {
+        let maintenance = Maintenance::spawn(&[]).unwrap();
+        let mut late = window(1000);
+        late.at = Some(DateTime::<Utc>::MAX_UTC);
+        assert!(maintenance.schedule(&late).is_err());
+        assert!(maintenance.schedule(&window(1000)).is_ok());
+    }
+}
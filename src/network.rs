@@ -0,0 +1,110 @@
+use crate::stream::SSEQuery;
+use axum::body::{Body, Bytes};
+use axum::response::Response;
+use futures::StreamExt;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::time::Duration;
+use tokio::time::{sleep, sleep_until, Instant};
+
+// the spike chance when only network.spike_ms is given
+const DEFAULT_SPIKE_PROB: f64 = 0.1;
+// pieces a trickled chunk is cut into
+const TRICKLE_PIECES: usize = 10;
+// a throughput cap sends at most this much of a second's allowance at once
+const RATE_SLICES: u64 = 10;
+
+// a slow or lossy link between the server and the client, applied to the response's bytes
+#[derive(Clone, Copy)]
+pub struct Network {
+    // spread each chunk's bytes over this long
+    pub trickle: Option<Duration>,
+    // hold a chunk back for a delay drawn from an exponential distribution with this mean
+    pub spike_prob: f64,
+    pub spike_mean: Duration,
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl Network {
+    // None unless some network.* parameter is set
+    pub fn from_query(query: &SSEQuery) -> Option<Self> {
+        let trickle = query.network_trickle_ms.map(Duration::from_millis);
+        let spike_mean = query.network_spike_ms.map(Duration::from_millis);
+        let spike_prob = query
+            .network_spike_prob
+            .or(spike_mean.map(|_| DEFAULT_SPIKE_PROB));
+        if trickle.is_none() && spike_prob.is_none() && query.network_bytes_per_sec.is_none() {
+            return None;
+        }
+        Some(Network {
+            trickle,
+            spike_prob: spike_prob.unwrap_or_default(),
+            spike_mean: spike_mean.unwrap_or_default(),
+            bytes_per_sec: query.network_bytes_per_sec,
+        })
+    }
+
+    fn spike(&self, rng: &mut impl Rng) -> Option<Duration> {
+        if !rng.gen_bool(self.spike_prob) {
+            return None;
+        }
+        // inverse transform sampling, 1 - u so ln never sees 0
+        let draw = -(1.0 - rng.gen::<f64>()).ln();
+        Some(self.spike_mean.mul_f64(draw))
+    }
+
+    // the largest piece that keeps to both the trickle and the cap
+    fn piece_len(&self, chunk_len: usize) -> usize {
+        let trickled = match self.trickle {
+            Some(_) => chunk_len.div_ceil(TRICKLE_PIECES),
+            None => chunk_len,
+        };
+        let capped = self
+            .bytes_per_sec
+            .map_or(chunk_len, |rate| (rate / RATE_SLICES).max(1) as usize);
+        trickled.min(capped).max(1)
+    }
+}
+
+// wraps the body so that it reaches the client at the pace of the simulated network
+pub fn throttle(response: Response, network: Option<Network>) -> Response {
+    let Some(network) = network else {
+        return response;
+    };
+    let (parts, body) = response.into_parts();
+    let mut data = body.into_data_stream();
+    let body = Body::from_stream(async_stream::stream! {
+        let mut rng = ChaCha8Rng::from_entropy();
+        // when the capped link is next free to send
+        let mut ready = Instant::now();
+        while let Some(chunk) = data.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            if let Some(spike) = network.spike(&mut rng) {
+                sleep(spike).await;
+            }
+            let pieces = chunk.chunks(network.piece_len(chunk.len())).collect::<Vec<_>>();
+            let gap = match network.trickle {
+                Some(trickle) if pieces.len() > 1 => trickle / (pieces.len() - 1) as u32,
+                _ => Duration::ZERO,
+            };
+            for (index, piece) in pieces.into_iter().enumerate() {
+                if index > 0 {
+                    sleep(gap).await;
+                }
+                if let Some(rate) = network.bytes_per_sec {
+                    sleep_until(ready).await;
+                    let sending = Duration::from_secs_f64(piece.len() as f64 / rate as f64);
+                    ready = ready.max(Instant::now()) + sending;
+                }
+                yield Ok::<_, axum::Error>(Bytes::copy_from_slice(piece));
+            }
+        }
+    });
+    Response::from_parts(parts, body)
+}
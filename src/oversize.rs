@@ -0,0 +1,137 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// what happens to an event whose serialized data exceeds max_event_bytes
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizeStrategy {
+    // shorten the longest strings until the event fits
+    #[default]
+    Truncate,
+    // remove the largest top-level fields until the event fits
+    DropFields,
+    // replace the event with an `oversize` error event
+    Error,
+}
+
+#[derive(Clone, Copy)]
+pub struct SizeLimit {
+    pub max_bytes: usize,
+    pub strategy: OversizeStrategy,
+}
+
+pub enum Enforced {
+    Fits(Value),
+    // the event could not be brought under the limit, carries the error event's data
+    Oversize(Value),
+}
+
+impl SizeLimit {
+    pub fn enforce(&self, sequence: u64, mut event: Value) -> Enforced {
+        let size = serialized_len(&event);
+        if size <= self.max_bytes {
+            return Enforced::Fits(event);
+        }
+
+        let fits = match self.strategy {
+            OversizeStrategy::Truncate => truncate_strings(&mut event, self.max_bytes),
+            OversizeStrategy::DropFields => drop_fields(&mut event, self.max_bytes),
+            OversizeStrategy::Error => false,
+        };
+
+        if fits {
+            Enforced::Fits(event)
+        } else {
+            Enforced::Oversize(json!({
+                "error": "oversize",
+                "sequence": sequence,
+                "size": size,
+                "max_bytes": self.max_bytes,
+            }))
+        }
+    }
+}
+
+fn serialized_len(event: &Value) -> usize {
+    event.to_string().len()
+}
+
+fn truncate_strings(event: &mut Value, max_bytes: usize) -> bool {
+    loop {
+        let size = serialized_len(event);
+        if size <= max_bytes {
+            return true;
+        }
+
+        let mut strings = Vec::new();
+        strings_mut(event, &mut strings);
+        let total = strings.iter().map(|string| string.len()).sum::<usize>();
+        if total == 0 {
+            return false;
+        }
+
+        // cap every string at the same length so short fields survive and long ones give way
+        let cap = water_level(&strings, total.saturating_sub(size - max_bytes));
+        for string in strings {
+            if string.len() > cap {
+                let mut keep = cap;
+                while !string.is_char_boundary(keep) {
+                    keep -= 1;
+                }
+                string.truncate(keep);
+            }
+        }
+    }
+}
+
+// the largest cap for which the capped string lengths sum to at most `budget`
+fn water_level(strings: &[&mut String], budget: usize) -> usize {
+    let mut lengths = strings
+        .iter()
+        .map(|string| string.len())
+        .collect::<Vec<_>>();
+    lengths.sort_unstable();
+
+    let mut remaining = budget;
+    for (index, length) in lengths.iter().enumerate() {
+        let uncapped = lengths.len() - index;
+        if length * uncapped > remaining {
+            return remaining / uncapped;
+        }
+        remaining -= length;
+    }
+
+    lengths.last().copied().unwrap_or_default()
+}
+
+fn strings_mut<'a>(value: &'a mut Value, strings: &mut Vec<&'a mut String>) {
+    match value {
+        Value::String(string) => strings.push(string),
+        Value::Array(items) => items.iter_mut().for_each(|item| strings_mut(item, strings)),
+        Value::Object(object) => object
+            .values_mut()
+            .for_each(|value| strings_mut(value, strings)),
+        _ => {}
+    }
+}
+
+fn drop_fields(event: &mut Value, max_bytes: usize) -> bool {
+    let Value::Object(object) = event else {
+        return false;
+    };
+
+    loop {
+        if serialized_len(&Value::Object(object.clone())) <= max_bytes {
+            return true;
+        }
+
+        let Some(largest) = object
+            .iter()
+            .max_by_key(|(_, value)| serialized_len(value))
+            .map(|(key, _)| key.clone())
+        else {
+            return false;
+        };
+        object.remove(&largest);
+    }
+}
@@ -0,0 +1,97 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+// strings that commonly break rendering, length counting and normalization
+const UNICODE_FRAGMENTS: &[&str] = &[
+    "e\u{301}",                                                      // e + combining acute
+    "Z\u{335}\u{321}\u{347}a\u{336}\u{31b}l\u{334}\u{324}g\u{337}o", // stacked combining marks
+    "\u{200f}\u{5e9}\u{5dc}\u{5d5}\u{5dd}",                          // RTL mark + Hebrew
+    "\u{202e}txt.exe",                                               // right-to-left override
+    "\u{627}\u{644}\u{633}\u{644}\u{627}\u{645}",                    // Arabic
+    "zero\u{200b}width",                                             // zero-width space
+    "\u{feff}bom",                                                   // byte order mark mid-string
+    "join\u{200d}er",                                                // stray zero-width joiner
+    "\u{d7ff}\u{e000}",                 // either side of the surrogate block
+    "\u{fffd}",                         // replacement character
+    "\u{10000}\u{10ffff}",              // first and last astral code points
+    "\u{1d573}\u{1d586}\u{1d591}",      // mathematical bold fraktur
+    "non\u{a0}breaking",                // no-break space
+    "\u{ff26}\u{ff55}\u{ff4c}\u{ff4c}", // fullwidth latin
+    "\u{1100}\u{1161}\u{11a8}",         // decomposed Hangul jamo
+    "\u{915}\u{94d}\u{937}",            // Devanagari conjunct
+    "\u{130}stanbul",                   // dotted capital I, changes length when lowercased
+    "stra\u{df}e",                      // sharp s, uppercases to two characters
+];
+
+const EMOJI_FRAGMENTS: &[&str] = &[
+    "\u{1f600}",                                                       // grinning face
+    "\u{1f44d}\u{1f3fd}",                                              // thumbs up + skin tone
+    "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}",    // family ZWJ sequence
+    "\u{1f3f3}\u{fe0f}\u{200d}\u{1f308}",                              // rainbow flag
+    "\u{1f1ef}\u{1f1f5}",                                              // regional indicator flag
+    "1\u{fe0f}\u{20e3}",                                               // keycap
+    "\u{2764}\u{fe0f}",                    // heart with variation selector
+    "\u{1f9d1}\u{1f3ff}\u{200d}\u{1f680}", // astronaut + skin tone
+    "\u{1f415}\u{200d}\u{1f9ba}",          // service dog
+    "\u{1f3f4}\u{e0067}\u{e0062}\u{e0073}\u{e0063}\u{e0074}\u{e007f}", // tag sequence flag
+];
+
+fn pick(fragments: &[&str], count: usize, rng: &mut impl Rng) -> Vec<String> {
+    (0..count)
+        .filter_map(|_| fragments.choose(rng))
+        .map(|fragment| fragment.to_string())
+        .collect()
+}
+
+pub fn unicode_stress(rng: &mut impl Rng) -> String {
+    let count = rng.gen_range(3..=6);
+    pick(UNICODE_FRAGMENTS, count, rng).join(" ")
+}
+
+pub fn emoji_heavy(rng: &mut impl Rng) -> String {
+    let count = rng.gen_range(4..=10);
+    pick(EMOJI_FRAGMENTS, count, rng).concat()
+}
+
+// opt-in via `[packs] injection = true`; payloads are inert strings meant to exercise escaping
+const XSS_PAYLOADS: &[&str] = &[
+    "<script>alert(1)</script>",
+    "\"><img src=x onerror=alert(1)>",
+    "<svg/onload=alert(document.domain)>",
+    "javascript:alert(document.cookie)",
+    "'-alert(1)-'",
+    "<iframe srcdoc=\"&lt;script&gt;alert(1)&lt;/script&gt;\"></iframe>",
+    "</textarea><script>alert(1)</script>",
+];
+
+const SQLI_PAYLOADS: &[&str] = &[
+    "' OR '1'='1",
+    "'; DROP TABLE users; --",
+    "1 UNION SELECT username, password FROM users",
+    "admin'--",
+    "\" OR \"\"=\"",
+    "1; WAITFOR DELAY '0:0:5'--",
+    "' AND 1=CONVERT(int, @@version)--",
+];
+
+const PATH_TRAVERSAL_PAYLOADS: &[&str] = &[
+    "../../../etc/passwd",
+    "..\\..\\..\\windows\\win.ini",
+    "%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+    "....//....//etc/passwd",
+    "/var/www/../../etc/shadow",
+    "..%c0%af..%c0%afetc/passwd",
+    "report.pdf%00.png",
+];
+
+pub fn xss(rng: &mut impl Rng) -> String {
+    pick(XSS_PAYLOADS, 1, rng).concat()
+}
+
+pub fn sqli(rng: &mut impl Rng) -> String {
+    pick(SQLI_PAYLOADS, 1, rng).concat()
+}
+
+pub fn path_traversal(rng: &mut impl Rng) -> String {
+    pick(PATH_TRAVERSAL_PAYLOADS, 1, rng).concat()
+}
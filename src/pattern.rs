@@ -0,0 +1,95 @@
+use crate::stats::Period;
+use crate::stream::SSEQuery;
+use rand::Rng;
+use serde::Deserialize;
+use std::time::Duration;
+
+const DEFAULT_RATE: f64 = 1.0;
+const DEFAULT_BURST_SIZE: u64 = 10;
+const DEFAULT_BURST_EVERY: Duration = Duration::from_secs(30);
+const DEFAULT_RAMP_EVENTS: u64 = 100;
+// keeps a poisson draw from an unlucky roll near zero finite
+const MAX_POISSON_DELAY: Duration = Duration::from_secs(3600);
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternKind {
+    #[default]
+    Uniform,
+    Poisson,
+    Burst,
+    Ramp,
+}
+
+// how the delay before each data event is picked
+#[derive(Clone, Copy, Default)]
+pub enum Pattern {
+    // anywhere between interval_min and interval_max
+    #[default]
+    Uniform,
+    // exponential gaps averaging rate events a second
+    Poisson {
+        rate: f64,
+    },
+    // size events back to back, then a pause of every
+    Burst {
+        size: u64,
+        every: Duration,
+    },
+    // from interval_max down to interval_min over the first events
+    Ramp {
+        events: u64,
+    },
+}
+
+impl Pattern {
+    pub fn from_query(query: &SSEQuery) -> Self {
+        match query.pattern.unwrap_or_default() {
+            PatternKind::Uniform => Pattern::Uniform,
+            PatternKind::Poisson => Pattern::Poisson {
+                rate: query.rate.unwrap_or(DEFAULT_RATE),
+            },
+            PatternKind::Burst => Pattern::Burst {
+                size: query.burst_size.unwrap_or(DEFAULT_BURST_SIZE),
+                every: query
+                    .burst_every
+                    .map_or(DEFAULT_BURST_EVERY, |Period(every)| every),
+            },
+            PatternKind::Ramp => Pattern::Ramp {
+                events: query.ramp_events.unwrap_or(DEFAULT_RAMP_EVENTS),
+            },
+        }
+    }
+
+    // the delay after event sequence, 0 being the wait for the first one; only uniform and
+    // poisson draw from the rng, so burst and ramp timings don't move the generated events
+    pub fn delay(
+        self,
+        rng: &mut impl Rng,
+        sequence: u64,
+        interval_min: u64,
+        interval_max: u64,
+    ) -> Duration {
+        match self {
+            Pattern::Uniform => Duration::from_millis(rng.gen_range(interval_min..=interval_max)),
+            Pattern::Poisson { rate } => {
+                let draw: f64 = rng.gen();
+                Duration::try_from_secs_f64(-(1.0 - draw).ln() / rate)
+                    .unwrap_or(MAX_POISSON_DELAY)
+                    .min(MAX_POISSON_DELAY)
+            }
+            Pattern::Burst { size, every } => {
+                if sequence > 0 && sequence.is_multiple_of(size) {
+                    every
+                } else {
+                    Duration::ZERO
+                }
+            }
+            Pattern::Ramp { events } => {
+                let progress = sequence.min(events) as f64 / events as f64;
+                let span = (interval_max - interval_min) as f64;
+                Duration::from_millis(interval_max - (span * progress).round() as u64)
+            }
+        }
+    }
+}
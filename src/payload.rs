@@ -0,0 +1,418 @@
+use crate::config::ProtobufConfig;
+use crate::error::AppError;
+use crate::protobuf::Descriptors;
+use crate::transport::Message;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+const ENCODING_ERROR_EVENT: &str = "encoding_error";
+
+// how an event's object is serialized into its data field; anything but json is sent base64
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    Msgpack,
+    Cbor,
+    Protobuf,
+}
+
+impl PayloadFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            PayloadFormat::Json => "json",
+            PayloadFormat::Msgpack => "msgpack",
+            PayloadFormat::Cbor => "cbor",
+            PayloadFormat::Protobuf => "protobuf",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum PayloadEncoding {
+    MessagePack,
+    Cbor,
+    // the [protobuf] descriptor set and the full name of the message events are encoded as
+    Protobuf(Arc<Descriptors>, String),
+}
+
+impl PayloadEncoding {
+    // None for json, which needs no re-encoding
+    pub fn resolve(
+        format: PayloadFormat,
+        message: Option<String>,
+        protobuf: &ProtobufConfig,
+    ) -> Result<Option<Self>, AppError> {
+        Ok(match format {
+            PayloadFormat::Json => None,
+            PayloadFormat::Msgpack => Some(PayloadEncoding::MessagePack),
+            PayloadFormat::Cbor => Some(PayloadEncoding::Cbor),
+            PayloadFormat::Protobuf => {
+                let descriptors = protobuf.descriptors.clone().ok_or_else(|| {
+                    AppError::bad_request(
+                        "payload_encoding=protobuf needs a [protobuf] descriptor set",
+                    )
+                })?;
+                let message = message.ok_or_else(|| {
+                    AppError::bad_request("payload_encoding=protobuf needs a proto_message")
+                })?;
+                if !descriptors.contains(&message) {
+                    return Err(AppError::bad_request(format!(
+                        "the [protobuf] descriptor set has no message {message}"
+                    )));
+                }
+                Some(PayloadEncoding::Protobuf(descriptors, message))
+            }
+        })
+    }
+
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        match self {
+            PayloadEncoding::MessagePack => msgpack(value, &mut out),
+            PayloadEncoding::Cbor => cbor(value, &mut out),
+            PayloadEncoding::Protobuf(descriptors, message) => {
+                out = descriptors.encode(message, value)?
+            }
+        }
+        Ok(out)
+    }
+}
+
+// replaces each event's data with its base64 encoding; control messages stay JSON so that
+// clients can still read the stream's own events
+pub fn with_payload_encoding(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    encoding: Option<PayloadEncoding>,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        let Some(encoding) = encoding else {
+            while let Some(message) = messages.next().await {
+                yield message;
+            }
+            return;
+        };
+
+        while let Some(mut message) = messages.next().await {
+            // garbled payloads go out as they are
+            if message.control || message.abort || message.raw.is_some() {
+                yield message;
+                continue;
+            }
+            match encoding.encode(&message.data) {
+                Ok(bytes) => {
+                    message.raw = Some(STANDARD.encode(bytes));
+                    yield message;
+                }
+                Err(error) => {
+                    let mut notice = Message::control(ENCODING_ERROR_EVENT, json!({ "error": error }));
+                    notice.id = message.id;
+                    yield notice;
+                }
+            }
+        }
+    }
+}
+
+fn msgpack(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(boolean) => out.push(if *boolean { 0xc3 } else { 0xc2 }),
+        Value::Number(number) => {
+            if let Some(unsigned) = number.as_u64() {
+                match unsigned {
+                    0..=0x7f => out.push(unsigned as u8),
+                    0x80..=0xff => out.extend([0xcc, unsigned as u8]),
+                    0x100..=0xffff => {
+                        out.push(0xcd);
+                        out.extend((unsigned as u16).to_be_bytes());
+                    }
+                    0x1_0000..=0xffff_ffff => {
+                        out.push(0xce);
+                        out.extend((unsigned as u32).to_be_bytes());
+                    }
+                    _ => {
+                        out.push(0xcf);
+                        out.extend(unsigned.to_be_bytes());
+                    }
+                }
+            } else if let Some(signed) = number.as_i64() {
+                match signed {
+                    -32..=-1 => out.push(signed as i8 as u8),
+                    -0x80..=-33 => out.extend([0xd0, signed as i8 as u8]),
+                    -0x8000..=-0x81 => {
+                        out.push(0xd1);
+                        out.extend((signed as i16).to_be_bytes());
+                    }
+                    -0x8000_0000..=-0x8001 => {
+                        out.push(0xd2);
+                        out.extend((signed as i32).to_be_bytes());
+                    }
+                    _ => {
+                        out.push(0xd3);
+                        out.extend(signed.to_be_bytes());
+                    }
+                }
+            } else {
+                out.push(0xcb);
+                out.extend(number.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(string) => {
+            let len = string.len();
+            match len {
+                0..=31 => out.push(0xa0 | len as u8),
+                32..=0xff => out.extend([0xd9, len as u8]),
+                0x100..=0xffff => {
+                    out.push(0xda);
+                    out.extend((len as u16).to_be_bytes());
+                }
+                _ => {
+                    out.push(0xdb);
+                    out.extend((len as u32).to_be_bytes());
+                }
+            }
+            out.extend(string.as_bytes());
+        }
+        Value::Array(items) => {
+            msgpack_collection(items.len(), 0x90, 0xdc, out);
+            for item in items {
+                msgpack(item, out);
+            }
+        }
+        Value::Object(object) => {
+            msgpack_collection(object.len(), 0x80, 0xde, out);
+            for (key, value) in object {
+                msgpack(&Value::from(key.as_str()), out);
+                msgpack(value, out);
+            }
+        }
+    }
+}
+
+// fix marks collections of under 16 entries, sized a 16-bit length and sized + 1 a 32-bit one
+fn msgpack_collection(len: usize, fix: u8, sized: u8, out: &mut Vec<u8>) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => {
+            out.push(sized);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(sized + 1);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn cbor(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(boolean) => out.push(if *boolean { 0xf5 } else { 0xf4 }),
+        Value::Number(number) => {
+            if let Some(unsigned) = number.as_u64() {
+                cbor_head(0, unsigned, out);
+            } else if let Some(signed) = number.as_i64() {
+                cbor_head(1, (-1 - signed) as u64, out);
+            } else {
+                out.push(0xfb);
+                out.extend(number.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(string) => {
+            cbor_head(3, string.len() as u64, out);
+            out.extend(string.as_bytes());
+        }
+        Value::Array(items) => {
+            cbor_head(4, items.len() as u64, out);
+            for item in items {
+                cbor(item, out);
+            }
+        }
+        Value::Object(object) => {
+            cbor_head(5, object.len() as u64, out);
+            for (key, value) in object {
+                cbor_head(3, key.len() as u64, out);
+                out.extend(key.as_bytes());
+                cbor(value, out);
+            }
+        }
+    }
+}
+
+fn cbor_head(major: u8, argument: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match argument {
+        0..=23 => out.push(major | argument as u8),
+        24..=0xff => out.extend([major | 24, argument as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((argument as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(argument.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn msgpack_hex(value: Value) -> String {
+        hex(&PayloadEncoding::MessagePack.encode(&value).unwrap())
+    }
+
+    fn cbor_hex(value: Value) -> String {
+        hex(&PayloadEncoding::Cbor.encode(&value).unwrap())
+    }
+
+    #[test]
+    fn msgpack_integers_take_the_smallest_format() {
+        let cases = [
+            (json!(0), "00"),
+            (json!(127), "7f"),
+            (json!(128), "cc80"),
+            (json!(255), "ccff"),
+            (json!(256), "cd0100"),
+            (json!(65535), "cdffff"),
+            (json!(65536), "ce00010000"),
+            (json!(4294967295u64), "ceffffffff"),
+            (json!(4294967296u64), "cf0000000100000000"),
+            (json!(u64::MAX), "cfffffffffffffffff"),
+            (json!(-1), "ff"),
+            (json!(-32), "e0"),
+            (json!(-33), "d0df"),
+            (json!(-128), "d080"),
+            (json!(-129), "d1ff7f"),
+            (json!(-32768), "d18000"),
+            (json!(-32769), "d2ffff7fff"),
+            (json!(-2147483648i64), "d280000000"),
+            (json!(-2147483649i64), "d3ffffffff7fffffff"),
+            (json!(i64::MIN), "d38000000000000000"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(msgpack_hex(value.clone()), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn msgpack_scalars_strings_and_collections() {
+        assert_eq!(msgpack_hex(json!(null)), "c0");
+        assert_eq!(msgpack_hex(json!(false)), "c2");
+        assert_eq!(msgpack_hex(json!(true)), "c3");
+        assert_eq!(msgpack_hex(json!(1.5)), "cb3ff8000000000000");
+        assert_eq!(msgpack_hex(json!(-0.1)), "cbbfb999999999999a");
+
+        assert_eq!(msgpack_hex(json!("")), "a0");
+        assert_eq!(msgpack_hex(json!("a")), "a161");
+        assert!(msgpack_hex(json!("x".repeat(31))).starts_with("bf78"));
+        assert!(msgpack_hex(json!("x".repeat(32))).starts_with("d92078"));
+        assert!(msgpack_hex(json!("x".repeat(255))).starts_with("d9ff78"));
+        assert!(msgpack_hex(json!("x".repeat(256))).starts_with("da010078"));
+        assert!(msgpack_hex(json!("x".repeat(65536))).starts_with("db0001000078"));
+        // lengths count UTF-8 bytes, not characters
+        assert_eq!(msgpack_hex(json!("é")), "a2c3a9");
+
+        assert_eq!(msgpack_hex(json!([])), "90");
+        assert_eq!(msgpack_hex(json!([1, 2, 3])), "93010203");
+        assert_eq!(
+            msgpack_hex(json!(vec![0; 15])),
+            format!("9f{}", "00".repeat(15))
+        );
+        assert_eq!(
+            msgpack_hex(json!(vec![0; 16])),
+            format!("dc0010{}", "00".repeat(16))
+        );
+        assert!(msgpack_hex(json!(vec![0; 65536])).starts_with("dd0001000000"));
+
+        assert_eq!(msgpack_hex(json!({})), "80");
+        assert_eq!(
+            msgpack_hex(json!({ "a": [1, { "b": null }], "c": "d" })),
+            "82a161920181a162c0a163a164"
+        );
+        let wide: serde_json::Map<String, Value> = (0..16)
+            .map(|key| (format!("{key:02}"), json!(key)))
+            .collect();
+        assert!(msgpack_hex(Value::Object(wide)).starts_with("de0010a23030"));
+    }
+
+    // the examples of RFC 8949 appendix A that have a JSON equivalent; floats are always sent as
+    // doubles rather than in their shortest form
+    #[test]
+    fn cbor_matches_the_rfc_8949_examples() {
+        let cases = [
+            (json!(0), "00"),
+            (json!(1), "01"),
+            (json!(10), "0a"),
+            (json!(23), "17"),
+            (json!(24), "1818"),
+            (json!(25), "1819"),
+            (json!(100), "1864"),
+            (json!(1000), "1903e8"),
+            (json!(1000000), "1a000f4240"),
+            (json!(1000000000000u64), "1b000000e8d4a51000"),
+            (json!(u64::MAX), "1bffffffffffffffff"),
+            (json!(-1), "20"),
+            (json!(-10), "29"),
+            (json!(-100), "3863"),
+            (json!(-1000), "3903e7"),
+            (json!(i64::MIN), "3b7fffffffffffffff"),
+            (json!(1.1), "fb3ff199999999999a"),
+            (json!(-4.1), "fbc010666666666666"),
+            (json!(1.0e300), "fb7e37e43c8800759c"),
+            (json!(false), "f4"),
+            (json!(true), "f5"),
+            (json!(null), "f6"),
+            (json!(""), "60"),
+            (json!("a"), "6161"),
+            (json!("IETF"), "6449455446"),
+            (json!("\"\\"), "62225c"),
+            (json!("\u{00fc}"), "62c3bc"),
+            (json!("\u{6c34}"), "63e6b0b4"),
+            (json!([]), "80"),
+            (json!([1, 2, 3]), "83010203"),
+            (json!([1, [2, 3], [4, 5]]), "8301820203820405"),
+            (json!({}), "a0"),
+            (json!({ "a": 1, "b": [2, 3] }), "a26161016162820203"),
+            (json!(["a", { "b": "c" }]), "826161a161626163"),
+            (
+                json!({ "a": "A", "b": "B", "c": "C", "d": "D", "e": "E" }),
+                "a56161614161626142616361436164614461656145",
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(cbor_hex(value.clone()), expected, "{value}");
+        }
+
+        let long: Vec<u64> = (1..=25).collect();
+        assert_eq!(
+            cbor_hex(json!(long)),
+            "98190102030405060708090a0b0c0d0e0f101112131415161718181819"
+        );
+    }
+
+    #[test]
+    fn cbor_lengths_grow_with_their_argument() {
+        assert!(cbor_hex(json!("x".repeat(23))).starts_with("7778"));
+        assert!(cbor_hex(json!("x".repeat(24))).starts_with("781878"));
+        assert!(cbor_hex(json!("x".repeat(256))).starts_with("79010078"));
+        assert!(cbor_hex(json!("x".repeat(65536))).starts_with("7a0001000078"));
+        assert!(cbor_hex(json!(vec![0; 65536])).starts_with("9a0001000000"));
+    }
+}
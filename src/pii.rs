@@ -0,0 +1,86 @@
+use crate::projection::{lookup, MASK, REDACTED};
+use crate::template::placeholders;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+pub const INLINE_KEY: &str = "_pii";
+
+// inline adds a `_pii` object to each event, sidecar follows each event with a `pii` event
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiMode {
+    Inline,
+    Sidecar,
+}
+
+fn category(placeholder: &str) -> Option<&'static str> {
+    match placeholder {
+        "name" => Some("name"),
+        "email" => Some("email"),
+        "phone" => Some("phone"),
+        "creditcard" => Some("card"),
+        "address" | "city" | "zip" => Some("address"),
+        "ip" => Some("ip"),
+        _ => None,
+    }
+}
+
+// which template fields carry synthetic PII, worked out once per stream from its placeholders
+#[derive(Default)]
+pub struct PiiMap {
+    fields: Vec<(Vec<String>, Vec<&'static str>)>,
+}
+
+impl PiiMap {
+    pub fn classify(template: &Map<String, Value>) -> Self {
+        let mut pii = PiiMap::default();
+        pii.walk(template, &mut Vec::new());
+        pii
+    }
+
+    pub fn merge(&mut self, other: PiiMap) {
+        for (path, categories) in other.fields {
+            if !self.fields.iter().any(|(known, _)| *known == path) {
+                self.fields.push((path, categories));
+            }
+        }
+    }
+
+    fn walk(&mut self, object: &Map<String, Value>, path: &mut Vec<String>) {
+        for (key, value) in object {
+            path.push(key.clone());
+            match value {
+                Value::Object(child) => self.walk(child, path),
+                Value::String(subject_string) => {
+                    let mut categories = placeholders(subject_string)
+                        .0
+                        .iter()
+                        // {email|null:0.3} is still an email
+                        .map(|name| name.split_once('|').map_or(name.as_str(), |(name, _)| name))
+                        .filter_map(category)
+                        .collect::<Vec<_>>();
+                    categories.sort_unstable();
+                    categories.dedup();
+                    if !categories.is_empty() {
+                        self.fields.push((path.clone(), categories));
+                    }
+                }
+                _ => {}
+            }
+            path.pop();
+        }
+    }
+
+    // dotted path => categories, limited to fields that survived projection unmasked and unredacted
+    pub fn annotate(&self, event: &Value) -> Value {
+        self.fields
+            .iter()
+            .filter(|(path, _)| {
+                lookup(event, path)
+                    .is_some_and(|value| !matches!(value.as_str(), Some(MASK | REDACTED)))
+            })
+            .map(|(path, categories)| (path.join("."), Value::from(categories.clone())))
+            .collect::<Map<String, Value>>()
+            .into()
+    }
+}
@@ -0,0 +1,230 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// how long a plugin has to announce its generators at startup
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+// how long one value may take before the plugin is given up on
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// a generator plugin, run as a process since no WASM runtime is available to the build: a
+// program started with the server that first prints a line of {"generators": [...]}, then
+// answers each {"generator", "seed"} line with a line of JSON
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub command: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Hello {
+    generators: Vec<String>,
+}
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    // stdout's lines, read on a thread so a stuck plugin can be timed out
+    lines: Receiver<std::io::Result<String>>,
+    // after a failure, answers may no longer line up with the calls, so none are made
+    failed: bool,
+}
+
+pub struct Plugin {
+    pub name: String,
+    // the placeholders it provides
+    pub generators: Vec<String>,
+    // one call at a time, in the order streams ask
+    process: Mutex<Process>,
+}
+
+impl Plugin {
+    pub fn start(name: &str, config: &PluginConfig) -> Result<Plugin, String> {
+        let (program, args) = config
+            .command
+            .split_first()
+            .ok_or_else(|| "needs a command".to_string())?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{program} could not be started: {e}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let lines = read_lines(child.stdout.take().expect("stdout is piped"));
+
+        let hello = match lines.recv_timeout(HELLO_TIMEOUT) {
+            Ok(Ok(line)) => serde_json::from_str::<Hello>(&line).map_err(|e| {
+                format!("{program} should first print {{\"generators\": [...]}}: {e}")
+            }),
+            Ok(Err(e)) => Err(format!("{program}: {e}")),
+            Err(_) => Err(format!(
+                "{program} didn't list its generators within {}s",
+                HELLO_TIMEOUT.as_secs()
+            )),
+        };
+        let hello = hello.inspect_err(|_| {
+            let _ = child.kill();
+        })?;
+
+        Ok(Plugin {
+            name: name.to_string(),
+            generators: hello.generators,
+            process: Mutex::new(Process {
+                child,
+                stdin,
+                lines,
+                failed: false,
+            }),
+        })
+    }
+
+    // strings are used as they are and other JSON as written; a failed call fills in ""
+    pub fn call(&self, generator: &str, seed: u64) -> String {
+        let mut process = self.process.lock().unwrap();
+        if process.failed {
+            return String::new();
+        }
+        let request = json!({ "generator": generator, "seed": seed });
+        let sent = writeln!(process.stdin, "{request}").and_then(|_| process.stdin.flush());
+        let error = match sent.map(|_| process.lines.recv_timeout(CALL_TIMEOUT)) {
+            Ok(Ok(Ok(line))) => match serde_json::from_str::<Value>(&line) {
+                Ok(Value::String(value)) => return value,
+                Ok(value) => return value.to_string(),
+                Err(e) => format!("answered {{{generator}}} with invalid JSON: {e}"),
+            },
+            Ok(Ok(Err(e))) | Err(e) => e.to_string(),
+            Ok(Err(mpsc::RecvTimeoutError::Disconnected)) => "exited".to_string(),
+            Ok(Err(mpsc::RecvTimeoutError::Timeout)) => {
+                format!("took over {}s to answer", CALL_TIMEOUT.as_secs())
+            }
+        };
+        warn!(
+            "plugin {} failed, its placeholders are now empty: {error}",
+            self.name
+        );
+        process.failed = true;
+        String::new()
+    }
+}
+
+fn read_lines(stdout: ChildStdout) -> Receiver<std::io::Result<String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+// each [plugins] entry, started
+pub fn start_plugins(configs: &BTreeMap<String, PluginConfig>) -> Result<Vec<Arc<Plugin>>, String> {
+    configs
+        .iter()
+        .map(|(name, config)| {
+            let plugin = Plugin::start(name, config).map_err(|e| format!("plugins.{name}: {e}"))?;
+            info!("plugin {name} provides {}", plugin.generators.join(", "));
+            Ok(Arc::new(plugin))
+        })
+        .collect()
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Ok(process) = self.process.get_mut() {
+            let _ = process.child.kill();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Packs;
+    use crate::custom::CustomSubstitutions;
+    use crate::pools::Pools;
+    use crate::template::{fill_string, Clock, GenContext};
+
+    // a plugin written as a shell script, listing `generators` before running `answers`
+    fn stub(generators: &str, answers: &str) -> PluginConfig {
+        PluginConfig {
+            command: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("echo '{{\"generators\": {generators}}}'; {answers}"),
+            ],
+        }
+    }
+
+    // answers a fixed ISIN for {isin} and echoes any other request back
+    const ECHO: &str = r#"while read -r line; do case "$line" in *isin*) echo '"US0378331005"';; *) echo "$line";; esac; done"#;
+
+    #[test]
+    fn answers_calls_over_json_lines() {
+        let plugin = Plugin::start("finance", &stub(r#"["isin", "echo"]"#, ECHO)).unwrap();
+        assert_eq!(plugin.generators, ["isin", "echo"]);
+        assert_eq!(plugin.call("isin", 1), "US0378331005");
+        // answers that aren't strings are used as written
+        assert_eq!(plugin.call("echo", 42), r#"{"generator":"echo","seed":42}"#);
+    }
+
+    #[test]
+    fn registers_its_generators_as_placeholders() {
+        let plugin = Arc::new(Plugin::start("finance", &stub(r#"["isin"]"#, ECHO)).unwrap());
+        let custom =
+            CustomSubstitutions::from_config(&BTreeMap::new(), std::slice::from_ref(&plugin))
+                .unwrap();
+        assert_eq!(custom.names(), ["isin"]);
+        let mut ctx = GenContext::new(
+            0,
+            Packs::default(),
+            Clock::default(),
+            Arc::new(Pools::new(0)),
+        )
+        .with_custom(Arc::new(custom));
+        assert_eq!(
+            fill_string("id {isin}", &mut ctx),
+            Value::from("id US0378331005")
+        );
+
+        let clash = CustomSubstitutions::from_config(&BTreeMap::new(), &[plugin.clone(), plugin]);
+        assert_eq!(
+            clash.err().unwrap(),
+            "plugins.finance: {isin} is already provided by plugin finance"
+        );
+    }
+
+    #[test]
+    fn a_failed_call_empties_the_placeholders_from_then_on() {
+        let answers =
+            r#"read -r line; echo 'not json'; while read -r line; do echo '"late"'; done"#;
+        let plugin = Plugin::start("broken", &stub(r#"["x"]"#, answers)).unwrap();
+        assert_eq!(plugin.call("x", 1), "");
+        assert_eq!(plugin.call("x", 2), "");
+
+        let plugin = Plugin::start("gone", &stub(r#"["x"]"#, "exit 0")).unwrap();
+        assert_eq!(plugin.call("x", 1), "");
+    }
+
+    #[test]
+    fn explains_plugins_that_cannot_start() {
+        let error = |config: PluginConfig| Plugin::start("p", &config).err().unwrap();
+        assert_eq!(error(PluginConfig { command: vec![] }), "needs a command");
+        assert!(error(PluginConfig {
+            command: vec!["/nonexistent/plugin".to_string()],
+        })
+        .starts_with("/nonexistent/plugin could not be started: "));
+        assert!(error(stub("\"isin\"", ""))
+            .starts_with("sh should first print {\"generators\": [...]}: "));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config::{Config, PoolConfig};
+use crate::custom::CustomSubstitutions;
+use crate::template::{fill_string, Clock, GenContext};
+use rand::seq::IteratorRandom;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// entity ids shared between streams, so one stream can reference what another produced
+pub struct Pools {
+    pools: Mutex<HashMap<String, Pool>>,
+    capacity: usize,
+}
+
+struct Pool {
+    capacity: usize,
+    entries: VecDeque<Value>,
+}
+
+#[derive(Serialize)]
+pub struct PoolSnapshot {
+    pub name: String,
+    pub capacity: usize,
+    pub size: usize,
+    pub values: Vec<Value>,
+}
+
+impl PoolConfig {
+    // explicit values win over `size` renderings of the value template
+    pub fn render(&self, ctx: &mut GenContext) -> Vec<Value> {
+        ctx.locale = self.locale.unwrap_or_default();
+        match &self.values {
+            Some(values) => values.clone(),
+            None => (0..self.size)
+                .map(|_| fill_string(&self.value, ctx))
+                .collect(),
+        }
+    }
+}
+
+impl Pools {
+    pub fn new(capacity: usize) -> Self {
+        Pools {
+            pools: Mutex::default(),
+            capacity,
+        }
+    }
+
+    // [pools.<name>] tables, filled once at startup
+    pub fn from_config(config: &Config, custom: &Arc<CustomSubstitutions>) -> Self {
+        let pools = Pools::new(config.limits.max_pool_entries);
+        let mut ctx = GenContext::new(
+            thread_rng().gen(),
+            config.packs,
+            Clock::default(),
+            Arc::new(Pools::new(0)),
+        )
+        .with_custom(custom.clone());
+
+        for (name, pool_config) in &config.pools {
+            pools.extend(name, pool_config.render(&mut ctx));
+        }
+
+        pools
+    }
+
+    // keeps the most recent entries, up to the pool's capacity
+    pub fn add(&self, pool: &str, value: Value) {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(pool.to_string()).or_insert_with(|| Pool {
+            capacity: self.capacity,
+            entries: VecDeque::new(),
+        });
+        if pool.entries.len() >= pool.capacity {
+            pool.entries.pop_front();
+        }
+        pool.entries.push_back(value);
+    }
+
+    // explicit uploads grow the pool's capacity to fit rather than evicting
+    pub fn extend(&self, pool: &str, values: Vec<Value>) -> usize {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(pool.to_string()).or_insert_with(|| Pool {
+            capacity: self.capacity,
+            entries: VecDeque::new(),
+        });
+        pool.entries.extend(values);
+        pool.capacity = pool.capacity.max(pool.entries.len());
+        pool.entries.len()
+    }
+
+    pub fn pick(&self, pool: &str, rng: &mut impl Rng) -> Option<Value> {
+        self.pools
+            .lock()
+            .unwrap()
+            .get(pool)?
+            .entries
+            .iter()
+            .choose(rng)
+            .cloned()
+    }
+
+    pub fn snapshot(&self, name: &str) -> Option<PoolSnapshot> {
+        let pools = self.pools.lock().unwrap();
+        let pool = pools.get(name)?;
+        Some(PoolSnapshot {
+            name: name.to_string(),
+            capacity: pool.capacity,
+            size: pool.entries.len(),
+            values: pool.entries.iter().cloned().collect(),
+        })
+    }
+
+    // pool name => number of entries
+    pub fn sizes(&self) -> BTreeMap<String, usize> {
+        self.pools
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, pool)| (name.clone(), pool.entries.len()))
+            .collect()
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        self.pools.lock().unwrap().remove(name).is_some()
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::Config;
+use crate::error::AppError;
+use crate::lint::{lint_shape, Severity};
+use crate::state::AppState;
+use axum::http::StatusCode;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+// named shapes registered over /shapes, so clients can connect with ?preset=<name>
+pub struct Presets {
+    shapes: RwLock<BTreeMap<String, Map<String, Value>>>,
+    // rewritten after every change when set
+    file: Option<PathBuf>,
+}
+
+// a preset must be an object that lints without errors
+pub fn check_shape(shape: Value, state: &AppState) -> Result<Map<String, Value>, AppError> {
+    let errors = lint_shape(
+        &shape,
+        state.config.packs,
+        &state.custom,
+        state.config.templates.strict,
+    )
+    .into_iter()
+    .filter(|diagnostic| diagnostic.severity == Severity::Error)
+    .collect::<Vec<_>>();
+    match shape {
+        Value::Object(shape) if errors.is_empty() => Ok(shape),
+        _ => Err(AppError::bad_request("the shape has lint errors")
+            .with_extension("diagnostics", serde_json::json!(errors))),
+    }
+}
+
+impl Presets {
+    // starts from the presets file when it exists
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let file = config.presets.file.clone();
+        let mut shapes = BTreeMap::new();
+        if let Some(path) = file.as_ref().filter(|path| path.exists()) {
+            let source = fs::read_to_string(path)
+                .map_err(|e| format!("presets.file {}: {e}", path.display()))?;
+            let stored: BTreeMap<String, Value> = serde_json::from_str(&source)
+                .map_err(|e| format!("presets.file {}: {e}", path.display()))?;
+            for (name, shape) in stored {
+                let Value::Object(shape) = shape else {
+                    return Err(format!(
+                        "presets.file {}: {name} must be a JSON object",
+                        path.display()
+                    ));
+                };
+                shapes.insert(name, shape);
+            }
+        }
+
+        Ok(Presets {
+            shapes: RwLock::new(shapes),
+            file,
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.shapes.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Map<String, Value>> {
+        self.shapes.read().unwrap().get(name).cloned()
+    }
+
+    // applies the change and persists it, leaving the presets as they were if either fails
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, Map<String, Value>>) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let mut shapes = self.shapes.write().unwrap();
+        let mut changed = shapes.clone();
+        let result = change(&mut changed)?;
+        self.persist(&changed)?;
+        *shapes = changed;
+        Ok(result)
+    }
+
+    // Ok(true) when the preset is new
+    pub fn put(&self, name: &str, shape: Map<String, Value>) -> Result<bool, AppError> {
+        self.update(|shapes| Ok(shapes.insert(name.to_string(), shape).is_none()))
+    }
+
+    pub fn create(&self, name: &str, shape: Map<String, Value>) -> Result<(), AppError> {
+        self.update(|shapes| {
+            if shapes.contains_key(name) {
+                return Err(AppError::new(StatusCode::CONFLICT)
+                    .with_detail(format!("a preset named {name} exists, PUT to replace it")));
+            }
+            shapes.insert(name.to_string(), shape);
+            Ok(())
+        })
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), AppError> {
+        self.update(|shapes| {
+            shapes
+                .remove(name)
+                .map(drop)
+                .ok_or_else(|| AppError::not_found(format!("no preset named {name}")))
+        })
+    }
+
+    fn persist(&self, shapes: &BTreeMap<String, Map<String, Value>>) -> Result<(), AppError> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let source = serde_json::to_string_pretty(shapes).unwrap_or_default();
+        fs::write(path, source + "\n").map_err(|e| {
+            AppError::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_detail(format!("writing {}: {e}", path.display()))
+        })
+    }
+}
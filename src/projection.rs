@@ -0,0 +1,126 @@
+use serde_json::{Map, Value};
+
+// per-connection view over generated events, applied after generation
+// bare names in exclude/mask match that key at any depth; dotted paths are anchored at the root
+#[derive(Default, Clone)]
+pub struct Projection {
+    fields: Vec<Vec<String>>,
+    exclude: Vec<Vec<String>>,
+    mask: Vec<Vec<String>>,
+    redacted: Vec<Vec<String>>,
+}
+
+pub const MASK: &str = "****";
+pub const REDACTED: &str = "[redacted]";
+
+fn parse_paths(list: &str) -> Vec<Vec<String>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(|path| path.split('.').map(str::to_string).collect())
+        .collect()
+}
+
+impl Projection {
+    pub fn new(fields: Option<&str>, exclude: Option<&str>, mask: Option<&str>) -> Self {
+        Projection {
+            fields: fields.map(parse_paths).unwrap_or_default(),
+            exclude: exclude.map(parse_paths).unwrap_or_default(),
+            mask: mask.map(parse_paths).unwrap_or_default(),
+            redacted: Vec::new(),
+        }
+    }
+
+    // fields redacted mid-stream, matched like mask entries
+    pub fn redact_fields(&mut self, fields: &[String]) {
+        self.redacted
+            .extend(fields.iter().flat_map(|field| parse_paths(field)));
+    }
+
+    pub fn apply(&self, event: Value) -> Value {
+        let mut event = self.select(event);
+        if let Value::Object(object) = &mut event {
+            self.redact(object, &mut Vec::new());
+        }
+        event
+    }
+
+    fn select(&self, event: Value) -> Value {
+        if self.fields.is_empty() {
+            return event;
+        }
+
+        let mut selected = Map::new();
+        for path in &self.fields {
+            if let Some(value) = lookup(&event, path) {
+                insert(&mut selected, path, value.clone());
+            }
+        }
+
+        Value::Object(selected)
+    }
+
+    fn redact(&self, object: &mut Map<String, Value>, path: &mut Vec<String>) {
+        if self.exclude.is_empty() && self.mask.is_empty() && self.redacted.is_empty() {
+            return;
+        }
+
+        object.retain(|key, _| !matches_any(&self.exclude, path, key));
+
+        for (key, value) in object.iter_mut() {
+            if matches_any(&self.mask, path, key) {
+                *value = Value::String(MASK.to_string());
+            } else if matches_any(&self.redacted, path, key) {
+                *value = Value::String(REDACTED.to_string());
+            } else if let Value::Object(child) = value {
+                path.push(key.clone());
+                self.redact(child, path);
+                path.pop();
+            }
+        }
+    }
+}
+
+fn matches_any(patterns: &[Vec<String>], parent: &[String], key: &str) -> bool {
+    patterns.iter().any(|pattern| match pattern.as_slice() {
+        [name] => name == key,
+        [prefix @ .., last] => last == key && prefix == parent,
+        [] => false,
+    })
+}
+
+pub fn lookup_mut<'a>(value: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    path.iter().try_fold(value, |value, segment| {
+        value.as_object_mut()?.get_mut(segment)
+    })
+}
+
+pub fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(value, |value, segment| value.as_object()?.get(segment))
+}
+
+pub fn remove(object: &mut Map<String, Value>, path: &[String]) -> Option<Value> {
+    match path {
+        [] => None,
+        [last] => object.remove(last),
+        [first, rest @ ..] => remove(object.get_mut(first)?.as_object_mut()?, rest),
+    }
+}
+
+pub fn insert(object: &mut Map<String, Value>, path: &[String], value: Value) {
+    match path {
+        [] => {}
+        [last] => {
+            object.insert(last.clone(), value);
+        }
+        [first, rest @ ..] => {
+            let child = object
+                .entry(first.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(child) = child {
+                insert(child, rest, value);
+            }
+        }
+    }
+}
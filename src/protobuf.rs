@@ -0,0 +1,776 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::DateTime;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+const TIMESTAMP: &str = "google.protobuf.Timestamp";
+
+// FieldDescriptorProto.Type
+const DOUBLE: u64 = 1;
+const FLOAT: u64 = 2;
+const INT64: u64 = 3;
+const UINT64: u64 = 4;
+const INT32: u64 = 5;
+const FIXED64: u64 = 6;
+const FIXED32: u64 = 7;
+const BOOL: u64 = 8;
+const STRING: u64 = 9;
+const MESSAGE: u64 = 11;
+const BYTES: u64 = 12;
+const UINT32: u64 = 13;
+const ENUM: u64 = 14;
+const SFIXED32: u64 = 15;
+const SFIXED64: u64 = 16;
+const SINT32: u64 = 17;
+const SINT64: u64 = 18;
+
+const REPEATED: u64 = 3;
+
+// the messages and enums of a FileDescriptorSet, as written by protoc --descriptor_set_out,
+// keyed by full name without the leading dot
+#[derive(Default)]
+pub struct Descriptors {
+    messages: HashMap<String, MessageDescriptor>,
+    enums: HashMap<String, HashMap<String, i64>>,
+}
+
+#[derive(Default)]
+struct MessageDescriptor {
+    fields: Vec<FieldDescriptor>,
+    map_entry: bool,
+}
+
+#[derive(Default)]
+struct FieldDescriptor {
+    name: String,
+    json_name: Option<String>,
+    number: u64,
+    repeated: bool,
+    kind: u64,
+    type_name: Option<String>,
+    packed: Option<bool>,
+}
+
+// one field of an encoded message, as read off the wire
+enum Wire<'a> {
+    Varint(u64),
+    // fixed32 and fixed64 values, which descriptors don't use
+    Fixed,
+    Bytes(&'a [u8]),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.bytes.split_first().ok_or("truncated varint")?;
+            self.bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_string())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("truncated field".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn field(&mut self) -> Result<Option<(u64, Wire<'a>)>, String> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.varint()?;
+        let wire = match tag & 7 {
+            0 => Wire::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Wire::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Wire::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Wire::Fixed
+            }
+            other => return Err(format!("unsupported wire type {other}")),
+        };
+        Ok(Some((tag >> 3, wire)))
+    }
+}
+
+// calls visit with each field of an encoded message
+fn fields<'a>(
+    bytes: &'a [u8],
+    mut visit: impl FnMut(u64, Wire<'a>) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut reader = Reader { bytes };
+    while let Some((number, wire)) = reader.field()? {
+        visit(number, wire)?;
+    }
+    Ok(())
+}
+
+fn text(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| "a name is not UTF-8".to_string())
+}
+
+impl Descriptors {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut descriptors = Descriptors::default();
+        // FileDescriptorSet.file
+        fields(bytes, |number, wire| match (number, wire) {
+            (1, Wire::Bytes(file)) => descriptors.file(file),
+            _ => Ok(()),
+        })?;
+        if descriptors.messages.is_empty() {
+            return Err("the descriptor set has no messages".to_string());
+        }
+        Ok(descriptors)
+    }
+
+    pub fn contains(&self, message: &str) -> bool {
+        self.messages.contains_key(message)
+    }
+
+    fn file(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let mut package = String::new();
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        fields(bytes, |number, wire| {
+            match (number, wire) {
+                (2, Wire::Bytes(name)) => package = text(name)?,
+                (4, Wire::Bytes(message)) => messages.push(message),
+                (5, Wire::Bytes(enumeration)) => enums.push(enumeration),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        for message in messages {
+            self.message(&package, message)?;
+        }
+        for enumeration in enums {
+            self.enumeration(&package, enumeration)?;
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, scope: &str, bytes: &[u8]) -> Result<(), String> {
+        let mut name = String::new();
+        let mut descriptor = MessageDescriptor::default();
+        let mut nested = Vec::new();
+        let mut enums = Vec::new();
+        fields(bytes, |number, wire| {
+            match (number, wire) {
+                (1, Wire::Bytes(bytes)) => name = text(bytes)?,
+                (2, Wire::Bytes(bytes)) => descriptor.fields.push(field(bytes)?),
+                (3, Wire::Bytes(bytes)) => nested.push(bytes),
+                (4, Wire::Bytes(bytes)) => enums.push(bytes),
+                // MessageOptions.map_entry
+                (7, Wire::Bytes(bytes)) => fields(bytes, |number, wire| {
+                    if let (7, Wire::Varint(map_entry)) = (number, wire) {
+                        descriptor.map_entry = map_entry != 0;
+                    }
+                    Ok(())
+                })?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        let full_name = qualified(scope, &name);
+        for bytes in nested {
+            self.message(&full_name, bytes)?;
+        }
+        for bytes in enums {
+            self.enumeration(&full_name, bytes)?;
+        }
+        self.messages.insert(full_name, descriptor);
+        Ok(())
+    }
+
+    fn enumeration(&mut self, scope: &str, bytes: &[u8]) -> Result<(), String> {
+        let mut name = String::new();
+        let mut values = HashMap::new();
+        fields(bytes, |number, wire| {
+            match (number, wire) {
+                (1, Wire::Bytes(bytes)) => name = text(bytes)?,
+                (2, Wire::Bytes(bytes)) => {
+                    let (mut value_name, mut value) = (String::new(), 0);
+                    fields(bytes, |number, wire| {
+                        match (number, wire) {
+                            (1, Wire::Bytes(bytes)) => value_name = text(bytes)?,
+                            (2, Wire::Varint(number)) => value = number as i32 as i64,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    values.insert(value_name, value);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        self.enums.insert(qualified(scope, &name), values);
+        Ok(())
+    }
+
+    // the event as the named message, matching its fields by proto or JSON name; fields the
+    // message doesn't have are left out
+    pub fn encode(&self, message: &str, value: &Value) -> Result<Vec<u8>, String> {
+        let Value::Object(object) = value else {
+            return Err(format!("{message} can only be encoded from an object"));
+        };
+        let mut out = Vec::new();
+        self.encode_object(message, object, &mut out)?;
+        Ok(out)
+    }
+
+    fn encode_object(
+        &self,
+        message: &str,
+        object: &Map<String, Value>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        if message == TIMESTAMP && !self.messages.contains_key(TIMESTAMP) {
+            return Err(format!("{TIMESTAMP} is encoded from an RFC 3339 string"));
+        }
+        let descriptor = self
+            .messages
+            .get(message)
+            .ok_or_else(|| format!("the descriptor set has no message {message}"))?;
+        for field in &descriptor.fields {
+            let value = object.get(&field.name).or_else(|| {
+                field
+                    .json_name
+                    .as_ref()
+                    .and_then(|json_name| object.get(json_name))
+            });
+            let Some(value) = value.filter(|value| !value.is_null()) else {
+                continue;
+            };
+            self.encode_field(field, value, out)
+                .map_err(|e| format!("{message}.{}: {e}", field.name))?;
+        }
+        Ok(())
+    }
+
+    fn encode_field(
+        &self,
+        field: &FieldDescriptor,
+        value: &Value,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let entry = field
+            .type_name
+            .as_ref()
+            .and_then(|type_name| self.messages.get(type_name))
+            .filter(|message| message.map_entry);
+        match (field.repeated, value) {
+            // a map<K, V> is a repeated entry message of key 1 and value 2
+            (true, Value::Object(object)) if entry.is_some() => {
+                let type_name = field.type_name.as_deref().unwrap_or_default();
+                for (key, value) in object {
+                    let mut entry = Map::new();
+                    entry.insert("key".to_string(), Value::from(key.as_str()));
+                    entry.insert("value".to_string(), value.clone());
+                    let mut bytes = Vec::new();
+                    self.encode_object(type_name, &entry, &mut bytes)?;
+                    tag(field.number, 2, out);
+                    length_delimited(&bytes, out);
+                }
+                Ok(())
+            }
+            (true, Value::Array(items)) if packable(field.kind) && field.packed != Some(false) => {
+                let mut packed = Vec::new();
+                for item in items {
+                    self.encode_scalar(field, item, &mut packed)?;
+                }
+                tag(field.number, 2, out);
+                length_delimited(&packed, out);
+                Ok(())
+            }
+            (true, Value::Array(items)) => items
+                .iter()
+                .try_for_each(|item| self.encode_one(field, item, out)),
+            (true, value) => self.encode_one(field, value, out),
+            (false, Value::Array(_)) => Err("is not repeated but got an array".to_string()),
+            (false, value) => self.encode_one(field, value, out),
+        }
+    }
+
+    fn encode_one(
+        &self,
+        field: &FieldDescriptor,
+        value: &Value,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        tag(field.number, wire_type(field.kind), out);
+        match field.kind {
+            STRING => length_delimited(string(value).as_bytes(), out),
+            BYTES => {
+                let string = string(value);
+                let bytes = STANDARD
+                    .decode(&string)
+                    .unwrap_or_else(|_| string.into_bytes());
+                length_delimited(&bytes, out);
+            }
+            MESSAGE => {
+                let type_name = field.type_name.as_deref().unwrap_or_default();
+                let mut bytes = Vec::new();
+                match value {
+                    Value::String(timestamp) if type_name == TIMESTAMP => {
+                        encode_timestamp(timestamp, &mut bytes)?
+                    }
+                    Value::Object(object) => self.encode_object(type_name, object, &mut bytes)?,
+                    _ => return Err(format!("{type_name} needs an object")),
+                }
+                length_delimited(&bytes, out);
+            }
+            _ => self.encode_scalar(field, value, out)?,
+        }
+        Ok(())
+    }
+
+    // the value of a numeric, bool or enum field, without its tag
+    fn encode_scalar(
+        &self,
+        field: &FieldDescriptor,
+        value: &Value,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        match field.kind {
+            DOUBLE => out.extend(float(value)?.to_le_bytes()),
+            FLOAT => out.extend((float(value)? as f32).to_le_bytes()),
+            FIXED64 => out.extend((integer(value)? as u64).to_le_bytes()),
+            SFIXED64 => out.extend((integer(value)? as i64).to_le_bytes()),
+            FIXED32 => out.extend((integer(value)? as u32).to_le_bytes()),
+            SFIXED32 => out.extend((integer(value)? as i32).to_le_bytes()),
+            BOOL => varint(u64::from(boolean(value)?), out),
+            // negative int32s and int64s take all ten bytes
+            INT32 | INT64 => varint(integer(value)? as i64 as u64, out),
+            UINT32 | UINT64 => varint(integer(value)? as u64, out),
+            SINT32 | SINT64 => {
+                let number = integer(value)? as i64;
+                varint(((number << 1) ^ (number >> 63)) as u64, out);
+            }
+            ENUM => {
+                let number = match value {
+                    Value::String(name) => field
+                        .type_name
+                        .as_ref()
+                        .and_then(|type_name| self.enums.get(type_name))
+                        .and_then(|values| values.get(name))
+                        .copied()
+                        .ok_or_else(|| format!("{name} is not a value of the enum"))?,
+                    value => integer(value)? as i64,
+                };
+                varint(number as u64, out);
+            }
+            other => return Err(format!("field type {other} is not supported")),
+        }
+        Ok(())
+    }
+}
+
+fn field(bytes: &[u8]) -> Result<FieldDescriptor, String> {
+    let mut field = FieldDescriptor::default();
+    fields(bytes, |number, wire| {
+        match (number, wire) {
+            (1, Wire::Bytes(bytes)) => field.name = text(bytes)?,
+            (3, Wire::Varint(number)) => field.number = number,
+            (4, Wire::Varint(label)) => field.repeated = label == REPEATED,
+            (5, Wire::Varint(kind)) => field.kind = kind,
+            (6, Wire::Bytes(bytes)) => {
+                field.type_name = Some(text(bytes)?.trim_start_matches('.').to_string())
+            }
+            (10, Wire::Bytes(bytes)) => field.json_name = Some(text(bytes)?),
+            // FieldOptions.packed
+            (8, Wire::Bytes(bytes)) => fields(bytes, |number, wire| {
+                if let (2, Wire::Varint(packed)) = (number, wire) {
+                    field.packed = Some(packed != 0);
+                }
+                Ok(())
+            })?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+    Ok(field)
+}
+
+fn qualified(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{scope}.{name}")
+    }
+}
+
+fn packable(kind: u64) -> bool {
+    !matches!(kind, STRING | BYTES | MESSAGE)
+}
+
+fn wire_type(kind: u64) -> u64 {
+    match kind {
+        DOUBLE | FIXED64 | SFIXED64 => 1,
+        FLOAT | FIXED32 | SFIXED32 => 5,
+        STRING | BYTES | MESSAGE => 2,
+        _ => 0,
+    }
+}
+
+fn varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn tag(number: u64, wire_type: u64, out: &mut Vec<u8>) {
+    varint(number << 3 | wire_type, out);
+}
+
+fn length_delimited(bytes: &[u8], out: &mut Vec<u8>) {
+    varint(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+fn encode_timestamp(timestamp: &str, out: &mut Vec<u8>) -> Result<(), String> {
+    let timestamp = DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|e| format!("{timestamp} is not an RFC 3339 timestamp: {e}"))?;
+    tag(1, 0, out);
+    varint(timestamp.timestamp() as u64, out);
+    tag(2, 0, out);
+    varint(u64::from(timestamp.timestamp_subsec_nanos()), out);
+    Ok(())
+}
+
+fn string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+// numbers may come as JSON numbers or, as int64s do in proto3 JSON, as strings
+fn float(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{value} is not a number"))
+}
+
+fn integer(value: &Value) -> Result<i128, String> {
+    match value {
+        Value::Number(number) => number
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| number.as_u64().map(i128::from))
+            .or_else(|| number.as_f64().map(|float| float as i128)),
+        Value::String(string) => string.parse().ok(),
+        Value::Bool(boolean) => Some(i128::from(*boolean)),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{value} is not an integer"))
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(boolean) => Ok(*boolean),
+        Value::String(string) if string == "true" || string == "false" => Ok(string == "true"),
+        _ => Err(format!("{value} is not a boolean")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn bytes_field(number: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        tag(number, 2, out);
+        length_delimited(bytes, out);
+    }
+
+    // a FieldDescriptorProto; label 1 is optional and 3 repeated
+    fn field_proto(name: &str, number: u64, kind: u64, label: u64, type_name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        bytes_field(1, name.as_bytes(), &mut out);
+        tag(3, 0, &mut out);
+        varint(number, &mut out);
+        tag(4, 0, &mut out);
+        varint(label, &mut out);
+        tag(5, 0, &mut out);
+        varint(kind, &mut out);
+        if !type_name.is_empty() {
+            bytes_field(6, format!(".{type_name}").as_bytes(), &mut out);
+        }
+        out
+    }
+
+    fn message_proto(
+        name: &str,
+        fields: &[Vec<u8>],
+        nested: &[Vec<u8>],
+        map_entry: bool,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        bytes_field(1, name.as_bytes(), &mut out);
+        for field in fields {
+            bytes_field(2, field, &mut out);
+        }
+        for message in nested {
+            bytes_field(3, message, &mut out);
+        }
+        if map_entry {
+            bytes_field(7, &[0x38, 0x01], &mut out);
+        }
+        out
+    }
+
+    // the messages of the protobuf encoding guide, plus one field of every scalar type, a map and
+    // a timestamp
+    fn descriptors() -> Descriptors {
+        let mut file = Vec::new();
+        bytes_field(2, b"t", &mut file);
+        let messages = [
+            message_proto("Test1", &[field_proto("a", 1, INT32, 1, "")], &[], false),
+            message_proto("Test2", &[field_proto("b", 2, STRING, 1, "")], &[], false),
+            message_proto(
+                "Test3",
+                &[field_proto("c", 3, MESSAGE, 1, "t.Test1")],
+                &[],
+                false,
+            ),
+            message_proto("Test4", &[field_proto("d", 4, INT32, 3, "")], &[], false),
+            message_proto(
+                "Scalars",
+                &[
+                    field_proto("s", 1, SINT32, 1, ""),
+                    field_proto("u", 2, UINT64, 1, ""),
+                    field_proto("d", 3, DOUBLE, 1, ""),
+                    field_proto("f", 4, FLOAT, 1, ""),
+                    field_proto("b", 5, BOOL, 1, ""),
+                    field_proto("y", 6, BYTES, 1, ""),
+                    field_proto("x", 7, FIXED32, 1, ""),
+                    field_proto("z", 8, SFIXED64, 1, ""),
+                    field_proto("e", 9, ENUM, 1, "t.Level"),
+                ],
+                &[],
+                false,
+            ),
+            message_proto(
+                "Labels",
+                &[field_proto("m", 1, MESSAGE, 3, "t.Labels.MEntry")],
+                &[message_proto(
+                    "MEntry",
+                    &[
+                        field_proto("key", 1, STRING, 1, ""),
+                        field_proto("value", 2, INT32, 1, ""),
+                    ],
+                    &[],
+                    true,
+                )],
+                false,
+            ),
+            message_proto(
+                "Event",
+                &[field_proto("at", 1, MESSAGE, 1, TIMESTAMP)],
+                &[],
+                false,
+            ),
+        ];
+        for message in &messages {
+            bytes_field(4, message, &mut file);
+        }
+        let mut level = Vec::new();
+        bytes_field(1, b"Level", &mut level);
+        for (name, number) in [("A", 0), ("B", 1)] {
+            let mut value = Vec::new();
+            bytes_field(1, name.as_bytes(), &mut value);
+            tag(2, 0, &mut value);
+            varint(number, &mut value);
+            bytes_field(2, &value, &mut level);
+        }
+        bytes_field(5, &level, &mut file);
+
+        let mut set = Vec::new();
+        bytes_field(1, &file, &mut set);
+        Descriptors::parse(&set).unwrap()
+    }
+
+    fn encoded(message: &str, value: Value) -> String {
+        hex(&descriptors().encode(message, &value).unwrap())
+    }
+
+    #[test]
+    fn matches_the_encoding_guide() {
+        assert_eq!(encoded("t.Test1", json!({ "a": 150 })), "089601");
+        assert_eq!(
+            encoded("t.Test2", json!({ "b": "testing" })),
+            "120774657374696e67"
+        );
+        assert_eq!(
+            encoded("t.Test3", json!({ "c": { "a": 150 } })),
+            "1a03089601"
+        );
+        assert_eq!(
+            encoded("t.Test4", json!({ "d": [3, 270, 86942] })),
+            "2206038e029ea705"
+        );
+    }
+
+    #[test]
+    fn varints_round_trip_at_every_length_boundary() {
+        let cases = [
+            (0, "00"),
+            (1, "01"),
+            (127, "7f"),
+            (128, "8001"),
+            (300, "ac02"),
+            (16383, "ff7f"),
+            (16384, "808001"),
+            (u64::from(u32::MAX), "ffffffff0f"),
+            (u64::MAX, "ffffffffffffffffff01"),
+        ];
+        for (value, expected) in cases {
+            let mut out = Vec::new();
+            varint(value, &mut out);
+            assert_eq!(hex(&out), expected);
+            let mut reader = Reader { bytes: &out };
+            assert_eq!(reader.varint(), Ok(value));
+            assert!(reader.bytes.is_empty());
+        }
+
+        assert_eq!(
+            Reader { bytes: &[0x80] }.varint(),
+            Err("truncated varint".to_string())
+        );
+        assert_eq!(
+            Reader { bytes: &[0xff; 11] }.varint(),
+            Err("varint is too long".to_string())
+        );
+    }
+
+    #[test]
+    fn negative_integers_by_field_type() {
+        // int32 sign-extends to ten bytes, sint32 zigzags
+        assert_eq!(
+            encoded("t.Test1", json!({ "a": -1 })),
+            "08ffffffffffffffffff01"
+        );
+        let sint = |number: i64| encoded("t.Scalars", json!({ "s": number }));
+        assert_eq!(sint(0), "0800");
+        assert_eq!(sint(-1), "0801");
+        assert_eq!(sint(1), "0802");
+        assert_eq!(sint(-2), "0803");
+        assert_eq!(sint(i64::from(i32::MAX)), "08feffffff0f");
+        assert_eq!(sint(i64::from(i32::MIN)), "08ffffffff0f");
+        assert_eq!(
+            encoded("t.Scalars", json!({ "z": -2 })),
+            "41feffffffffffffff"
+        );
+    }
+
+    #[test]
+    fn scalar_types_use_their_wire_format() {
+        assert_eq!(
+            encoded("t.Scalars", json!({ "u": u64::MAX })),
+            "10ffffffffffffffffff01"
+        );
+        // proto3 JSON writes 64-bit integers as strings
+        assert_eq!(
+            encoded("t.Scalars", json!({ "u": "18446744073709551615" })),
+            "10ffffffffffffffffff01"
+        );
+        assert_eq!(
+            encoded("t.Scalars", json!({ "d": 1.5 })),
+            "19000000000000f83f"
+        );
+        assert_eq!(encoded("t.Scalars", json!({ "f": 1.5 })), "250000c03f");
+        assert_eq!(encoded("t.Scalars", json!({ "b": true })), "2801");
+        assert_eq!(encoded("t.Scalars", json!({ "x": 1 })), "3d01000000");
+        assert_eq!(encoded("t.Scalars", json!({ "e": "B" })), "4801");
+        assert_eq!(encoded("t.Scalars", json!({ "e": 1 })), "4801");
+        // fields are written in descriptor order and nulls are left out
+        assert_eq!(
+            encoded("t.Scalars", json!({ "b": false, "s": 1, "u": null })),
+            "08022800"
+        );
+    }
+
+    #[test]
+    fn strings_and_bytes_are_length_prefixed() {
+        assert_eq!(encoded("t.Test2", json!({ "b": "" })), "1200");
+        assert!(encoded("t.Test2", json!({ "b": "x".repeat(127) })).starts_with("127f78"));
+        assert!(encoded("t.Test2", json!({ "b": "x".repeat(128) })).starts_with("12800178"));
+        assert_eq!(encoded("t.Test2", json!({ "b": "é" })), "1202c3a9");
+        // bytes come base64-encoded, or as the string's own bytes when they aren't
+        assert_eq!(encoded("t.Scalars", json!({ "y": "AQID" })), "3203010203");
+        assert_eq!(encoded("t.Scalars", json!({ "y": "hi!" })), "3203686921");
+    }
+
+    #[test]
+    fn maps_and_timestamps_are_nested_messages() {
+        assert_eq!(
+            encoded("t.Labels", json!({ "m": { "k": 1 } })),
+            "0a050a016b1001"
+        );
+        assert_eq!(
+            encoded("t.Event", json!({ "at": "1970-01-01T00:00:01.5Z" })),
+            "0a0808011080cab5ee01"
+        );
+    }
+
+    #[test]
+    fn explains_values_that_do_not_fit() {
+        let descriptors = descriptors();
+        let error = |message: &str, value: Value| descriptors.encode(message, &value).unwrap_err();
+        assert_eq!(
+            error("t.Test1", json!([1])),
+            "t.Test1 can only be encoded from an object"
+        );
+        assert_eq!(
+            error("t.Test1", json!({ "a": [1] })),
+            "t.Test1.a: is not repeated but got an array"
+        );
+        assert_eq!(
+            error("t.Test1", json!({ "a": "many" })),
+            "t.Test1.a: \"many\" is not an integer"
+        );
+        assert_eq!(
+            error("t.Scalars", json!({ "e": "C" })),
+            "t.Scalars.e: C is not a value of the enum"
+        );
+        assert_eq!(
+            error("t.Test3", json!({ "c": 1 })),
+            "t.Test3.c: t.Test1 needs an object"
+        );
+        assert_eq!(
+            error("t.Missing", json!({})),
+            "the descriptor set has no message t.Missing"
+        );
+        assert!(!descriptors.contains("t.Missing"));
+        assert!(descriptors.contains("t.Labels.MEntry"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::config::{ApiKeyConfig, Config, LimitMode};
+use crate::error::AppError;
+use crate::transport::Message;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+const API_KEY_HEADER: &str = "x-api-key";
+const WINDOW: Duration = Duration::from_secs(60);
+// what a client over its connection quota is told to wait
+const CONNECTION_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+// what a stream does once its simulated quota_after events are used up
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    // stays connected with no more data
+    #[default]
+    Stop,
+    Close,
+}
+
+#[derive(Default)]
+struct Usage {
+    connections: usize,
+    window_start: Option<Instant>,
+    events: u32,
+}
+
+impl Usage {
+    // Err holds the wait until the next fixed one-minute window
+    fn check_events(&mut self, limit: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let window_start = *self.window_start.get_or_insert(now);
+        if now.duration_since(window_start) >= WINDOW {
+            self.window_start = Some(now);
+            self.events = 0;
+        }
+
+        if self.events < limit {
+            Ok(())
+        } else {
+            Err(WINDOW.saturating_sub(now.duration_since(window_start)))
+        }
+    }
+}
+
+// connections and events used by each API key against its [api_keys] quota
+#[derive(Default)]
+pub struct Quotas {
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+pub fn api_key<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Option<&'a str> {
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .or(query)
+}
+
+fn rate_limited(retry_after: Duration, detail: String) -> AppError {
+    let seconds = retry_after.as_secs_f64().ceil() as u64;
+    AppError::new(StatusCode::TOO_MANY_REQUESTS)
+        .with_type("/problems/rate-limited", "Rate limit exceeded")
+        .with_detail(detail)
+        .with_extension("retry_after_s", json!(seconds))
+        .with_header(header::RETRY_AFTER, HeaderValue::from(seconds))
+}
+
+impl Quotas {
+    // with no keys configured every request is let through unmetered
+    pub fn admit(
+        self: &Arc<Self>,
+        config: &Config,
+        key: Option<&str>,
+    ) -> Result<Option<Permit>, AppError> {
+        if config.api_keys.is_empty() {
+            return Ok(None);
+        }
+
+        let unauthorized = |detail: &str| {
+            AppError::new(StatusCode::UNAUTHORIZED)
+                .with_type("/problems/unauthorized", "Missing or unknown API key")
+                .with_detail(detail)
+        };
+        let key = key.ok_or_else(|| {
+            unauthorized("pass an API key in the x-api-key header or the api_key parameter")
+        })?;
+        let limits = config
+            .api_keys
+            .get(key)
+            .ok_or_else(|| unauthorized("the API key is not configured"))?;
+
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(key.to_string()).or_default();
+        if let Some(max_connections) = limits.max_connections {
+            if usage.connections >= max_connections {
+                return Err(rate_limited(
+                    CONNECTION_RETRY_AFTER,
+                    format!("this key is limited to {max_connections} concurrent connections"),
+                ));
+            }
+        }
+        if let (Some(limit), LimitMode::Reject) = (limits.events_per_minute, limits.on_limit) {
+            usage.check_events(limit).map_err(|retry_after| {
+                rate_limited(
+                    retry_after,
+                    format!("this key is limited to {limit} events per minute"),
+                )
+            })?;
+        }
+        usage.connections += 1;
+
+        Ok(Some(Permit {
+            quotas: self.clone(),
+            key: key.to_string(),
+            limits: limits.clone(),
+        }))
+    }
+}
+
+// one admitted connection; dropping it frees the connection slot
+pub struct Permit {
+    quotas: Arc<Quotas>,
+    key: String,
+    limits: ApiKeyConfig,
+}
+
+impl Permit {
+    fn take_event(&self) -> Result<(), Duration> {
+        let Some(limit) = self.limits.events_per_minute else {
+            return Ok(());
+        };
+
+        let mut usage = self.quotas.usage.lock().unwrap();
+        let usage = usage.entry(self.key.clone()).or_default();
+        usage.check_events(limit)?;
+        usage.events += 1;
+        Ok(())
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(usage) = self.quotas.usage.lock().unwrap().get_mut(&self.key) {
+            usage.connections -= 1;
+        }
+    }
+}
+
+// counts data events against the key's quota; over it, the stream either ends with a
+// rate-limited event or holds data back behind a throttled notice until the window resets
+pub fn metered(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    permit: Option<Permit>,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        while let Some(message) = messages.next().await {
+            if let (Some(permit), false) = (&permit, message.control) {
+                while let Err(retry_after) = permit.take_event() {
+                    let notice = json!({ "retry_after_ms": retry_after.as_millis() as u64 });
+                    match permit.limits.on_limit {
+                        LimitMode::Reject => {
+                            yield Message::control("rate-limited", notice);
+                            return;
+                        }
+                        LimitMode::Throttle => {
+                            yield Message::control("throttled", notice);
+                            sleep(retry_after).await;
+                        }
+                    }
+                }
+            }
+            yield message;
+        }
+    }
+}
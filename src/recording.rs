@@ -0,0 +1,475 @@
+use crate::error::AppError;
+use crate::stream::StreamSettings;
+use crate::transport::Message;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::AbortHandle;
+use tokio::time::{sleep_until, timeout_at, Instant};
+use tracing::error;
+
+// events kept per recording unless max_events says otherwise
+const MAX_EVENTS: usize = 10_000;
+
+// one event as it came off the upstream feed, at_ms after the recording started
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordedEvent {
+    pub at_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingStatus {
+    Recording,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Recording {
+    pub url: String,
+    pub started: DateTime<Utc>,
+    pub status: RecordingStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub events: Vec<RecordedEvent>,
+}
+
+#[derive(Serialize)]
+pub struct RecordingSummary {
+    name: String,
+    url: String,
+    started: DateTime<Utc>,
+    status: RecordingStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    events: usize,
+}
+
+impl Recording {
+    fn summary(&self, name: &str) -> RecordingSummary {
+        RecordingSummary {
+            name: name.to_string(),
+            url: self.url.clone(),
+            started: self.started,
+            status: self.status,
+            error: self.error.clone(),
+            events: self.events.len(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewRecording {
+    pub name: String,
+    pub url: String,
+    pub max_events: Option<usize>,
+    pub duration_secs: Option<u64>,
+}
+
+struct Entry {
+    recording: Arc<Mutex<Recording>>,
+    task: Option<AbortHandle>,
+}
+
+// recordings of upstream SSE feeds, taken over /recordings and kept as JSON files in
+// [recordings] dir when it is set
+pub struct Recordings {
+    entries: RwLock<BTreeMap<String, Entry>>,
+    dir: Option<PathBuf>,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl Recordings {
+    // starts from the recordings already in dir
+    pub fn load(dir: Option<&Path>) -> Result<Self, String> {
+        let mut entries = BTreeMap::new();
+        if let Some(dir) = dir.filter(|dir| dir.exists()) {
+            let listing =
+                fs::read_dir(dir).map_err(|e| format!("recordings.dir {}: {e}", dir.display()))?;
+            for file in listing.flatten().map(|entry| entry.path()) {
+                let Some(name) = file
+                    .extension()
+                    .filter(|extension| *extension == "json")
+                    .and(file.file_stem())
+                    .and_then(|stem| stem.to_str())
+                    .filter(|stem| valid_name(stem))
+                else {
+                    continue;
+                };
+                let recording: Recording = fs::read_to_string(&file)
+                    .map_err(|e| e.to_string())
+                    .and_then(|source| serde_json::from_str(&source).map_err(|e| e.to_string()))
+                    .map_err(|e| format!("recordings.dir {}: {e}", file.display()))?;
+                entries.insert(
+                    name.to_string(),
+                    Entry {
+                        recording: Arc::new(Mutex::new(recording)),
+                        task: None,
+                    },
+                );
+            }
+        }
+        Ok(Recordings {
+            entries: RwLock::new(entries),
+            dir: dir.map(Path::to_path_buf),
+        })
+    }
+
+    pub fn summaries(&self) -> Vec<RecordingSummary> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| entry.recording.lock().unwrap().summary(name))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Recording> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|entry| entry.recording.lock().unwrap().clone())
+    }
+
+    pub fn start(&self, request: NewRecording) -> Result<RecordingSummary, AppError> {
+        if !valid_name(&request.name) {
+            return Err(AppError::bad_request(format!(
+                "{:?} is not a valid name, use letters, digits, - and _",
+                request.name
+            )));
+        }
+        let upstream = Upstream::parse(&request.url).map_err(AppError::bad_request)?;
+        let mut entries = self.entries.write().unwrap();
+        if entries.contains_key(&request.name) {
+            return Err(AppError::new(StatusCode::CONFLICT).with_detail(format!(
+                "a recording named {} exists, DELETE it first",
+                request.name
+            )));
+        }
+
+        let recording = Arc::new(Mutex::new(Recording {
+            url: request.url.clone(),
+            started: Utc::now(),
+            status: RecordingStatus::Recording,
+            error: None,
+            events: Vec::new(),
+        }));
+        let limits = Limits {
+            max_events: request.max_events.unwrap_or(MAX_EVENTS).min(MAX_EVENTS),
+            duration: request.duration_secs.map(Duration::from_secs),
+        };
+        let file = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", request.name)));
+        let task = tokio::spawn(record(upstream, limits, recording.clone(), file));
+        let summary = recording.lock().unwrap().summary(&request.name);
+        entries.insert(
+            request.name,
+            Entry {
+                recording,
+                task: Some(task.abort_handle()),
+            },
+        );
+        Ok(summary)
+    }
+
+    // stops the recording if it is still running
+    pub fn delete(&self, name: &str) -> Result<(), AppError> {
+        let entry = self
+            .entries
+            .write()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| AppError::not_found(format!("no recording named {name}")))?;
+        if let Some(task) = entry.task {
+            task.abort();
+        }
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_file(dir.join(format!("{name}.json")));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Limits {
+    max_events: usize,
+    duration: Option<Duration>,
+}
+
+async fn record(
+    upstream: Upstream,
+    limits: Limits,
+    recording: Arc<Mutex<Recording>>,
+    file: Option<PathBuf>,
+) {
+    let started = Instant::now();
+    let deadline = limits.duration.map(|duration| started + duration);
+
+    let result = async {
+        let mut feed = upstream.connect(None).await.map_err(|e| e.to_string())?;
+        loop {
+            let next = match deadline {
+                Some(deadline) => match timeout_at(deadline, feed.next_event()).await {
+                    Ok(next) => next,
+                    Err(_) => return Ok(()),
+                },
+                None => feed.next_event().await,
+            };
+            let Some(mut event) = next.map_err(|e| e.to_string())? else {
+                return Ok(());
+            };
+            event.at_ms = started.elapsed().as_millis() as u64;
+            let mut recording = recording.lock().unwrap();
+            recording.events.push(event);
+            if recording.events.len() >= limits.max_events {
+                return Ok(());
+            }
+        }
+    }
+    .await;
+
+    let mut recording = recording.lock().unwrap();
+    match result {
+        Ok(()) => recording.status = RecordingStatus::Done,
+        Err(error) => {
+            recording.status = RecordingStatus::Failed;
+            recording.error = Some(error);
+        }
+    }
+    if let Some(file) = file {
+        let source = serde_json::to_string_pretty(&*recording).unwrap_or_default();
+        let written = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&file, source + "\n"));
+        if let Err(error) = written {
+            error!("writing {}: {error}", file.display());
+        }
+    }
+}
+
+// an http:// SSE endpoint; there is no TLS client, so https upstreams need a local proxy
+pub(crate) struct Upstream {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Upstream {
+    pub(crate) fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("{url} is not an http:// URL"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !authority.ends_with(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("{url} has an invalid port"))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("{url} has no host"));
+        }
+        Ok(Upstream {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    // last_event_id resumes the stream the way a reconnecting EventSource does
+    pub(crate) async fn connect(&self, last_event_id: Option<&str>) -> io::Result<Feed> {
+        let address = (self.host.trim_matches(['[', ']']), self.port);
+        let mut stream = TcpStream::connect(address).await?;
+        let resume = last_event_id
+            .map(|id| format!("Last-Event-ID: {id}\r\n"))
+            .unwrap_or_default();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nAccept: text/event-stream\r\n{resume}Connection: close\r\n\r\n",
+            self.path, self.host, self.port
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        reader.read_line(&mut status).await?;
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+        if code != "200" {
+            return Err(io::Error::other(format!(
+                "upstream answered {}",
+                status.trim()
+            )));
+        }
+        let mut chunked = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                chunked |= name.trim().eq_ignore_ascii_case("transfer-encoding")
+                    && value.trim().eq_ignore_ascii_case("chunked");
+            }
+        }
+        Ok(Feed {
+            reader,
+            chunked,
+            finished: false,
+            pending: Vec::new(),
+        })
+    }
+}
+
+// the response body, decoded and split back into SSE events
+pub(crate) struct Feed {
+    reader: BufReader<TcpStream>,
+    chunked: bool,
+    finished: bool,
+    // body bytes not yet split into lines
+    pending: Vec<u8>,
+}
+
+impl Feed {
+    // the next piece of the body, None once it has ended
+    async fn read_body(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+        if !self.chunked {
+            let mut buffer = vec![0; 8192];
+            let read = self.reader.read(&mut buffer).await?;
+            buffer.truncate(read);
+            self.finished = read == 0;
+            return Ok((read > 0).then_some(buffer));
+        }
+
+        let mut size = String::new();
+        self.reader.read_line(&mut size).await?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| io::Error::other(format!("bad chunk size {size:?}")))?;
+        if size == 0 {
+            self.finished = true;
+            return Ok(None);
+        }
+        let mut chunk = vec![0; size + 2];
+        self.reader.read_exact(&mut chunk).await?;
+        chunk.truncate(size);
+        Ok(Some(chunk))
+    }
+
+    async fn next_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            if let Some(newline) = self.pending.iter().position(|byte| *byte == b'\n') {
+                let mut line = self.pending.drain(..=newline).collect::<Vec<_>>();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+            }
+            match self.read_body().await? {
+                Some(piece) => self.pending.extend(piece),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub(crate) async fn next_event(&mut self) -> io::Result<Option<RecordedEvent>> {
+        let mut event = None;
+        let mut id = None;
+        let mut data: Option<String> = None;
+        while let Some(line) = self.next_line().await? {
+            if line.is_empty() {
+                if let Some(data) = data.take() {
+                    return Ok(Some(RecordedEvent {
+                        at_ms: 0,
+                        event: event.take(),
+                        id: id.take(),
+                        data,
+                    }));
+                }
+                event = None;
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((&line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => event = Some(value.to_string()),
+                "id" => id = Some(value.to_string()),
+                "data" => match &mut data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => data = Some(value.to_string()),
+                },
+                // comments and retry
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+// the recorded events with their original gaps divided by speed, then the end of the stream
+pub fn recorded_events(
+    settings: StreamSettings,
+    recording: Recording,
+    speed: f64,
+) -> impl Stream<Item = Message> {
+    async_stream::stream! {
+        yield Message::control("handshake", settings.handshake());
+        let started = Instant::now();
+
+        for (index, recorded) in recording.events.into_iter().enumerate() {
+            sleep_until(started + Duration::from_millis(recorded.at_ms).div_f64(speed)).await;
+            let id = recorded
+                .id
+                .as_deref()
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(index as u64 + 1);
+            // JSON data goes back out as JSON, anything else exactly as it came
+            let message = match serde_json::from_str::<Value>(&recorded.data) {
+                Ok(data) => Message::data(recorded.event, data),
+                Err(_) => {
+                    let mut message = Message::data(recorded.event, Value::Null);
+                    message.raw = Some(recorded.data);
+                    message
+                }
+            };
+            yield message.with_id(id);
+        }
+    }
+}
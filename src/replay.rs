@@ -0,0 +1,137 @@
+use crate::resume::Position;
+use crate::shape::shape_hash;
+use crate::stream::StreamSettings;
+use crate::transport::Message;
+use axum::http::HeaderMap;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+const LAST_EVENT_ID: &str = "last-event-id";
+// events kept per stream key
+const CAPACITY: usize = 100;
+// stream keys remembered before the least recently used is forgotten
+const MAX_KEYS: usize = 1000;
+
+// the tail of the latest connection under one stream key
+struct Recording {
+    stream_id: Uuid,
+    seed: u64,
+    shape_hash: String,
+    // where the generator stood after the last recorded tick
+    position: Position,
+    events: VecDeque<Message>,
+    used: Instant,
+}
+
+#[derive(Default)]
+pub struct Replays {
+    recordings: Mutex<HashMap<String, Arc<Mutex<Recording>>>>,
+}
+
+pub fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse().ok())
+}
+
+impl Replays {
+    // continues the key's recording when the client reconnects with a Last-Event-ID it holds,
+    // adopting its stream id, seed and position; otherwise starts a new recording under the key
+    pub fn start(
+        &self,
+        key: String,
+        settings: &mut StreamSettings,
+        last_event_id: Option<u64>,
+    ) -> Recorder {
+        let mut recordings = self.recordings.lock().unwrap();
+        let hash = shape_hash(&settings.shape);
+
+        let resumed = last_event_id.and_then(|last_id| {
+            let recording = recordings.get(&key)?;
+            let mut held = recording.lock().unwrap();
+            if held.shape_hash != hash || last_id > held.position.sequence {
+                return None;
+            }
+            held.used = Instant::now();
+            settings.stream_id = held.stream_id;
+            settings.seed = held.seed;
+            settings.position = held.position;
+
+            let oldest = held.events.front().and_then(|message| message.id);
+            let gap = oldest
+                .filter(|oldest| last_id + 1 < *oldest)
+                .map(|oldest| json!({ "last_event_id": last_id, "oldest_id": oldest }));
+            let backlog = held
+                .events
+                .iter()
+                .filter(|message| message.id.is_some_and(|id| id > last_id))
+                .cloned()
+                .collect();
+            Some((recording.clone(), backlog, gap))
+        });
+        if let Some((recording, backlog, gap)) = resumed {
+            return Recorder {
+                recording,
+                backlog,
+                gap,
+            };
+        }
+
+        if recordings.len() >= MAX_KEYS && !recordings.contains_key(&key) {
+            let oldest = recordings
+                .iter()
+                .min_by_key(|(_, recording)| recording.lock().unwrap().used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                recordings.remove(&oldest);
+            }
+        }
+        let recording = Arc::new(Mutex::new(Recording {
+            stream_id: settings.stream_id,
+            seed: settings.seed,
+            shape_hash: hash,
+            position: settings.position,
+            events: VecDeque::new(),
+            used: Instant::now(),
+        }));
+        recordings.insert(key, recording.clone());
+        Recorder {
+            recording,
+            backlog: Vec::new(),
+            gap: None,
+        }
+    }
+}
+
+// one connection's handle on its key's recording
+#[derive(Clone)]
+pub struct Recorder {
+    recording: Arc<Mutex<Recording>>,
+    // recorded events after the client's Last-Event-ID, sent before live ones
+    pub backlog: Vec<Message>,
+    // set when events between the Last-Event-ID and the oldest kept one were lost
+    pub gap: Option<Value>,
+}
+
+impl Recorder {
+    pub fn record(&self, message: &Message) {
+        if message.control || message.id.is_none() {
+            return;
+        }
+        let mut recording = self.recording.lock().unwrap();
+        if recording.events.len() == CAPACITY {
+            recording.events.pop_front();
+        }
+        recording.events.push_back(message.clone());
+    }
+
+    pub fn advance(&self, position: Position) {
+        let mut recording = self.recording.lock().unwrap();
+        recording.position = position;
+        recording.used = Instant::now();
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::AppError;
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const TOKEN_VERSION: u32 = 1;
+
+// where a stream's generator stands: events emitted so far and the rng's word position
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Position {
+    pub sequence: u64,
+    #[serde(with = "u128_string")]
+    pub word_pos: u128,
+}
+
+// self-contained, so a token stays usable across server restarts
+#[derive(Serialize, Deserialize)]
+pub struct ResumeToken {
+    pub v: u32,
+    pub stream_id: Uuid,
+    pub seed: u64,
+    pub interval_min: u64,
+    pub interval_max: u64,
+    pub shape_hash: String,
+    pub position: Position,
+}
+
+impl ResumeToken {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(token: &str) -> Result<Self, AppError> {
+        let invalid = || {
+            AppError::bad_request("resume is not a token issued by this server")
+                .with_type("/problems/invalid-resume-token", "Invalid resume token")
+        };
+
+        let json = URL_SAFE_NO_PAD
+            .decode(token.trim_end_matches('='))
+            .map_err(|_| invalid())?;
+        let token: ResumeToken = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        if token.v != TOKEN_VERSION {
+            return Err(invalid());
+        }
+
+        Ok(token)
+    }
+
+    pub fn check_shape(&self, shape_hash: &str) -> Result<(), AppError> {
+        if self.shape_hash == shape_hash {
+            return Ok(());
+        }
+
+        Err(AppError::new(StatusCode::CONFLICT)
+            .with_type(
+                "/problems/resume-shape-mismatch",
+                "Resume token shape mismatch",
+            )
+            .with_detail(format!(
+                "the token was issued for shape {}, this request resolves to shape {shape_hash}",
+                self.shape_hash
+            )))
+    }
+}
+
+// u128 does not survive JSON number round-trips, so word positions travel as strings
+mod u128_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,302 @@
+use crate::config::{Packs, ScenarioConfig};
+use crate::custom::CustomSubstitutions;
+use crate::directives::StreamDirectives;
+use crate::error::AppError;
+use crate::lint::{lint_shape, Severity};
+use crate::pools::Pools;
+use crate::resume::Position;
+use crate::stream::{EventGenerator, StreamSettings};
+use crate::template::GenContext;
+use crate::transport::Message;
+use axum::http::StatusCode;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::{sleep, sleep_until, Instant};
+
+const DEFAULT_RATE: f64 = 1.0;
+const MAX_RATE: f64 = 1000.0;
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FixedEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    #[serde(default)]
+    pub data: Value,
+}
+
+// one step of a timeline: exactly one of fixed events, generated events or a pause
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhaseDef {
+    // sent back to back as written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<FixedEvent>>,
+    // generated at rate events a second until duration_secs or count runs out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shape: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pause_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioDef {
+    pub phases: Vec<PhaseDef>,
+}
+
+enum Phase {
+    Fixed(Vec<FixedEvent>),
+    Generate {
+        shape: Map<String, Value>,
+        every: Duration,
+        duration: Option<Duration>,
+        count: Option<u64>,
+    },
+    Pause(Duration),
+}
+
+// an ordered timeline of phases, played from the start for every connection
+pub struct Scenario {
+    pub def: ScenarioDef,
+    phases: Vec<Phase>,
+}
+
+impl Scenario {
+    fn compile(
+        def: ScenarioDef,
+        packs: Packs,
+        custom: &CustomSubstitutions,
+        strict: bool,
+    ) -> Result<Self, String> {
+        if def.phases.is_empty() {
+            return Err("a scenario needs at least one phase".to_string());
+        }
+        let last = def.phases.len() - 1;
+        let phases = def
+            .phases
+            .iter()
+            .enumerate()
+            .map(|(index, phase)| {
+                Self::compile_phase(phase, index == last, packs, custom, strict)
+                    .map_err(|e| format!("phases[{index}]: {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Scenario { def, phases })
+    }
+
+    fn compile_phase(
+        phase: &PhaseDef,
+        last: bool,
+        packs: Packs,
+        custom: &CustomSubstitutions,
+        strict: bool,
+    ) -> Result<Phase, String> {
+        let generated =
+            phase.rate.is_some() || phase.duration_secs.is_some() || phase.count.is_some();
+        match (&phase.events, &phase.shape, phase.pause_ms) {
+            (Some(events), None, None) if !generated => Ok(Phase::Fixed(events.clone())),
+            (None, None, Some(pause_ms)) if !generated => {
+                Ok(Phase::Pause(Duration::from_millis(pause_ms)))
+            }
+            (None, Some(shape), None) => {
+                let shape = match shape {
+                    // shapes may be written as objects or as JSON strings
+                    Value::String(source) => serde_json::from_str(source)
+                        .map_err(|e| format!("shape is not JSON: {e}"))?,
+                    shape => shape.clone(),
+                };
+                if let Some(error) = lint_shape(&shape, packs, custom, strict)
+                    .into_iter()
+                    .find(|diagnostic| diagnostic.severity == Severity::Error)
+                {
+                    return Err(format!("shape {}: {}", error.path, error.message));
+                }
+                let Value::Object(shape) = shape else {
+                    return Err("shape must be a JSON object".to_string());
+                };
+
+                let rate = phase.rate.unwrap_or(DEFAULT_RATE);
+                if !(rate > 0.0 && rate <= MAX_RATE) {
+                    return Err(format!("rate must be above 0 and at most {MAX_RATE}"));
+                }
+                let duration = phase
+                    .duration_secs
+                    .map(|secs| {
+                        Duration::try_from_secs_f64(secs)
+                            .map_err(|_| "duration_secs must be a positive number".to_string())
+                    })
+                    .transpose()?;
+                if duration.is_none() && phase.count.is_none() && !last {
+                    return Err(
+                        "only the last phase may run forever, give this one duration_secs or count"
+                            .to_string(),
+                    );
+                }
+                Ok(Phase::Generate {
+                    shape,
+                    every: Duration::from_secs_f64(1.0 / rate),
+                    duration,
+                    count: phase.count,
+                })
+            }
+            _ => Err(
+                "needs exactly one of events, shape (with rate, duration_secs and count) and pause_ms"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+// scenarios from [scenarios] in config and those registered over /scenarios
+#[derive(Default)]
+pub struct Scenarios {
+    scenarios: RwLock<BTreeMap<String, Arc<Scenario>>>,
+}
+
+impl Scenarios {
+    pub fn from_config(
+        configs: &BTreeMap<String, ScenarioConfig>,
+        packs: Packs,
+        custom: &CustomSubstitutions,
+        strict: bool,
+    ) -> Result<Self, String> {
+        let scenarios = configs
+            .iter()
+            .map(|(name, config)| {
+                let table = format!("scenarios.{name}");
+                let def = match &config.file {
+                    Some(file) => {
+                        let source = fs::read_to_string(file)
+                            .map_err(|e| format!("{table}.file {}: {e}", file.display()))?;
+                        serde_json::from_str(&source)
+                            .map_err(|e| format!("{table}.file {}: {e}", file.display()))?
+                    }
+                    None => ScenarioDef {
+                        phases: config.phases.clone(),
+                    },
+                };
+                Scenario::compile(def, packs, custom, strict)
+                    .map(|scenario| (name.clone(), Arc::new(scenario)))
+                    .map_err(|e| format!("{table}: {e}"))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Scenarios {
+            scenarios: RwLock::new(scenarios),
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.scenarios.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Scenario>> {
+        self.scenarios.read().unwrap().get(name).cloned()
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        def: ScenarioDef,
+        packs: Packs,
+        custom: &CustomSubstitutions,
+        strict: bool,
+    ) -> Result<(), AppError> {
+        let scenario =
+            Scenario::compile(def, packs, custom, strict).map_err(AppError::bad_request)?;
+        let mut scenarios = self.scenarios.write().unwrap();
+        if scenarios.contains_key(name) {
+            return Err(AppError::new(StatusCode::CONFLICT)
+                .with_detail(format!("a scenario named {name} exists, DELETE it first")));
+        }
+        scenarios.insert(name.to_string(), Arc::new(scenario));
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), AppError> {
+        self.scenarios
+            .write()
+            .unwrap()
+            .remove(name)
+            .map(drop)
+            .ok_or_else(|| AppError::not_found(format!("no scenario named {name}")))
+    }
+}
+
+// plays the scenario's phases in order, with ids running across all of them, then ends
+pub fn scenario_events(
+    settings: StreamSettings,
+    scenario: Arc<Scenario>,
+    pools: Arc<Pools>,
+) -> impl Stream<Item = Message> {
+    async_stream::stream! {
+        yield Message::control("handshake", settings.handshake());
+        let mut sequence = 0u64;
+
+        for (index, phase) in scenario.phases.iter().enumerate() {
+            match phase {
+                Phase::Fixed(events) => {
+                    for fixed in events {
+                        sequence += 1;
+                        yield Message::data(
+                            fixed.event.clone().or_else(|| settings.event.clone()),
+                            fixed.data.clone(),
+                        )
+                        .with_id(sequence);
+                    }
+                }
+                Phase::Pause(pause) => sleep(*pause).await,
+                Phase::Generate { shape, every, duration, count } => {
+                    let mut generator = EventGenerator::new(
+                        shape,
+                        &StreamDirectives::default(),
+                        GenContext::new(
+                            settings.seed.wrapping_add(index as u64),
+                            settings.packs,
+                            settings.clock,
+                            pools.clone(),
+                        )
+                        .with_normalization(settings.normalization)
+                        .with_locale(settings.locale)
+                        .with_custom(settings.custom.clone()),
+                        Position::default(),
+                    );
+                    let started = Instant::now();
+                    let deadline = duration.map(|duration| started + duration);
+                    let mut sent = 0u64;
+                    let mut next = started;
+
+                    while count.is_none_or(|count| sent < count) {
+                        if deadline.is_some_and(|deadline| next >= deadline) {
+                            break;
+                        }
+                        sleep_until(next).await;
+                        let (_, events) = generator.next_events();
+                        for event in events {
+                            sequence += 1;
+                            let event = settings.projection.apply(event);
+                            yield settings.data_message(sequence, None, event);
+                        }
+                        sent += 1;
+                        next += *every;
+                    }
+                    // a phase with a duration fills it, even when its count ran out first
+                    if let Some(deadline) = deadline {
+                        sleep_until(deadline).await;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::calendar::{CalendarEvent, Occurrences};
+use crate::config::{Config, ScheduleConfig};
+use crate::connections::{Command, Connections};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+use uuid::Uuid;
+
+// how long the scheduler idles when nothing is pending
+const IDLE: std::time::Duration = std::time::Duration::from_secs(3600);
+
+// either an RFC 3339 timestamp or a UTC time of day ("14:00"), meaning its next occurrence
+pub fn parse_at(at: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(at) {
+        let at = at.with_timezone(&Utc);
+        if at <= now {
+            return Err(format!("{at} is in the past"));
+        }
+        return Ok(at);
+    }
+
+    let time = NaiveTime::parse_from_str(at, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(at, "%H:%M"))
+        .map_err(|_| format!("{at} is neither an RFC 3339 timestamp nor a HH:MM[:SS] time"))?;
+    let today = now.date_naive().and_time(time).and_utc();
+    Ok(if today > now {
+        today
+    } else {
+        today + Duration::days(1)
+    })
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScheduledEvent {
+    pub id: Uuid,
+    pub at: DateTime<Utc>,
+    pub event: String,
+    pub data: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    pub burst: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rrule: Option<String>,
+    // calendar events come back for their next occurrence after firing
+    #[serde(skip)]
+    occurrences: Option<Occurrences>,
+}
+
+// one-off and calendar events waiting for their time, injected into live streams by a background task
+pub struct Scheduler {
+    pending: Mutex<Vec<ScheduledEvent>>,
+    changed: Notify,
+}
+
+impl Scheduler {
+    pub fn spawn(connections: Arc<Connections>, config: &Config) -> Arc<Self> {
+        let scheduler = Arc::new(Scheduler {
+            pending: Mutex::default(),
+            changed: Notify::new(),
+        });
+        for entry in &config.schedule {
+            scheduler.add(entry.clone());
+        }
+        if let Some(calendar) = &config.calendar {
+            scheduler.add_calendar(&calendar.events, calendar.stream.clone());
+        }
+
+        tokio::spawn(scheduler.clone().run(connections));
+        scheduler
+    }
+
+    pub fn add(&self, entry: ScheduleConfig) -> ScheduledEvent {
+        let scheduled = ScheduledEvent {
+            id: Uuid::new_v4(),
+            at: entry.at,
+            event: entry.event,
+            data: entry.data,
+            stream: entry.stream,
+            burst: 1,
+            rrule: None,
+            occurrences: None,
+        };
+        self.pending.lock().unwrap().push(scheduled.clone());
+        self.changed.notify_one();
+        scheduled
+    }
+
+    // schedules the next occurrence of each event; events with none left are skipped
+    pub fn add_calendar(
+        &self,
+        events: &[CalendarEvent],
+        stream: Option<String>,
+    ) -> Vec<ScheduledEvent> {
+        let now = Utc::now();
+        let scheduled = events
+            .iter()
+            .filter_map(|event| {
+                Some(ScheduledEvent {
+                    id: Uuid::new_v4(),
+                    at: event.occurrences.next(now)?,
+                    event: event.summary.clone(),
+                    data: event.data.clone(),
+                    stream: stream.clone(),
+                    burst: event.burst,
+                    rrule: event.occurrences.rrule.clone(),
+                    occurrences: Some(event.occurrences.clone()),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        self.pending
+            .lock()
+            .unwrap()
+            .extend(scheduled.iter().cloned());
+        self.changed.notify_one();
+        scheduled
+    }
+
+    pub fn pending(&self) -> Vec<ScheduledEvent> {
+        let mut pending = self.pending.lock().unwrap().clone();
+        pending.sort_by_key(|scheduled| scheduled.at);
+        pending
+    }
+
+    pub fn cancel(&self, id: Uuid) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let before = pending.len();
+        pending.retain(|scheduled| scheduled.id != id);
+        pending.len() < before
+    }
+
+    async fn run(self: Arc<Self>, connections: Arc<Connections>) {
+        loop {
+            let next = self
+                .pending
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|scheduled| scheduled.at)
+                .min();
+            let wait = next.map_or(IDLE, |at| (at - Utc::now()).to_std().unwrap_or_default());
+
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = self.changed.notified() => continue,
+            }
+
+            let now = Utc::now();
+            let due = {
+                let mut pending = self.pending.lock().unwrap();
+                let (due, waiting): (Vec<_>, Vec<_>) =
+                    pending.drain(..).partition(|scheduled| scheduled.at <= now);
+                *pending = waiting;
+                due
+            };
+            for mut scheduled in due {
+                for _ in 0..scheduled.burst {
+                    connections.broadcast(scheduled.stream.as_deref(), || Command::Emit {
+                        event: scheduled.event.clone(),
+                        data: scheduled.data.clone(),
+                    });
+                }
+
+                // occurrences missed while the server was busy are skipped, not replayed
+                let next = scheduled
+                    .occurrences
+                    .as_ref()
+                    .and_then(|occurrences| occurrences.next(now));
+                if let Some(next) = next {
+                    scheduled.at = next;
+                    self.pending.lock().unwrap().push(scheduled);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use serde_json::{json, Map, Value};
+
+// $refs followed within one branch before giving up, so recursive schemas end
+const MAX_DEPTH: usize = 16;
+const DEFAULT_MAX_ITEMS: u64 = 3;
+
+// a shape whose events fit a JSON Schema: the schema's types, enums, consts, formats, bounds
+// and array lengths become placeholders, and untyped strings are guessed from the field name
+pub fn shape_from_schema(schema: &Value) -> Result<Value, String> {
+    shape_from_schema_in(schema, schema)
+}
+
+// for a schema inside a larger document, such as an OpenAPI spec, that its $refs point into
+pub fn shape_from_schema_in(document: &Value, schema: &Value) -> Result<Value, String> {
+    let shape = Schema { root: document }.derive(schema, "", 0)?;
+    if !shape.is_object() {
+        return Err("the schema must describe an object".to_string());
+    }
+    Ok(shape)
+}
+
+struct Schema<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Schema<'a> {
+    // #/$defs/... and #/definitions/... within the same document
+    fn resolve(&self, reference: &str) -> Result<&'a Value, String> {
+        reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| format!("$ref {reference} does not point into this schema"))
+    }
+
+    fn derive(&self, schema: &Value, name: &str, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Ok(Value::Null);
+        }
+        let Value::Object(schema) = schema else {
+            // true and the empty schema take anything
+            return Ok(string_for(name, None));
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return self.derive(self.resolve(reference)?, name, depth + 1);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(value.clone());
+        }
+        if let Some(choices) = schema.get("enum").and_then(Value::as_array) {
+            return Ok(one_of(choices));
+        }
+        // the first branch that fits, merged over allOf's requirements
+        for keyword in ["oneOf", "anyOf"] {
+            if let Some(branch) = schema
+                .get(keyword)
+                .and_then(Value::as_array)
+                .and_then(|branches| branches.iter().find(|branch| !is_null(branch)))
+            {
+                return self.derive(branch, name, depth + 1);
+            }
+        }
+        if let Some(branches) = schema.get("allOf").and_then(Value::as_array) {
+            let mut merged = schema.clone();
+            merged.remove("allOf");
+            for branch in branches {
+                let branch = match branch.get("$ref").and_then(Value::as_str) {
+                    Some(reference) => self.resolve(reference)?,
+                    None => branch,
+                };
+                merge(&mut merged, branch);
+            }
+            return self.derive_typed(&merged, name, depth);
+        }
+        self.derive_typed(schema, name, depth)
+    }
+
+    fn derive_typed(
+        &self,
+        schema: &Map<String, Value>,
+        name: &str,
+        depth: usize,
+    ) -> Result<Value, String> {
+        let kind = match schema.get("type") {
+            Some(Value::String(kind)) => Some(kind.as_str()),
+            // ["string", "null"] is taken as its first non-null type
+            Some(Value::Array(kinds)) => kinds
+                .iter()
+                .filter_map(Value::as_str)
+                .find(|kind| *kind != "null"),
+            _ => None,
+        };
+        let kind = kind.or_else(|| {
+            if schema.contains_key("properties") {
+                Some("object")
+            } else if schema.contains_key("items") {
+                Some("array")
+            } else {
+                None
+            }
+        });
+
+        Ok(match kind {
+            Some("object") => {
+                let fields = schema
+                    .get("properties")
+                    .and_then(Value::as_object)
+                    .into_iter()
+                    .flatten()
+                    .map(|(field, property)| {
+                        self.derive(property, field, depth + 1)
+                            .map(|value| (field.clone(), value))
+                    })
+                    .collect::<Result<Map<_, _>, String>>()?;
+                Value::Object(fields)
+            }
+            Some("array") => {
+                let min = bound(schema, "minItems").unwrap_or(1.0) as u64;
+                let max = bound(schema, "maxItems")
+                    .map_or(min.max(DEFAULT_MAX_ITEMS), |max| max as u64)
+                    .max(min);
+                let items = match schema.get("items") {
+                    Some(items) => self.derive(items, name, depth + 1)?,
+                    None => string_for(name, None),
+                };
+                json!({ "$repeat": { "count": format!("{min}-{max}"), "shape": items } })
+            }
+            Some("integer") => {
+                let min = bound(schema, "minimum")
+                    .or(bound(schema, "exclusiveMinimum").map(|min| min.floor() + 1.0))
+                    .unwrap_or(0.0)
+                    .ceil();
+                let max = bound(schema, "maximum")
+                    .or(bound(schema, "exclusiveMaximum").map(|max| max.ceil() - 1.0))
+                    .unwrap_or(min.max(0.0) + 100.0)
+                    .floor()
+                    .max(min);
+                if min >= 0.0 {
+                    Value::from(format!("{{int:{min}-{max}}}"))
+                } else {
+                    Value::from(format!("{{float:{min}-{max}:0}}"))
+                }
+            }
+            Some("number") => {
+                let min = bound(schema, "minimum")
+                    .or(bound(schema, "exclusiveMinimum").map(|min| min + 0.01))
+                    .unwrap_or(0.0);
+                let max = bound(schema, "maximum")
+                    .or(bound(schema, "exclusiveMaximum").map(|max| max - 0.01))
+                    .unwrap_or(min.max(0.0) + 100.0)
+                    .max(min);
+                Value::from(format!("{{float:{min}-{max}:2}}"))
+            }
+            Some("boolean") => Value::from("{bool}"),
+            Some("null") => Value::Null,
+            _ => string_for(name, Some(schema)),
+        })
+    }
+}
+
+fn is_null(schema: &Value) -> bool {
+    schema.get("type").and_then(Value::as_str) == Some("null")
+}
+
+fn bound(schema: &Map<String, Value>, keyword: &str) -> Option<f64> {
+    schema.get(keyword).and_then(Value::as_f64)
+}
+
+// later allOf branches add properties to, and override keywords of, the earlier ones
+fn merge(into: &mut Map<String, Value>, branch: &Value) {
+    let Value::Object(branch) = branch else {
+        return;
+    };
+    for (keyword, value) in branch {
+        match (into.get_mut(keyword), value) {
+            (Some(Value::Object(existing)), Value::Object(value)) if keyword == "properties" => {
+                existing.extend(value.clone());
+            }
+            _ => {
+                into.insert(keyword.clone(), value.clone());
+            }
+        }
+    }
+}
+
+// {oneof:...} for string choices; other enums always send their first choice, as {oneof}
+// only picks strings
+fn one_of(choices: &[Value]) -> Value {
+    let strings = choices
+        .iter()
+        .map(|choice| {
+            choice
+                .as_str()
+                .filter(|choice| !choice.contains(['|', '{', '}', '*', '\\']))
+        })
+        .collect::<Option<Vec<_>>>();
+    match strings {
+        Some(strings) if !strings.is_empty() => {
+            Value::from(format!("{{oneof:{}}}", strings.join("|")))
+        }
+        _ => choices.first().cloned().unwrap_or(Value::Null),
+    }
+}
+
+fn string_for(name: &str, schema: Option<&Map<String, Value>>) -> Value {
+    let format = schema
+        .and_then(|schema| schema.get("format"))
+        .and_then(Value::as_str);
+    let placeholder = match format {
+        Some("email" | "idn-email") => "{email}",
+        Some("uuid") => "{uuid}",
+        Some("date-time") => "{datetime}",
+        Some("ipv4") => "{ip}",
+        Some("uri" | "url" | "iri") => "https://example.com/{uuid}",
+        Some("hostname" | "idn-hostname") => "{uuid}.example.com",
+        _ => {
+            let name = name.to_lowercase();
+            match name.as_str() {
+                "id" | "uuid" | "guid" => "{uuid}",
+                "email" => "{email}",
+                "phone" | "phone_number" => "{phone}",
+                "city" => "{city}",
+                "zip" | "zipcode" | "postcode" | "postal_code" => "{zip}",
+                "address" | "street" => "{address}",
+                "color" | "colour" => "{color}",
+                "ip" => "{ip}",
+                _ if name.ends_with("name") => "{name}",
+                _ if name.ends_with("_at") || name.ends_with("time") => "{datetime}",
+                _ if name.ends_with("_id") => "{uuid}",
+                _ => {
+                    // a single short word when the schema caps the length
+                    let short = schema
+                        .and_then(|schema| bound(schema, "maxLength"))
+                        .is_some_and(|max| max < 20.0);
+                    if short {
+                        "{words:1}"
+                    } else {
+                        "{words}"
+                    }
+                }
+            }
+        }
+    };
+    Value::from(placeholder)
+}
@@ -0,0 +1,90 @@
+use crate::transport::Message;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Value};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+use tracing::warn;
+
+const SCRIPT_ERROR_EVENT: &str = "script_error";
+// how long the script may take over one event before the stream carries on without it
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// a stream's `script` command, run as a process since no embedded script engine is available to
+// the build, and started once per connection so it can keep state between events:
+// each data event goes to its stdin as a line of {"id", "event", "data"}, and it answers each with
+// a line holding the data to send instead, or null to leave the event out
+pub fn with_script(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    script: Option<Vec<String>>,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        let Some((program, args)) = script.as_deref().and_then(<[String]>::split_first) else {
+            while let Some(message) = messages.next().await {
+                yield message;
+            }
+            return;
+        };
+
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let (_child, mut stdin, mut stdout) = match child {
+            Ok(mut child) => match (child.stdin.take(), child.stdout.take()) {
+                (Some(stdin), Some(stdout)) => (child, stdin, BufReader::new(stdout).lines()),
+                _ => unreachable!("stdin and stdout are piped"),
+            },
+            Err(e) => {
+                yield failure(format!("{program} could not be started: {e}"));
+                while let Some(message) = messages.next().await {
+                    yield message;
+                }
+                return;
+            }
+        };
+
+        // after a failure the rest of the stream goes out as generated
+        let mut failed = false;
+        while let Some(mut message) = messages.next().await {
+            if failed || message.control || message.abort || message.raw.is_some() {
+                yield message;
+                continue;
+            }
+            let request = json!({ "id": message.id, "event": message.event, "data": message.data });
+            let answer = timeout(ANSWER_TIMEOUT, async {
+                stdin.write_all(format!("{request}\n").as_bytes()).await?;
+                stdin.flush().await?;
+                stdout.next_line().await
+            })
+            .await;
+            let error = match answer {
+                Ok(Ok(Some(line))) => match serde_json::from_str::<Value>(&line) {
+                    Ok(Value::Null) => continue,
+                    Ok(data) => {
+                        message.data = data;
+                        yield message;
+                        continue;
+                    }
+                    Err(e) => format!("{program} answered with invalid JSON: {e}"),
+                },
+                Ok(Ok(None)) => format!("{program} exited"),
+                Ok(Err(e)) => format!("{program}: {e}"),
+                Err(_) => format!("{program} took over {}s to answer", ANSWER_TIMEOUT.as_secs()),
+            };
+            failed = true;
+            yield failure(error);
+            yield message;
+        }
+    }
+}
+
+fn failure(error: String) -> Message {
+    warn!("script failed: {error}");
+    Message::control(SCRIPT_ERROR_EVENT, json!({ "error": error }))
+}
@@ -0,0 +1,138 @@
+use crate::config::Server;
+use rand::{thread_rng, Rng};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener};
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+const BACKLOG: i32 = 1024;
+
+// every address each host resolves to, so "localhost" covers both 127.0.0.1 and ::1
+async fn addresses(server: &Server) -> io::Result<Vec<SocketAddr>> {
+    let mut addresses = Vec::new();
+    for host in server.host.list() {
+        // [::] is how IPv6 hosts are usually written next to a port
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let resolved = lookup_host((host, server.port))
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("{host}: {e}")))?;
+        for address in resolved {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "host resolves to no address",
+        ));
+    }
+    Ok(addresses)
+}
+
+pub async fn listeners(server: &Server) -> io::Result<Vec<TcpListener>> {
+    let addresses = addresses(server).await?;
+    // a dual-stack [::] would already hold the port for 0.0.0.0
+    let v6_only = server
+        .v6_only
+        .unwrap_or_else(|| addresses.iter().any(SocketAddr::is_ipv4));
+
+    addresses
+        .into_iter()
+        .map(|address| {
+            listener(address, server, v6_only)
+                .map_err(|e| io::Error::new(e.kind(), format!("{address}: {e}")))
+        })
+        .collect()
+}
+
+// with reuse_port a new instance can bind alongside this one while it drains
+fn listener(address: SocketAddr, server: &Server, v6_only: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    if server.reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reuse_port needs SO_REUSEPORT, which this platform lacks",
+        ));
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// set once the server stops accepting; live streams then end at random points within the
+// drain window, so their clients reconnect elsewhere a few at a time instead of all at once
+pub struct Shutdown {
+    stopping: watch::Sender<bool>,
+    drain: Duration,
+}
+
+impl Shutdown {
+    pub fn new(drain: Duration) -> Self {
+        Shutdown {
+            stopping: watch::Sender::new(false),
+            drain,
+        }
+    }
+
+    pub fn begin(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    pub async fn started(&self) {
+        let _ = self
+            .stopping
+            .subscribe()
+            .wait_for(|stopping| *stopping)
+            .await;
+    }
+
+    // resolves when the stream holding it should close
+    pub fn stream_deadline(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut stopping = self.stopping.subscribe();
+        let drain = self.drain;
+        async move {
+            let _ = stopping.wait_for(|stopping| *stopping).await;
+            if !drain.is_zero() {
+                let offset = thread_rng().gen_range(Duration::ZERO..drain);
+                sleep(offset).await;
+            }
+        }
+    }
+}
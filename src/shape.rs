@@ -0,0 +1,169 @@
+use crate::config::Packs;
+use crate::custom::CustomSubstitutions;
+use crate::directives::StreamDirectives;
+use crate::error::AppError;
+use crate::lint::{lint_shape, type_name, Severity};
+use crate::pools::Pools;
+use crate::resume::Position;
+use crate::stream::EventGenerator;
+use crate::template::{Clock, GenContext};
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use serde_json::{Map, Value};
+use std::io::Read;
+use std::sync::Arc;
+
+pub enum ShapeParam {
+    Json(String),
+    Base64(String),
+    Gzip(String),
+}
+
+impl ShapeParam {
+    pub fn from_query(
+        shape: Option<String>,
+        shape_b64: Option<String>,
+        shape_gz: Option<String>,
+    ) -> Result<Option<Self>, AppError> {
+        let mut params = [
+            shape.map(ShapeParam::Json),
+            shape_b64.map(ShapeParam::Base64),
+            shape_gz.map(ShapeParam::Gzip),
+        ]
+        .into_iter()
+        .flatten();
+
+        let param = params.next();
+        if params.next().is_some() {
+            return Err(AppError::bad_request(
+                "pass only one of shape, shape_b64 and shape_gz",
+            ));
+        }
+
+        Ok(param)
+    }
+
+    pub fn decode(self, max_bytes: usize) -> Result<Map<String, Value>, AppError> {
+        let source = match self {
+            ShapeParam::Json(source) => source.into_bytes(),
+            ShapeParam::Base64(encoded) => decode_base64("shape_b64", &encoded)?,
+            ShapeParam::Gzip(encoded) => {
+                let compressed = decode_base64("shape_gz", &encoded)?;
+                let mut source = Vec::new();
+                GzDecoder::new(compressed.as_slice())
+                    .take(max_bytes as u64 + 1)
+                    .read_to_end(&mut source)
+                    .map_err(|e| AppError::bad_request(format!("shape_gz is not gzip: {e}")))?;
+                source
+            }
+        };
+
+        if source.len() > max_bytes {
+            return Err(AppError::new(StatusCode::PAYLOAD_TOO_LARGE)
+                .with_detail(format!("shapes are limited to {max_bytes} bytes")));
+        }
+
+        let source = String::from_utf8(source)
+            .map_err(|_| AppError::bad_request("shape does not decode to UTF-8"))?;
+        parse_shape(&source)
+    }
+}
+
+fn decode_base64(param: &str, encoded: &str) -> Result<Vec<u8>, AppError> {
+    URL_SAFE_NO_PAD
+        .decode(encoded.trim_end_matches('='))
+        .map_err(|e| AppError::bad_request(format!("{param} is not base64url: {e}")))
+}
+
+const INVALID_SHAPE: &str = "/problems/invalid-shape";
+const EXPECTED_SHAPE: &str = r#"a JSON object of fields to placeholders, such as {"id": "{uuid}"}"#;
+
+fn invalid_shape(detail: String) -> AppError {
+    AppError::new(StatusCode::UNPROCESSABLE_ENTITY)
+        .with_type(INVALID_SHAPE, "Invalid shape")
+        .with_detail(detail)
+        .with_extension("expected", Value::from(EXPECTED_SHAPE))
+}
+
+pub fn parse_shape(source: &str) -> Result<Map<String, Value>, AppError> {
+    let shape = serde_json::from_str(source).map_err(|e| {
+        invalid_shape(format!("shape is not valid JSON: {e}"))
+            .with_extension("line", Value::from(e.line()))
+            .with_extension("column", Value::from(e.column()))
+    })?;
+    shape_object(shape)
+}
+
+pub fn shape_object(shape: Value) -> Result<Map<String, Value>, AppError> {
+    match shape {
+        Value::Object(object) => Ok(object),
+        other => {
+            let found = type_name(&other);
+            Err(
+                invalid_shape(format!("shape must be a JSON object, found {found}"))
+                    .with_extension("found", Value::from(found)),
+            )
+        }
+    }
+}
+
+// FNV-1a over the canonical (key-sorted) serialization, stable across restarts and versions
+pub fn shape_hash(shape: &Map<String, Value>) -> String {
+    let canonical = Value::Object(shape.clone()).to_string();
+    let hash = canonical
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    format!("{hash:016x}")
+}
+
+// a shape checked the way the server checks one, for generating its events outside a server
+pub struct ShapeTemplate {
+    shape: Map<String, Value>,
+    directives: StreamDirectives,
+}
+
+impl ShapeTemplate {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let shape = serde_json::from_str(source).map_err(|e| format!("shape is not JSON: {e}"))?;
+        Self::from_value(shape)
+    }
+
+    pub fn from_value(shape: Value) -> Result<Self, String> {
+        if let Some(error) = lint_shape(
+            &shape,
+            Packs::default(),
+            &CustomSubstitutions::default(),
+            false,
+        )
+        .into_iter()
+        .find(|diagnostic| diagnostic.severity == Severity::Error)
+        {
+            return Err(format!("shape {}: {}", error.path, error.message));
+        }
+        let Value::Object(shape) = shape else {
+            return Err("shape must be a JSON object".to_string());
+        };
+        let directives = StreamDirectives::parse(&shape)?;
+        Ok(ShapeTemplate { shape, directives })
+    }
+
+    pub fn hash(&self) -> String {
+        shape_hash(&self.shape)
+    }
+
+    // the events a stream with this shape and seed would send, in order
+    pub fn generator(&self, seed: u64) -> EventGenerator {
+        let ctx = GenContext::new(
+            seed,
+            Packs::default(),
+            Clock::default(),
+            Arc::new(Pools::new(0)),
+        );
+        EventGenerator::new(&self.shape, &self.directives, ctx, Position::default())
+    }
+}
@@ -0,0 +1,277 @@
+use crate::directives::EVENTS;
+use crate::schema::shape_from_schema_in;
+use serde_json::{json, Value};
+
+const EVENT_STREAM: &str = "text/event-stream";
+
+// one streaming operation of an OpenAPI or AsyncAPI document, served as a named stream
+pub struct SpecStream {
+    pub name: String,
+    // with {param} segments written :param
+    pub path: String,
+    // None when the document gives no object schema, so the [defaults] shape is used
+    pub shape: Option<Value>,
+    // the SSE event name, for operations with a single named message
+    pub event: Option<String>,
+}
+
+pub fn spec_streams(document: &Value) -> Result<Vec<SpecStream>, String> {
+    if document.get("openapi").is_some() {
+        openapi_streams(document)
+    } else if let Some(version) = document.get("asyncapi").and_then(Value::as_str) {
+        if version.starts_with("2.") {
+            asyncapi2_streams(document)
+        } else {
+            asyncapi3_streams(document)
+        }
+    } else {
+        Err("not an OpenAPI or AsyncAPI document, it has neither openapi nor asyncapi".to_string())
+    }
+}
+
+// follows $refs within the document
+fn resolve<'a>(document: &'a Value, mut value: &'a Value) -> Result<&'a Value, String> {
+    for _ in 0..16 {
+        let Some(reference) = value.get("$ref").and_then(Value::as_str) else {
+            return Ok(value);
+        };
+        value = reference
+            .strip_prefix('#')
+            .and_then(|pointer| document.pointer(pointer))
+            .ok_or_else(|| format!("$ref {reference} does not point into the document"))?;
+    }
+    Err("$refs nest too deeply".to_string())
+}
+
+// /orders/{id}/events as /orders/:id/events
+fn route_path(path: &str) -> String {
+    let path = path
+        .trim_start_matches('/')
+        .split('/')
+        .map(
+            |segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(param) => format!(":{param}"),
+                None => segment.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("/{path}")
+}
+
+fn stream_name(path: &str) -> String {
+    let name = path
+        .chars()
+        .map(|char| {
+            if char.is_ascii_alphanumeric() {
+                char
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    name.trim_matches('_').to_string()
+}
+
+fn shape(document: &Value, schema: Option<&Value>) -> Result<Option<Value>, String> {
+    let Some(schema) = schema else {
+        return Ok(None);
+    };
+    let schema = resolve(document, schema)?;
+    // text/event-stream responses are often declared as plain strings
+    Ok(shape_from_schema_in(document, schema).ok())
+}
+
+// operations answering with text/event-stream; OpenAPI 3.2's itemSchema describes one event
+fn openapi_streams(document: &Value) -> Result<Vec<SpecStream>, String> {
+    let mut streams = Vec::new();
+    for (path, item) in document
+        .get("paths")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        let item = resolve(document, item)?;
+        for method in ["get", "post"] {
+            let Some(operation) = item.get(method) else {
+                continue;
+            };
+            let media = operation
+                .get("responses")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter(|(status, _)| status.starts_with('2') || *status == "default")
+                .map(|(_, response)| resolve(document, response))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .find_map(|response| response.get("content")?.get(EVENT_STREAM));
+            let Some(media) = media else {
+                continue;
+            };
+            let name = operation
+                .get("operationId")
+                .and_then(Value::as_str)
+                .map_or_else(|| stream_name(&format!("{method}_{path}")), str::to_string);
+            streams.push(SpecStream {
+                shape: shape(
+                    document,
+                    media.get("itemSchema").or_else(|| media.get("schema")),
+                )
+                .map_err(|e| format!("paths.{path}.{method}: {e}"))?,
+                name,
+                path: route_path(path),
+                event: None,
+            });
+        }
+    }
+    Ok(streams)
+}
+
+struct Message<'a> {
+    name: Option<String>,
+    payload: Option<&'a Value>,
+}
+
+fn message<'a>(
+    document: &'a Value,
+    key: Option<&str>,
+    message: &'a Value,
+) -> Result<Message<'a>, String> {
+    // a message's name, else the key it is declared or referenced under
+    let referenced = message
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|reference| reference.rsplit('/').next());
+    let message = resolve(document, message)?;
+    let name = message
+        .get("name")
+        .or_else(|| message.get("messageId"))
+        .and_then(Value::as_str)
+        .or(referenced)
+        .or(key)
+        .map(str::to_string);
+    Ok(Message {
+        name,
+        payload: message.get("payload"),
+    })
+}
+
+// several messages on one operation become event types, each named after its message
+fn stream(
+    document: &Value,
+    name: String,
+    path: &str,
+    messages: Vec<Message>,
+) -> Result<SpecStream, String> {
+    let path = route_path(path);
+    if let [message] = messages.as_slice() {
+        return Ok(SpecStream {
+            shape: shape(document, message.payload)?,
+            event: message.name.clone(),
+            name,
+            path,
+        });
+    }
+    let events = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| {
+            Ok(json!({
+                "name": message.name.clone().unwrap_or_else(|| format!("message{}", index + 1)),
+                "shape": shape(document, message.payload)?.unwrap_or_else(|| json!({})),
+            }))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(SpecStream {
+        shape: (!events.is_empty()).then(|| json!({ EVENTS: events })),
+        event: None,
+        name,
+        path,
+    })
+}
+
+// channels with a subscribe operation, which in 2.x is what the application sends
+fn asyncapi2_streams(document: &Value) -> Result<Vec<SpecStream>, String> {
+    let mut streams = Vec::new();
+    for (channel, item) in document
+        .get("channels")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        let Some(operation) = resolve(document, item)?.get("subscribe") else {
+            continue;
+        };
+        let messages = match operation.get("message").map(|m| resolve(document, m)) {
+            Some(Ok(message)) => match message.get("oneOf").and_then(Value::as_array) {
+                Some(messages) => messages
+                    .iter()
+                    .map(|m| self::message(document, None, m))
+                    .collect::<Result<Vec<_>, _>>(),
+                None => self::message(document, None, operation.get("message").unwrap_or(message))
+                    .map(|message| vec![message]),
+            },
+            Some(Err(e)) => Err(e),
+            None => Ok(Vec::new()),
+        }
+        .map_err(|e| format!("channels.{channel}: {e}"))?;
+        let name = operation
+            .get("operationId")
+            .and_then(Value::as_str)
+            .map_or_else(|| stream_name(channel), str::to_string);
+        streams.push(
+            stream(document, name, channel, messages)
+                .map_err(|e| format!("channels.{channel}: {e}"))?,
+        );
+    }
+    Ok(streams)
+}
+
+// send operations, at their channel's address
+fn asyncapi3_streams(document: &Value) -> Result<Vec<SpecStream>, String> {
+    let mut streams = Vec::new();
+    for (key, operation) in document
+        .get("operations")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        let operation = resolve(document, operation)?;
+        if operation.get("action").and_then(Value::as_str) != Some("send") {
+            continue;
+        }
+        let error = |e: String| format!("operations.{key}: {e}");
+        let channel_ref = operation
+            .get("channel")
+            .ok_or_else(|| error("needs a channel".to_string()))?;
+        let channel_key = channel_ref
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|reference| reference.rsplit('/').next())
+            .unwrap_or(key);
+        let channel = resolve(document, channel_ref).map_err(error)?;
+        let address = channel
+            .get("address")
+            .and_then(Value::as_str)
+            .unwrap_or(channel_key);
+
+        // the operation's messages when it lists them, else all of its channel's
+        let messages = match operation.get("messages").and_then(Value::as_array) {
+            Some(messages) => messages
+                .iter()
+                .map(|m| self::message(document, None, m))
+                .collect::<Result<Vec<_>, _>>(),
+            None => channel
+                .get("messages")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .map(|(key, m)| self::message(document, Some(key), m))
+                .collect(),
+        }
+        .map_err(error)?;
+        streams.push(stream(document, key.clone(), address, messages).map_err(error)?);
+    }
+    Ok(streams)
+}
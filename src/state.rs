@@ -0,0 +1,39 @@
+use crate::channels::Channels;
+use crate::config::Config;
+use crate::connections::Connections;
+use crate::custom::CustomSubstitutions;
+use crate::feeds::Feeds;
+use crate::maintenance::Maintenance;
+use crate::pools::Pools;
+use crate::presets::Presets;
+use crate::quotas::Quotas;
+use crate::recording::Recordings;
+use crate::replay::Replays;
+use crate::scenario::Scenarios;
+use crate::schedule::Scheduler;
+use crate::server::Shutdown;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub connections: Arc<Connections>,
+    pub pools: Arc<Pools>,
+    pub feeds: Arc<Feeds>,
+    pub scheduler: Arc<Scheduler>,
+    pub quotas: Arc<Quotas>,
+    pub maintenance: Arc<Maintenance>,
+    pub shutdown: Arc<Shutdown>,
+    pub presets: Arc<Presets>,
+    pub replays: Arc<Replays>,
+    pub custom: Arc<CustomSubstitutions>,
+    pub scenarios: Arc<Scenarios>,
+    pub recordings: Arc<Recordings>,
+    pub channels: Arc<Channels>,
+    // one permit per live stream under [server] max_connections
+    pub connection_slots: Option<Arc<Semaphore>>,
+    // for /readyz's uptime
+    pub started: Instant,
+}
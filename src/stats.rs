@@ -0,0 +1,111 @@
+use crate::transport::Message;
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+const STATS_EVENT: &str = "stats";
+const MIN_PERIOD: Duration = Duration::from_millis(100);
+
+// a period such as "10s", "500ms", "2m" or "1h"; a bare number is milliseconds
+#[derive(Clone, Copy)]
+pub struct Period(pub Duration);
+
+impl Period {
+    fn parse(source: &str) -> Result<Self, String> {
+        let source = source.trim();
+        let split = source
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(source.len());
+        let (amount, unit) = source.split_at(split);
+        let amount: u64 = amount
+            .parse()
+            .map_err(|_| format!("{source} is not a period such as 10s or 500ms"))?;
+        let period = match unit {
+            "" | "ms" => Duration::from_millis(amount),
+            "s" => Duration::from_secs(amount),
+            "m" => Duration::from_secs(amount.saturating_mul(60)),
+            "h" => Duration::from_secs(amount.saturating_mul(3600)),
+            other => {
+                return Err(format!(
+                    "unknown unit {other} in {source}, use ms, s, m or h"
+                ))
+            }
+        };
+        if period < MIN_PERIOD {
+            return Err(format!("{source} is under the 100ms minimum"));
+        }
+        Ok(Period(period))
+    }
+}
+
+impl<'de> Deserialize<'de> for Period {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Millis(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Millis(millis) => Period::parse(&millis.to_string()),
+            Raw::Text(text) => Period::parse(&text),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+// interleaves a stats event every period with the connection's data event count, its rate
+// since the last stats event and overall, and its uptime
+pub fn with_stats(
+    messages: impl Stream<Item = Message> + Send + 'static,
+    every: Option<Period>,
+) -> impl Stream<Item = Message> + Send + 'static {
+    async_stream::stream! {
+        let mut messages = Box::pin(messages);
+        let Some(Period(every)) = every else {
+            while let Some(message) = messages.next().await {
+                yield message;
+            }
+            return;
+        };
+
+        let started = Instant::now();
+        let mut ticks = interval_at(started + every, every);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let (mut events, mut last_events, mut last_tick) = (0u64, 0u64, started);
+
+        loop {
+            tokio::select! {
+                message = messages.next() => match message {
+                    Some(message) => {
+                        if !message.control {
+                            events += 1;
+                        }
+                        yield message;
+                    }
+                    None => break,
+                },
+                now = ticks.tick() => {
+                    let uptime = now.duration_since(started);
+                    let since_last = now.duration_since(last_tick).as_secs_f64();
+                    let rate = (events - last_events) as f64 / since_last.max(f64::EPSILON);
+                    let average = events as f64 / uptime.as_secs_f64().max(f64::EPSILON);
+                    yield Message::control(
+                        STATS_EVENT,
+                        json!({
+                            "events": events,
+                            "rate_per_s": (rate * 100.0).round() / 100.0,
+                            "avg_rate_per_s": (average * 100.0).round() / 100.0,
+                            "uptime_ms": uptime.as_millis() as u64,
+                            "ts": Utc::now().to_rfc3339(),
+                        }),
+                    );
+                    (last_events, last_tick) = (events, now);
+                }
+            }
+        }
+    }
+}
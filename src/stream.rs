@@ -0,0 +1,1377 @@
+use crate::chaos::Chaos;
+use crate::config::{Config, Packs, StreamConfig};
+use crate::connections::{Command, Control};
+use crate::crud::{lifecycle_event, Churn, Collection, Crud};
+use crate::custom::CustomSubstitutions;
+use crate::directives::{
+    template, EventType, Evolution, KeyReuse, Migration, Saga, StreamDirectives, EVENTS,
+};
+use crate::error::AppError;
+use crate::finite::{Finish, Stop};
+use crate::fixture::{Fixture, Pace};
+use crate::network::Network;
+use crate::oversize::{Enforced, OversizeStrategy, SizeLimit};
+use crate::pattern::{Pattern, PatternKind};
+use crate::payload::{PayloadEncoding, PayloadFormat};
+use crate::pii::{PiiMap, PiiMode, INLINE_KEY};
+use crate::pools::Pools;
+use crate::projection::{insert, lookup, lookup_mut, remove, Projection};
+use crate::quotas::QuotaAction;
+use crate::recording::Recording;
+use crate::replay::Recorder;
+use crate::resume::{Position, ResumeToken, TOKEN_VERSION};
+use crate::scenario::Scenario;
+use crate::shape::{shape_hash, shape_object, ShapeParam};
+use crate::stats::Period;
+use crate::template::{
+    fill_detached, fill_object_fields, Clock, CompiledShape, GenContext, Locale, Normalization,
+    MAX_SHIFT_MS,
+};
+use crate::transport::{FieldOrder, Framing, LineEnding, Message, Transport};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use chrono::Utc;
+use futures::{future, Stream};
+use rand::{thread_rng, Rng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::VecDeque;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{interval_at, sleep, Instant, Interval, MissedTickBehavior, Sleep};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Deserialize, Validate, Default, Clone)]
+pub struct SSEQuery {
+    pub interval_min: Option<u64>,
+    pub interval_max: Option<u64>,
+    pub shape: Option<String>,
+    pub shape_b64: Option<String>,
+    pub shape_gz: Option<String>,
+    // makes the stream reproducible: same seed and shape, same events at the same intervals
+    #[validate(range(max = 9007199254740991_u64, message = "seed must be at most 2^53 - 1"))]
+    pub seed: Option<u64>,
+    // event types as JSON, merged into the shape as its $events directive
+    pub events: Option<String>,
+    // a shape registered over /shapes
+    pub preset: Option<String>,
+    // a timeline registered over /scenarios or under [scenarios], played instead of the shape
+    pub scenario: Option<String>,
+    // a recording taken over /recordings, played back instead of the shape
+    pub replay: Option<String>,
+    // playback speed of a replayed recording, 2.0 halving its gaps
+    #[validate(range(
+        exclusive_min = 0.0,
+        max = 1000.0,
+        message = "speed must be above 0 and at most 1000"
+    ))]
+    pub speed: Option<f64>,
+    // a .jsonl file in [fixtures] dir, its lines streamed instead of the shape
+    pub fixture: Option<String>,
+    pub fixture_pace: Option<Pace>,
+    #[validate(range(min = 1, message = "fixture_interval_ms must be at least 1"))]
+    pub fixture_interval_ms: Option<u64>,
+    // the dotted field fixture_pace=timestamps reads
+    pub fixture_timestamp: Option<String>,
+    pub fixture_loop: Option<bool>,
+    pub resume: Option<String>,
+    pub resume_every: Option<u64>,
+    #[validate(range(min = 100, message = "heartbeat_ms must be >= 100ms"))]
+    pub heartbeat_ms: Option<u64>,
+    #[validate(range(min = 100, message = "keepalive_ms must be >= 100ms"))]
+    pub keepalive_ms: Option<u64>,
+    pub fields: Option<String>,
+    pub exclude: Option<String>,
+    pub mask: Option<String>,
+    #[validate(range(min = 0.0, max = 1.0, message = "sample must be between 0 and 1"))]
+    pub sample: Option<f64>,
+    #[validate(range(min = 64, message = "max_event_bytes must be >= 64"))]
+    pub max_event_bytes: Option<usize>,
+    pub oversize: Option<OversizeStrategy>,
+    pub pii: Option<PiiMode>,
+    pub redact: Option<String>,
+    pub redact_after_ms: Option<u64>,
+    #[validate(range(
+        min = -MAX_SHIFT_MS,
+        max = MAX_SHIFT_MS,
+        message = "clock_skew_ms is limited to 100 years"
+    ))]
+    pub clock_skew_ms: Option<i64>,
+    #[validate(range(
+        min = -86_400_000.0,
+        max = 86_400_000.0,
+        message = "clock_drift_ms_per_min is limited to a day a minute"
+    ))]
+    pub clock_drift_ms_per_min: Option<f64>,
+    #[validate(range(min = 0.0, max = 1.0, message = "disorder must be between 0 and 1"))]
+    pub disorder: Option<f64>,
+    #[validate(range(
+        min = 1,
+        max = 100,
+        message = "disorder_window must be between 1 and 100"
+    ))]
+    pub disorder_window: Option<usize>,
+    pub pattern: Option<PatternKind>,
+    // poisson events per second
+    #[validate(range(
+        min = 0.001,
+        max = 1000.0,
+        message = "rate must be between 0.001 and 1000"
+    ))]
+    pub rate: Option<f64>,
+    #[validate(range(
+        min = 1,
+        max = 10000,
+        message = "burst_size must be between 1 and 10000"
+    ))]
+    pub burst_size: Option<u64>,
+    pub burst_every: Option<Period>,
+    #[validate(range(min = 1, message = "ramp_events must be at least 1"))]
+    pub ramp_events: Option<u64>,
+    #[serde(rename = "chaos.drop_prob")]
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "chaos.drop_prob must be between 0 and 1"
+    ))]
+    pub chaos_drop_prob: Option<f64>,
+    #[serde(rename = "chaos.stall_prob")]
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "chaos.stall_prob must be between 0 and 1"
+    ))]
+    pub chaos_stall_prob: Option<f64>,
+    #[serde(rename = "chaos.stall_ms")]
+    #[validate(range(max = 600000, message = "chaos.stall_ms must be at most 10 minutes"))]
+    pub chaos_stall_ms: Option<u64>,
+    #[serde(rename = "chaos.malformed_prob")]
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "chaos.malformed_prob must be between 0 and 1"
+    ))]
+    pub chaos_malformed_prob: Option<f64>,
+    #[serde(rename = "network.trickle_ms")]
+    #[validate(range(
+        max = 600000,
+        message = "network.trickle_ms must be at most 10 minutes"
+    ))]
+    pub network_trickle_ms: Option<u64>,
+    #[serde(rename = "network.spike_prob")]
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "network.spike_prob must be between 0 and 1"
+    ))]
+    pub network_spike_prob: Option<f64>,
+    #[serde(rename = "network.spike_ms")]
+    #[validate(range(max = 600000, message = "network.spike_ms must be at most 10 minutes"))]
+    pub network_spike_ms: Option<u64>,
+    #[serde(rename = "network.bytes_per_sec")]
+    #[validate(range(min = 1, message = "network.bytes_per_sec must be at least 1"))]
+    pub network_bytes_per_sec: Option<u64>,
+    pub crud: Option<bool>,
+    pub crud_key: Option<String>,
+    #[validate(range(min = 1, message = "crud_entities must be at least 1"))]
+    pub crud_entities: Option<usize>,
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "crud_update_rate must be between 0 and 1"
+    ))]
+    pub crud_update_rate: Option<f64>,
+    #[validate(range(
+        min = 0.0,
+        max = 1.0,
+        message = "crud_delete_rate must be between 0 and 1"
+    ))]
+    pub crud_delete_rate: Option<f64>,
+    pub conflate: Option<bool>,
+    pub normalization: Option<Normalization>,
+    pub locale: Option<Locale>,
+    pub stats_every: Option<Period>,
+    #[validate(range(min = 1, message = "max_events must be at least 1"))]
+    pub max_events: Option<u64>,
+    #[validate(range(min = 1, message = "duration_secs must be at least 1"))]
+    pub duration_secs: Option<u64>,
+    // false ends finite streams without the final end event
+    pub end_event: Option<bool>,
+    pub encoding: Option<Transport>,
+    // how each event's object is serialized into data, apart from the transport's encoding
+    pub payload_encoding: Option<PayloadFormat>,
+    pub proto_message: Option<String>,
+    // the Last-Event-ID replay buffer to record into, when several clients share a route
+    pub stream_key: Option<String>,
+    #[validate(range(min = 1, message = "quota_after must be at least 1"))]
+    pub quota_after: Option<u64>,
+    pub on_quota: Option<QuotaAction>,
+    pub line_endings: Option<LineEnding>,
+    pub bom: Option<bool>,
+    pub multiline_data: Option<bool>,
+    pub field_order: Option<FieldOrder>,
+    #[validate(range(min = 0.0, max = 1.0, message = "comment_rate must be between 0 and 1"))]
+    pub comment_rate: Option<f64>,
+    #[validate(range(max = 16777216, message = "pad_bytes must be at most 16MiB"))]
+    pub pad_bytes: Option<usize>,
+    #[validate(range(min = 1, message = "chunk_bytes must be at least 1"))]
+    pub chunk_bytes: Option<usize>,
+    // read by the quota check, for clients that can't set x-api-key
+    pub api_key: Option<String>,
+    // read by the JWT check, for clients that can't set Authorization
+    pub access_token: Option<String>,
+    // read by the [auth] check with mode = "query"
+    pub token: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct StreamSettings {
+    pub stream_id: Uuid,
+    pub seed: u64,
+    pub interval_min: u64,
+    pub interval_max: u64,
+    // [limits] max_events_per_sec, and the intervals asked for when they were slowed to it
+    pub max_events_per_sec: Option<f64>,
+    pub requested_intervals: Option<(u64, u64)>,
+    pub shape: Map<String, Value>,
+    pub event: Option<String>,
+    pub headers: HeaderMap,
+    pub directives: StreamDirectives,
+    pub position: Position,
+    pub resume_every: u64,
+    pub heartbeat: Option<Duration>,
+    pub keepalive: Option<Duration>,
+    pub projection: Projection,
+    pub sample: f64,
+    pub size_limit: Option<SizeLimit>,
+    pub packs: Packs,
+    pub pii: Option<PiiMode>,
+    pub redact: Vec<String>,
+    pub redact_after: Option<Duration>,
+    pub clock: Clock,
+    pub disorder: Option<Disorder>,
+    pub chaos: Option<Chaos>,
+    pub network: Option<Network>,
+    pub pattern: Pattern,
+    pub links: Links,
+    pub crud: Option<Crud>,
+    // collapse pending snapshot-stream deltas per key for this subscriber
+    pub conflate: bool,
+    pub quota: Option<QuotaExhaustion>,
+    pub framing: Framing,
+    pub normalization: Option<Normalization>,
+    pub locale: Locale,
+    pub custom: Arc<CustomSubstitutions>,
+    pub stats_every: Option<Period>,
+    pub finish: Finish,
+    pub encoding: Option<Transport>,
+    pub payload_format: PayloadFormat,
+    pub payload: Option<PayloadEncoding>,
+    pub script: Option<Vec<String>>,
+    pub stream_key: Option<String>,
+    // set by the handler for generated streams
+    pub replay: Option<Recorder>,
+    // set by the handler for ?scenario=
+    pub scenario: Option<Arc<Scenario>>,
+    // set by the handler for ?replay=, with its speed
+    pub recording: Option<(Recording, f64)>,
+    // set by the handler for ?fixture= or a configured fixture
+    pub fixture: Option<Fixture>,
+}
+
+// how a named stream feeds and draws from the shared entity pools
+#[derive(Default, Clone)]
+pub struct Links {
+    produces: Option<(String, Vec<String>)>,
+    references: Vec<(Vec<String>, String)>,
+}
+
+impl Links {
+    fn from_config(stream: &StreamConfig) -> Self {
+        Links {
+            produces: stream
+                .produces
+                .as_ref()
+                .map(|produces| (produces.pool.clone(), dotted(&produces.field))),
+            references: stream
+                .references
+                .iter()
+                .map(|(field, pool)| (dotted(field), pool.clone()))
+                .collect(),
+        }
+    }
+
+    // references resolve to null until the producing stream has emitted something; the picks
+    // come from the stream's rng so a seed reproduces them against the same pool contents
+    pub fn apply(&self, event: &mut Value, pools: &Pools, rng: &mut impl Rng) {
+        for (path, pool) in &self.references {
+            if let Some(value) = lookup_mut(event, path) {
+                *value = pools.pick(pool, rng).unwrap_or(Value::Null);
+            }
+        }
+
+        if let Some((pool, path)) = &self.produces {
+            if let Some(value) = lookup(event, path) {
+                pools.add(pool, value.clone());
+            }
+        }
+    }
+}
+
+// hold back a `rate` fraction of events until up to `window` later events have been generated
+#[derive(Clone, Copy)]
+pub struct Disorder {
+    pub rate: f64,
+    pub window: usize,
+}
+
+// a quota_exceeded event once the stream reaches sequence `after`, then `action`
+#[derive(Clone, Copy)]
+pub struct QuotaExhaustion {
+    pub after: u64,
+    pub action: QuotaAction,
+}
+
+const DEFAULT_EVENT_NAME: &str = "message";
+// seeds stay within JavaScript's safe integer range so browser clients can echo them back
+const MAX_SAFE_SEED: u64 = 1 << 53;
+const DEFAULT_RESUME_EVERY: u64 = 10;
+const DEFAULT_DISORDER_WINDOW: usize = 3;
+const DEFAULT_CRUD_KEY: &str = "id";
+const OVERSIZE_EVENT: &str = "oversize";
+const QUOTA_EXCEEDED_EVENT: &str = "quota_exceeded";
+const REDACTION_NOTICE_EVENT: &str = "redaction-notice";
+
+impl StreamSettings {
+    // crud_entities implies crud
+    fn resolve_crud(
+        query: &SSEQuery,
+        stream: Option<&StreamConfig>,
+    ) -> Result<Option<Crud>, AppError> {
+        let entities = query
+            .crud_entities
+            .or(stream.and_then(|stream| stream.crud_entities));
+        let enabled = query
+            .crud
+            .or(stream.and_then(|stream| stream.crud))
+            .unwrap_or(entities.is_some());
+        if !enabled {
+            return Ok(None);
+        }
+        let defaults = Churn::default();
+        let churn = Churn {
+            update: query
+                .crud_update_rate
+                .or(stream.and_then(|stream| stream.crud_update_rate))
+                .unwrap_or(defaults.update),
+            delete: query
+                .crud_delete_rate
+                .or(stream.and_then(|stream| stream.crud_delete_rate))
+                .unwrap_or(defaults.delete),
+        };
+        if churn.update + churn.delete > 1.0 {
+            return Err(AppError::bad_request(format!(
+                "crud_update_rate ({}) and crud_delete_rate ({}) must not add up to more than 1",
+                churn.update, churn.delete
+            )));
+        }
+        Ok(Some(Crud {
+            key: query
+                .crud_key
+                .clone()
+                .or(stream.and_then(|stream| stream.crud_key.clone()))
+                .unwrap_or_else(|| DEFAULT_CRUD_KEY.to_string()),
+            entities,
+            churn,
+        }))
+    }
+
+    pub fn resolve(
+        query: SSEQuery,
+        config: &Config,
+        stream: Option<&StreamConfig>,
+        custom: &Arc<CustomSubstitutions>,
+    ) -> Result<Self, AppError> {
+        let defaults = &config.defaults;
+        let chaos = Chaos::from_query(&query);
+        let network = Network::from_query(&query);
+        let crud = Self::resolve_crud(&query, stream)?;
+        let pattern = Pattern::from_query(&query);
+        let resume = query
+            .resume
+            .as_deref()
+            .map(ResumeToken::decode)
+            .transpose()?;
+        let interval_min = query
+            .interval_min
+            .or(stream.and_then(|stream| stream.interval_min))
+            .or(resume.as_ref().map(|token| token.interval_min))
+            .unwrap_or(defaults.interval_min);
+        let interval_max = query
+            .interval_max
+            .or(stream.and_then(|stream| stream.interval_max))
+            .or(resume.as_ref().map(|token| token.interval_max))
+            .unwrap_or(defaults.interval_max);
+        config
+            .limits
+            .check_intervals(Some(interval_min), Some(interval_max))?;
+        let max_events_per_sec = config.limits.max_events_per_sec;
+        let requested = (interval_min, interval_max);
+        let (interval_min, interval_max) =
+            clamp_intervals(max_events_per_sec, interval_min, interval_max).unwrap_or(requested);
+
+        let mut shape = match ShapeParam::from_query(query.shape, query.shape_b64, query.shape_gz)?
+        {
+            Some(param) => param.decode(config.limits.max_shape_bytes)?,
+            None => shape_object(
+                stream
+                    .and_then(|stream| stream.shape.clone())
+                    .unwrap_or_else(|| defaults.shape.clone()),
+            )?,
+        };
+
+        if let Some(events) = query.events {
+            let events = serde_json::from_str(&events)
+                .map_err(|e| AppError::bad_request(format!("events: {e}")))?;
+            shape.insert(EVENTS.to_string(), events);
+        }
+        if let Some(token) = &resume {
+            token.check_shape(&shape_hash(&shape))?;
+        }
+        let directives = StreamDirectives::parse(&shape).map_err(AppError::bad_request)?;
+        let payload_format = query
+            .payload_encoding
+            .or(stream.and_then(|stream| stream.payload_encoding))
+            .unwrap_or_default();
+        let payload = PayloadEncoding::resolve(
+            payload_format,
+            query
+                .proto_message
+                .or(stream.and_then(|stream| stream.proto_message.clone())),
+            &config.protobuf,
+        )?;
+        let seed = query.seed.or(stream.and_then(|stream| stream.seed));
+        let seed = match (&resume, seed) {
+            (Some(token), Some(seed)) if token.seed != seed => {
+                return Err(AppError::bad_request(format!(
+                    "seed {seed} does not match the resume token's seed {}",
+                    token.seed
+                )));
+            }
+            (Some(token), _) => token.seed,
+            (None, Some(seed)) => seed,
+            (None, None) => thread_rng().gen_range(0..MAX_SAFE_SEED),
+        };
+
+        Ok(StreamSettings {
+            stream_id: resume
+                .as_ref()
+                .map_or_else(Uuid::new_v4, |token| token.stream_id),
+            seed,
+            interval_min,
+            interval_max,
+            max_events_per_sec,
+            requested_intervals: (requested != (interval_min, interval_max)).then_some(requested),
+            shape,
+            event: stream.and_then(|stream| stream.event.clone()),
+            headers: {
+                let mut headers = config.server.headers.clone();
+                headers.extend(
+                    stream
+                        .map(|stream| stream.headers.clone())
+                        .unwrap_or_default(),
+                );
+                headers
+            },
+            directives,
+            position: resume.map(|token| token.position).unwrap_or_default(),
+            resume_every: query.resume_every.unwrap_or(DEFAULT_RESUME_EVERY),
+            heartbeat: query
+                .heartbeat_ms
+                .or(stream.and_then(|stream| stream.heartbeat_ms))
+                .or(defaults.heartbeat_ms)
+                .map(Duration::from_millis),
+            keepalive: query
+                .keepalive_ms
+                .or(stream.and_then(|stream| stream.keepalive_ms))
+                .or(defaults.keepalive_ms)
+                .map(Duration::from_millis),
+            projection: Projection::new(
+                query.fields.as_deref(),
+                query.exclude.as_deref(),
+                query.mask.as_deref(),
+            ),
+            sample: query.sample.unwrap_or(1.0),
+            size_limit: query
+                .max_event_bytes
+                .or(stream.and_then(|stream| stream.max_event_bytes))
+                .map(|max_bytes| SizeLimit {
+                    max_bytes,
+                    strategy: query
+                        .oversize
+                        .or(stream.and_then(|stream| stream.oversize))
+                        .unwrap_or_default(),
+                }),
+            packs: config.packs,
+            pii: query.pii,
+            redact: query
+                .redact
+                .as_deref()
+                .map(|fields| {
+                    fields
+                        .split(',')
+                        .map(str::trim)
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            redact_after: query.redact_after_ms.map(Duration::from_millis),
+            clock: Clock {
+                skew_ms: query.clock_skew_ms.unwrap_or_default(),
+                drift_ms_per_min: query.clock_drift_ms_per_min.unwrap_or_default(),
+                ..Clock::default()
+            },
+            chaos,
+            network,
+            pattern,
+            disorder: query.disorder.map(|rate| Disorder {
+                rate,
+                window: query.disorder_window.unwrap_or(DEFAULT_DISORDER_WINDOW),
+            }),
+            links: stream.map(Links::from_config).unwrap_or_default(),
+            crud,
+            conflate: query.conflate.unwrap_or_default(),
+            quota: query
+                .quota_after
+                .or(stream.and_then(|stream| stream.quota_after))
+                .map(|after| QuotaExhaustion {
+                    after,
+                    action: query
+                        .on_quota
+                        .or(stream.and_then(|stream| stream.on_quota))
+                        .unwrap_or_default(),
+                }),
+            normalization: query
+                .normalization
+                .or(stream.and_then(|stream| stream.normalization)),
+            locale: query
+                .locale
+                .or(stream.and_then(|stream| stream.locale))
+                .unwrap_or_default(),
+            custom: custom.clone(),
+            stats_every: query
+                .stats_every
+                .or(stream.and_then(|stream| stream.stats_every)),
+            finish: Finish {
+                max_events: query
+                    .max_events
+                    .or(stream.and_then(|stream| stream.max_events)),
+                duration: query
+                    .duration_secs
+                    .or(stream.and_then(|stream| stream.duration_secs))
+                    .map(Duration::from_secs),
+                end_event: query.end_event.unwrap_or(true),
+                stop: Stop::default(),
+            },
+            encoding: query.encoding,
+            payload_format,
+            payload,
+            script: stream
+                .and_then(|stream| stream.script.clone())
+                .or_else(|| defaults.script.clone()),
+            stream_key: query.stream_key,
+            replay: None,
+            scenario: None,
+            recording: None,
+            fixture: None,
+            framing: Framing {
+                line_ending: query.line_endings.unwrap_or_default(),
+                bom: query.bom.unwrap_or_default(),
+                multiline_data: query.multiline_data.unwrap_or_default(),
+                field_order: query.field_order.unwrap_or_default(),
+                comment_rate: query.comment_rate.unwrap_or_default(),
+                pad_bytes: query.pad_bytes,
+                chunk_bytes: query.chunk_bytes,
+            },
+        })
+    }
+}
+
+// the intervals slowed to the ceiling, when the shorter one would beat it
+pub fn clamp_intervals(
+    max_events_per_sec: Option<f64>,
+    interval_min: u64,
+    interval_max: u64,
+) -> Option<(u64, u64)> {
+    let shortest = (1000.0 / max_events_per_sec?).ceil() as u64;
+    (interval_min < shortest).then(|| (shortest, interval_max.max(shortest)))
+}
+
+impl StreamSettings {
+    // the config event sent after the handshake when the stream was slowed down
+    pub fn config_notice(&self) -> Option<Value> {
+        let (requested_min, requested_max) = self.requested_intervals?;
+        Some(json!({
+            "interval_min": self.interval_min,
+            "interval_max": self.interval_max,
+            "requested_interval_min": requested_min,
+            "requested_interval_max": requested_max,
+            "max_events_per_sec": self.max_events_per_sec,
+        }))
+    }
+
+    pub fn handshake(&self) -> Value {
+        json!({
+            "stream_id": self.stream_id,
+            "seed": self.seed,
+            "interval_min": self.interval_min,
+            "interval_max": self.interval_max,
+            "event": self.event.as_deref().unwrap_or(DEFAULT_EVENT_NAME),
+            "shape_hash": shape_hash(&self.shape),
+            "event_types": self.directives.events.as_ref().map(|events| {
+                events.iter().map(|event| event.name.as_str()).collect::<Vec<_>>()
+            }).or_else(|| self.directives.saga.as_ref().map(|saga| {
+                saga.steps.iter().map(|step| step.name.as_str()).collect::<Vec<_>>()
+            })),
+            "heartbeat_ms": self.heartbeat.map(|heartbeat| heartbeat.as_millis() as u64),
+            "sample": self.sample,
+            "max_event_bytes": self.size_limit.map(|limit| limit.max_bytes),
+            "payload_encoding": self.payload_format.name(),
+            "sequence": self.position.sequence,
+            "resume_token": self.resume_token(self.position),
+        })
+    }
+
+    // event_type names the event when the shape has several
+    pub fn data_message(&self, sequence: u64, event_type: Option<&str>, event: Value) -> Message {
+        let enforced = match self.size_limit {
+            Some(limit) => limit.enforce(sequence, event),
+            None => Enforced::Fits(event),
+        };
+
+        match enforced {
+            Enforced::Fits(event) => Message::data(
+                event_type
+                    .map(str::to_string)
+                    .or_else(|| self.event.clone()),
+                event,
+            ),
+            Enforced::Oversize(error) => Message::data(Some(OVERSIZE_EVENT.to_string()), error),
+        }
+        .with_id(sequence)
+    }
+
+    pub fn resume_token(&self, position: Position) -> String {
+        ResumeToken {
+            v: TOKEN_VERSION,
+            stream_id: self.stream_id,
+            seed: self.seed,
+            interval_min: self.interval_min,
+            interval_max: self.interval_max,
+            shape_hash: shape_hash(&self.shape),
+            position,
+        }
+        .encode()
+    }
+
+    pub fn metadata_headers(&self) -> HeaderMap {
+        let metadata = [
+            ("x-shape-hash", shape_hash(&self.shape)),
+            (
+                "x-stream-event",
+                self.event
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_EVENT_NAME.to_string()),
+            ),
+            (
+                "x-stream-interval-ms",
+                format!("{}-{}", self.interval_min, self.interval_max),
+            ),
+            (
+                "x-server-version",
+                format!("sse-proto/{}", env!("CARGO_PKG_VERSION")),
+            ),
+        ];
+
+        metadata
+            .into_iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(&value).ok()?,
+                ))
+            })
+            .collect()
+    }
+}
+
+// the data side of a stream: renders one event per call from the stream's seeded context
+pub struct EventGenerator {
+    template: Map<String, Value>,
+    // template, and the shapes below, compiled for filling
+    compiled: CompiledShape,
+    type_shapes: Vec<CompiledShape>,
+    migrated: Option<CompiledShape>,
+    ctx: GenContext,
+    sequence: u64,
+    key_pool: Option<KeyPool>,
+    evolution: Option<Evolution>,
+    // evolution steps folded into the template so far
+    evolved: usize,
+    migration: Option<Migration>,
+    types: Vec<EventType>,
+    // index into types of the latest tick's event
+    picked: Option<usize>,
+    saga: Option<SagaRun>,
+    // index into the saga's steps of the latest tick's event
+    stepped: Option<usize>,
+}
+
+struct SagaRun {
+    saga: Saga,
+    context: CompiledShape,
+    steps: Vec<CompiledShape>,
+    in_flight: Vec<Transaction>,
+}
+
+struct Transaction {
+    context: Value,
+    // index of the step it sends next
+    next: usize,
+}
+
+impl SagaRun {
+    // tops up the transactions in flight, then moves a random one on a step, returning which
+    fn advance(&mut self, ctx: &mut GenContext) -> usize {
+        loop {
+            while self.in_flight.len() < self.saga.concurrency {
+                let context = Value::Object(fill_detached(&self.context, ctx));
+                self.in_flight.push(Transaction { context, next: 0 });
+            }
+            let index = ctx.rng.gen_range(0..self.in_flight.len());
+            let transaction = &mut self.in_flight[index];
+            let step = transaction.next;
+            if !ctx.rng.gen_bool(self.saga.steps[step].probability) {
+                // abandoned, it never sends the rest of its steps
+                self.in_flight.swap_remove(index);
+                continue;
+            }
+            ctx.saga = transaction.context.clone();
+            transaction.next += 1;
+            if transaction.next == self.steps.len() {
+                self.in_flight.swap_remove(index);
+            }
+            return step;
+        }
+    }
+}
+
+struct KeyPool {
+    directive: KeyReuse,
+    path: Vec<String>,
+    values: VecDeque<Value>,
+}
+
+impl KeyPool {
+    fn apply(&mut self, event: &mut Value, rng: &mut impl Rng) {
+        let Some(key) = lookup_mut(event, &self.path) else {
+            return;
+        };
+
+        if !self.values.is_empty() && rng.gen_bool(self.directive.reuse) {
+            *key = self.values[rng.gen_range(0..self.values.len())].clone();
+            return;
+        }
+
+        if self.values.len() == self.directive.pool_size {
+            self.values.pop_front();
+        }
+        self.values.push_back(key.clone());
+    }
+}
+
+// an index into types, each as likely as its share of the total weight
+fn pick_type(types: &[EventType], rng: &mut impl Rng) -> usize {
+    let total = types.iter().map(|event| u64::from(event.weight)).sum();
+    let mut roll = rng.gen_range(0..total);
+    types
+        .iter()
+        .position(|event| {
+            let weight = u64::from(event.weight);
+            roll = match roll.checked_sub(weight) {
+                Some(rest) => rest,
+                None => return true,
+            };
+            false
+        })
+        .unwrap_or(0)
+}
+
+fn dotted(path: &str) -> Vec<String> {
+    path.split('.').map(str::to_string).collect()
+}
+
+impl EventGenerator {
+    pub fn new(
+        shape: &Map<String, Value>,
+        directives: &StreamDirectives,
+        mut ctx: GenContext,
+        position: Position,
+    ) -> Self {
+        ctx.rng.set_word_pos(position.word_pos);
+        ctx.history.start = position.sequence;
+        let base = template(shape);
+        let types = directives.events.clone().unwrap_or_default();
+        let migration = directives.migration.clone().map(|mut migration| {
+            migration.to = template(&migration.to);
+            migration
+        });
+
+        EventGenerator {
+            compiled: CompiledShape::compile(&base),
+            template: base,
+            type_shapes: types
+                .iter()
+                .map(|event| CompiledShape::compile(&event.shape))
+                .collect(),
+            migrated: migration
+                .as_ref()
+                .map(|migration| CompiledShape::compile(&migration.to)),
+            ctx,
+            sequence: position.sequence,
+            key_pool: directives.key_reuse.clone().map(|directive| KeyPool {
+                path: dotted(&directive.field),
+                values: VecDeque::with_capacity(directive.pool_size),
+                directive,
+            }),
+            evolution: directives.evolution.clone(),
+            evolved: 0,
+            migration,
+            types,
+            picked: None,
+            saga: directives.saga.clone().map(|saga| SagaRun {
+                context: CompiledShape::compile(&saga.context),
+                steps: saga
+                    .steps
+                    .iter()
+                    .map(|step| CompiledShape::compile(&step.shape))
+                    .collect(),
+                in_flight: Vec::with_capacity(saga.concurrency),
+                saga,
+            }),
+            stepped: None,
+        }
+    }
+
+    fn evolve(&mut self) {
+        let Some(evolution) = &self.evolution else {
+            return;
+        };
+        let evolved = self.evolved;
+
+        for step in &evolution.steps[self.evolved..] {
+            if step.at > self.sequence {
+                break;
+            }
+
+            for field in &step.remove {
+                remove(&mut self.template, &dotted(field));
+            }
+            for (from, to) in &step.rename {
+                if let (Some(value), Some(to)) =
+                    (remove(&mut self.template, &dotted(from)), to.as_str())
+                {
+                    insert(&mut self.template, &dotted(to), value);
+                }
+            }
+            for (field, value) in &step.add {
+                insert(&mut self.template, &dotted(field), value.clone());
+            }
+            self.evolved += 1;
+        }
+        if self.evolved > evolved {
+            self.compiled = CompiledShape::compile(&self.template);
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            sequence: self.sequence,
+            word_pos: self.ctx.rng.get_word_pos(),
+        }
+    }
+
+    // returns the tick's events with their shared 1-based sequence number; there are two
+    // only while a migration overlaps v1 and v2
+    pub fn next_events(&mut self) -> (u64, Vec<Value>) {
+        self.sequence += 1;
+        self.evolve();
+
+        let (emit_v1, emit_v2) = match &self.migration {
+            Some(migration) => (
+                self.sequence < migration.at.saturating_add(migration.overlap),
+                self.sequence >= migration.at,
+            ),
+            None => (true, false),
+        };
+
+        let mut events = Vec::new();
+        if emit_v1 {
+            let template = if let Some(saga) = &mut self.saga {
+                let step = saga.advance(&mut self.ctx);
+                self.stepped = Some(step);
+                &saga.steps[step]
+            } else if self.types.is_empty() {
+                &self.compiled
+            } else {
+                let index = pick_type(&self.types, &mut self.ctx.rng);
+                self.picked = Some(index);
+                &self.type_shapes[index]
+            };
+            let mut event = fill_object_fields(template, &mut self.ctx);
+            if let Some(version_field) = self
+                .evolution
+                .as_ref()
+                .and_then(|e| e.version_field.as_ref())
+            {
+                event.insert(version_field.clone(), Value::from(self.evolved as u64 + 1));
+            }
+            if let Some(version_field) = self
+                .migration
+                .as_ref()
+                .and_then(|m| m.version_field.as_ref())
+            {
+                event.insert(version_field.clone(), Value::from(1));
+            }
+            events.push(Value::Object(event));
+        }
+        if let (true, Some(migration), Some(migrated)) = (emit_v2, &self.migration, &self.migrated)
+        {
+            let mut event = fill_object_fields(migrated, &mut self.ctx);
+            if let Some(version_field) = &migration.version_field {
+                event.insert(version_field.clone(), Value::from(2));
+            }
+            events.push(Value::Object(event));
+        }
+
+        if let Some(key_pool) = &mut self.key_pool {
+            for event in &mut events {
+                key_pool.apply(event, &mut self.ctx.rng);
+            }
+        }
+        (self.sequence, events)
+    }
+
+    // the name of the latest tick's event type, when the shape has several
+    pub fn event_type(&self) -> Option<&str> {
+        match (&self.saga, self.stepped) {
+            (Some(run), Some(step)) => Some(run.saga.steps[step].name.as_str()),
+            _ => self.picked.map(|index| self.types[index].name.as_str()),
+        }
+    }
+
+    // the schema-migrated payload, on the tick where v2 first goes out
+    pub fn migration_notice(&self, sequence: u64) -> Option<Value> {
+        self.migration
+            .as_ref()
+            .filter(|migration| migration.at == sequence)
+            .map(|migration| {
+                json!({
+                    "from": 1,
+                    "to": 2,
+                    "sequence": sequence,
+                    "overlap": migration.overlap,
+                })
+            })
+    }
+
+    // sampled-out ticks still consume a sequence number, leaving visible gaps in ids
+    pub fn sampled(&mut self, rate: f64) -> bool {
+        rate >= 1.0 || self.ctx.rng.gen_bool(rate)
+    }
+
+    pub fn rng(&mut self) -> &mut ChaCha8Rng {
+        &mut self.ctx.rng
+    }
+
+    // how many later events should overtake this one, if it is to be held back at all
+    pub fn holdback(&mut self, disorder: Option<Disorder>) -> Option<usize> {
+        let disorder = disorder?;
+        self.ctx
+            .rng
+            .gen_bool(disorder.rate)
+            .then(|| self.ctx.rng.gen_range(1..=disorder.window))
+    }
+
+    pub fn next_delay(&mut self, interval_min: u64, interval_max: u64) -> Duration {
+        self.pattern_delay(Pattern::Uniform, interval_min, interval_max)
+    }
+
+    pub fn pattern_delay(
+        &mut self,
+        pattern: Pattern,
+        interval_min: u64,
+        interval_max: u64,
+    ) -> Duration {
+        pattern.delay(&mut self.ctx.rng, self.sequence, interval_min, interval_max)
+    }
+}
+
+// events held back for out-of-order delivery, each with the number of later events still to
+// come before it; every event counts, held or not, so nothing waits more than the window
+#[derive(Default)]
+struct Holdback {
+    held: Vec<(usize, Vec<Message>)>,
+}
+
+impl Holdback {
+    fn push(&mut self, messages: Vec<Message>, hold_for: Option<usize>) -> Vec<Message> {
+        for (remaining, _) in &mut self.held {
+            *remaining -= 1;
+        }
+        let (released, held): (Vec<_>, Vec<_>) = mem::take(&mut self.held)
+            .into_iter()
+            .partition(|(remaining, _)| *remaining == 0);
+        self.held = held;
+
+        let mut messages = match hold_for {
+            Some(overtakes) => {
+                self.held.push((overtakes, messages));
+                Vec::new()
+            }
+            None => messages,
+        };
+        messages.extend(released.into_iter().flat_map(|(_, messages)| messages));
+        messages
+    }
+
+    fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    // everything still held, oldest first, for a stream about to stop sending data
+    fn flush(&mut self) -> Vec<Message> {
+        mem::take(&mut self.held)
+            .into_iter()
+            .flat_map(|(_, messages)| messages)
+            .collect()
+    }
+}
+
+enum Tick {
+    Data,
+    // a data event asked for out of turn, which doesn't restart the data timer
+    Step,
+    Pause(bool),
+    SetRate(u64, u64),
+    Heartbeat,
+    Redact(Vec<String>, RedactionTrigger),
+    Emit(String, Value),
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum RedactionTrigger {
+    Scheduled,
+    Requested,
+}
+
+async fn next_heartbeat(heartbeat: &mut Option<Interval>) {
+    match heartbeat {
+        Some(heartbeat) => {
+            heartbeat.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+async fn at_deadline(deadline: &mut Option<Pin<Box<Sleep>>>) {
+    match deadline {
+        Some(deadline) => deadline.as_mut().await,
+        None => future::pending().await,
+    }
+}
+
+pub fn fake_events(
+    mut settings: StreamSettings,
+    mut control: Control,
+    pools: Arc<Pools>,
+) -> impl Stream<Item = Message> {
+    async_stream::stream! {
+        let mut handshake = settings.handshake();
+        if let Some(replay) = &settings.replay {
+            handshake["replayed"] = json!(replay.backlog.len());
+        }
+        yield Message::control("handshake", handshake);
+        if let Some(replay) = settings.replay.as_mut() {
+            if let Some(gap) = replay.gap.take() {
+                yield Message::control("replay-gap", gap);
+            }
+            for message in mem::take(&mut replay.backlog) {
+                yield message;
+            }
+        }
+
+        let mut generator = EventGenerator::new(
+            &settings.shape,
+            &settings.directives,
+            GenContext::new(settings.seed, settings.packs, settings.clock, pools.clone())
+                .with_normalization(settings.normalization)
+                .with_locale(settings.locale)
+                .with_custom(settings.custom.clone()),
+            settings.position,
+        );
+        let mut pii = PiiMap::classify(&template(&settings.shape));
+        if let Some(migration) = &settings.directives.migration {
+            pii.merge(PiiMap::classify(&migration.to));
+        }
+        let mut heartbeat = settings.heartbeat.map(|period| {
+            let mut heartbeat = interval_at(Instant::now() + period, period);
+            heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            heartbeat
+        });
+
+        let mut holdback = Holdback::default();
+        let collection = settings.crud.as_ref().map(|crud| {
+            let collection = Arc::new(Mutex::new(Collection::new(&crud.key, crud.entities, crud.churn)));
+            control.attach_collection(collection.clone());
+            collection
+        });
+        let mut redaction_timer = settings.redact_after.map(|after| Box::pin(sleep(after)));
+
+        let data_timer = sleep(generator.pattern_delay(settings.pattern, settings.interval_min, settings.interval_max));
+        tokio::pin!(data_timer);
+        let mut exhausted = false;
+        let mut paused = false;
+        // data messages sent so far, as until_finished counts them
+        let mut delivered = 0u64;
+        let mut stop = settings.finish.stop.listen();
+
+        loop {
+            // a stream ended from outside still sends the events it held back
+            if *stop.borrow() {
+                for message in holdback.flush() {
+                    if let Some(replay) = &settings.replay {
+                        replay.record(&message);
+                    }
+                    yield message;
+                }
+                return;
+            }
+            let tick = tokio::select! {
+                _ = stop.changed() => continue,
+                _ = &mut data_timer, if !exhausted && !paused => Tick::Data,
+                _ = next_heartbeat(&mut heartbeat) => Tick::Heartbeat,
+                _ = at_deadline(&mut redaction_timer) => Tick::Redact(settings.redact.clone(), RedactionTrigger::Scheduled),
+                Some(command) = control.recv() => match command {
+                    Command::Redact { fields } => Tick::Redact(fields, RedactionTrigger::Requested),
+                    Command::Emit { event, data } => Tick::Emit(event, data),
+                    Command::Pause => Tick::Pause(true),
+                    Command::Resume => Tick::Pause(false),
+                    Command::Step => Tick::Step,
+                    Command::SetRate { interval_min, interval_max } => Tick::SetRate(interval_min, interval_max),
+                },
+            };
+
+            match tick {
+                Tick::Pause(pause) => {
+                    // resuming waits a fresh interval rather than firing a timer that ran out while paused
+                    if paused && !pause {
+                        let delay = generator.pattern_delay(settings.pattern, settings.interval_min, settings.interval_max);
+                        data_timer.as_mut().reset(Instant::now() + delay);
+                    }
+                    paused = pause;
+                }
+                Tick::SetRate(interval_min, interval_max) => {
+                    settings.interval_min = interval_min;
+                    settings.interval_max = interval_max;
+                    let delay = generator.pattern_delay(settings.pattern, settings.interval_min, settings.interval_max);
+                    data_timer.as_mut().reset(Instant::now() + delay);
+                }
+                Tick::Data | Tick::Step if exhausted => {}
+                Tick::Data | Tick::Step => {
+                    let (sequence, events) = generator.next_events();
+                    if let Some(notice) = generator.migration_notice(sequence) {
+                        yield Message::control("schema-migrated", notice);
+                    }
+                    if generator.sampled(settings.sample) {
+                        for mut event in events {
+                            settings.links.apply(&mut event, &pools, generator.rng());
+                            let mut event = settings.projection.apply(event);
+                            let annotation = settings.pii.map(|mode| (mode, pii.annotate(&event)));
+                            if let (Some((PiiMode::Inline, fields)), Value::Object(object)) = (&annotation, &mut event) {
+                                object.insert(INLINE_KEY.to_string(), fields.clone());
+                            }
+                            if let Some(collection) = &collection {
+                                event = collection.lock().unwrap().apply(event, generator.rng());
+                            }
+                            let lifecycle = settings
+                                .crud
+                                .as_ref()
+                                .filter(|crud| crud.entities.is_some())
+                                .and_then(|_| lifecycle_event(&event));
+                            let event_type = lifecycle.as_deref().or(generator.event_type());
+                            let mut messages = vec![settings.data_message(sequence, event_type, event)];
+                            if let Some((PiiMode::Sidecar, fields)) = annotation {
+                                messages.push(Message::control("pii", json!({ "sequence": sequence, "fields": fields })));
+                            }
+
+                            let hold_for = generator.holdback(settings.disorder);
+                            let mut released = holdback.push(messages, hold_for);
+                            let releasing = released.iter().filter(|message| !message.control).count();
+                            // the last events of a max_events stream can't wait for ones that won't be sent
+                            if settings.finish.max_events.is_some_and(|max| delivered + (releasing + holdback.len()) as u64 >= max) {
+                                released.extend(holdback.flush());
+                            }
+                            for message in released {
+                                if let Some(replay) = &settings.replay {
+                                    replay.record(&message);
+                                }
+                                if !message.control {
+                                    delivered += 1;
+                                }
+                                yield message;
+                            }
+                        }
+                    }
+
+                    if let Some(replay) = &settings.replay {
+                        replay.advance(generator.position());
+                    }
+
+                    if settings.resume_every > 0 && sequence % settings.resume_every == 0 {
+                        let resume_token = settings.resume_token(generator.position());
+                        yield Message::control(
+                            "resume",
+                            json!({ "sequence": sequence, "resume_token": resume_token }),
+                        );
+                    }
+
+                    if let Some(quota) = settings.quota.filter(|quota| sequence >= quota.after) {
+                        for message in holdback.flush() {
+                            if let Some(replay) = &settings.replay {
+                                replay.record(&message);
+                            }
+                            if !message.control {
+                                delivered += 1;
+                            }
+                            yield message;
+                        }
+                        delivered += 1;
+                        yield Message::data(
+                            Some(QUOTA_EXCEEDED_EVENT.to_string()),
+                            json!({
+                                "limit": quota.after,
+                                "after_sequence": sequence,
+                                "ts": Utc::now().to_rfc3339(),
+                            }),
+                        );
+                        if quota.action == QuotaAction::Close {
+                            return;
+                        }
+                        exhausted = true;
+                    }
+
+                    if matches!(tick, Tick::Data) {
+                        let delay = generator.pattern_delay(settings.pattern, settings.interval_min, settings.interval_max);
+                        data_timer.as_mut().reset(Instant::now() + delay);
+                    }
+                }
+                Tick::Heartbeat => {
+                    yield Message::control("heartbeat", json!({ "ts": Utc::now().to_rfc3339() }));
+                }
+                Tick::Emit(event, data) => {
+                    delivered += 1;
+                    yield Message::data(Some(event), data);
+                }
+                Tick::Redact(fields, trigger) => {
+                    if trigger == RedactionTrigger::Scheduled {
+                        redaction_timer = None;
+                    }
+                    let fields = if fields.is_empty() { settings.redact.clone() } else { fields };
+                    settings.projection.redact_fields(&fields);
+                    delivered += 1;
+                    yield Message::data(
+                        Some(REDACTION_NOTICE_EVENT.to_string()),
+                        json!({
+                            "fields": fields,
+                            "trigger": trigger,
+                            "after_sequence": generator.position().sequence,
+                            "ts": Utc::now().to_rfc3339(),
+                        }),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn event(id: u64) -> Vec<Message> {
+        vec![Message::data(None, json!(id))]
+    }
+
+    fn ids(messages: Vec<Message>) -> Vec<Value> {
+        messages.into_iter().map(|message| message.data).collect()
+    }
+
+    #[test]
+    fn links_pick_references_with_the_stream_rng() {
+        let pools = Pools::new(100);
+        pools.extend("users", (0..100).map(|id| json!(id)).collect());
+        let links = Links {
+            produces: None,
+            references: vec![(vec!["user".to_string()], "users".to_string())],
+        };
+        let picks = |seed| {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            (0..10)
+                .map(|_| {
+                    let mut event = json!({ "user": null });
+                    links.apply(&mut event, &pools, &mut rng);
+                    event
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7), picks(7));
+    }
+
+    #[test]
+    fn migration_overlap_saturates() {
+        let shape = json!({
+            "n": "{int}",
+            "$migration": { "to": { "m": "{int}" }, "at": u64::MAX, "overlap": u64::MAX },
+        });
+        let template = crate::shape::ShapeTemplate::from_value(shape).unwrap();
+        let (_, events) = template.generator(0).next_events();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn holdback_releases_when_every_event_is_held() {
+        let mut holdback = Holdback::default();
+        let mut sent = Vec::new();
+        for id in 1..=5 {
+            sent.extend(ids(holdback.push(event(id), Some(2))));
+            assert!(holdback.len() <= 2);
+        }
+        sent.extend(ids(holdback.flush()));
+        assert_eq!(sent, [1, 2, 3, 4, 5].map(|id| json!(id)));
+    }
+
+    #[test]
+    fn holdback_lets_later_events_overtake() {
+        let mut holdback = Holdback::default();
+        assert!(holdback.push(event(1), Some(2)).is_empty());
+        assert_eq!(ids(holdback.push(event(2), None)), [json!(2)]);
+        assert_eq!(ids(holdback.push(event(3), None)), [json!(3), json!(1)]);
+        assert!(holdback.flush().is_empty());
+    }
+}
@@ -0,0 +1,1785 @@
+use crate::condition::Condition;
+use crate::config::Packs;
+use crate::custom::CustomSubstitutions;
+use crate::geo::{self, Fix, GeoWalk};
+use crate::packs;
+use crate::pools::Pools;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, Utc};
+use fake::faker::boolean::en::Boolean;
+use fake::faker::color::en::HexColor;
+use fake::faker::creditcard::en::CreditCardNumber;
+use fake::faker::internet::en::{IPv4, SafeEmail};
+use fake::faker::lorem::en::{Paragraph, Words};
+use fake::faker::number::en::NumberWithFormat;
+use fake::locales::{AR_SA, DE_DE, EN, FR_FR, JA_JP, PT_BR, ZH_CN, ZH_TW};
+use fake::Fake;
+use lazy_static::lazy_static;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+use std::time::Instant;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Builder;
+
+// per-stream generation state; every generator draws from the stream's seeded rng
+pub struct GenContext {
+    pub rng: ChaCha8Rng,
+    pub packs: Packs,
+    pub clock: Clock,
+    pub pools: Arc<Pools>,
+    pub normalization: Option<Normalization>,
+    pub locale: Locale,
+    pub custom: Arc<CustomSubstitutions>,
+    // the event being filled, which {ref:...} placeholders read from
+    pub event: Value,
+    // the context of the $saga transaction the event belongs to, for {saga:...}
+    pub saga: Value,
+    pub history: History,
+}
+
+// what a stream carries from one event to the next, for {seq} and {prev:...}
+#[derive(Default)]
+pub struct History {
+    // per {seq} argument, the last number it handed out
+    counters: HashMap<String, u64>,
+    // per {walk} argument, where it stands
+    walks: HashMap<String, f64>,
+    // per {geo_walk} argument, where its track stands
+    tracks: HashMap<String, Fix>,
+    // where counters start, so a resumed stream carries on from its position
+    pub start: u64,
+    previous: Option<Value>,
+    // set once a {prev:...} is seen, so events are only kept when something reads them
+    keep_previous: bool,
+}
+
+impl History {
+    fn next(&mut self, counter: &str) -> u64 {
+        let last = self
+            .counters
+            .entry(counter.to_string())
+            .or_insert(self.start);
+        *last += 1;
+        *last
+    }
+
+    // the walk's first use gives its start, later ones a step on from the last
+    fn walk(&mut self, key: &str, walk: Walk, rng: &mut impl Rng) -> f64 {
+        match self.walks.get_mut(key) {
+            Some(value) => {
+                *value = walk.advance(*value, rng);
+                *value
+            }
+            None => *self.walks.entry(key.to_string()).or_insert(walk.start),
+        }
+    }
+
+    fn track(&mut self, key: &str, walk: GeoWalk, rng: &mut impl Rng) -> Fix {
+        match self.tracks.get_mut(key) {
+            Some(fix) => {
+                *fix = walk.advance(*fix, rng);
+                *fix
+            }
+            None => *self
+                .tracks
+                .entry(key.to_string())
+                .or_insert(walk.start(rng)),
+        }
+    }
+
+    fn previous(&mut self, path: &str) -> Option<&Value> {
+        self.keep_previous = true;
+        lookup(self.previous.as_ref()?, path)
+    }
+}
+
+impl GenContext {
+    pub fn new(seed: u64, packs: Packs, clock: Clock, pools: Arc<Pools>) -> Self {
+        GenContext {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            packs,
+            clock,
+            pools,
+            normalization: None,
+            locale: Locale::default(),
+            custom: Arc::default(),
+            event: Value::Null,
+            saga: Value::Null,
+            history: History::default(),
+        }
+    }
+
+    pub fn with_custom(mut self, custom: Arc<CustomSubstitutions>) -> Self {
+        self.custom = custom;
+        self
+    }
+
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn with_normalization(mut self, normalization: Option<Normalization>) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+}
+
+// the fake crate locale behind names, addresses and phone numbers
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Locale {
+    #[default]
+    En,
+    FrFr,
+    DeDe,
+    PtBr,
+    JaJp,
+    ZhCn,
+    ZhTw,
+    ArSa,
+}
+
+// a raw fake such as name::raw::Name, rendered in the context's locale
+macro_rules! localized {
+    ($ctx:expr, $($faker:ident)::+) => {{
+        let ctx: &mut GenContext = $ctx;
+        match ctx.locale {
+            Locale::En => fake::faker::$($faker)::+(EN).fake_with_rng(&mut ctx.rng),
+            Locale::FrFr => fake::faker::$($faker)::+(FR_FR).fake_with_rng(&mut ctx.rng),
+            Locale::DeDe => fake::faker::$($faker)::+(DE_DE).fake_with_rng(&mut ctx.rng),
+            Locale::PtBr => fake::faker::$($faker)::+(PT_BR).fake_with_rng(&mut ctx.rng),
+            Locale::JaJp => fake::faker::$($faker)::+(JA_JP).fake_with_rng(&mut ctx.rng),
+            Locale::ZhCn => fake::faker::$($faker)::+(ZH_CN).fake_with_rng(&mut ctx.rng),
+            Locale::ZhTw => fake::faker::$($faker)::+(ZH_TW).fake_with_rng(&mut ctx.rng),
+            Locale::ArSa => fake::faker::$($faker)::+(AR_SA).fake_with_rng(&mut ctx.rng),
+        }
+    }};
+}
+
+// the Unicode normalization form generated strings are given; `mixed` picks one per string
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    Nfc,
+    Nfd,
+    Mixed,
+}
+
+impl Normalization {
+    fn apply(self, string: String, rng: &mut impl Rng) -> String {
+        let composed = match self {
+            Normalization::Nfc => true,
+            Normalization::Nfd => false,
+            Normalization::Mixed => rng.gen_bool(0.5),
+        };
+        if composed {
+            string.nfc().collect()
+        } else {
+            string.nfd().collect()
+        }
+    }
+}
+
+// the producer's view of time: server time shifted by a constant skew plus a drift that
+// accumulates from the start of the stream
+#[derive(Clone, Copy)]
+pub struct Clock {
+    pub skew_ms: i64,
+    pub drift_ms_per_min: f64,
+    pub started: Instant,
+    // stands in for server time, so output can be reproduced exactly
+    pub frozen: Option<DateTime<Utc>>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            skew_ms: 0,
+            drift_ms_per_min: 0.0,
+            started: Instant::now(),
+            frozen: None,
+        }
+    }
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        let elapsed_min = self.started.elapsed().as_secs_f64() / 60.0;
+        let offset_ms = self
+            .skew_ms
+            .saturating_add((self.drift_ms_per_min * elapsed_min) as i64)
+            // Duration stops one short of i64::MIN
+            .max(-i64::MAX);
+        let now = self.frozen.unwrap_or_else(Utc::now);
+        // a drifting clock runs into the end of the calendar eventually and stays there
+        now.checked_add_signed(Duration::milliseconds(offset_ms))
+            .unwrap_or(if offset_ms < 0 {
+                DateTime::<Utc>::MIN_UTC
+            } else {
+                DateTime::<Utc>::MAX_UTC
+            })
+    }
+}
+
+// a placeholder argument: a single value n or an inclusive range a-b, e.g. {number:1-500}
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Span {
+    fn parse(argument: &str, bounds: &RangeInclusive<u64>) -> Result<Self, String> {
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("{argument} is not a number or a range such as 1-500"))
+        };
+        let span = match argument.split_once('-') {
+            Some((min, max)) => Span {
+                min: number(min)?,
+                max: number(max)?,
+            },
+            None => {
+                let value = number(argument)?;
+                Span {
+                    min: value,
+                    max: value,
+                }
+            }
+        };
+        if span.min > span.max {
+            return Err(format!("{argument} runs backwards"));
+        }
+        if !bounds.contains(&span.min) || !bounds.contains(&span.max) {
+            return Err(format!(
+                "{argument} is outside {}-{}",
+                bounds.start(),
+                bounds.end()
+            ));
+        }
+        Ok(span)
+    }
+
+    pub fn pick(self, rng: &mut impl Rng) -> u64 {
+        rng.gen_range(self.min..=self.max)
+    }
+
+    fn range(self) -> Range<usize> {
+        self.min as usize..self.max as usize + 1
+    }
+}
+
+const MAX_DECIMALS: usize = 15;
+
+// a decimal placeholder argument: a range a-b with an optional number of decimal places after
+// another colon, e.g. {float:0-1:2}
+#[derive(Clone, Copy)]
+pub struct Decimal {
+    pub min: f64,
+    pub max: f64,
+    pub places: usize,
+}
+
+impl Default for Decimal {
+    fn default() -> Self {
+        Decimal {
+            min: 0.0,
+            max: 1.0,
+            places: 2,
+        }
+    }
+}
+
+impl Decimal {
+    fn parse(argument: &str) -> Result<Self, String> {
+        let (range, places) = match argument.split_once(':') {
+            Some((range, places)) => (
+                range,
+                places
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|places| *places <= MAX_DECIMALS)
+                    .ok_or_else(|| {
+                        format!("{places} is not a number of decimal places 0-{MAX_DECIMALS}")
+                    })?,
+            ),
+            None => (argument, Decimal::default().places),
+        };
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("{range} is not a number or a range such as 0-1"))
+        };
+        // the separating - is the first one after a leading minus sign
+        let (min, max) = match range.get(1..).and_then(|rest| rest.find('-')) {
+            Some(split) => (number(&range[..split + 1])?, number(&range[split + 2..])?),
+            None => {
+                let value = number(range)?;
+                (value, value)
+            }
+        };
+        if min > max {
+            return Err(format!("{range} runs backwards"));
+        }
+        // the sampler needs the width itself to be a finite number
+        if !(max - min).is_finite() {
+            return Err(format!("{range} is too wide to pick from"));
+        }
+        Ok(Decimal { min, max, places })
+    }
+
+    pub fn pick(self, rng: &mut impl Rng) -> String {
+        format!("{:.*}", self.places, rng.gen_range(self.min..=self.max))
+    }
+}
+
+// comma-separated key=value settings whose values are all numbers, e.g. start=100,step=5
+pub fn numeric_settings(argument: &str) -> Result<Vec<(&str, f64)>, String> {
+    argument
+        .split(',')
+        .map(|setting| {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("{setting} is not a key=value setting"))?;
+            let number = value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("{key} {value} is not a number"))?;
+            Ok((key.trim(), number))
+        })
+        .collect()
+}
+
+fn decimal_places(number: f64) -> Result<usize, String> {
+    if number.fract() != 0.0 || !(0.0..=MAX_DECIMALS as f64).contains(&number) {
+        return Err(format!(
+            "places {number} is not a number of decimal places 0-{MAX_DECIMALS}"
+        ));
+    }
+    Ok(number as usize)
+}
+
+// where a walk without min or max stops, leaving room to step past it and bounce back
+const UNBOUNDED_WALK: f64 = f64::MAX / 4.0;
+
+// a random walk argument, e.g. {walk:start=100,step=5,min=0,max=200}; each event moves the
+// value by up to step either way, bouncing off min and max
+#[derive(Clone, Copy)]
+pub struct Walk {
+    pub start: f64,
+    pub step: f64,
+    pub min: f64,
+    pub max: f64,
+    pub places: usize,
+}
+
+impl Walk {
+    fn parse(argument: &str) -> Result<Self, String> {
+        let mut start = None;
+        let mut step = 1.0;
+        let mut min = -UNBOUNDED_WALK;
+        let mut max = UNBOUNDED_WALK;
+        let mut places = None;
+        for (key, number) in numeric_settings(argument)? {
+            match key {
+                "start" => start = Some(number),
+                "step" if number >= 0.0 => step = number,
+                "step" => return Err(format!("step {number} is negative")),
+                "min" => min = number,
+                "max" => max = number,
+                "places" => places = Some(decimal_places(number)?),
+                other => {
+                    return Err(format!(
+                        "has no setting {other}, use start, step, min, max and places"
+                    ))
+                }
+            }
+        }
+        if min > max {
+            return Err(format!("min {min} is above max {max}"));
+        }
+        // advance steps up to step past either bound before bouncing back
+        if !(max - min).is_finite() {
+            return Err(format!("min {min} to max {max} is too wide"));
+        }
+        if !(2.0 * step).is_finite() || !(min - step).is_finite() || !(max + step).is_finite() {
+            return Err(format!(
+                "step {step} is too large for min {min} and max {max}"
+            ));
+        }
+        let start = start.unwrap_or(if min > 0.0 || max < 0.0 { min } else { 0.0 });
+        if start < min {
+            return Err(format!("start {start} is below min {min}"));
+        }
+        if start > max {
+            return Err(format!("start {start} is above max {max}"));
+        }
+        // whole numbers walk in whole numbers unless told otherwise
+        let places = places.unwrap_or(if start.fract() == 0.0 && step.fract() == 0.0 {
+            0
+        } else {
+            2
+        });
+        Ok(Walk {
+            start,
+            step,
+            min,
+            max,
+            places,
+        })
+    }
+
+    fn advance(self, value: f64, rng: &mut impl Rng) -> f64 {
+        let moved = value + rng.gen_range(-self.step..=self.step);
+        let bounced = if moved > self.max {
+            self.max - (moved - self.max)
+        } else if moved < self.min {
+            self.min + (self.min - moved)
+        } else {
+            moved
+        };
+        bounced.clamp(self.min, self.max)
+    }
+}
+
+// shifts past this are surely typos, and would overflow a DateTime long before i64 does
+pub const MAX_SHIFT_MS: i64 = 100 * 366 * 24 * 60 * 60 * 1000;
+
+// {datetime:+2h:%H:%M}: colon-separated offsets from the emission time, fixed (+2h, -15m) or
+// random within a window (past:30d, future:1w), then an optional strftime format
+#[derive(Default)]
+pub struct TimeSpec {
+    shift_ms: i64,
+    // a random shift of up to this much, negative for the past
+    window_ms: i64,
+    format: Option<String>,
+}
+
+// 90s, 15m, 1h30m, 7d, 2w or 250ms
+fn duration_ms(text: &str) -> Option<i64> {
+    let mut total = 0i64;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount = rest[..digits].parse::<i64>().ok()?;
+        rest = &rest[digits..];
+        let (unit, len) = match rest {
+            _ if rest.starts_with("ms") => (1, 2),
+            _ if rest.starts_with('s') => (1000, 1),
+            _ if rest.starts_with('m') => (60 * 1000, 1),
+            _ if rest.starts_with('h') => (60 * 60 * 1000, 1),
+            _ if rest.starts_with('d') => (24 * 60 * 60 * 1000, 1),
+            _ if rest.starts_with('w') => (7 * 24 * 60 * 60 * 1000, 1),
+            _ => return None,
+        };
+        rest = &rest[len..];
+        total = total.checked_add(amount.checked_mul(unit)?)?;
+    }
+    (!text.is_empty()).then_some(total)
+}
+
+impl TimeSpec {
+    // `formatted` is false for the epoch placeholders, which only take offsets
+    fn parse(argument: &str, formatted: bool) -> Result<Self, String> {
+        // checked per segment, so no run of large offsets can add up past i64
+        let add = |total: i64, ms: i64| {
+            total
+                .checked_add(ms)
+                .filter(|total| total.abs() <= MAX_SHIFT_MS)
+                .ok_or_else(|| "offsets are limited to 100 years".to_string())
+        };
+        let mut spec = TimeSpec::default();
+        let mut rest = argument;
+        while !rest.is_empty() {
+            let (segment, after) = rest.split_once(':').unwrap_or((rest, ""));
+            if segment == "past" || segment == "future" {
+                let (window, after) = after.split_once(':').unwrap_or((after, ""));
+                let window = duration_ms(window)
+                    .ok_or_else(|| format!("{segment} needs a duration such as 30d"))?;
+                spec.window_ms = add(
+                    spec.window_ms,
+                    if segment == "past" { -window } else { window },
+                )?;
+                rest = after;
+            } else if let Some(shift) =
+                segment.strip_prefix('+').and_then(duration_ms).or_else(|| {
+                    segment
+                        .strip_prefix('-')
+                        .and_then(duration_ms)
+                        .map(|ms| -ms)
+                })
+            {
+                spec.shift_ms = add(spec.shift_ms, shift)?;
+                rest = after;
+            } else {
+                break;
+            }
+        }
+        if !rest.is_empty() {
+            if !formatted {
+                return Err(format!(
+                    "{rest} is not an offset such as +2h, -15m or past:30d"
+                ));
+            }
+            if StrftimeItems::new(rest).any(|item| item == Item::Error) {
+                return Err(format!("{rest} is not an offset or a strftime format"));
+            }
+            spec.format = Some(rest.to_string());
+        }
+        Ok(spec)
+    }
+
+    fn at(&self, ctx: &mut GenContext) -> DateTime<Utc> {
+        let window = match self.window_ms {
+            0 => 0,
+            past if past < 0 => ctx.rng.gen_range(past..=0),
+            future => ctx.rng.gen_range(0..=future),
+        };
+        let now = ctx.now();
+        now.checked_add_signed(Duration::milliseconds(self.shift_ms + window))
+            .unwrap_or(now)
+    }
+
+    fn render(&self, ctx: &mut GenContext, default_format: Option<&str>) -> String {
+        let at = self.at(ctx);
+        match self.format.as_deref().or(default_format) {
+            Some(format) => at.format(format).to_string(),
+            None => at.to_rfc3339(),
+        }
+    }
+}
+
+// what a substitution takes after the colon
+enum Parameter {
+    None,
+    // a number or range within these bounds
+    Span(RangeInclusive<u64>),
+    Decimal,
+    // free text with a # where the number goes, e.g. order-#
+    Template,
+    Walk,
+    GeoWalk,
+    // offsets from the emission time, and a format when `formatted`
+    Time { formatted: bool },
+}
+
+pub enum Argument {
+    Span(Span),
+    Decimal(Decimal),
+    Template(String),
+    Walk(String, Walk),
+    GeoWalk(String, GeoWalk),
+    Time(TimeSpec),
+}
+
+type Generate = Box<dyn Fn(&mut GenContext, Option<&Argument>) -> String + Send + Sync>;
+
+// one named substitution, with an optional argument of the kind its parameter says
+pub struct Substitution {
+    parameter: Parameter,
+    generate: Generate,
+}
+
+impl Substitution {
+    pub fn parse_argument(&self, argument: Option<&str>) -> Result<Option<Argument>, String> {
+        match (&self.parameter, argument) {
+            (_, None) => Ok(None),
+            (Parameter::Span(bounds), Some(argument)) => {
+                Span::parse(argument, bounds).map(|span| Some(Argument::Span(span)))
+            }
+            (Parameter::Decimal, Some(argument)) => {
+                Decimal::parse(argument).map(|decimal| Some(Argument::Decimal(decimal)))
+            }
+            (Parameter::Template, Some(argument)) if argument.contains('#') => {
+                Ok(Some(Argument::Template(argument.to_string())))
+            }
+            (Parameter::Template, Some(_)) => Err("needs a # where the number goes".to_string()),
+            (Parameter::Walk, Some(argument)) => {
+                Walk::parse(argument).map(|walk| Some(Argument::Walk(argument.to_string(), walk)))
+            }
+            (Parameter::GeoWalk, Some(argument)) => GeoWalk::parse(argument)
+                .map(|walk| Some(Argument::GeoWalk(argument.to_string(), walk))),
+            (Parameter::Time { formatted }, Some(argument)) => {
+                TimeSpec::parse(argument, *formatted).map(|spec| Some(Argument::Time(spec)))
+            }
+            (Parameter::None, Some(_)) => Err("takes no arguments".to_string()),
+        }
+    }
+
+    fn generate(&self, ctx: &mut GenContext, argument: Option<&Argument>) -> String {
+        (self.generate)(ctx, argument)
+    }
+}
+
+pub type StringSubstitutionsMap = HashMap<&'static str, Substitution>;
+
+fn plain(generate: impl Fn(&mut GenContext) -> String + Send + Sync + 'static) -> Substitution {
+    Substitution {
+        parameter: Parameter::None,
+        generate: Box::new(move |ctx, _| generate(ctx)),
+    }
+}
+
+fn ranged(
+    bounds: RangeInclusive<u64>,
+    generate: impl Fn(&mut GenContext, Option<Span>) -> String + Send + Sync + 'static,
+) -> Substitution {
+    Substitution {
+        parameter: Parameter::Span(bounds),
+        generate: Box::new(move |ctx, argument| match argument {
+            Some(Argument::Span(span)) => generate(ctx, Some(*span)),
+            _ => generate(ctx, None),
+        }),
+    }
+}
+
+fn decimal(
+    generate: impl Fn(&mut GenContext, Decimal) -> String + Send + Sync + 'static,
+) -> Substitution {
+    Substitution {
+        parameter: Parameter::Decimal,
+        generate: Box::new(move |ctx, argument| match argument {
+            Some(Argument::Decimal(decimal)) => generate(ctx, *decimal),
+            _ => generate(ctx, Decimal::default()),
+        }),
+    }
+}
+
+fn templated(
+    generate: impl Fn(&mut GenContext, Option<&str>) -> String + Send + Sync + 'static,
+) -> Substitution {
+    Substitution {
+        parameter: Parameter::Template,
+        generate: Box::new(move |ctx, argument| match argument {
+            Some(Argument::Template(template)) => generate(ctx, Some(template)),
+            _ => generate(ctx, None),
+        }),
+    }
+}
+
+fn walking(
+    generate: impl Fn(&mut GenContext, &str, Walk) -> String + Send + Sync + 'static,
+) -> Substitution {
+    Substitution {
+        parameter: Parameter::Walk,
+        generate: Box::new(move |ctx, argument| match argument {
+            Some(Argument::Walk(key, walk)) => generate(ctx, key, *walk),
+            _ => generate(ctx, "", DEFAULT_WALK),
+        }),
+    }
+}
+
+fn tracking(
+    generate: impl Fn(&mut GenContext, &str, GeoWalk) -> String + Send + Sync + 'static,
+) -> Substitution {
+    Substitution {
+        parameter: Parameter::GeoWalk,
+        generate: Box::new(move |ctx, argument| match argument {
+            Some(Argument::GeoWalk(key, walk)) => generate(ctx, key, *walk),
+            _ => generate(ctx, "", GeoWalk::default()),
+        }),
+    }
+}
+
+fn timed(
+    formatted: bool,
+    generate: impl Fn(&mut GenContext, &TimeSpec) -> String + Send + Sync + 'static,
+) -> Substitution {
+    Substitution {
+        parameter: Parameter::Time { formatted },
+        generate: Box::new(move |ctx, argument| match argument {
+            Some(Argument::Time(spec)) => generate(ctx, spec),
+            _ => generate(ctx, &TimeSpec::default()),
+        }),
+    }
+}
+
+macro_rules! substitution {
+    ([time] $generator:expr) => {
+        timed(true, $generator)
+    };
+    ([offsets] $generator:expr) => {
+        timed(false, $generator)
+    };
+    ([geo_walk] $generator:expr) => {
+        tracking($generator)
+    };
+    ([walk] $generator:expr) => {
+        walking($generator)
+    };
+    ([decimal] $generator:expr) => {
+        decimal($generator)
+    };
+    ([template] $generator:expr) => {
+        templated($generator)
+    };
+    ([$($bounds:tt)+] $generator:expr) => {
+        ranged($($bounds)+, $generator)
+    };
+    ($generator:expr) => {
+        plain($generator)
+    };
+}
+
+macro_rules! generate_replacements {
+    ($($placeholder:literal $(($($bounds:tt)+))? => $generator:expr),*) => {{
+        let mut replacements: StringSubstitutionsMap = HashMap::new();
+        $(replacements.insert($placeholder, substitution!($([$($bounds)+])? $generator));)*
+        replacements
+    }};
+}
+
+lazy_static! {
+    pub static ref STRING_SUBSTITUTIONS: StringSubstitutionsMap = generate_replacements! {
+        "address" => |ctx| localized!(ctx, address::raw::StreetName),
+        // {bool:80} is true 80% of the time
+        "bool"(0..=100) => |ctx, percent| {
+            let ratio = percent.map_or(50, |percent| percent.pick(&mut ctx.rng));
+            Boolean(ratio as u8).fake_with_rng::<bool, _>(&mut ctx.rng).to_string()
+        },
+        "city" => |ctx| localized!(ctx, address::raw::CityName),
+        "color" => |ctx| HexColor().fake_with_rng(&mut ctx.rng),
+        "creditcard" => |ctx| CreditCardNumber().fake_with_rng(&mut ctx.rng),
+        // {date:past:30d}, {datetime:+2h}, {datetime:%d/%m/%Y %H:%M}: see TimeSpec
+        "date"(time) => |ctx, spec| spec.render(ctx, Some("%Y-%m-%d")),
+        "datetime"(time) => |ctx, spec| spec.render(ctx, None),
+        "email" => |ctx| SafeEmail().fake_with_rng(&mut ctx.rng),
+        "emoji_heavy" => |ctx| packs::emoji_heavy(&mut ctx.rng),
+        // {float:0-1:2} is between 0 and 1 with 2 decimal places
+        "float"(decimal) => |ctx, decimal| decimal.pick(&mut ctx.rng),
+        "int"(0..=u64::MAX) => |ctx, span| {
+            span.unwrap_or(DEFAULT_INT).pick(&mut ctx.rng).to_string()
+        },
+        // a {"lat", "lng"} object that moves speed metres with each event on the connection
+        "geo_walk"(geo_walk) => |ctx, key, walk| ctx.history.track(key, walk, &mut ctx.rng).to_json(),
+        "ip" => |ctx| IPv4().fake_with_rng(&mut ctx.rng),
+        "lat" => |ctx| geo::coordinate(geo::latitude(&mut ctx.rng)),
+        "lng" => |ctx| geo::coordinate(geo::longitude(&mut ctx.rng)),
+        "name" => |ctx| localized!(ctx, name::raw::Name),
+        "number"(0..=u64::MAX) => |ctx, span| match span {
+            Some(span) => span.pick(&mut ctx.rng).to_string(),
+            None => NumberWithFormat("^###").fake_with_rng(&mut ctx.rng),
+        },
+        // {paragraph:3-5} has 3 to 5 sentences
+        "paragraph"(1..=100) => |ctx, sentences| {
+            Paragraph(sentences.map_or(1..3, Span::range)).fake_with_rng(&mut ctx.rng)
+        },
+        "phone" => |ctx| localized!(ctx, phone_number::raw::PhoneNumber),
+        // {seq} counts 1, 2, 3... per connection; {seq:order-#} counts on its own as order-1...
+        "seq"(template) => |ctx, template| {
+            let number = ctx.history.next(template.unwrap_or_default());
+            match template {
+                Some(template) => template.replace('#', &number.to_string()),
+                None => number.to_string(),
+            }
+        },
+        "time"(time) => |ctx, spec| spec.render(ctx, Some("%H:%M:%S")),
+        "timestamp"(offsets) => |ctx, spec| spec.at(ctx).timestamp().to_string(),
+        "timestamp_ms"(offsets) => |ctx, spec| spec.at(ctx).timestamp_millis().to_string(),
+        "unicode_stress" => |ctx| packs::unicode_stress(&mut ctx.rng),
+        "uuid" => |ctx| Builder::from_random_bytes(ctx.rng.gen()).into_uuid().to_string(),
+        // starts at start and moves by up to step with each event on the connection
+        "walk"(walk) => |ctx, key, walk| {
+            let value = ctx.history.walk(key, walk, &mut ctx.rng);
+            format!("{value:.*}", walk.places)
+        },
+        "words"(1..=1000) => |ctx, count| {
+            Words(count.map_or(3..5, Span::range))
+                .fake_with_rng::<Vec<String>, _>(&mut ctx.rng)
+                .join(" ")
+        },
+        "zip" => |ctx| localized!(ctx, address::raw::ZipCode)
+    };
+    pub static ref INJECTION_SUBSTITUTIONS: StringSubstitutionsMap = generate_replacements! {
+        "pathtraversal" => |ctx| packs::path_traversal(&mut ctx.rng),
+        "sqli" => |ctx| packs::sqli(&mut ctx.rng),
+        "xss" => |ctx| packs::xss(&mut ctx.rng)
+    };
+}
+
+// the substitution for a placeholder name, looking into opt-in packs only when they are enabled
+pub fn substitution(name: &str, packs: Packs) -> Option<&'static Substitution> {
+    STRING_SUBSTITUTIONS.get(name).or_else(|| {
+        packs
+            .injection
+            .then(|| INJECTION_SUBSTITUTIONS.get(name))
+            .flatten()
+    })
+}
+
+pub fn substitution_names(packs: Packs) -> Vec<&'static str> {
+    let mut names = STRING_SUBSTITUTIONS.keys().copied().collect::<Vec<_>>();
+    if packs.injection {
+        names.extend(INJECTION_SUBSTITUTIONS.keys().copied());
+    }
+    names
+}
+
+const DEFAULT_INT: Span = Span { min: 0, max: 100 };
+const DEFAULT_WALK: Walk = Walk {
+    start: 0.0,
+    step: 1.0,
+    min: f64::MIN,
+    max: f64::MAX,
+    places: 0,
+};
+
+// placeholders that fill a field holding nothing else with a JSON number or boolean
+pub const NATIVE: [&str; 10] = [
+    "bool",
+    "float",
+    "geo_walk",
+    "int",
+    "lat",
+    "lng",
+    "seq",
+    "timestamp",
+    "timestamp_ms",
+    "walk",
+];
+
+// placeholders whose argument after the colon names something, e.g. {pool:customers}
+pub const PARAMETERIZED: [&str; 5] = ["oneof", "pool", "prev", "ref", "saga"];
+
+// {oneof:ok*9|error*1} picks one of the |-separated choices, each weighted 1 unless it ends in *weight
+pub struct OneOf {
+    choices: Vec<(String, u64)>,
+    total: u64,
+}
+
+impl OneOf {
+    pub fn parse(argument: &str) -> Result<Self, String> {
+        let choices = argument
+            .split('|')
+            .map(|choice| match choice.rsplit_once('*') {
+                Some((value, weight)) => match weight.parse::<u64>() {
+                    Ok(weight) => (value.to_string(), weight),
+                    Err(_) => (choice.to_string(), 1),
+                },
+                None => (choice.to_string(), 1),
+            })
+            .collect::<Vec<_>>();
+        let total = choices
+            .iter()
+            .try_fold(0u64, |total, (_, weight)| total.checked_add(*weight))
+            .ok_or_else(|| "weights add up to too much".to_string())?;
+        if total == 0 {
+            return Err("needs a choice with a weight above 0".to_string());
+        }
+        Ok(OneOf { choices, total })
+    }
+
+    pub fn pick(&self, rng: &mut impl Rng) -> &str {
+        let mut pick = rng.gen_range(0..self.total);
+        for (value, weight) in &self.choices {
+            if pick < *weight {
+                return value;
+            }
+            pick -= weight;
+        }
+        unreachable!("pick is below the total weight")
+    }
+}
+
+// a placeholder looked up and its argument parsed when its string is compiled, so that filling
+// it for each event does neither
+struct Placeholder {
+    // as written between the braces, and sent that way when it fills nothing
+    source: String,
+    resolved: Resolved,
+}
+
+enum Resolved {
+    Builtin(&'static Substitution, Option<Argument>),
+    // an injection pack substitution, which only applies to streams with the pack on and is
+    // otherwise a [substitutions] name like any other
+    Pack(&'static Substitution),
+    // a [substitutions] entry, which belongs to the connection rather than the shape
+    Custom,
+    Ref(String),
+    Prev(String),
+    Saga(String),
+    OneOf(OneOf),
+    Pool(String),
+    // an unknown name or an argument its substitution can't take
+    Unfilled,
+}
+
+impl Placeholder {
+    fn resolve(source: String) -> Self {
+        let (name, argument) = match source.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (source.as_str(), None),
+        };
+        let resolved = if let Some(substitution) = STRING_SUBSTITUTIONS.get(name) {
+            match substitution.parse_argument(argument) {
+                Ok(argument) => Resolved::Builtin(substitution, argument),
+                Err(_) => Resolved::Unfilled,
+            }
+        } else {
+            match (name, argument) {
+                (_, None) => INJECTION_SUBSTITUTIONS
+                    .get(name)
+                    .map_or(Resolved::Custom, Resolved::Pack),
+                (_, Some(_)) if INJECTION_SUBSTITUTIONS.contains_key(name) => Resolved::Unfilled,
+                ("ref", Some(path)) => Resolved::Ref(path.to_string()),
+                ("prev", Some(path)) => Resolved::Prev(path.to_string()),
+                ("saga", Some(path)) => Resolved::Saga(path.to_string()),
+                ("oneof", Some(argument)) => {
+                    OneOf::parse(argument).map_or(Resolved::Unfilled, Resolved::OneOf)
+                }
+                ("pool", Some(pool)) => Resolved::Pool(pool.to_string()),
+                _ => Resolved::Unfilled,
+            }
+        };
+        Placeholder { source, resolved }
+    }
+
+    fn fill(&self, ctx: &mut GenContext) -> Option<String> {
+        match &self.resolved {
+            Resolved::Builtin(substitution, argument) => {
+                Some(substitution.generate(ctx, argument.as_ref()))
+            }
+            Resolved::Pack(substitution) if ctx.packs.injection => {
+                Some(substitution.generate(ctx, None))
+            }
+            Resolved::Pack(_) | Resolved::Custom => ctx
+                .custom
+                .get(&self.source)
+                .map(|custom| custom.generate(ctx)),
+            // a missing field, or one the connection hasn't sent yet, renders as an empty string
+            Resolved::Ref(path) => Some(lookup(&ctx.event, path).map(text).unwrap_or_default()),
+            Resolved::Prev(path) => Some(ctx.history.previous(path).map(text).unwrap_or_default()),
+            Resolved::Saga(path) => Some(lookup(&ctx.saga, path).map(text).unwrap_or_default()),
+            Resolved::OneOf(one_of) => Some(one_of.pick(&mut ctx.rng).to_string()),
+            // an empty or unknown pool renders as an empty string
+            Resolved::Pool(pool) => Some(
+                ctx.pools
+                    .pick(pool, &mut ctx.rng)
+                    .map(|value| text(&value))
+                    .unwrap_or_default(),
+            ),
+            Resolved::Unfilled => None,
+        }
+    }
+}
+
+// {int:1-100} as a field's whole value fills it with 57 rather than "57"
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn normalize(string: String, ctx: &mut GenContext) -> String {
+    match ctx.normalization {
+        Some(normalization) => normalization.apply(string, &mut ctx.rng),
+        None => string,
+    }
+}
+
+// the placeholder a string is made of, when it may fill the field with a non-string value
+fn native_placeholder(subject_string: &str) -> Option<&str> {
+    let placeholder = subject_string.strip_prefix('{')?.strip_suffix('}')?;
+    if ["ref:", "prev:", "saga:"]
+        .iter()
+        .any(|prefix| placeholder.starts_with(prefix))
+    {
+        return Some(placeholder);
+    }
+    let name = placeholder
+        .split_once(':')
+        .map_or(placeholder, |(name, _)| name);
+    (NATIVE.contains(&name) && !placeholder.contains(['{', '}', '\\'])).then_some(placeholder)
+}
+
+fn native_value(placeholder: &Placeholder, ctx: &mut GenContext) -> Option<Value> {
+    // a lone {ref:...}, {prev:...} or {saga:...} copies the field, whatever its type
+    match &placeholder.resolved {
+        Resolved::Ref(path) => return lookup(&ctx.event, path).cloned(),
+        Resolved::Prev(path) => return ctx.history.previous(path).cloned(),
+        Resolved::Saga(path) => return lookup(&ctx.saga, path).cloned(),
+        _ => {}
+    }
+    // {seq:order-#} isn't a number, but its counter has already moved on
+    let replacement = placeholder.fill(ctx)?;
+    Some(
+        serde_json::from_str(&replacement)
+            .unwrap_or_else(|_| Value::String(normalize(replacement, ctx))),
+    )
+}
+
+// for strings filled once; ones filled for every event are compiled first
+pub fn fill_string(subject_string: &str, ctx: &mut GenContext) -> Value {
+    CompiledString::compile(subject_string).fill(ctx)
+}
+
+fn fill_pieces(pieces: &[Piece], ctx: &mut GenContext) -> Value {
+    let mut result = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) | Piece::Unclosed(text) => result.push_str(text),
+            Piece::Placeholder(placeholder) => match placeholder.fill(ctx) {
+                Some(replacement) => result.push_str(&replacement),
+                // kept as written, for templates meant for the client
+                None => {
+                    result.push('{');
+                    result.push_str(&placeholder.source);
+                    result.push('}');
+                }
+            },
+        }
+    }
+    Value::String(normalize(result, ctx))
+}
+
+const REPEAT: &str = "$repeat";
+const MAX_REPEAT: u64 = 10_000;
+
+// {"$repeat": {"count": "5-20", "shape": ...}} renders as an array of that many shapes
+pub struct Repeat<'a> {
+    pub count: Span,
+    pub shape: &'a Value,
+}
+
+impl<'a> Repeat<'a> {
+    // None for objects that are not a $repeat; Err for a malformed one
+    pub fn parse(object: &'a Map<String, Value>) -> Option<Result<Self, String>> {
+        let repeat = object.get(REPEAT).filter(|_| object.len() == 1)?;
+        Some(Self::parse_body(repeat))
+    }
+
+    fn parse_body(repeat: &'a Value) -> Result<Self, String> {
+        let Value::Object(repeat) = repeat else {
+            return Err(format!("{REPEAT} takes an object with count and shape"));
+        };
+        if let Some(unknown) = repeat
+            .keys()
+            .find(|key| !matches!(key.as_str(), "count" | "shape"))
+        {
+            return Err(format!("{REPEAT} has an unknown field {unknown}"));
+        }
+        let count = match repeat.get("count") {
+            Some(Value::Number(count)) => count.to_string(),
+            Some(Value::String(count)) => count.clone(),
+            Some(_) => {
+                return Err(format!(
+                    "{REPEAT} count must be a number or a range such as \"5-20\""
+                ))
+            }
+            None => return Err(format!("{REPEAT} needs a count")),
+        };
+        let shape = repeat
+            .get("shape")
+            .ok_or_else(|| format!("{REPEAT} needs a shape"))?;
+
+        Ok(Repeat {
+            count: Span::parse(&count, &(0..=MAX_REPEAT))
+                .map_err(|error| format!("{REPEAT} count {error}"))?,
+            shape,
+        })
+    }
+}
+
+const IF: &str = "$if";
+
+// {"$if": "status == 'refunded'", "then": "{words}", "else": null} fills a field with `then` or
+// `else` once the rest of its event is filled, leaving it out when neither applies
+pub struct If<'a> {
+    pub condition: Condition,
+    pub then: &'a Value,
+    pub otherwise: Option<&'a Value>,
+}
+
+impl<'a> If<'a> {
+    // None for objects that are not an $if; Err for a malformed one
+    pub fn parse(object: &'a Map<String, Value>) -> Option<Result<Self, String>> {
+        let condition = object.get(IF)?;
+        Some(Self::parse_body(object, condition))
+    }
+
+    fn parse_body(object: &'a Map<String, Value>, condition: &Value) -> Result<Self, String> {
+        if let Some(unknown) = object
+            .keys()
+            .find(|key| !matches!(key.as_str(), IF | "then" | "else"))
+        {
+            return Err(format!("{IF} has an unknown field {unknown}"));
+        }
+        let Value::String(condition) = condition else {
+            return Err(format!(
+                "{IF} takes a condition such as \"status == 'refunded'\""
+            ));
+        };
+        Ok(If {
+            condition: Condition::parse(condition).map_err(|e| format!("{IF} {condition}: {e}"))?,
+            then: object
+                .get("then")
+                .ok_or_else(|| format!("{IF} needs a then"))?,
+            otherwise: object.get("else"),
+        })
+    }
+}
+
+pub const OPTIONAL: &str = "$optional";
+pub const NULLABLE: &str = "$nullable";
+
+// how often a field is left out of its event, and how often it is null when it is there
+#[derive(Clone, Copy, Default)]
+pub struct Presence {
+    pub omit: Option<f64>,
+    pub null: Option<f64>,
+}
+
+// {email|null:0.3} and {email|omit:0.3}: a lone placeholder without its trailing modifiers, and
+// what they say; None for strings without any
+pub fn modifiers(subject_string: &str) -> Option<Result<(String, Presence), String>> {
+    let placeholder = subject_string.strip_prefix('{')?.strip_suffix('}')?;
+    let mut rest = placeholder;
+    let mut presence = Presence::default();
+    while let Some((head, modifier)) = rest.rsplit_once('|') {
+        let Some((kind, probability)) = modifier.split_once(':') else {
+            break;
+        };
+        let slot = match kind {
+            "null" => &mut presence.null,
+            "omit" => &mut presence.omit,
+            _ => break,
+        };
+        match probability.parse::<f64>() {
+            Ok(probability) if (0.0..=1.0).contains(&probability) => *slot = Some(probability),
+            _ => {
+                return Some(Err(format!(
+                    "{{{placeholder}}}: {kind} takes a probability between 0 and 1"
+                )))
+            }
+        }
+        rest = head;
+    }
+    (rest.len() < placeholder.len()).then(|| Ok((format!("{{{rest}}}"), presence)))
+}
+
+pub fn is_presence_directive(key: &str) -> bool {
+    key == OPTIONAL || key == NULLABLE
+}
+
+// an object's $optional or $nullable for one of its fields: a probability for all of them, or
+// an object of one per field
+pub fn directive_probability(
+    object: &Map<String, Value>,
+    directive: &str,
+    key: &str,
+) -> Option<f64> {
+    match object.get(directive)? {
+        Value::Number(probability) => probability.as_f64(),
+        Value::Object(fields) => fields.get(key)?.as_f64(),
+        _ => None,
+    }
+    .filter(|probability| (0.0..=1.0).contains(probability))
+}
+
+const REF: &str = "ref:";
+
+// a dotted path from the event's root, with array items by index, e.g. user.first or items.0.id
+pub fn lookup<'a>(event: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(event, |value, segment| match value {
+            Value::Object(object) => object.get(segment),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+fn pointer_segment(segment: &str) -> String {
+    format!("/{}", segment.replace('~', "~0").replace('/', "~1"))
+}
+
+// the JSON pointers of the fields a string refers to
+fn referenced(pieces: &[Piece]) -> Vec<String> {
+    pieces
+        .iter()
+        .filter_map(|piece| match piece {
+            Piece::Placeholder(placeholder) => placeholder.source.strip_prefix(REF),
+            _ => None,
+        })
+        .map(|path| path.split('.').map(pointer_segment).collect())
+        .collect()
+}
+
+// whether one field is, holds or sits inside the other
+fn overlaps(pointer: &str, other: &str) -> bool {
+    pointer == other
+        || pointer.starts_with(&format!("{other}/"))
+        || other.starts_with(&format!("{pointer}/"))
+}
+
+enum Piece {
+    Text(String),
+    Placeholder(Placeholder),
+    // a '{' that is never closed, with the text after it, kept as written
+    Unclosed(String),
+}
+
+// \{, \} and \\ stand for the character itself, so braces meant for the client survive; any
+// other backslash is kept. a '{' opened again before it closes is text
+fn pieces(source: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut open: Option<String> = None;
+    let mut chars = source.chars().peekable();
+    while let Some(char) = chars.next() {
+        let escape = (char == '\\')
+            .then(|| chars.next_if(|next| matches!(next, '{' | '}' | '\\')))
+            .flatten();
+        let (char, escaped) = (escape.unwrap_or(char), escape.is_some());
+        match open.as_mut() {
+            None if char == '{' && !escaped => {
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                open = Some(String::new());
+            }
+            None => text.push(char),
+            Some(placeholder) if char == '}' && !escaped => {
+                pieces.push(Piece::Placeholder(Placeholder::resolve(std::mem::take(
+                    placeholder,
+                ))));
+                open = None;
+            }
+            Some(placeholder) if char == '{' && !escaped => {
+                pieces.push(Piece::Unclosed(format!("{{{placeholder}")));
+                placeholder.clear();
+            }
+            Some(placeholder) => placeholder.push(char),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    if let Some(placeholder) = open {
+        pieces.push(Piece::Unclosed(format!("{{{placeholder}")));
+    }
+    pieces
+}
+
+// the placeholders in a string, and the unclosed '{'s that were taken as text
+pub fn placeholders(subject_string: &str) -> (Vec<String>, usize) {
+    let mut placeholders = Vec::new();
+    let mut unclosed = 0;
+    for piece in pieces(subject_string) {
+        match piece {
+            Piece::Placeholder(placeholder) => placeholders.push(placeholder.source),
+            Piece::Unclosed(_) => unclosed += 1,
+            Piece::Text(_) => {}
+        }
+    }
+    (placeholders, unclosed)
+}
+
+// a shape string split into text and resolved placeholders once per connection rather than per
+// event
+pub struct CompiledString {
+    native: Option<Placeholder>,
+    pieces: Vec<Piece>,
+    // the fields it reads, when it has to wait for them
+    references: Option<Vec<String>>,
+}
+
+impl CompiledString {
+    pub fn compile(source: &str) -> Self {
+        let pieces = pieces(source);
+        let references = referenced(&pieces);
+        CompiledString {
+            native: native_placeholder(source)
+                .map(|native| Placeholder::resolve(native.to_string())),
+            references: (!references.is_empty()).then_some(references),
+            pieces,
+        }
+    }
+
+    pub fn fill(&self, ctx: &mut GenContext) -> Value {
+        if let Some(value) = self
+            .native
+            .as_ref()
+            .and_then(|placeholder| native_value(placeholder, ctx))
+        {
+            return value;
+        }
+        fill_pieces(&self.pieces, ctx)
+    }
+}
+
+enum Node {
+    Object(Vec<Field>),
+    Repeat(Span, Box<Node>),
+    If(Box<CompiledIf>),
+    String(CompiledString),
+    // arrays, numbers and the like, and malformed $repeats, emitted as written
+    Verbatim(Value),
+}
+
+struct CompiledIf {
+    condition: Condition,
+    then: Node,
+    otherwise: Option<Node>,
+}
+
+struct Field {
+    key: String,
+    segment: String,
+    node: Node,
+    presence: Presence,
+}
+
+// a shape walked once, so filling an event doesn't reparse its placeholders
+pub struct CompiledShape {
+    fields: Vec<Field>,
+}
+
+impl CompiledShape {
+    pub fn compile(shape: &Map<String, Value>) -> Self {
+        CompiledShape {
+            fields: compile_fields(shape),
+        }
+    }
+}
+
+fn compile_fields(object: &Map<String, Value>) -> Vec<Field> {
+    object
+        .iter()
+        .filter(|(key, _)| !is_presence_directive(key))
+        .map(|(key, value)| {
+            // a field's own modifiers win over its object's directives
+            let (node, presence) = match value.as_str().and_then(modifiers) {
+                Some(Ok((source, presence))) => {
+                    (Node::String(CompiledString::compile(&source)), presence)
+                }
+                _ => (compile_node(value), Presence::default()),
+            };
+            Field {
+                key: key.clone(),
+                segment: pointer_segment(key),
+                node,
+                presence: Presence {
+                    omit: presence
+                        .omit
+                        .or_else(|| directive_probability(object, OPTIONAL, key)),
+                    null: presence
+                        .null
+                        .or_else(|| directive_probability(object, NULLABLE, key)),
+                },
+            }
+        })
+        .collect()
+}
+
+fn compile_node(value: &Value) -> Node {
+    match value {
+        Value::Object(object) => match (Repeat::parse(object), If::parse(object)) {
+            (Some(Ok(repeat)), _) => {
+                Node::Repeat(repeat.count, Box::new(compile_node(repeat.shape)))
+            }
+            (None, Some(Ok(conditional))) => Node::If(Box::new(CompiledIf {
+                condition: conditional.condition,
+                then: compile_node(conditional.then),
+                otherwise: conditional.otherwise.map(compile_node),
+            })),
+            // a malformed $repeat or $if is emitted verbatim, as the linter warns
+            (Some(Err(_)), _) | (None, Some(Err(_))) => Node::Verbatim(value.clone()),
+            (None, None) => Node::Object(compile_fields(object)),
+        },
+        Value::String(subject_string) => Node::String(CompiledString::compile(subject_string)),
+        _ => Node::Verbatim(value.clone()),
+    }
+}
+
+// strings referring to other fields are left for a second pass and $if fields for a third, both
+// keyed by JSON pointer
+#[derive(Default)]
+struct Deferred<'a> {
+    strings: Vec<(String, &'a CompiledString)>,
+    conditionals: Vec<(String, &'a CompiledIf)>,
+}
+
+fn fill_node<'a>(
+    node: &'a Node,
+    pointer: &str,
+    deferred: &mut Deferred<'a>,
+    ctx: &mut GenContext,
+) -> Value {
+    match node {
+        Node::Object(fields) => Value::Object(fill_fields(fields, pointer, deferred, ctx)),
+        Node::Repeat(count, shape) => {
+            let count = count.pick(&mut ctx.rng);
+            Value::Array(
+                (0..count)
+                    .map(|index| fill_node(shape, &format!("{pointer}/{index}"), deferred, ctx))
+                    .collect(),
+            )
+        }
+        Node::If(conditional) => {
+            deferred
+                .conditionals
+                .push((pointer.to_string(), conditional));
+            Value::Null
+        }
+        Node::String(string) if string.references.is_some() => {
+            deferred.strings.push((pointer.to_string(), string));
+            Value::Null
+        }
+        Node::String(string) => string.fill(ctx),
+        Node::Verbatim(value) => value.clone(),
+    }
+}
+
+fn fill_fields<'a>(
+    fields: &'a [Field],
+    pointer: &str,
+    deferred: &mut Deferred<'a>,
+    ctx: &mut GenContext,
+) -> Map<String, Value> {
+    fields
+        .iter()
+        .filter_map(|field| {
+            // only fields with a probability draw from the rng, so other shapes fill as before
+            if field
+                .presence
+                .omit
+                .is_some_and(|omit| ctx.rng.gen_bool(omit))
+            {
+                return None;
+            }
+            if field
+                .presence
+                .null
+                .is_some_and(|null| ctx.rng.gen_bool(null))
+            {
+                return Some((field.key.clone(), Value::Null));
+            }
+            let pointer = format!("{pointer}{}", field.segment);
+            Some((
+                field.key.clone(),
+                fill_node(&field.node, &pointer, deferred, ctx),
+            ))
+        })
+        .collect::<Map<String, Value>>()
+}
+
+// fields with {ref:...} are filled once the rest are, each after the referring fields it
+// reads; a cycle is broken in key order, its fields reading the others as null
+fn fill_deferred(
+    mut event: Value,
+    mut deferred: Vec<(String, &CompiledString)>,
+    ctx: &mut GenContext,
+) -> Value {
+    while !deferred.is_empty() {
+        let next = deferred
+            .iter()
+            .position(|(_, string)| {
+                string.references.iter().flatten().all(|reference| {
+                    deferred
+                        .iter()
+                        .all(|(pending, _)| !overlaps(reference, pending))
+                })
+            })
+            .unwrap_or(0);
+        let (pointer, string) = deferred.remove(next);
+        ctx.event = event;
+        let value = string.fill(ctx);
+        event = std::mem::take(&mut ctx.event);
+        if let Some(field) = event.pointer_mut(&pointer) {
+            *field = value;
+        }
+    }
+    event
+}
+
+// $if fields are decided in shape order once everything else is filled, so each sees the
+// fields before it; a branch's own $ifs are decided after it. one without a branch to fill
+// leaves its key out of an object and its element out of a $repeat's array
+fn fill_conditionals(
+    mut event: Value,
+    mut conditionals: Vec<(String, &CompiledIf)>,
+    ctx: &mut GenContext,
+) -> Value {
+    conditionals.reverse();
+    while let Some((pointer, conditional)) = conditionals.pop() {
+        let (parent, segment) = pointer.rsplit_once('/').unwrap_or_default();
+        let scope = event.pointer(parent).unwrap_or(&Value::Null);
+        let branch = if conditional.condition.holds(scope, &event) {
+            Some(&conditional.then)
+        } else {
+            conditional.otherwise.as_ref()
+        };
+        let Some(branch) = branch else {
+            let key = segment.replace("~1", "/").replace("~0", "~");
+            match event.pointer_mut(parent) {
+                Some(Value::Object(object)) => {
+                    object.remove(&key);
+                }
+                Some(Value::Array(items)) => {
+                    if let Some(index) = key.parse().ok().filter(|index| *index < items.len()) {
+                        items.remove(index);
+                        shift_pointers(&mut conditionals, parent, index);
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        };
+        let mut deferred = Deferred::default();
+        let value = fill_node(branch, &pointer, &mut deferred, ctx);
+        if let Some(field) = event.pointer_mut(&pointer) {
+            *field = value;
+        }
+        event = fill_deferred(event, deferred.strings, ctx);
+        conditionals.extend(deferred.conditionals.into_iter().rev());
+    }
+    event
+}
+
+// points the pending $ifs after a removed array element at the elements that moved into its place
+fn shift_pointers(conditionals: &mut [(String, &CompiledIf)], array: &str, removed: usize) {
+    for (pointer, _) in conditionals {
+        let Some(rest) = pointer
+            .strip_prefix(array)
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            continue;
+        };
+        let (index, tail) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if let Some(index) = index.parse::<usize>().ok().filter(|index| *index > removed) {
+            *pointer = format!("{array}/{}{tail}", index - 1);
+        }
+    }
+}
+
+pub fn fill_object_fields(shape: &CompiledShape, ctx: &mut GenContext) -> Map<String, Value> {
+    let mut deferred = Deferred::default();
+    let event = Value::Object(fill_fields(&shape.fields, "", &mut deferred, ctx));
+    let event = fill_deferred(event, deferred.strings, ctx);
+    let event = fill_conditionals(event, deferred.conditionals, ctx);
+
+    if ctx.history.keep_previous {
+        ctx.history.previous = Some(event.clone());
+    }
+    let Value::Object(event) = event else {
+        unreachable!("the event is filled from an object")
+    };
+    event
+}
+
+// filled like an event, without becoming the one {prev:...} reads from
+pub fn fill_detached(shape: &CompiledShape, ctx: &mut GenContext) -> Map<String, Value> {
+    let keep_previous = std::mem::replace(&mut ctx.history.keep_previous, false);
+    let filled = fill_object_fields(shape, ctx);
+    ctx.history.keep_previous = keep_previous;
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fill(shape: Value) -> Value {
+        let Value::Object(shape) = shape else {
+            unreachable!("shapes are objects")
+        };
+        let mut ctx = context();
+        Value::Object(fill_object_fields(
+            &CompiledShape::compile(&shape),
+            &mut ctx,
+        ))
+    }
+
+    fn context() -> GenContext {
+        GenContext::new(
+            0,
+            Packs::default(),
+            Clock::default(),
+            Arc::new(Pools::new(0)),
+        )
+    }
+
+    #[test]
+    fn placeholders_are_resolved_when_compiled() {
+        let compiled = CompiledString::compile(
+            "{int:5-9} {float:0-1:2} {oneof:a*2|b} {pool:ids} {ref:user.id} {xss} {mine} {int:x} {nope:1}",
+        );
+        let resolved: Vec<_> = compiled
+            .pieces
+            .iter()
+            .filter_map(|piece| match piece {
+                Piece::Placeholder(placeholder) => Some(&placeholder.resolved),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(
+            resolved[0],
+            Resolved::Builtin(_, Some(Argument::Span(Span { min: 5, max: 9 })))
+        ));
+        assert!(matches!(
+            resolved[1],
+            Resolved::Builtin(_, Some(Argument::Decimal(Decimal { places: 2, .. })))
+        ));
+        assert!(matches!(resolved[2], Resolved::OneOf(one_of) if one_of.total == 3));
+        assert!(matches!(resolved[3], Resolved::Pool(pool) if pool == "ids"));
+        assert!(matches!(resolved[4], Resolved::Ref(path) if path == "user.id"));
+        assert!(matches!(resolved[5], Resolved::Pack(_)));
+        assert!(matches!(resolved[6], Resolved::Custom));
+        assert!(matches!(resolved[7], Resolved::Unfilled));
+        assert!(matches!(resolved[8], Resolved::Unfilled));
+        assert!(matches!(
+            CompiledString::compile("{timestamp:+1h}").native,
+            Some(Placeholder {
+                resolved: Resolved::Builtin(_, Some(Argument::Time(_))),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn compiled_strings_fill_as_written_strings_do() {
+        let template = "{int:5-5}/{seq:n-#}/{oneof:x}/{int:x}/{xss}/{walk:start=3}";
+        let compiled = CompiledString::compile(template);
+        let mut ctx = context();
+        assert_eq!(compiled.fill(&mut ctx), json!("5/n-1/x/{int:x}/{xss}/3"));
+        // the counter and the walk carry on from the first fill
+        let second = compiled.fill(&mut ctx);
+        assert!(second
+            .as_str()
+            .unwrap()
+            .starts_with("5/n-2/x/{int:x}/{xss}/"));
+        assert_ne!(second, json!("5/n-2/x/{int:x}/{xss}/3"));
+        assert_eq!(
+            fill_string(template, &mut context()),
+            json!("5/n-1/x/{int:x}/{xss}/3")
+        );
+        assert_eq!(
+            CompiledString::compile("{int:7-7}").fill(&mut ctx),
+            json!(7)
+        );
+
+        let mut packed = context();
+        packed.packs.injection = true;
+        assert_ne!(
+            CompiledString::compile("{xss}").fill(&mut packed),
+            json!("{xss}")
+        );
+    }
+
+    #[test]
+    fn false_ifs_leave_their_field_or_element_out() {
+        let event = fill(json!({
+            "kind": "order",
+            "refund": { "$if": "kind == 'refund'", "then": "{int}" },
+            "none": { "$repeat": { "count": 3, "shape": { "$if": "false", "then": 1 } } },
+            "all": { "$repeat": { "count": 2, "shape": { "$if": "kind", "then": "x", "else": null } } },
+            "fallback": { "$repeat": { "count": 2, "shape": { "$if": "!kind", "then": 1, "else": 2 } } },
+        }));
+        assert_eq!(event.get("refund"), None);
+        assert_eq!(event["none"], json!([]));
+        assert_eq!(event["all"], json!(["x", "x"]));
+        assert_eq!(event["fallback"], json!([2, 2]));
+    }
+
+    #[test]
+    fn later_elements_are_decided_where_they_moved_to() {
+        // only the first element finds items.0 unset; each later one is dropped in turn
+        let event = fill(json!({
+            "items": { "$repeat": { "count": 4, "shape": { "$if": "items.0 != 'x'", "then": "x" } } },
+        }));
+        assert_eq!(event["items"], json!(["x"]));
+
+        let event = fill(json!({
+            "rows": { "$repeat": { "count": 2, "shape": { "$repeat": { "count": 2, "shape": {
+                "$if": "false", "then": 1
+            } } } } },
+            "branch": { "$if": "true", "then": {
+                "inner": { "$repeat": { "count": 2, "shape": { "$if": "false", "then": 1 } } },
+                "kept": { "$if": "inner", "then": "never", "else": "emptied arrays are falsy" },
+            } },
+        }));
+        assert_eq!(event["rows"], json!([[], []]));
+        assert_eq!(
+            event["branch"],
+            json!({ "inner": [], "kept": "emptied arrays are falsy" })
+        );
+    }
+
+    #[test]
+    fn clock_saturates_instead_of_overflowing() {
+        let clock = Clock {
+            skew_ms: MAX_SHIFT_MS,
+            frozen: Some(DateTime::<Utc>::MAX_UTC),
+            ..Clock::default()
+        };
+        assert_eq!(clock.now(), DateTime::<Utc>::MAX_UTC);
+        let clock = Clock {
+            skew_ms: i64::MIN,
+            drift_ms_per_min: -86_400_000.0,
+            ..Clock::default()
+        };
+        assert_eq!(clock.now(), DateTime::<Utc>::MIN_UTC);
+    }
+
+    #[test]
+    fn time_offsets_are_bounded_per_segment() {
+        // each near i64::MAX on its own, which summed unchecked used to overflow
+        let huge = ["+100000000000d"; 2].join(":");
+        assert!(TimeSpec::parse(&huge, true).is_err());
+        let many = ["+36000d"; 20].join(":");
+        assert_eq!(
+            TimeSpec::parse(&many, false).err().unwrap(),
+            "offsets are limited to 100 years"
+        );
+        assert!(TimeSpec::parse("past:30d:past:30d", false).is_ok());
+        assert_eq!(
+            TimeSpec::parse("+2h:-1h", false).unwrap().shift_ms,
+            3_600_000
+        );
+    }
+
+    #[test]
+    fn float_ranges_must_have_a_finite_width() {
+        assert!(Decimal::parse("-1e308-1e308").is_err());
+        assert!(Decimal::parse("1e309").is_err());
+        let decimal = Decimal::parse("-1e307-1e307").unwrap();
+        decimal.pick(&mut ChaCha8Rng::seed_from_u64(0));
+    }
+
+    #[test]
+    fn walks_must_step_and_bounce_within_finite_numbers() {
+        assert!(Walk::parse("step=1e308").is_err());
+        assert!(Walk::parse("min=-1e308,max=1e308").is_err());
+        assert!(Walk::parse("min=-1.7e308,max=0,step=1e307").is_err());
+        let walk = Walk::parse("step=1e307").unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut value = walk.start;
+        for _ in 0..1000 {
+            value = walk.advance(value, &mut rng);
+            assert!(value.is_finite());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::transport::Message;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use futures::{Stream, StreamExt};
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use serde_json::json;
+use tracing::info;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACERESPONSE_HEADER: &str = "traceresponse";
+const TRACE_KEY: &str = "_trace";
+const MAX_REQUEST_ID_LEN: usize = 256;
+
+// W3C trace context, continued with a span of our own for the stream
+#[derive(Serialize, Clone)]
+struct TraceContext {
+    trace_id: String,
+    parent_id: String,
+    span_id: String,
+    #[serde(skip)]
+    flags: String,
+}
+
+impl TraceContext {
+    // <2 hex version>-<32 hex trace id>-<16 hex parent id>-<2 hex flags>
+    fn parse(traceparent: &str) -> Option<Self> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        let &[version, trace_id, parent_id, flags, ..] = parts.as_slice() else {
+            return None;
+        };
+        let hex = |value: &str, len: usize| {
+            value.len() == len
+                && value
+                    .bytes()
+                    .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        let valid = hex(version, 2)
+            && version != "ff"
+            && (version != "00" || parts.len() == 4)
+            && hex(trace_id, 32)
+            && trace_id.bytes().any(|b| b != b'0')
+            && hex(parent_id, 16)
+            && parent_id.bytes().any(|b| b != b'0')
+            && hex(flags, 2);
+        valid.then(|| TraceContext {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+            span_id: format!("{:016x}", thread_rng().gen_range(1..=u64::MAX)),
+            flags: flags.to_string(),
+        })
+    }
+}
+
+// the ids a client connected with, so its traces can be stitched to the stream's events
+#[derive(Serialize, Clone)]
+pub struct Correlation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    trace: Option<TraceContext>,
+}
+
+impl Correlation {
+    // None when the request carries neither an X-Request-Id nor a valid traceparent
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let request_id = header(REQUEST_ID_HEADER)
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .map(str::to_string);
+        let trace = header(TRACEPARENT_HEADER).and_then(TraceContext::parse);
+
+        (request_id.is_some() || trace.is_some()).then_some(Correlation { request_id, trace })
+    }
+
+    pub fn response_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = self
+            .request_id
+            .as_deref()
+            .and_then(|id| HeaderValue::from_str(id).ok())
+        {
+            headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        if let Some(trace) = &self.trace {
+            let traceresponse = format!("00-{}-{}-{}", trace.trace_id, trace.span_id, trace.flags);
+            if let Ok(value) = HeaderValue::from_str(&traceresponse) {
+                headers.insert(HeaderName::from_static(TRACERESPONSE_HEADER), value);
+            }
+        }
+        headers
+    }
+
+    fn log(&self, stream_id: &str, what: &str) {
+        info!("stream {stream_id} {what} {}", json!(self));
+    }
+
+    // adds the ids under _trace to every object event and logs when the stream opens and ends
+    pub fn annotate(
+        self,
+        stream_id: String,
+        messages: impl Stream<Item = Message> + Send + 'static,
+    ) -> impl Stream<Item = Message> + Send + 'static {
+        let trace = json!(self);
+        async_stream::stream! {
+            self.log(&stream_id, "opened");
+            // logs the close even when the client goes away mid-stream
+            let _closed = Closed { correlation: self, stream_id };
+            let mut messages = Box::pin(messages);
+            while let Some(mut message) = messages.next().await {
+                if let Some(data) = message.data.as_object_mut() {
+                    data.insert(TRACE_KEY.to_string(), trace.clone());
+                }
+                yield message;
+            }
+        }
+    }
+}
+
+struct Closed {
+    correlation: Correlation,
+    stream_id: String,
+}
+
+impl Drop for Closed {
+    fn drop(&mut self) {
+        self.correlation.log(&self.stream_id, "closed");
+    }
+}
@@ -0,0 +1,396 @@
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::sse::{Event, KeepAlive};
+use axum::response::{IntoResponse, Response, Sse};
+use fake::faker::lorem::en::Words;
+use fake::Fake;
+use futures::{future, Stream, StreamExt};
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+const EVENT_STREAM: &str = "text/event-stream";
+const NDJSON: &str = "application/x-ndjson";
+const PADDING_KEY: &str = "_padding";
+const KEEP_ALIVE: &str = "keep-alive";
+
+#[derive(Clone)]
+pub struct Message {
+    // None leaves the SSE event field off, which clients treat as "message"
+    pub event: Option<String>,
+    pub id: Option<u64>,
+    pub data: Value,
+    // control messages describe the stream itself and are only framed by event-aware transports
+    pub control: bool,
+    // sent verbatim in place of data, for payloads that aren't valid JSON
+    pub raw: Option<String>,
+    // cuts the connection off instead of being sent
+    pub abort: bool,
+}
+
+// fails the response body, so the connection drops without its proper ending
+#[derive(Debug, Clone, Copy)]
+pub struct StreamError(&'static str);
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+fn body_item<T>(message: Message, item: impl FnOnce(Message) -> T) -> Result<T, StreamError> {
+    if message.abort {
+        return Err(StreamError("stream aborted"));
+    }
+    Ok(item(message))
+}
+
+impl Message {
+    pub fn data(event: Option<String>, data: Value) -> Self {
+        Message {
+            event,
+            id: None,
+            data,
+            control: false,
+            raw: None,
+            abort: false,
+        }
+    }
+
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn control(event: &str, data: Value) -> Self {
+        Message {
+            event: Some(event.to_string()),
+            id: None,
+            data,
+            control: true,
+            raw: None,
+            abort: false,
+        }
+    }
+
+    pub fn abort() -> Self {
+        Message {
+            abort: true,
+            ..Message::control("abort", Value::Null)
+        }
+    }
+
+    pub fn payload(&self) -> String {
+        self.raw.clone().unwrap_or_else(|| self.data.to_string())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldOrder {
+    // event, id, data
+    #[default]
+    Standard,
+    // data, id, event
+    Reversed,
+    // event and id anywhere among the data lines
+    Shuffled,
+}
+
+// low-level SSE framing variations real servers produce, for client robustness testing
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Framing {
+    pub line_ending: LineEnding,
+    // lead the body with a UTF-8 byte order mark
+    pub bom: bool,
+    // pretty-print data over several data: lines
+    pub multiline_data: bool,
+    pub field_order: FieldOrder,
+    // chance of a comment line before each line, between and within events
+    pub comment_rate: f64,
+    // a _padding string of this many bytes in every data event, to span many TCP segments
+    pub pad_bytes: Option<usize>,
+    // write frames in pieces of up to this many bytes, splitting lines (and characters)
+    pub chunk_bytes: Option<usize>,
+}
+
+fn comment(rng: &mut impl Rng) -> String {
+    if rng.gen_bool(0.5) {
+        format!(": {KEEP_ALIVE}")
+    } else {
+        format!(
+            ": {}",
+            Words(1..4).fake_with_rng::<Vec<String>, _>(rng).join(" ")
+        )
+    }
+}
+
+impl Framing {
+    fn frame(self, message: &Message) -> String {
+        let mut data = message.data.clone();
+        if let (Some(pad_bytes), Value::Object(object), false) =
+            (self.pad_bytes, &mut data, message.control)
+        {
+            object.insert(
+                PADDING_KEY.to_string(),
+                Value::String("x".repeat(pad_bytes)),
+            );
+        }
+        let data = if let Some(raw) = &message.raw {
+            raw.clone()
+        } else if self.multiline_data {
+            serde_json::to_string_pretty(&data).unwrap_or_default()
+        } else {
+            data.to_string()
+        };
+        let mut lines = data
+            .split('\n')
+            .map(|line| format!("data: {line}"))
+            .collect::<Vec<_>>();
+
+        let mut rng = thread_rng();
+        let fields = [
+            message.id.map(|id| format!("id: {id}")),
+            message
+                .event
+                .as_ref()
+                .map(|event| format!("event: {event}")),
+        ];
+        for field in fields.into_iter().flatten() {
+            match self.field_order {
+                FieldOrder::Standard => lines.insert(0, field),
+                FieldOrder::Reversed => lines.push(field),
+                FieldOrder::Shuffled => {
+                    let at = rng.gen_range(0..=lines.len());
+                    lines.insert(at, field);
+                }
+            }
+        }
+
+        if self.comment_rate > 0.0 {
+            for at in (0..lines.len()).rev() {
+                if rng.gen_bool(self.comment_rate) {
+                    lines.insert(at, comment(&mut rng));
+                }
+            }
+        }
+
+        let line_ending = self.line_ending.as_str();
+        let mut frame = lines.join(line_ending);
+        frame.push_str(line_ending);
+        frame.push_str(line_ending);
+        frame
+    }
+
+    // frames the stream by hand where axum's Sse writer has only one way to do it
+    fn respond(
+        self,
+        keepalive: Option<Duration>,
+        messages: impl Stream<Item = Message> + Send + 'static,
+    ) -> Response {
+        let line_ending = self.line_ending.as_str();
+        let beat = Bytes::from(format!(": {KEEP_ALIVE}{line_ending}{line_ending}"));
+        let frames = async_stream::stream! {
+            if self.bom {
+                yield Ok::<_, StreamError>(Bytes::from_static("\u{feff}".as_bytes()));
+            }
+
+            let mut messages = Box::pin(messages);
+            while let Some(message) = messages.next().await {
+                let frame = match body_item(message, |message| Bytes::from(self.frame(&message))) {
+                    Ok(frame) => frame,
+                    Err(error) => {
+                        yield Err(error);
+                        return;
+                    }
+                };
+                let Some(chunk_bytes) = self.chunk_bytes else {
+                    yield Ok(frame);
+                    continue;
+                };
+
+                let mut rest = frame;
+                while !rest.is_empty() {
+                    let size = thread_rng().gen_range(1..=chunk_bytes).min(rest.len());
+                    yield Ok(rest.split_to(size));
+                    // hand control back so each piece goes out in its own write
+                    tokio::task::yield_now().await;
+                }
+            }
+        };
+        let mut response =
+            Body::from_stream(keep_alive(frames, keepalive, Ok(beat))).into_response();
+
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(EVENT_STREAM));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Sse,
+    Ndjson,
+}
+
+impl Transport {
+    // picks the highest-q supported media type from Accept, defaulting to SSE
+    pub fn negotiate(headers: &HeaderMap) -> Transport {
+        let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+            return Transport::Sse;
+        };
+
+        let mut best: Option<(f32, Transport)> = None;
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let transport = match media_type {
+                EVENT_STREAM => Transport::Sse,
+                NDJSON | "application/ndjson" => Transport::Ndjson,
+                _ => continue,
+            };
+
+            if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                best = Some((quality, transport));
+            }
+        }
+
+        best.map_or(Transport::Sse, |(_, transport)| transport)
+    }
+
+    // an explicit ?encoding= wins over Accept, for clients that can't set headers
+    pub fn select(encoding: Option<Transport>, headers: &HeaderMap) -> Transport {
+        encoding.unwrap_or_else(|| Transport::negotiate(headers))
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Transport::Sse => EVENT_STREAM,
+            Transport::Ndjson => NDJSON,
+        }
+    }
+
+    // headers a stream response would carry, without its body
+    pub fn head(self) -> Response {
+        let mut response = Response::new(Body::empty());
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(self.content_type()),
+        );
+        headers.append(header::VARY, HeaderValue::from_static("accept"));
+        response
+    }
+
+    pub fn respond(
+        self,
+        framing: Framing,
+        keepalive: Option<Duration>,
+        messages: impl Stream<Item = Message> + Send + 'static,
+    ) -> Response {
+        let mut response = match self {
+            Transport::Sse if framing != Framing::default() => framing.respond(keepalive, messages),
+            Transport::Sse => {
+                let sse = Sse::new(messages.map(|message| body_item(message, sse_event)));
+                match keepalive {
+                    Some(every) => sse
+                        .keep_alive(KeepAlive::new().interval(every).text(KEEP_ALIVE))
+                        .into_response(),
+                    None => sse.into_response(),
+                }
+            }
+            Transport::Ndjson => {
+                let lines = messages
+                    .filter(|message| future::ready(!message.control || message.abort))
+                    .map(|message| {
+                        body_item(message, |message| format!("{}\n", message.payload()))
+                    });
+                // NDJSON readers skip blank lines
+                let lines = keep_alive(lines, keepalive, Ok("\n".to_string()));
+                let mut response = Body::from_stream(lines).into_response();
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_TYPE, HeaderValue::from_static(NDJSON));
+                response
+            }
+        };
+
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
+
+// yields beat whenever every passes without an item
+pub fn keep_alive<T: Clone + Send + 'static>(
+    items: impl Stream<Item = T> + Send + 'static,
+    every: Option<Duration>,
+    beat: T,
+) -> impl Stream<Item = T> + Send + 'static {
+    async_stream::stream! {
+        let mut items = Box::pin(items);
+        let Some(every) = every else {
+            while let Some(item) = items.next().await {
+                yield item;
+            }
+            return;
+        };
+
+        let idle = sleep(every);
+        tokio::pin!(idle);
+        loop {
+            tokio::select! {
+                item = items.next() => match item {
+                    Some(item) => yield item,
+                    None => break,
+                },
+                _ = &mut idle => yield beat.clone(),
+            }
+            idle.as_mut().reset(Instant::now() + every);
+        }
+    }
+}
+
+fn sse_event(message: Message) -> Event {
+    let payload = message.payload();
+    let mut event = Event::default();
+    if let Some(name) = message.event {
+        event = event.event(name);
+    }
+    if let Some(id) = message.id {
+        event = event.id(id.to_string());
+    }
+    event.data(payload)
+}
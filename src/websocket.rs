@@ -0,0 +1,405 @@
+use crate::error::AppError;
+use crate::transport::{keep_alive, Message};
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::{Stream, StreamExt};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+// RFC 6455's fixed key suffix for Sec-WebSocket-Accept
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_TOO_BIG: u16 = 1009;
+// clients only ever send control frames and small messages to a mock
+const MAX_CLIENT_FRAME: u64 = 64 * 1024;
+
+// a request to switch the connection to the WebSocket protocol, for clients that can't use SSE
+pub struct WebSocketUpgrade {
+    key: String,
+    on_upgrade: OnUpgrade,
+}
+
+fn header_has(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for WebSocketUpgrade {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let headers = &parts.headers;
+        if !header_has(headers, header::UPGRADE, "websocket")
+            || !header_has(headers, header::CONNECTION, "upgrade")
+        {
+            return Err(AppError::new(StatusCode::UPGRADE_REQUIRED)
+                .with_detail("this route only speaks WebSocket")
+                .with_header(header::UPGRADE, HeaderValue::from_static("websocket")));
+        }
+        if !header_has(headers, header::SEC_WEBSOCKET_VERSION, "13") {
+            return Err(
+                AppError::bad_request("only WebSocket version 13 is supported").with_header(
+                    header::SEC_WEBSOCKET_VERSION,
+                    HeaderValue::from_static("13"),
+                ),
+            );
+        }
+        let key = headers
+            .get(header::SEC_WEBSOCKET_KEY)
+            .and_then(|key| key.to_str().ok())
+            .filter(|key| STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16))
+            .ok_or_else(|| AppError::bad_request("Sec-WebSocket-Key is missing or malformed"))?
+            .to_string();
+        let on_upgrade = parts
+            .extensions
+            .remove::<OnUpgrade>()
+            .ok_or_else(|| AppError::bad_request("this connection can't be upgraded"))?;
+
+        Ok(WebSocketUpgrade { key, on_upgrade })
+    }
+}
+
+impl WebSocketUpgrade {
+    // answers 101 and sends every message, control ones included, as a JSON text frame, with a
+    // ping after every keepalive without one
+    pub fn respond(
+        self,
+        keepalive: Option<Duration>,
+        messages: impl Stream<Item = Message> + Send + 'static,
+    ) -> Response {
+        let accept = accept_key(&self.key);
+        tokio::spawn(async move {
+            if let Ok(upgraded) = self.on_upgrade.await {
+                serve(
+                    TokioIo::new(upgraded),
+                    keep_alive(messages.map(Some), keepalive, None),
+                )
+                .await;
+            }
+        });
+
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = response.headers_mut();
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        if let Ok(accept) = HeaderValue::from_str(&accept) {
+            headers.insert(header::SEC_WEBSOCKET_ACCEPT, accept);
+        }
+        response
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    STANDARD.encode(sha1(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn close_frame(code: u16) -> Vec<u8> {
+    frame(OP_CLOSE, &code.to_be_bytes())
+}
+
+// one client frame with its mask taken off, the close code for a frame breaking the protocol,
+// or None once the connection is closed
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> Option<Result<(u8, Vec<u8>), u16>> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await.ok()?;
+    let opcode = head[0] & 0x0f;
+    // RFC 6455 section 5.1: a server must close the connection on an unmasked client frame
+    if head[1] & 0x80 == 0 {
+        return Some(Err(CLOSE_PROTOCOL_ERROR));
+    }
+    let len = match head[1] & 0x7f {
+        126 => reader.read_u16().await.ok()? as u64,
+        127 => reader.read_u64().await.ok()?,
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_FRAME {
+        return Some(Err(CLOSE_TOO_BIG));
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await.ok()?;
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await.ok()?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+    Some(Ok((opcode, payload)))
+}
+
+async fn write(writer: &mut (impl AsyncWrite + Unpin), frame: &[u8]) -> std::io::Result<()> {
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+async fn serve(
+    io: impl AsyncRead + AsyncWrite + Send + 'static,
+    messages: impl Stream<Item = Option<Message>> + Send + 'static,
+) {
+    let (mut reader, mut writer) = tokio::io::split(io);
+    // pongs and close replies from the reader, interleaved with the event frames
+    let (replies, mut pending) = mpsc::channel::<Vec<u8>>(8);
+    tokio::spawn(async move {
+        while let Some(received) = read_frame(&mut reader).await {
+            let reply = match received {
+                Ok((OP_PING, payload)) => frame(OP_PONG, &payload[..payload.len().min(125)]),
+                Ok((OP_CLOSE, _)) => {
+                    let _ = replies.send(close_frame(CLOSE_NORMAL)).await;
+                    return;
+                }
+                Ok(_) => continue,
+                Err(code) => {
+                    let _ = replies.send(close_frame(code)).await;
+                    return;
+                }
+            };
+            if replies.send(reply).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut messages = Box::pin(messages);
+    loop {
+        let outgoing = tokio::select! {
+            reply = pending.recv() => match reply {
+                Some(reply) => {
+                    let closing = reply[0] & 0x0f == OP_CLOSE;
+                    if write(&mut writer, &reply).await.is_err() || closing {
+                        return;
+                    }
+                    continue;
+                }
+                // the client went away without a close frame
+                None => return,
+            },
+            message = messages.next() => match message {
+                Some(None) => frame(OP_PING, &[]),
+                // drop the connection without a close frame
+                Some(Some(message)) if message.abort => return,
+                Some(Some(message)) => {
+                    let text = match message.raw {
+                        Some(raw) => raw,
+                        None => json!({ "event": message.event, "id": message.id, "data": message.data })
+                            .to_string(),
+                    };
+                    frame(OP_TEXT, text.as_bytes())
+                }
+                None => {
+                    let _ = write(&mut writer, &close_frame(CLOSE_NORMAL)).await;
+                    return;
+                }
+            },
+        };
+        if write(&mut writer, &outgoing).await.is_err() {
+            return;
+        }
+    }
+}
+
+// SHA-1 is only needed for the handshake, not for anything security-sensitive
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks_exact(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (chunk, value) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a client frame the way a browser sends it, masked with a fixed key
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = frame(opcode, payload);
+        let start = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        for (index, byte) in frame[start..].iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+        frame.splice(start..start, mask);
+        frame
+    }
+
+    async fn read(bytes: &[u8]) -> Option<Result<(u8, Vec<u8>), u16>> {
+        read_frame(&mut &bytes[..]).await
+    }
+
+    // what the server writes back after the client sends `sent`, while no events are due
+    async fn replies_to(sent: &[u8]) -> Vec<u8> {
+        let (mut client, server) = tokio::io::duplex(1024);
+        tokio::spawn(serve(server, futures::stream::pending()));
+        client.write_all(sent).await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        reply
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_6455() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frame_lengths_use_the_shortest_encoding() {
+        assert_eq!(frame(OP_TEXT, b"Hello"), b"\x81\x05Hello");
+        assert_eq!(frame(OP_TEXT, &[0; 125])[..2], [0x81, 125]);
+
+        let medium = frame(OP_TEXT, &[0; 256]);
+        assert_eq!(medium[..4], [0x81, 126, 0x01, 0x00]);
+        assert_eq!(medium.len(), 4 + 256);
+        assert_eq!(frame(OP_TEXT, &[0; 0xffff])[..4], [0x81, 126, 0xff, 0xff]);
+
+        let large = frame(OP_TEXT, &[0; 0x10000]);
+        assert_eq!(large[..10], [0x81, 127, 0, 0, 0, 0, 0, 0x01, 0, 0]);
+        assert_eq!(large.len(), 10 + 0x10000);
+    }
+
+    #[tokio::test]
+    async fn reads_masked_frames_of_every_length() {
+        // the masked "Hello" from RFC 6455 section 5.7
+        let hello = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(masked(OP_TEXT, b"Hello"), hello);
+        assert_eq!(read(&hello).await, Some(Ok((OP_TEXT, b"Hello".to_vec()))));
+
+        let medium: Vec<u8> = (0..300).map(|byte| byte as u8).collect();
+        assert_eq!(
+            read(&masked(OP_TEXT, &medium)).await,
+            Some(Ok((OP_TEXT, medium)))
+        );
+
+        let mut large = vec![0x81, 0xff];
+        large.extend_from_slice(&70_000u64.to_be_bytes());
+        large.extend_from_slice(&[0x37, 0xfa, 0x21, 0x3d]);
+        large.resize(large.len() + 70_000, 0);
+        assert_eq!(read(&large).await, Some(Err(CLOSE_TOO_BIG)));
+        let mut allowed = vec![0x82, 0xff];
+        allowed.extend_from_slice(&(MAX_CLIENT_FRAME).to_be_bytes());
+        allowed.extend_from_slice(&[0; 4]);
+        allowed.resize(allowed.len() + MAX_CLIENT_FRAME as usize, 7);
+        assert_eq!(
+            read(&allowed).await,
+            Some(Ok((0x2, vec![7; MAX_CLIENT_FRAME as usize])))
+        );
+
+        assert_eq!(read(&hello[..6]).await, None);
+    }
+
+    #[tokio::test]
+    async fn unmasked_client_frames_close_with_a_protocol_error() {
+        assert_eq!(
+            read(b"\x81\x05Hello").await,
+            Some(Err(CLOSE_PROTOCOL_ERROR))
+        );
+        assert_eq!(replies_to(b"\x81\x05Hello").await, close_frame(1002));
+        assert_eq!(close_frame(1002), [0x88, 0x02, 0x03, 0xea]);
+    }
+
+    #[tokio::test]
+    async fn answers_pings_and_closes() {
+        let mut sent = masked(OP_PING, b"Hello");
+        sent.extend(masked(OP_CLOSE, &CLOSE_NORMAL.to_be_bytes()));
+        let mut expected = b"\x8a\x05Hello".to_vec();
+        expected.extend([0x88, 0x02, 0x03, 0xe8]);
+        assert_eq!(replies_to(&sent).await, expected);
+    }
+
+    #[tokio::test]
+    async fn sends_messages_as_text_frames_and_closes_when_they_end() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let message = Message::data(Some("tick".to_string()), json!({ "n": 1 }));
+        tokio::spawn(serve(server, futures::stream::iter([Some(message), None])));
+
+        let text = r#"{"data":{"n":1},"event":"tick","id":null}"#;
+        let mut expected = frame(OP_TEXT, text.as_bytes());
+        expected.extend([0x89, 0x00]);
+        expected.extend(close_frame(CLOSE_NORMAL));
+        // the reader keeps its half open for the client's close reply, so read just the frames sent
+        let mut received = vec![0; expected.len()];
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+    }
+}
@@ -0,0 +1,78 @@
+use sse_proto::MockSseServer;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// the raw response to a GET, read until the server closes it
+async fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nhost: {addr}\r\nconnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn data_lines(response: &str) -> Vec<&str> {
+    response
+        .lines()
+        .filter(|line| line.starts_with("data: {\"n\""))
+        .collect()
+}
+
+#[tokio::test]
+async fn streams_events_and_shuts_down() {
+    let server = MockSseServer::builder()
+        .port(0)
+        .set("limits.interval_floor_ms", 10)
+        .start()
+        .await
+        .unwrap();
+    assert_ne!(server.addr().port(), 0);
+
+    let path =
+        "/?seed=7&interval_min=10&interval_max=10&max_events=3&shape=%7B%22n%22:%22%7Bint%7D%22%7D";
+    let response = get(server.addr(), path).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("event: handshake"), "{response}");
+    assert!(response.contains("event: end"), "{response}");
+    let events = data_lines(&response);
+    assert_eq!(events.len(), 3, "{response}");
+
+    // the same seed and shape give the same events
+    assert_eq!(data_lines(&get(server.addr(), path).await), events);
+
+    let health = get(server.addr(), "/healthz").await;
+    assert!(health.starts_with("HTTP/1.1 200"), "{health}");
+    let addr = server.addr();
+    server.shutdown().await;
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn duration_bounded_disordered_stream_delivers_every_sequence() {
+    let server = MockSseServer::builder()
+        .port(0)
+        .set("limits.interval_floor_ms", 10)
+        .start()
+        .await
+        .unwrap();
+
+    let path = "/?duration_secs=1&disorder=1&disorder_window=100&interval_min=10&interval_max=10&shape=%7B%22n%22:%22%7Bint%7D%22%7D";
+    let response = get(server.addr(), path).await;
+    let mut ids: Vec<u64> = response
+        .lines()
+        .filter_map(|line| line.strip_prefix("id: "))
+        .map(|id| id.parse().unwrap())
+        .collect();
+    ids.sort_unstable();
+    assert!(!ids.is_empty(), "{response}");
+    assert_eq!(
+        ids,
+        (1..=ids.len() as u64).collect::<Vec<_>>(),
+        "{response}"
+    );
+    let end = format!("data: {{\"events\":{},\"reason\":\"duration\"", ids.len());
+    assert!(response.contains(&end), "{response}");
+    server.shutdown().await;
+}